@@ -0,0 +1,10 @@
+// Generates the gRPC service from its protobuf definition. The definition is parsed
+// with protox, so that building the data service does not need `protoc` installed.
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    println!("cargo:rerun-if-changed=proto");
+    let descriptors = protox::compile(["zenithds.proto"], ["proto"])?;
+    tonic_prost_build::configure()
+        .build_client(false)
+        .compile_fds(descriptors)?;
+    Ok(())
+}
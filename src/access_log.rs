@@ -0,0 +1,66 @@
+use std::{
+    fs::OpenOptions,
+    io::Write,
+    net::SocketAddr,
+    sync::Mutex,
+    time::Instant,
+};
+use axum::{
+    body::HttpBody,
+    extract::{ConnectInfo, Request},
+    http::header::{REFERER, USER_AGENT},
+    middleware::Next,
+    response::Response,
+};
+use tracing::{info, error};
+
+use crate::config;
+
+
+/// Held while a line is appended to the access log, so that lines are not interleaved.
+static ACCESS_LOG_LOCK: Mutex<()> = Mutex::new(());
+
+
+/// Logs every request in the Combined Log Format, followed by how long it took:
+/// the address of the client, the time, the request line, the status of the response,
+/// its size in bytes (`-` if it is streamed), the referer, the user agent, and the latency in microseconds.
+///
+/// Lines are logged by `zenithds::access_log`, and also appended to the file
+/// at `ZENITHDS_ACCESS_LOG`, if set.
+pub async fn log(
+    request: Request,
+    next: Next,
+) -> Response {
+
+    let started = Instant::now();
+    let time = chrono::Utc::now().format("%d/%b/%Y:%H:%M:%S %z").to_string();
+    let client = match request.extensions().get::<ConnectInfo<SocketAddr>>() {
+        Some(ConnectInfo(address)) => address.ip().to_string(),
+        None => "-".to_string(),
+    };
+    let headers = request.headers();
+    let (referer, user_agent) = [REFERER, USER_AGENT]
+        .map(|name| headers.get(name).and_then(|v| v.to_str().ok()).unwrap_or("-").to_string()).into();
+    let request_line = format!("{} {} {:?}", request.method(), request.uri(), request.version());
+
+    let response = next.run(request).await;
+
+    let size = match response.body().size_hint().exact() {
+        Some(size) => size.to_string(),
+        None => "-".to_string(),
+    };
+    let line = format!("{} - - [{}] \"{}\" {} {} \"{}\" \"{}\" {}",
+        client, time, request_line, response.status().as_u16(), size, referer, user_agent, started.elapsed().as_micros());
+    info!("{}", line);
+
+    let path = config::envar_str("ZENITHDS_ACCESS_LOG");
+    if !path.is_empty() {
+        let _guard = ACCESS_LOG_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let result = OpenOptions::new().create(true).append(true).open(&path)
+            .and_then(|mut file| file.write_all(format!("{}\n", line).as_bytes()));
+        if let Err(err) = result {
+            error!("Could not write to the access log '{}': {}", path, err);
+        }
+    }
+    response
+}
@@ -0,0 +1,134 @@
+use std::{
+    net::{IpAddr, SocketAddr},
+    sync::{LazyLock, RwLock},
+};
+use axum::{
+    extract::{ConnectInfo, Request},
+    middleware::Next,
+    response::Response,
+};
+use tracing::warn;
+
+use crate::types::error::ZenithError;
+use crate::config;
+
+
+/// A range of IP addresses, given as an address and the number of leading bits that
+/// must match it (for example, `10.0.0.0/8`). A single address matches all of its bits.
+#[derive(Debug)]
+struct Network {
+    address: IpAddr,
+    prefix: u32,
+}
+
+impl Network {
+    fn parse(s: &str) -> Result<Network, String> {
+        let (address, prefix) = match s.split_once('/') {
+            Some((address, prefix)) => (address, Some(prefix)),
+            None => (s, None),
+        };
+        let address: IpAddr = address.parse().map_err(|_| format!("'{}' is not an IP address", s))?;
+        let address = address.to_canonical();
+        let bits = if address.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(prefix) => prefix.parse::<u32>().ok().filter(|p| *p <= bits)
+                .ok_or_else(|| format!("'{}' does not have a valid prefix length", s))?,
+            None => bits,
+        };
+        Ok(Network { address, prefix })
+    }
+
+    fn contains(&self, address: IpAddr) -> bool {
+        match (self.address, address.to_canonical()) {
+            (IpAddr::V4(network), IpAddr::V4(address)) => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix).unwrap_or(0);
+                u32::from(network) & mask == u32::from(address) & mask
+            },
+            (IpAddr::V6(network), IpAddr::V6(address)) => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix).unwrap_or(0);
+                u128::from(network) & mask == u128::from(address) & mask
+            },
+            _ => false,
+        }
+    }
+}
+
+
+/// The networks clients are allowed from, and the networks they are denied from.
+struct Rules {
+    allowed: Vec<Network>,
+    denied: Vec<Network>,
+}
+
+/// Parses the networks in the environment variable `v`, separated by commas.
+fn networks(v: &str) -> Result<Vec<Network>, String> {
+    config::envar_str(v)
+        .split(',')
+        .map(|s| s.trim())
+        .filter(|s| !s.is_empty())
+        .map(|s| Network::parse(s).map_err(|err| format!("{} in {}", err, v)))
+        .collect()
+}
+
+/// Parses the rules in `ZENITHDS_ALLOWED_IPS` and `ZENITHDS_DENIED_IPS`.
+fn rules() -> Result<Rules, String> {
+    Ok(Rules {
+        allowed: networks("ZENITHDS_ALLOWED_IPS")?,
+        denied: networks("ZENITHDS_DENIED_IPS")?,
+    })
+}
+
+/// Parsed when first used, and again when the configuration is reloaded.
+static RULES: LazyLock<RwLock<Result<Rules, String>>> = LazyLock::new(|| RwLock::new(rules()));
+
+
+/// Whether clients are allowed or denied by their IP address. Returns an error if
+/// any of the networks are not valid, so that the data service is not left open.
+pub fn enabled() -> Result<bool, String> {
+    match RULES.read().unwrap_or_else(|e| e.into_inner()).as_ref() {
+        Ok(rules) => Ok(!rules.allowed.is_empty() || !rules.denied.is_empty()),
+        Err(err) => Err(err.clone()),
+    }
+}
+
+
+/// Parses the networks again. If any of them are not valid, returns an error
+/// and keeps the networks that were in use.
+pub fn reload() -> Result<(), String> {
+    let rules = rules()?;
+    *RULES.write().unwrap_or_else(|e| e.into_inner()) = Ok(rules);
+    Ok(())
+}
+
+
+/// Whether a client at `address` can make requests. It must not be in a network in
+/// `ZENITHDS_DENIED_IPS`, and must be in a network in `ZENITHDS_ALLOWED_IPS`, if any are set.
+pub fn allowed(address: IpAddr) -> bool {
+    match RULES.read().unwrap_or_else(|e| e.into_inner()).as_ref() {
+        Ok(rules) => !rules.denied.iter().any(|n| n.contains(address))
+            && (rules.allowed.is_empty() || rules.allowed.iter().any(|n| n.contains(address))),
+        Err(_) => false,
+    }
+}
+
+
+/// Rejects requests from clients that are not allowed by their IP address with a `Forbidden` error.
+/// The address is the one the connection came from, so a proxy in front of the
+/// data service must be allowed itself.
+pub async fn filter(
+    request: Request,
+    next: Next,
+) -> Result<Response, ZenithError> {
+
+    if !enabled().unwrap_or(true) {
+        return Ok(next.run(request).await);
+    }
+    match request.extensions().get::<ConnectInfo<SocketAddr>>() {
+        Some(ConnectInfo(address)) if allowed(address.ip()) => Ok(next.run(request).await),
+        Some(ConnectInfo(address)) => {
+            warn!("Denied a request from {}", address.ip());
+            Err(ZenithError::Forbidden("Requests are not allowed from this address".to_string()))
+        },
+        None => Err(ZenithError::Forbidden("The address of the request is not known".to_string())),
+    }
+}
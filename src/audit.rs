@@ -0,0 +1,148 @@
+use std::{
+    fs::OpenOptions,
+    io::{BufRead, BufReader, Write},
+    sync::Mutex,
+    time::SystemTime,
+};
+use serde::{Serialize, Deserialize};
+use tracing::error;
+
+use crate::types::{error::ZenithError, api::AuditParameters};
+use crate::{auth::Principal, config};
+
+/// The most entries returned from the audit log at once.
+const MAX_ENTRIES: usize = 1000;
+
+
+/// Held while an entry is appended to the audit log, so that entries are not interleaved.
+static AUDIT_LOCK: Mutex<()> = Mutex::new(());
+
+
+/// A change made to a collection, as recorded in the audit log.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct AuditEntry {
+    /// When the change was made, in milliseconds since the Unix epoch.
+    pub time: u64,
+    /// The name of the principal that made the change (see `auth::Principal`).
+    pub principal: String,
+    /// What was done, such as `create` or `delete`.
+    pub action: String,
+    pub collection: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub filename: Option<String>,
+    /// The rows in the file before the change, if it existed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub previous_rows: Option<usize>,
+    /// The rows in the file after the change, if it still exists.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rows: Option<usize>,
+    /// Anything else about the change, such as the column that was renamed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+}
+
+impl AuditEntry {
+    /// Starts an entry for `action` by `principal` on `collection`, made now.
+    pub fn new(principal: &Principal, action: &str, collection: &str) -> Self {
+        let time = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH)
+            .map(|d| d.as_millis() as u64).unwrap_or(0);
+        AuditEntry {
+            time,
+            principal: principal.name.clone(),
+            action: action.to_string(),
+            collection: collection.to_string(),
+            filename: None,
+            previous_rows: None,
+            rows: None,
+            detail: None,
+        }
+    }
+
+    pub fn filename(mut self, filename: &str) -> Self {
+        self.filename = Some(filename.to_string());
+        self
+    }
+
+    pub fn rows(mut self, previous_rows: Option<usize>, rows: Option<usize>) -> Self {
+        self.previous_rows = previous_rows;
+        self.rows = rows;
+        self
+    }
+
+    pub fn detail(mut self, detail: String) -> Self {
+        self.detail = Some(detail);
+        self
+    }
+}
+
+
+/// Whether changes are recorded, which is when `ZENITHDS_AUDIT_LOG` is set.
+pub fn enabled() -> bool {
+    !config::envar_str("ZENITHDS_AUDIT_LOG").is_empty()
+}
+
+
+/// Appends `entry` to the audit log at `ZENITHDS_AUDIT_LOG`, as a line of JSON.
+/// The change has already been made, so failures are only reported.
+pub fn record(entry: AuditEntry) {
+    if !enabled() {
+        return;
+    }
+    let path = config::envar_str("ZENITHDS_AUDIT_LOG");
+    let _guard = AUDIT_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let result = serde_json::to_string(&entry)
+        .map_err(std::io::Error::other)
+        .and_then(|line| {
+            let mut file = OpenOptions::new().create(true).append(true).open(&path)?;
+            file.write_all(format!("{}\n", line).as_bytes())
+        });
+    if let Err(err) = result {
+        error!("Could not record {} in collection '{}' in the audit log '{}': {}", entry.action, entry.collection, path, err);
+    }
+}
+
+
+/// Reads the entries in the audit log that match `parameters`, oldest first.
+/// Only the last `limit` entries that match are returned, up to `MAX_ENTRIES`.
+pub fn read(
+    parameters: &AuditParameters,
+) -> Result<Vec<AuditEntry>, ZenithError> {
+
+    if !enabled() {
+        return Err(ZenithError::QueryError("The audit log is not enabled".to_string()));
+    }
+    let path = config::envar_str("ZENITHDS_AUDIT_LOG");
+    let file = match std::fs::File::open(&path) {
+        Ok(file) => file,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(err) => return Err(err.into()),
+    };
+
+    let matches = |entry: &AuditEntry| {
+        parameters.collection.as_ref().is_none_or(|c| *c == entry.collection)
+        && parameters.filename.as_ref().is_none_or(|f| entry.filename.as_ref() == Some(f))
+        && parameters.principal.as_ref().is_none_or(|p| *p == entry.principal)
+        && parameters.action.as_ref().is_none_or(|a| *a == entry.action)
+        && parameters.since.is_none_or(|since| entry.time >= since)
+    };
+    let limit = parameters.limit.unwrap_or(MAX_ENTRIES).min(MAX_ENTRIES);
+    if limit == 0 {
+        return Ok(Vec::new());
+    }
+
+    let mut entries = std::collections::VecDeque::new();
+    for line in BufReader::new(file).lines() {
+        let line = line?;
+        // A line that was cut short, for example by a crash, is skipped.
+        let Ok(entry) = serde_json::from_str::<AuditEntry>(&line) else {
+            continue;
+        };
+        if matches(&entry) {
+            if entries.len() == limit {
+                entries.pop_front();
+            }
+            entries.push_back(entry);
+        }
+    }
+    Ok(entries.into())
+}
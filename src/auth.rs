@@ -0,0 +1,216 @@
+use std::sync::LazyLock;
+use base64::prelude::{Engine, BASE64_STANDARD};
+use axum::{
+    extract::{MatchedPath, Request},
+    http::{HeaderMap, Method, header::AUTHORIZATION},
+    middleware::Next,
+    response::Response,
+};
+use tracing::{warn, error};
+
+use crate::types::error::ZenithError;
+use crate::{catalog, config, jwt, tls::{self, ClientCertificate}};
+
+/// The header an API key can be given in, as an alternative to a bearer token.
+pub const API_KEY_HEADER: &str = "x-api-key";
+
+
+/// What a request made with an API key is allowed to do.
+#[derive(Clone, Copy, Debug, PartialEq, PartialOrd)]
+pub enum Scope {
+    /// Read files and query collections.
+    Read,
+    /// Everything, including changing collections.
+    Write,
+}
+
+
+/// Who a request was made by, as given by its API key or token.
+/// Requests are made by an anonymous principal that can write when authentication is not enabled.
+#[derive(Clone, Debug)]
+pub struct Principal {
+    /// Who the principal is, as recorded in the audit log. This is not the API key itself.
+    pub name: String,
+    pub scope: Scope,
+    /// The roles of the principal, which decide the columns it can see (see `schema::Mask`).
+    pub roles: Vec<String>,
+}
+
+impl Default for Principal {
+    fn default() -> Self {
+        Principal { name: "anonymous".to_string(), scope: Scope::Write, roles: Vec::new() }
+    }
+}
+
+
+/// The API keys accepted by the data service, each with its principal.
+///
+/// Parsed once from `ZENITHDS_API_KEYS`, separated by commas, and the lines of the
+/// file at `ZENITHDS_API_KEYS_FILE`. Each key is in the form `key`, `key:scope`, or
+/// `key:scope:roles`, where the scope is `read` or `write`, and the roles are separated
+/// by `|`. Keys without a scope can write. Blank lines and lines starting with `#` in
+/// the file are ignored, as are entries without a key, such as `:read`.
+static API_KEYS: LazyLock<Vec<(String, Principal)>> = LazyLock::new(|| {
+    let mut entries: Vec<String> = config::envar_str("ZENITHDS_API_KEYS")
+        .split(',').map(|s| s.to_string()).collect();
+    let path = config::envar_str("ZENITHDS_API_KEYS_FILE");
+    if !path.is_empty() {
+        match std::fs::read_to_string(&path) {
+            Ok(contents) => entries.extend(contents.lines()
+                .filter(|line| !line.trim_start().starts_with('#'))
+                .map(|line| line.to_string())),
+            Err(err) => error!("Could not read API keys from '{}': {}", path, err),
+        }
+    }
+
+    entries.iter()
+        .map(|entry| entry.trim())
+        .filter(|entry| !entry.is_empty())
+        .filter_map(|entry| {
+            let mut parts = entry.splitn(3, ':');
+            let key = parts.next().unwrap_or("").trim().to_string();
+            if key.is_empty() {
+                warn!("Ignoring API key entry without a key");
+                return None;
+            }
+            let scope = match parts.next() {
+                None | Some("write") => Scope::Write,
+                Some("read") => Scope::Read,
+                Some(scope) => {
+                    warn!("Ignoring API key with unknown scope '{}'", scope);
+                    return None;
+                },
+            };
+            let roles = parts.next().unwrap_or("")
+                .split('|').filter(|r| !r.is_empty()).map(|r| r.to_string()).collect();
+            // Keys are named by the start of their checksum, so they are not written to the audit log.
+            let name = format!("key:{}", &catalog::checksum(key.as_bytes())[..12]);
+            Some((key, Principal { name, scope, roles }))
+        })
+        .collect()
+});
+
+
+/// Whether requests need an API key, token, or client certificate, which is when any API
+/// keys are configured, an issuer of JWTs is set (see `jwt`), or client certificates are
+/// verified (see `tls`).
+pub fn enabled() -> bool {
+    !API_KEYS.is_empty() || jwt::enabled() || tls::client_auth_enabled()
+}
+
+
+/// Compares `a` and `b` in time that depends only on their lengths.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+
+/// The routes, besides `GET` requests, that only need to read: queries and the requests like
+/// them, which do not change any collection, and cancelling queries and jobs, and removing
+/// result sets, which a principal can only do to its own.
+const READ_ONLY_ROUTES: &[(Method, &str)] = &[
+    (Method::POST, "/query/{collection}"),
+    (Method::POST, "/jobs/query/{collection}"),
+    (Method::POST, "/explain/{collection}"),
+    (Method::POST, "/profile/{collection}"),
+    (Method::POST, "/top/{collection}"),
+    (Method::POST, "/histogram/{collection}"),
+    (Method::POST, "/graphql"),
+    (Method::POST, "/render"),
+    (Method::POST, "/validate/{collection}"),
+    (Method::POST, "/views/{name}/query"),
+    (Method::POST, "/queries/saved/{name}/run"),
+    (Method::DELETE, "/queries/{id}"),
+    (Method::DELETE, "/jobs/{id}"),
+    (Method::DELETE, "/results/{id}"),
+];
+
+
+/// Returns the scope a request needs. Requests to administer the data service always need to
+/// write, and other `GET` requests and the `READ_ONLY_ROUTES` only need to read.
+fn required_scope(request: &Request) -> Scope {
+    let path = request.extensions().get::<MatchedPath>().map(|p| p.as_str()).unwrap_or("");
+    let route = path.strip_prefix(config::prefix("v1").as_str()).unwrap_or(path);
+    if route.starts_with("/admin/") {
+        Scope::Write
+    }
+    else if request.method() == Method::GET
+        || READ_ONLY_ROUTES.iter().any(|(method, read_only)| request.method() == method && route == *read_only) {
+        Scope::Read
+    }
+    else {
+        Scope::Write
+    }
+}
+
+
+/// Returns the principal of the API `key`, raising `Unauthorized` if it is not valid.
+fn key_principal(key: &str) -> Result<Principal, ZenithError> {
+    API_KEYS.iter()
+        .filter(|(k, _)| constant_time_eq(k.as_bytes(), key.as_bytes()))
+        .map(|(_, principal)| principal.clone())
+        .next()
+        .ok_or_else(|| ZenithError::Unauthorized("The API key is not valid".to_string()))
+}
+
+
+/// Requires an API key or JWT with the scope needed by the request, when authentication
+/// is enabled. An API key is given in the `X-Api-Key` header or as a bearer token, and
+/// a JWT is given as a bearer token. Requests without either are made by the client
+/// certificate of the connection, if it has one. The `Principal` of the request is added to it.
+pub async fn authenticate(
+    mut request: Request,
+    next: Next,
+) -> Result<Response, ZenithError> {
+
+    if !enabled() {
+        request.extensions_mut().insert(Principal::default());
+        return Ok(next.run(request).await);
+    }
+
+    let certificate = request.extensions().get::<Option<ClientCertificate>>()
+        .and_then(|c| c.as_ref())
+        .map(|c| c.fingerprint.clone());
+    let principal = principal(request.headers(), certificate).await?;
+    if principal.scope < required_scope(&request) {
+        return Err(ZenithError::Forbidden("The credentials of the request do not have the scope it needs".to_string()));
+    }
+    request.extensions_mut().insert(principal);
+    Ok(next.run(request).await)
+}
+
+
+/// Returns the principal of a request with `headers`, made over a connection with the client
+/// certificate whose `certificate` fingerprint is given, if any. Raises `Unauthorized` if the
+/// request has no valid API key or token, and no certificate.
+pub async fn principal(
+    headers: &HeaderMap,
+    certificate: Option<String>,
+) -> Result<Principal, ZenithError> {
+
+    let key = headers.get(API_KEY_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .map(|v| v.trim().to_string());
+    let authorization = headers.get(AUTHORIZATION).and_then(|v| v.to_str().ok());
+    let bearer = authorization
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .map(|v| v.trim().to_string())
+        // Clients that can only send a user name and password, such as spreadsheets, give the
+        // key or token as the password, with any user name.
+        .or_else(|| authorization
+            .and_then(|v| v.strip_prefix("Basic "))
+            .and_then(|v| BASE64_STANDARD.decode(v.trim()).ok())
+            .and_then(|v| String::from_utf8(v).ok())
+            .and_then(|v| v.split_once(':').map(|(_, password)| password.trim().to_string())));
+
+    Ok(match (key, bearer) {
+        (Some(key), _) => key_principal(&key)?,
+        // A JWT has three parts separated by dots.
+        (None, Some(token)) if jwt::enabled() && token.split('.').count() == 3 => jwt::principal(&token).await?,
+        (None, Some(key)) => key_principal(&key)?,
+        (None, None) => match certificate {
+            Some(fingerprint) => Principal { name: format!("cert:{}", &fingerprint[..12]), scope: tls::client_scope(), roles: Vec::new() },
+            None => return Err(ZenithError::Unauthorized("An API key, token, or client certificate is required".to_string())),
+        },
+    })
+}
@@ -0,0 +1,176 @@
+use apache_avro::{types::Value, Codec, DeflateSettings, Reader, Schema, Writer};
+use chrono::{DateTime, NaiveDate, NaiveDateTime, NaiveTime};
+
+use crate::schema::{self, ColumnType};
+use crate::types::error::ZenithError;
+
+/// The custom attribute of a field holding the name of its column, when the name is not a valid Avro name.
+const COLUMN_ATTRIBUTE: &str = "column";
+
+
+/// Whether `bytes` are an Avro object container file.
+pub fn is_avro(bytes: &[u8]) -> bool {
+    bytes.starts_with(b"Obj\x01")
+}
+
+
+/// Returns `name` as a valid Avro name, which has only letters, digits, and `_`, and does
+/// not start with a digit. Other characters are replaced by `_`.
+fn avro_name(name: &str) -> String {
+    let mut avro: String = name.chars().map(|c| if c.is_ascii_alphanumeric() { c } else { '_' }).collect();
+    if !avro.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_') {
+        avro.insert(0, '_');
+    }
+    avro
+}
+
+
+/// Returns a date as it is written in CSV, as `YYYY-MM-DD` if it has no time.
+fn date_text(datetime: NaiveDateTime) -> String {
+    match datetime.time() == NaiveTime::MIN {
+        true => datetime.format("%Y-%m-%d").to_string(),
+        false => datetime.format("%Y-%m-%d %H:%M:%S%.f").to_string(),
+    }
+}
+
+
+/// Returns the Avro schema of records with a field for each column of `header`, of the
+/// type in `types`. Columns of any type but `string` can be `null`, for empty values.
+fn record_schema(name: &str, header: &[String], types: &[ColumnType]) -> Result<Schema, ZenithError> {
+    let mut names: Vec<String> = Vec::new();
+    let fields: Vec<serde_json::Value> = header.iter().enumerate()
+        .map(|(i, column)| {
+            let mut field_name = avro_name(column);
+            while names.contains(&field_name) {
+                field_name.push('_');
+            }
+            names.push(field_name.clone());
+            let field_type = match types.get(i).copied().unwrap_or_default() {
+                ColumnType::String => serde_json::json!("string"),
+                ColumnType::Int => serde_json::json!(["null", "long"]),
+                ColumnType::Float => serde_json::json!(["null", "double"]),
+                ColumnType::Bool => serde_json::json!(["null", "boolean"]),
+                ColumnType::Date => serde_json::json!(["null", {"type": "long", "logicalType": "timestamp-millis"}]),
+            };
+            let mut field = serde_json::json!({"name": field_name, "type": field_type});
+            if field_name != *column {
+                field[COLUMN_ATTRIBUTE] = serde_json::json!(column);
+            }
+            field
+        })
+        .collect();
+    let schema = serde_json::json!({"type": "record", "name": avro_name(name), "fields": fields});
+    Schema::parse(&schema).map_err(|err| ZenithError::QueryError(format!("Avro schema cannot be made: {}", err)))
+}
+
+
+/// Returns `value` as an Avro value of `column_type`, raising a `QueryError` if it cannot be parsed.
+fn avro_value(column: &str, column_type: ColumnType, value: &str) -> Result<Value, ZenithError> {
+    if column_type == ColumnType::String {
+        return Ok(Value::String(value.to_string()));
+    }
+    if value.is_empty() {
+        return Ok(Value::Union(0, Box::new(Value::Null)));
+    }
+    let typed = match column_type {
+        ColumnType::Int => value.parse::<i64>().ok().map(Value::Long),
+        ColumnType::Float => value.parse::<f64>().ok().map(Value::Double),
+        ColumnType::Bool => schema::parse_bool(value).map(Value::Boolean),
+        ColumnType::Date => schema::parse_date(value).map(|d| Value::TimestampMillis(d.and_utc().timestamp_millis())),
+        ColumnType::String => None,
+    };
+    match typed {
+        Some(typed) => Ok(Value::Union(1, Box::new(typed))),
+        None => Err(ZenithError::QueryError(format!(
+            "Value '{}' in column '{}' is not a {}", value, column, format!("{:?}", column_type).to_lowercase()
+        ))),
+    }
+}
+
+
+/// Writes `header` and `rows` as an Avro object container file of records named `name`,
+/// with the values of each column typed by `types`. See `record_schema`.
+pub fn write(
+    name: &str,
+    header: &[String],
+    types: &[ColumnType],
+    rows: &[Vec<String>],
+) -> Result<Vec<u8>, ZenithError> {
+
+    let schema = record_schema(name, header, types)?;
+    let Schema::Record(record) = &schema else {
+        return Err(ZenithError::QueryError("Avro schema is not a record".to_string()));
+    };
+    let avro_error = |err: apache_avro::Error| ZenithError::QueryError(format!("Avro file cannot be written: {}", err));
+    let mut writer = Writer::with_codec(&schema, Vec::new(), Codec::Deflate(DeflateSettings::default()))
+        .map_err(avro_error)?;
+    for row in rows {
+        let fields = record.fields.iter().zip(header).zip(row).enumerate()
+            .map(|(i, ((field, column), value))| {
+                Ok((field.name.clone(), avro_value(column, types.get(i).copied().unwrap_or_default(), value)?))
+            })
+            .collect::<Result<Vec<(String, Value)>, ZenithError>>()?;
+        writer.append_value(Value::Record(fields)).map_err(avro_error)?;
+    }
+    writer.into_inner().map_err(avro_error)
+}
+
+
+/// Returns an Avro `value` as it is written in CSV, with `null` as the empty value and dates
+/// and timestamps as with `date_text`. Raises a `QueryError` for values that are not scalars.
+fn cell(field: &str, value: Value) -> Result<String, ZenithError> {
+    let timestamp = |datetime: Option<DateTime<chrono::Utc>>| datetime.map(|d| date_text(d.naive_utc())).unwrap_or_default();
+    Ok(match value {
+        Value::Null => String::new(),
+        Value::Boolean(b) => b.to_string(),
+        Value::Int(i) => i.to_string(),
+        Value::Long(i) => i.to_string(),
+        Value::Float(f) => f.to_string(),
+        Value::Double(f) => f.to_string(),
+        Value::String(s) | Value::Enum(_, s) => s,
+        Value::Uuid(uuid) => uuid.to_string(),
+        Value::BigDecimal(decimal) => decimal.to_string(),
+        Value::Date(days) => NaiveDate::from_num_days_from_ce_opt(days + 719_163)
+            .map(|d| d.format("%Y-%m-%d").to_string()).unwrap_or_default(),
+        Value::TimeMillis(ms) => NaiveTime::MIN.overflowing_add_signed(chrono::Duration::milliseconds(ms as i64)).0
+            .format("%H:%M:%S%.f").to_string(),
+        Value::TimeMicros(us) => NaiveTime::MIN.overflowing_add_signed(chrono::Duration::microseconds(us)).0
+            .format("%H:%M:%S%.f").to_string(),
+        Value::TimestampMillis(ms) | Value::LocalTimestampMillis(ms) => timestamp(DateTime::from_timestamp_millis(ms)),
+        Value::TimestampMicros(us) | Value::LocalTimestampMicros(us) => timestamp(DateTime::from_timestamp_micros(us)),
+        Value::TimestampNanos(ns) | Value::LocalTimestampNanos(ns) => timestamp(Some(DateTime::from_timestamp_nanos(ns))),
+        Value::Union(_, value) => cell(field, *value)?,
+        _ => return Err(ZenithError::QueryError(format!("Field '{}' is not a string, number, boolean, or date", field))),
+    })
+}
+
+
+/// Reads an Avro object container file of records, returning a header with the name of each
+/// field, and the values of each record as rows. Fields written by `write` are given the
+/// names of their columns.
+#[allow(clippy::type_complexity)]
+pub fn read(bytes: &[u8]) -> Result<(Vec<String>, Vec<Vec<String>>), ZenithError> {
+    let avro_error = |err: apache_avro::Error| ZenithError::QueryError(format!("Avro file cannot be read: {}", err));
+    let reader = Reader::new(bytes).map_err(avro_error)?;
+    let Schema::Record(record) = reader.writer_schema() else {
+        return Err(ZenithError::QueryError("Avro file does not hold records".to_string()));
+    };
+    let header: Vec<String> = record.fields.iter()
+        .map(|field| match field.custom_attributes.get(COLUMN_ATTRIBUTE) {
+            Some(serde_json::Value::String(column)) => column.clone(),
+            _ => field.name.clone(),
+        })
+        .collect();
+
+    let mut rows = Vec::new();
+    for value in reader {
+        let Value::Record(fields) = value.map_err(avro_error)? else {
+            return Err(ZenithError::QueryError("Avro file does not hold records".to_string()));
+        };
+        let row = fields.into_iter()
+            .map(|(field, value)| cell(&field, value))
+            .collect::<Result<Vec<String>, ZenithError>>()?;
+        rows.push(row);
+    }
+    Ok((header, rows))
+}
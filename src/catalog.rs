@@ -0,0 +1,159 @@
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::types::{api::Dialect, error::ZenithError};
+use crate::{config, crypto, db, storage::{storage, list_data_files}};
+
+/// The file in each collection that holds its catalog.
+pub const CATALOG_FILENAME: &str = ".catalog.json";
+
+
+/// What the data service knows about a file in a collection.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct CatalogFile {
+    pub rows: usize,
+    pub size: u64,
+    pub sha256: String,
+    /// When the file was last recorded, in milliseconds since the Unix epoch.
+    pub updated: u64,
+}
+
+/// The catalog of a collection, recording its canonical `header` and its `files`,
+/// and the `dialect` its files are read and written with.
+#[derive(Serialize, Deserialize, Default, Debug)]
+pub struct Catalog {
+    pub header: Vec<String>,
+    pub files: BTreeMap<String, CatalogFile>,
+    #[serde(default, skip_serializing_if = "Dialect::is_default")]
+    pub dialect: Dialect,
+}
+
+
+/// Returns the SHA-256 checksum of `bytes` as a hexadecimal string.
+pub fn checksum(bytes: &[u8]) -> String {
+    Sha256::digest(bytes).iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+
+fn catalog_path(collection: &str) -> PathBuf {
+    config::collection_path(collection).join(CATALOG_FILENAME)
+}
+
+
+/// Reads the file at `path` as it is stored, returning its header and catalog entry.
+fn describe(path: &Path, dialect: &Dialect) -> Result<(Vec<String>, CatalogFile), ZenithError> {
+    let bytes = storage().read(path)?;
+    let (size, sha256) = (bytes.len() as u64, checksum(&bytes));
+    let (header, rows, _) = db::render(&crypto::decrypt(bytes)?, dialect)?;
+    let updated = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64).unwrap_or(0);
+    Ok((header, CatalogFile { rows: rows.len(), size, sha256, updated }))
+}
+
+
+/// Builds a catalog of `collection` from the files in it, keeping its dialect.
+/// 
+/// The canonical header is the header shared by the most files, with ties going to
+/// the header of the first file by name. Returns the catalog along with the names
+/// of any files that do not have the canonical header.
+pub fn build(collection: &str) -> Result<(Catalog, Vec<String>), ZenithError> {
+    let mut entries = list_data_files(&config::collection_path(collection))?;
+    entries.sort_by(|a, b| a.name.cmp(&b.name));
+
+    let mut catalog = Catalog { dialect: dialect(collection)?, ..Catalog::default() };
+    let mut headers: Vec<(String, Vec<String>)> = Vec::new();
+    for entry in entries {
+        let (header, file) = describe(&entry.path, &catalog.dialect)?;
+        catalog.files.insert(entry.name.clone(), file);
+        headers.push((entry.name, header));
+    }
+
+    let mut counts: Vec<(&Vec<String>, usize)> = Vec::new();
+    for (_, header) in &headers {
+        match counts.iter_mut().find(|(h, _)| *h == header) {
+            Some((_, n)) => *n += 1,
+            None => counts.push((header, 1)),
+        }
+    }
+    // `max_by_key` returns the last maximum, so search from the back.
+    if let Some((header, _)) = counts.iter().rev().max_by_key(|(_, n)| *n) {
+        catalog.header = header.to_vec();
+    }
+
+    let mismatched = headers.into_iter()
+        .filter(|(_, header)| *header != catalog.header)
+        .map(|(filename, _)| filename)
+        .collect();
+    Ok((catalog, mismatched))
+}
+
+
+/// Reads the catalog of `collection`.
+/// 
+/// If the collection does not have a catalog yet, one is built from its files.
+/// It is written the next time the collection is changed.
+pub fn read(collection: &str) -> Result<Catalog, ZenithError> {
+    let path = catalog_path(collection);
+    if !storage().is_file(&path) {
+        return Ok(build(collection)?.0);
+    }
+    Ok(serde_json::from_slice(&storage().read(&path)?)?)
+}
+
+
+/// Returns the dialect of `collection`, without building a catalog if it does not have one.
+pub fn dialect(collection: &str) -> Result<Dialect, ZenithError> {
+    let path = catalog_path(collection);
+    if !storage().is_file(&path) {
+        return Ok(Dialect::default());
+    }
+    Ok(serde_json::from_slice::<Catalog>(&storage().read(&path)?)?.dialect)
+}
+
+
+/// Writes the `catalog` of `collection`, replacing the previous one.
+/// 
+/// The collection lock must be held while calling this.
+pub fn write(collection: &str, catalog: &Catalog) -> Result<(), ZenithError> {
+    let path = catalog_path(collection);
+    let temp_path = path.with_extension("json.tmp");
+    storage().write(&temp_path, &serde_json::to_vec_pretty(catalog)?)?;
+    storage().rename(&temp_path, &path)?;
+    Ok(())
+}
+
+
+/// Records `filenames` in the catalog of `collection` as they are stored,
+/// forgetting any files that no longer exist.
+/// 
+/// If the collection has no files left, its canonical header is cleared.
+/// The collection lock must be held while calling this.
+pub fn update(collection: &str, filenames: &[String]) -> Result<Catalog, ZenithError> {
+    let mut catalog = read(collection)?;
+    let collection_path = config::collection_path(collection);
+
+    for filename in filenames {
+        let path = collection_path.join(filename);
+        if storage().is_file(&path) {
+            let (header, file) = describe(&path, &catalog.dialect)?;
+            if catalog.header.is_empty() {
+                catalog.header = header;
+            }
+            catalog.files.insert(filename.to_string(), file);
+        }
+        else {
+            catalog.files.remove(filename);
+        }
+    }
+    if catalog.files.is_empty() {
+        catalog.header.clear();
+    }
+
+    write(collection, &catalog)?;
+    Ok(catalog)
+}
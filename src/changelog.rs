@@ -0,0 +1,127 @@
+use std::{
+    fs::OpenOptions,
+    io::{BufRead, BufReader, Write},
+    sync::Mutex,
+};
+use serde::{Serialize, Deserialize};
+use tracing::error;
+
+use crate::changes::Change;
+use crate::config;
+use crate::types::{error::ZenithError, api::ChangeLogParameters};
+
+/// The most changes returned from the change log at once.
+const MAX_CHANGES: usize = 1000;
+
+
+/// The change log appended to last, and the offset of the next change in it. Held while a
+/// change is appended, so that changes are not interleaved and their offsets are in order.
+static NEXT_OFFSET: Mutex<Option<(String, u64)>> = Mutex::new(None);
+
+
+/// A change to the files of a collection, as recorded in the change log.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct LoggedChange {
+    /// The position of the change in the log, counting from 1.
+    pub offset: u64,
+    /// When the change was made, in milliseconds since the Unix epoch.
+    pub time: u64,
+    /// What was done, such as `create` or `delete`.
+    pub operation: String,
+    pub collection: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub filename: Option<String>,
+    /// The rows in the file before the change, if it existed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub previous_rows: Option<usize>,
+    /// The rows in the file after the change, if it still exists.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rows: Option<usize>,
+}
+
+
+/// Whether changes are logged, which is when `ZENITHDS_CHANGE_LOG` is set.
+pub fn enabled() -> bool {
+    !config::envar_str("ZENITHDS_CHANGE_LOG").is_empty()
+}
+
+
+/// Returns the changes in the change log at `path`, oldest first, skipping lines that
+/// were cut short, for example by a crash. A log that does not exist yet has none.
+fn changes(path: &str) -> Result<impl Iterator<Item = LoggedChange>, ZenithError> {
+    let file = match std::fs::File::open(path) {
+        Ok(file) => Some(file),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => None,
+        Err(err) => return Err(err.into()),
+    };
+    Ok(file.into_iter()
+        .flat_map(|file| BufReader::new(file).lines())
+        .map_while(Result::ok)
+        .filter_map(|line| serde_json::from_str::<LoggedChange>(&line).ok()))
+}
+
+
+/// Appends `change` to the change log at `ZENITHDS_CHANGE_LOG`, as a line of JSON, with the
+/// offset after the last change in it. The change has already been made, so failures are only
+/// reported.
+pub fn append(change: &Change) {
+    if !enabled() {
+        return;
+    }
+    let path = config::envar_str("ZENITHDS_CHANGE_LOG");
+    let mut next_offset = NEXT_OFFSET.lock().unwrap_or_else(|e| e.into_inner());
+    let result = (|| {
+        // The log is read once for its last offset, and again if it has been moved.
+        let offset = match &*next_offset {
+            Some((logged, offset)) if *logged == path => *offset,
+            _ => changes(&path)?.last().map(|c| c.offset + 1).unwrap_or(1),
+        };
+        let logged = LoggedChange {
+            offset,
+            time: change.time,
+            operation: change.action.clone(),
+            collection: change.collection.clone(),
+            filename: change.filename.clone(),
+            previous_rows: change.previous_rows,
+            rows: change.rows,
+        };
+        let line = serde_json::to_string(&logged)?;
+        let mut file = OpenOptions::new().create(true).append(true).open(&path)?;
+        file.write_all(format!("{}\n", line).as_bytes())?;
+        *next_offset = Some((path.clone(), offset + 1));
+        Ok::<(), ZenithError>(())
+    })();
+    if let Err(err) = result {
+        error!("Could not record {} in collection '{}' in the change log '{}': {}", change.action, change.collection, path, err);
+    }
+}
+
+
+/// Reads the changes in the change log after the offset `since` that match the `collection`, if
+/// given, oldest first, up to a `limit` of `MAX_CHANGES`. Returns them with the offset to read
+/// the changes after them from, which is the last offset read, even if no changes matched it.
+pub fn read(
+    parameters: &ChangeLogParameters,
+) -> Result<(Vec<LoggedChange>, u64), ZenithError> {
+
+    if !enabled() {
+        return Err(ZenithError::QueryError("The change log is not enabled".to_string()));
+    }
+    let since = parameters.since.unwrap_or(0);
+    let limit = parameters.limit.unwrap_or(MAX_CHANGES).min(MAX_CHANGES);
+    let (mut matched, mut offset) = (Vec::new(), since);
+    if limit == 0 {
+        return Ok((matched, offset));
+    }
+
+    for change in changes(&config::envar_str("ZENITHDS_CHANGE_LOG"))?.filter(|c| c.offset > since) {
+        offset = change.offset;
+        if parameters.collection.as_ref().is_none_or(|c| *c == change.collection) {
+            matched.push(change);
+            if matched.len() == limit {
+                break;
+            }
+        }
+    }
+    Ok((matched, offset))
+}
@@ -0,0 +1,136 @@
+use std::sync::{Arc, LazyLock};
+
+use axum::extract::ws::{Message, WebSocket};
+use serde::Serialize;
+use tokio::sync::broadcast::{self, error::RecvError};
+use tracing::{debug, warn};
+
+use crate::audit::AuditEntry;
+use crate::auth::Principal;
+use crate::{changelog, db, replication, schema, shutdown, triggers, views, webhooks};
+
+/// The most changes kept for a subscriber that has not sent them yet. A subscriber
+/// that falls further behind misses the oldest, and is told how many it missed.
+const CAPACITY: usize = 1024;
+
+
+/// The header and rows of a file after a change.
+#[derive(Serialize, Clone, Debug)]
+pub struct Contents {
+    pub header: Vec<String>,
+    pub rows: Vec<Vec<String>>,
+}
+
+/// A change to the files of a collection, as it is sent to subscribers. The same as
+/// the entry recorded in the audit log, without the principal that made it.
+#[derive(Serialize, Clone, Debug)]
+pub struct Change {
+    /// When the change was made, in milliseconds since the Unix epoch.
+    pub time: u64,
+    /// What was done, such as `create` or `delete`.
+    pub action: String,
+    pub collection: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub filename: Option<String>,
+    /// The rows in the file before the change, if it existed.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub previous_rows: Option<usize>,
+    /// The rows in the file after the change, if it still exists.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rows: Option<usize>,
+    /// The file after the change, if it was created. Only sent to subscribers that ask for it.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub contents: Option<Contents>,
+}
+
+
+static CHANGES: LazyLock<broadcast::Sender<Arc<Change>>> = LazyLock::new(|| broadcast::channel(CAPACITY).0);
+
+
+/// Whether any client is subscribed to changes, so that it is worth keeping the contents of files.
+pub fn watched() -> bool {
+    CHANGES.receiver_count() > 0
+}
+
+
+/// Records the change recorded by `entry` in the change log, and sends it to the webhooks and
+/// subscribers of its collection, with the header and rows of the file after the change, if
+/// they are given, to subscribers. Views of the collection refreshed on change are refreshed, its
+/// triggers are fired if a file was created, and the change is replicated to peers.
+pub fn publish(entry: &AuditEntry, contents: Option<db::Selection>) {
+    let change = Change {
+        time: entry.time,
+        action: entry.action.clone(),
+        collection: entry.collection.clone(),
+        filename: entry.filename.clone(),
+        previous_rows: entry.previous_rows,
+        rows: entry.rows,
+        contents: None,
+    };
+    changelog::append(&change);
+    webhooks::trigger(&change);
+    views::trigger(&change);
+    triggers::fire(&change);
+    replication::trigger(&change);
+    if !watched() {
+        return;
+    }
+    let change = Change { contents: contents.map(|(header, rows)| Contents { header, rows }), ..change };
+    // Subscribers may have gone since they were counted.
+    let _ = CHANGES.send(Arc::new(change));
+}
+
+
+/// Returns `change` as a message for `principal`, with the contents of the file only if
+/// `with_rows` is set, and with the columns it cannot see masked.
+fn message(change: &Change, with_rows: bool, principal: &Principal) -> Result<String, serde_json::Error> {
+    let contents = match (with_rows, &change.contents) {
+        (true, Some(contents)) => {
+            let masks = schema::read(&change.collection).ok().flatten()
+                .map(|schema| schema.masks(&principal.roles))
+                .unwrap_or_default();
+            Some(Contents {
+                header: schema::mask_header(&masks, contents.header.clone()),
+                rows: contents.rows.iter().map(|row| schema::mask_row(&masks, &contents.header, row.clone())).collect(),
+            })
+        },
+        _ => None,
+    };
+    serde_json::to_string(&Change { contents, ..change.clone() })
+}
+
+
+/// Sends a message over `socket` for each change to the files in `collection`, until the client
+/// closes it or the data service is asked to stop. Each message is a `Change` as JSON, or, if
+/// the client fell behind, `{"action": "missed", "changes": n}`, after which it should refresh.
+pub async fn forward(mut socket: WebSocket, collection: String, with_rows: bool, principal: Principal) {
+    let mut changes = CHANGES.subscribe();
+    debug!("Subscribed to changes in collection '{}'", collection);
+    loop {
+        let text = tokio::select! {
+            change = changes.recv() => match change {
+                Ok(change) if change.collection == collection => match message(&change, with_rows, &principal) {
+                    Ok(text) => text,
+                    Err(err) => {
+                        warn!("Could not send a change in collection '{}': {}", collection, err);
+                        continue;
+                    },
+                },
+                Ok(_) => continue,
+                Err(RecvError::Lagged(missed)) => serde_json::json!({"action": "missed", "changes": missed}).to_string(),
+                Err(RecvError::Closed) => break,
+            },
+            // Pings are answered as they are received, so only the end of the connection matters.
+            received = socket.recv() => match received {
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                Some(Ok(_)) => continue,
+            },
+            _ = shutdown::requested() => break,
+        };
+        if socket.send(Message::Text(text.into())).await.is_err() {
+            break;
+        }
+    }
+    let _ = socket.send(Message::Close(None)).await;
+    debug!("Unsubscribed from changes in collection '{}'", collection);
+}
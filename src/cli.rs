@@ -0,0 +1,91 @@
+use std::collections::HashMap;
+use clap::Parser;
+
+
+/// A data service for collections of CSV files.
+///
+/// Settings given here override environment variables and the config file.
+#[derive(Parser, Debug)]
+#[command(version, about)]
+pub struct Cli {
+    /// The path of the config file, instead of ZENITHDS_CONFIG or zenithds.toml.
+    #[arg(long, value_name = "PATH")]
+    pub config: Option<String>,
+
+    /// The host name to serve on (ZENITHDS_HOST).
+    #[arg(long)]
+    host: Option<String>,
+
+    /// The port to serve on (ZENITHDS_PORT).
+    #[arg(long)]
+    port: Option<u16>,
+
+    /// The directory that collections are kept in (ZENITHDS_DATA_PATH).
+    #[arg(long, value_name = "PATH")]
+    data_dir: Option<String>,
+
+    /// The number of workers each query is divided between (ZENITHDS_NUM_WORKERS).
+    #[arg(long)]
+    workers: Option<usize>,
+
+    /// The number of rows in each page of a query by default (ZENITHDS_DEFAULT_PAGE_SIZE).
+    #[arg(long)]
+    page_size: Option<usize>,
+
+    /// Where files are stored, filesystem or memory (ZENITHDS_STORAGE).
+    #[arg(long)]
+    storage: Option<String>,
+
+    /// The levels to log at (ZENITHDS_LOG).
+    #[arg(long, value_name = "LEVELS")]
+    log: Option<String>,
+
+    /// The format of logs, text or json (ZENITHDS_LOG_FORMAT).
+    #[arg(long, value_name = "FORMAT")]
+    log_format: Option<String>,
+
+    /// The PEM file with the certificate chain to serve HTTPS with (ZENITHDS_TLS_CERT).
+    #[arg(long, value_name = "PATH")]
+    tls_cert: Option<String>,
+
+    /// The PEM file with the private key to serve HTTPS with (ZENITHDS_TLS_KEY).
+    #[arg(long, value_name = "PATH")]
+    tls_key: Option<String>,
+
+    /// Any other setting, named as in the config file, such as rate_limit=10. Can be given more than once.
+    #[arg(long = "set", value_name = "NAME=VALUE", value_parser = parse_setting)]
+    settings: Vec<(String, String)>,
+}
+
+/// Parses a `name=value` setting, naming it by its environment variable.
+fn parse_setting(s: &str) -> Result<(String, String), String> {
+    match s.split_once('=') {
+        Some((name, value)) if !name.trim().is_empty() => Ok((
+            format!("ZENITHDS_{}", name.trim().to_uppercase().replace(['-', '.'], "_")),
+            value.to_string(),
+        )),
+        _ => Err(format!("'{}' is not in the form NAME=VALUE", s)),
+    }
+}
+
+impl Cli {
+    /// Returns the settings given, by the name of the environment variable each stands for.
+    pub fn overrides(&self) -> HashMap<String, String> {
+        let named = [
+            ("ZENITHDS_HOST", self.host.clone()),
+            ("ZENITHDS_PORT", self.port.map(|p| p.to_string())),
+            ("ZENITHDS_DATA_PATH", self.data_dir.clone()),
+            ("ZENITHDS_NUM_WORKERS", self.workers.map(|w| w.to_string())),
+            ("ZENITHDS_DEFAULT_PAGE_SIZE", self.page_size.map(|n| n.to_string())),
+            ("ZENITHDS_STORAGE", self.storage.clone()),
+            ("ZENITHDS_LOG", self.log.clone()),
+            ("ZENITHDS_LOG_FORMAT", self.log_format.clone()),
+            ("ZENITHDS_TLS_CERT", self.tls_cert.clone()),
+            ("ZENITHDS_TLS_KEY", self.tls_key.clone()),
+        ];
+        // Named flags take precedence over the same setting given with --set.
+        self.settings.iter().cloned()
+            .chain(named.into_iter().filter_map(|(name, value)| value.map(|v| (name.to_string(), v))))
+            .collect()
+    }
+}
@@ -0,0 +1,183 @@
+use reqwest::{Response, StatusCode};
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::auth::API_KEY_HEADER;
+use crate::types::{
+    error::{Missing, ZenithError},
+    api::{CreatePayload, CreateResponse, QueryPredicates, QueryResponse, RenderResponse},
+};
+
+
+/// A client of the API of a data service, for Rust programs that use one.
+///
+/// Errors returned by the data service are given as the `ZenithError` it returned them as,
+/// where it can be told from the code or status of the response, and as `RemoteError` otherwise.
+pub struct Client {
+    http: reqwest::Client,
+    /// The base URL of the API, including its prefix and version, such as `http://localhost:8750/api/v1`.
+    base_url: String,
+    api_key: Option<String>,
+}
+
+impl Client {
+    /// Returns a client of the API at `base_url`, such as `http://localhost:8750/api/v1`.
+    pub fn new(base_url: &str) -> Self {
+        Client {
+            http: reqwest::Client::new(),
+            base_url: base_url.trim_end_matches('/').to_string(),
+            api_key: None,
+        }
+    }
+
+    /// Gives `api_key` as the API key of each request.
+    pub fn api_key(mut self, api_key: &str) -> Self {
+        self.api_key = Some(api_key.to_string());
+        self
+    }
+
+    /// Makes requests with `http`, such as one with a timeout or proxy of its own.
+    pub fn http_client(mut self, http: reqwest::Client) -> Self {
+        self.http = http;
+        self
+    }
+
+    /// Sends `request` with the API key, returning the response if it was successful.
+    async fn send(&self, request: reqwest::RequestBuilder) -> Result<Response, ZenithError> {
+        let request = match &self.api_key {
+            Some(api_key) => request.header(API_KEY_HEADER, api_key),
+            None => request,
+        };
+        let response = request.send().await
+            .map_err(|err| ZenithError::RemoteError(err.to_string()))?;
+        match response.status().is_success() {
+            true => Ok(response),
+            false => Err(error(response).await),
+        }
+    }
+
+    /// Queries `collection` with `predicates`, returning the default page of rows.
+    pub async fn query(
+        &self,
+        collection: &str,
+        predicates: &QueryPredicates,
+    ) -> Result<QueryResponse, ZenithError> {
+
+        self.post_query(collection, predicates, &[]).await
+    }
+
+    /// Queries `collection` with `predicates`, returning `page`, counting from 0, of `per_page`
+    /// rows, or `ZENITHDS_DEFAULT_PAGE_SIZE` rows if it is not given.
+    pub async fn query_page(
+        &self,
+        collection: &str,
+        predicates: &QueryPredicates,
+        page: usize,
+        per_page: Option<usize>,
+    ) -> Result<QueryResponse, ZenithError> {
+
+        let mut parameters = vec![("page", page)];
+        parameters.extend(per_page.map(|per_page| ("per_page", per_page)));
+        self.post_query(collection, predicates, &parameters).await
+    }
+
+    async fn post_query(
+        &self,
+        collection: &str,
+        predicates: &QueryPredicates,
+        parameters: &[(&str, usize)],
+    ) -> Result<QueryResponse, ZenithError> {
+
+        let request = self.http.post(format!("{}/query/{}", self.base_url, collection))
+            .query(parameters)
+            .json(predicates);
+        self.send(request).await?.json().await
+            .map_err(|err| ZenithError::RemoteError(err.to_string()))
+    }
+
+    /// Creates or overwrites `payload.filename` in `collection` with the header and rows of `payload`,
+    /// returning the number of rows created, and the rows rejected if `payload.partial` is set.
+    pub async fn create(
+        &self,
+        collection: &str,
+        payload: &CreatePayload,
+    ) -> Result<CreateResponse, ZenithError> {
+
+        self.send(self.http.post(format!("{}/create/{}", self.base_url, collection)).json(payload)).await?
+            .json().await
+            .map_err(|err| ZenithError::RemoteError(err.to_string()))
+    }
+
+    /// Deletes `filename` in `collection`.
+    pub async fn delete(
+        &self,
+        collection: &str,
+        filename: &str,
+    ) -> Result<(), ZenithError> {
+
+        self.send(self.http.delete(format!("{}/delete/{}/{}", self.base_url, collection, filename))).await?;
+        Ok(())
+    }
+
+    /// Renders `csv` as CSV data, returning its header, rows, and any removed records.
+    pub async fn render(
+        &self,
+        csv: Vec<u8>,
+    ) -> Result<RenderResponse, ZenithError> {
+
+        let request = self.http.post(format!("{}/render", self.base_url))
+            .header(reqwest::header::CONTENT_TYPE, "text/csv")
+            .body(csv);
+        self.send(request).await?.json().await
+            .map_err(|err| ZenithError::RemoteError(err.to_string()))
+    }
+}
+
+
+/// Returns the error of an unsuccessful `response`, as the data service returned it.
+async fn error(response: Response) -> ZenithError {
+    #[derive(Deserialize, Default)]
+    struct ErrorResponse {
+        message: String,
+        #[serde(default)]
+        code: String,
+        #[serde(default)]
+        details: Value,
+    }
+
+    let status = response.status();
+    let body = response.json::<ErrorResponse>().await.unwrap_or_default();
+    let detail = |name: &str| body.details[name].as_str().unwrap_or_default().to_string();
+    match body.code.as_str() {
+        "COLLECTION_NOT_FOUND" => return ZenithError::NotFound(Missing::Collection(detail("collection"))),
+        "FILE_NOT_FOUND" => return ZenithError::NotFound(Missing::File(detail("collection"), detail("filename"))),
+        "HEADER_MISMATCH" => {
+            let header = |name: &str| serde_json::from_value(body.details[name].clone()).unwrap_or_default();
+            return ZenithError::HeaderMismatch { collection: detail("collection"), expected: header("expected"), found: header("found") };
+        },
+        _ => (),
+    }
+
+    // Messages start with the kind of error, as the error given for it does when it is shown.
+    let message = match body.message.split_once(": ") {
+        Some((_, message)) => message.to_string(),
+        None if body.message.is_empty() => status.to_string(),
+        None => body.message,
+    };
+    match status {
+        StatusCode::NOT_FOUND => ZenithError::NotFound(Missing::Other(message)),
+        StatusCode::CONFLICT => ZenithError::Conflict(message),
+        StatusCode::PAYLOAD_TOO_LARGE => ZenithError::PayloadTooLarge(message),
+        StatusCode::UNPROCESSABLE_ENTITY if body.code == "PREDICATE_PARSE" => ZenithError::PredicateError(message),
+        StatusCode::UNPROCESSABLE_ENTITY if body.code == "RESULT_TOO_LARGE" => ZenithError::ResultTooLarge(message),
+        StatusCode::UNPROCESSABLE_ENTITY => ZenithError::QueryError(message),
+        StatusCode::UNSUPPORTED_MEDIA_TYPE => ZenithError::UnsupportedMediaType(message),
+        StatusCode::UNAUTHORIZED => ZenithError::Unauthorized(message),
+        StatusCode::FORBIDDEN => ZenithError::Forbidden(message),
+        StatusCode::TOO_MANY_REQUESTS => ZenithError::TooManyRequests(message),
+        StatusCode::INSUFFICIENT_STORAGE => ZenithError::InsufficientStorage(message),
+        StatusCode::SERVICE_UNAVAILABLE => ZenithError::ServiceUnavailable(message),
+        _ if status.as_u16() == 499 => ZenithError::Cancelled(message),
+        _ => ZenithError::RemoteError(format!("{} ({})", message, status)),
+    }
+}
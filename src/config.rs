@@ -1,31 +1,184 @@
-use std::env;
+use std::{
+    collections::HashMap,
+    env,
+    path::{Path, PathBuf},
+    sync::{LazyLock, OnceLock, RwLock},
+    time::SystemTime,
+};
+
+use crate::types::api::ConfigSetting;
+
+/// The path of the config file read, if any.
+static FILE_PATH: OnceLock<PathBuf> = OnceLock::new();
+
+/// The values in the config file, by the name of the environment variable each stands for.
+/// They are replaced when the file is reloaded.
+static FILE_VALUES: LazyLock<RwLock<HashMap<String, String>>> = LazyLock::new(|| RwLock::new(HashMap::new()));
+
+/// The values given on the command line, named in the same way.
+static CLI_VALUES: OnceLock<HashMap<String, String>> = OnceLock::new();
+
+/// Settings that are only used when the data service starts. When the config file is
+/// reloaded, they keep the values they started with.
+const RESTART_REQUIRED: &[&str] = &[
+    "ZENITHDS_HOST",
+    "ZENITHDS_PORT",
+    "ZENITHDS_GRPC_PORT",
+    "ZENITHDS_PG_PORT",
+    "ZENITHDS_UNIX_SOCKET",
+    "ZENITHDS_USE_PREFIX",
+    "ZENITHDS_STORAGE",
+    "ZENITHDS_DATA_PATH",
+    "ZENITHDS_COLLECTION_PATHS",
+    "ZENITHDS_ENCRYPTION_KEY",
+    "ZENITHDS_API_KEYS",
+    "ZENITHDS_API_KEYS_FILE",
+    "ZENITHDS_JWT_ISSUER",
+    "ZENITHDS_JWT_JWKS_URL",
+    "ZENITHDS_TLS_CERT",
+    "ZENITHDS_TLS_KEY",
+    "ZENITHDS_TLS_CLIENT_CA",
+    "ZENITHDS_TLS_CLIENT_OPTIONAL",
+    "ZENITHDS_ALLOW_CREDENTIALS",
+    "ZENITHDS_RETENTION",
+    "ZENITHDS_ROW_RETENTION",
+    "ZENITHDS_RETENTION_INTERVAL",
+    "ZENITHDS_KAFKA_BROKERS",
+    "ZENITHDS_KAFKA_TOPICS",
+    "ZENITHDS_KAFKA_BATCH_ROWS",
+    "ZENITHDS_KAFKA_BATCH_SECONDS",
+    "ZENITHDS_WATCH",
+    "ZENITHDS_LOG_FORMAT",
+    "ZENITHDS_CONFIG_WATCH_INTERVAL",
+];
+
+/// Returns the value of `v`, from the command line, or else the environment, or else the config file.
+fn var(v: &str) -> Option<String> {
+    CLI_VALUES.get().and_then(|values| values.get(v).cloned())
+        .or_else(|| env::var(v).ok())
+        .or_else(|| FILE_VALUES.read().unwrap_or_else(|e| e.into_inner()).get(v).cloned())
+}
 
 fn unpack_var_usize(v: &str, default: usize) -> usize {
-    env::var(v).unwrap_or_else(|_| default.to_string()).parse().unwrap_or(default)
+    var(v).unwrap_or_else(|| default.to_string()).parse().unwrap_or(default)
 }
 fn unpack_var_str(v: & str, default: &str) -> String {
-    env::var(v).unwrap_or_else(|_| default.to_string()).to_string()
+    var(v).unwrap_or_else(|| default.to_string()).to_string()
 }
 
-pub const DATA_PATH: &'static str = if cfg!(debug_assertions) { "./data" } else { "/data" };
-pub const DEFAULT_COLLECTION: &'static str = "main";
+const DATA_PATH: &str = if cfg!(debug_assertions) { "./data" } else { "/data" };
+pub const DEFAULT_COLLECTION: &str = "main";
+pub const CONFIG_FILE: &str = "zenithds.toml";
 
 const NUM_WORKERS: usize = 4;
 const DEFAULT_PAGE: usize = 0;
 const DEFAULT_PAGE_SIZE: usize = 10;
 const HOST: &str = "0.0.0.0";
 const PORT: usize = 8750;
+const RETENTION_INTERVAL: usize = 3600;
+const IMPORT_MAX_BYTES: usize = 100_000_000;
+const IMPORT_TIMEOUT: usize = 30;
+const REPLICA_TIMEOUT: usize = 30;
+const WEBHOOK_TIMEOUT: usize = 10;
+const WEBHOOK_RETRIES: usize = 5;
+const KAFKA_BATCH_ROWS: usize = 10_000;
+const KAFKA_BATCH_SECONDS: usize = 10;
+const DRAIN_TIMEOUT: usize = 30;
+const CONFIG_WATCH_INTERVAL: usize = 5;
+const MAX_OPEN_FILES: usize = 256;
+const QUERY_QUEUE_TIMEOUT: usize = 30;
+const JOB_TTL: usize = 3600;
+const RESULT_TTL: usize = 600;
+const MAX_RESULT_SETS: usize = 100;
+
+/// The settings that are numbers, with their defaults.
+const USIZE_SETTINGS: &[(&str, usize)] = &[
+    ("ZENITHDS_NUM_WORKERS", NUM_WORKERS),
+    ("ZENITHDS_DEFAULT_PAGE", DEFAULT_PAGE),
+    ("ZENITHDS_DEFAULT_PAGE_SIZE", DEFAULT_PAGE_SIZE),
+    ("ZENITHDS_PORT", PORT),
+    ("ZENITHDS_RETENTION_INTERVAL", RETENTION_INTERVAL),
+    ("ZENITHDS_IMPORT_MAX_BYTES", IMPORT_MAX_BYTES),
+    ("ZENITHDS_IMPORT_TIMEOUT", IMPORT_TIMEOUT),
+    ("ZENITHDS_REPLICA_TIMEOUT", REPLICA_TIMEOUT),
+    ("ZENITHDS_DRAIN_TIMEOUT", DRAIN_TIMEOUT),
+    ("ZENITHDS_CONFIG_WATCH_INTERVAL", CONFIG_WATCH_INTERVAL),
+    ("ZENITHDS_RATE_LIMIT", 0),
+    ("ZENITHDS_RATE_BURST", 0),
+    ("ZENITHDS_MAX_CLIENT_QUERIES", 0),
+    ("ZENITHDS_MAX_QUERIES", 0),
+    ("ZENITHDS_MAX_BATCH_QUERIES", 0),
+    ("ZENITHDS_QUERY_QUEUE", 0),
+    ("ZENITHDS_QUERY_QUEUE_TIMEOUT", QUERY_QUEUE_TIMEOUT),
+    ("ZENITHDS_JOB_TTL", JOB_TTL),
+    ("ZENITHDS_RESULT_TTL", RESULT_TTL),
+    ("ZENITHDS_MAX_RESULT_SETS", MAX_RESULT_SETS),
+    ("ZENITHDS_SLOW_QUERY_MS", 0),
+    ("ZENITHDS_DISK_HIGH_WATERMARK", 0),
+    ("ZENITHDS_QUERY_MEMORY_BUDGET", 0),
+    ("ZENITHDS_MAX_OPEN_FILES", MAX_OPEN_FILES),
+    ("ZENITHDS_GRPC_PORT", 0),
+    ("ZENITHDS_PG_PORT", 0),
+    ("ZENITHDS_WEBHOOK_TIMEOUT", WEBHOOK_TIMEOUT),
+    ("ZENITHDS_WEBHOOK_RETRIES", WEBHOOK_RETRIES),
+    ("ZENITHDS_KAFKA_BATCH_ROWS", KAFKA_BATCH_ROWS),
+    ("ZENITHDS_KAFKA_BATCH_SECONDS", KAFKA_BATCH_SECONDS),
+];
+
+/// The settings that are strings, with their defaults.
+const STR_SETTINGS: &[(&str, &str)] = &[
+    ("ZENITHDS_HOST", HOST),
+    ("ZENITHDS_UNIX_SOCKET", ""),
+    ("ZENITHDS_DATA_PATH", DATA_PATH),
+    ("ZENITHDS_COLLECTION_PATHS", ""),
+    ("ZENITHDS_USE_PREFIX", ""),
+    ("ZENITHDS_ALLOWED_ORIGINS", ""),
+    ("ZENITHDS_ALLOW_CREDENTIALS", ""),
+    ("ZENITHDS_RETENTION", ""),
+    ("ZENITHDS_ROW_RETENTION", ""),
+    ("ZENITHDS_ENCRYPTION_KEY", ""),
+    ("ZENITHDS_VERIFY_ON_READ", ""),
+    ("ZENITHDS_IMPORT_ALLOWED_HOSTS", ""),
+    ("ZENITHDS_REPLICA_PEERS", ""),
+    ("ZENITHDS_FEDERATION_NODES", ""),
+    ("ZENITHDS_KAFKA_BROKERS", ""),
+    ("ZENITHDS_KAFKA_TOPICS", ""),
+    ("ZENITHDS_WATCH", ""),
+    ("ZENITHDS_WATCH_REJECT", ""),
+    ("ZENITHDS_STORAGE", "filesystem"),
+    ("ZENITHDS_INFER_SCHEMA", ""),
+    ("ZENITHDS_API_KEYS", ""),
+    ("ZENITHDS_API_KEYS_FILE", ""),
+    ("ZENITHDS_PEER_API_KEY", ""),
+    ("ZENITHDS_JWT_ISSUER", ""),
+    ("ZENITHDS_JWT_JWKS_URL", ""),
+    ("ZENITHDS_JWT_AUDIENCE", ""),
+    ("ZENITHDS_JWT_ROLES_CLAIM", "roles"),
+    ("ZENITHDS_JWT_ROLES", ""),
+    ("ZENITHDS_BATCH_ROLES", ""),
+    ("ZENITHDS_TLS_CERT", ""),
+    ("ZENITHDS_TLS_KEY", ""),
+    ("ZENITHDS_TLS_CLIENT_CA", ""),
+    ("ZENITHDS_TLS_CLIENT_OPTIONAL", ""),
+    ("ZENITHDS_TLS_CLIENT_SCOPE", "write"),
+    ("ZENITHDS_AUDIT_LOG", ""),
+    ("ZENITHDS_CHANGE_LOG", ""),
+    ("ZENITHDS_ALLOWED_IPS", ""),
+    ("ZENITHDS_DENIED_IPS", ""),
+    ("ZENITHDS_ESCAPE_FORMULAS", ""),
+    ("ZENITHDS_LOG", "info"),
+    ("ZENITHDS_LOG_FORMAT", "text"),
+    ("ZENITHDS_SLOW_QUERY_LOG", ""),
+    ("ZENITHDS_ACCESS_LOG", ""),
+];
 
 /// Retrieve the value of environment variable `v` as a `usize`.
 /// 
 /// Returns `0` if variable name not found, or the default if not set.
 pub fn envar_usize(v: &str) -> usize {
-    match v {
-        "ZENITHDS_NUM_WORKERS" => unpack_var_usize(v, NUM_WORKERS),
-        "ZENITHDS_DEFAULT_PAGE" => unpack_var_usize(v, DEFAULT_PAGE),
-        "ZENITHDS_DEFAULT_PAGE_SIZE" => unpack_var_usize(v, DEFAULT_PAGE_SIZE),
-        "ZENITHDS_PORT" => unpack_var_usize(v, PORT),
-        _ => 0,
+    match USIZE_SETTINGS.iter().find(|(name, _)| *name == v) {
+        Some((_, default)) => unpack_var_usize(v, *default),
+        None => 0,
     }
 }
 
@@ -33,14 +186,108 @@ pub fn envar_usize(v: &str) -> usize {
 /// 
 /// Returns the empty string if variable name not found, or the default if not set.
 pub fn envar_str(v: &str) -> String {
-    match v {
-        "ZENITHDS_HOST" => unpack_var_str(v, HOST),
-        "ZENITHDS_USE_PREFIX" => unpack_var_str(v, ""),
-        "ZENITHDS_ALLOWED_ORIGINS" => unpack_var_str(v, ""),
-        _ => "".to_string(),
+    match STR_SETTINGS.iter().find(|(name, _)| *name == v) {
+        Some((_, default)) => unpack_var_str(v, default),
+        None => "".to_string(),
     }
 }
 
+/// Settings whose values are secret, which are redacted by `resolved`.
+const SECRET_SETTINGS: &[&str] = &[
+    "ZENITHDS_ENCRYPTION_KEY",
+    "ZENITHDS_API_KEYS",
+    "ZENITHDS_PEER_API_KEY",
+];
+
+/// Returns where the value of `v` is from: the command line, the environment,
+/// the config file, or its default.
+fn source(v: &str) -> &'static str {
+    if CLI_VALUES.get().is_some_and(|values| values.contains_key(v)) {
+        "command_line"
+    }
+    else if env::var(v).is_ok() {
+        "environment"
+    }
+    else if FILE_VALUES.read().unwrap_or_else(|e| e.into_inner()).contains_key(v) {
+        "file"
+    }
+    else {
+        "default"
+    }
+}
+
+/// Returns every setting with the value in use, sorted by name.
+/// Secrets that are set are given as `[redacted]`.
+pub fn resolved() -> Vec<ConfigSetting> {
+    let usize_settings = USIZE_SETTINGS.iter().map(|(name, _)| (*name, envar_usize(name).to_string()));
+    let str_settings = STR_SETTINGS.iter().map(|(name, _)| (*name, envar_str(name)));
+    let mut settings: Vec<ConfigSetting> = usize_settings.chain(str_settings)
+        .map(|(name, value)| ConfigSetting {
+            name: name.to_string(),
+            value: match SECRET_SETTINGS.contains(&name) && !value.is_empty() {
+                true => "[redacted]".to_string(),
+                false => value,
+            },
+            source: source(name).to_string(),
+            restart_required: RESTART_REQUIRED.contains(&name),
+        })
+        .collect();
+    settings.sort_by(|a, b| a.name.cmp(&b.name));
+    settings
+}
+
+/// Returns the directory that collections are kept in, set by `ZENITHDS_DATA_PATH`.
+/// By default, it is `./data` in debug mode and `/data` otherwise.
+pub fn data_path() -> PathBuf {
+    PathBuf::from(envar_str("ZENITHDS_DATA_PATH"))
+}
+
+/// Returns the directories of collections that are kept outside of the data path,
+/// such as on cheaper storage for archives.
+/// 
+/// Parsed from `ZENITHDS_COLLECTION_PATHS` in the form `collection:path`, separated by commas.
+/// Entries that cannot be parsed are ignored.
+pub fn collection_paths() -> Vec<(String, PathBuf)> {
+    envar_str("ZENITHDS_COLLECTION_PATHS")
+        .split(',')
+        .filter_map(|s| s.split_once(':'))
+        .filter(|(collection, path)| !collection.trim().is_empty() && !path.trim().is_empty())
+        .map(|(collection, path)| (collection.trim().to_string(), PathBuf::from(path.trim())))
+        .collect()
+}
+
+/// Returns the directory of `collection`, which is in the data path
+/// unless it is given a directory in `ZENITHDS_COLLECTION_PATHS`.
+pub fn collection_path(collection: &str) -> PathBuf {
+    collection_paths().into_iter()
+        .find(|(c, _)| c == collection)
+        .map(|(_, path)| path)
+        .unwrap_or_else(|| data_path().join(collection))
+}
+
+/// Returns the directory that snapshots of `collection` are kept in. A collection that is given
+/// its own directory keeps them in it, so that files can be hard linked on the same device.
+pub fn snapshots_path(collection: &str) -> PathBuf {
+    match collection_paths().into_iter().find(|(c, _)| c == collection) {
+        Some((_, path)) => path.join(".snapshots"),
+        None => data_path().join(".snapshots").join(collection),
+    }
+}
+
+/// Returns the directory that previous versions of files in `collection` are kept in,
+/// which is in its own directory if it is given one, as with `snapshots_path`.
+pub fn versions_path(collection: &str) -> PathBuf {
+    match collection_paths().into_iter().find(|(c, _)| c == collection) {
+        Some((_, path)) => path.join(".versions"),
+        None => data_path().join(".versions").join(collection),
+    }
+}
+
+/// Returns the directory that materialized views are kept in, each in a directory named after it.
+pub fn views_path() -> PathBuf {
+    data_path().join(".views")
+}
+
 /// Get the address for establishing the data service server.
 /// 
 /// Uses the values set in `HOST` and `PORT`.
@@ -54,6 +301,26 @@ pub fn address() -> String {
     }
 }
 
+/// Get the address for the gRPC server, on the same host as `address`, at `ZENITHDS_GRPC_PORT`.
+pub fn grpc_address() -> String {
+    if cfg!(debug_assertions) {
+        format!("127.0.0.1:{}", envar_usize("ZENITHDS_GRPC_PORT"))
+    }
+    else {
+        format!("{}:{}", envar_str("ZENITHDS_HOST"), envar_usize("ZENITHDS_GRPC_PORT"))
+    }
+}
+
+/// Get the address for the PostgreSQL listener, on the same host as `address`, at `ZENITHDS_PG_PORT`.
+pub fn pg_address() -> String {
+    if cfg!(debug_assertions) {
+        format!("127.0.0.1:{}", envar_usize("ZENITHDS_PG_PORT"))
+    }
+    else {
+        format!("{}:{}", envar_str("ZENITHDS_HOST"), envar_usize("ZENITHDS_PG_PORT"))
+    }
+}
+
 /// Returns the API resource prefix for the given `version`.
 /// If `ZENITHDS_USE_PREFIX` is set, prepends the application name.
 pub fn prefix(version: &str) -> String {
@@ -64,3 +331,215 @@ pub fn prefix(version: &str) -> String {
         format!("/zenithds/api/{version}")
     }
 }
+
+/// Parses a list of base URLs in the environment variable `v`, separated by commas.
+fn base_urls(v: &str) -> Vec<String> {
+    envar_str(v)
+        .split(',')
+        .map(|s| s.trim().trim_end_matches('/').to_string())
+        .filter(|s| !s.is_empty())
+        .collect()
+}
+
+/// Returns the base URLs of the peers that mutations are replicated to.
+/// 
+/// Parsed from `ZENITHDS_REPLICA_PEERS`, separated by commas. Each URL includes the
+/// API prefix of the peer, for example `http://standby:8750/api/v1`.
+pub fn replica_peers() -> Vec<String> {
+    base_urls("ZENITHDS_REPLICA_PEERS")
+}
+
+/// Returns the base URLs of the nodes that queries are federated to,
+/// given in `ZENITHDS_FEDERATION_NODES` in the same form as `replica_peers`.
+pub fn federation_nodes() -> Vec<String> {
+    base_urls("ZENITHDS_FEDERATION_NODES")
+}
+
+/// Returns the addresses of the Kafka brokers to consume from, given in `ZENITHDS_KAFKA_BROKERS`
+/// as `host:port`, separated by commas.
+pub fn kafka_brokers() -> Vec<String> {
+    envar_str("ZENITHDS_KAFKA_BROKERS")
+        .split(',')
+        .map(|broker| broker.trim().to_string())
+        .filter(|broker| !broker.is_empty())
+        .collect()
+}
+
+/// Returns the Kafka topics to consume, each with the collection its records are added to.
+/// 
+/// Parsed from `ZENITHDS_KAFKA_TOPICS` in the form `topic:collection`, separated by commas.
+/// A topic given without a collection is added to the collection with the same name.
+pub fn kafka_topics() -> Vec<(String, String)> {
+    envar_str("ZENITHDS_KAFKA_TOPICS")
+        .split(',')
+        .map(|s| match s.split_once(':') {
+            Some((topic, collection)) => (topic.trim().to_string(), collection.trim().to_string()),
+            None => (s.trim().to_string(), s.trim().to_string()),
+        })
+        .filter(|(topic, collection)| !topic.is_empty() && !collection.is_empty())
+        .collect()
+}
+
+/// Returns the retention period in days for each collection that has one.
+/// 
+/// Parsed from `ZENITHDS_RETENTION` in the form `collection:days`, separated by commas.
+/// Entries that cannot be parsed are ignored.
+pub fn retention() -> Vec<(String, u64)> {
+    envar_str("ZENITHDS_RETENTION")
+        .split(',')
+        .filter_map(|s| s.split_once(':'))
+        .filter_map(|(collection, days)| {
+            match days.trim().parse::<u64>() {
+                Ok(days) if !collection.trim().is_empty() => Some((collection.trim().to_string(), days)),
+                _ => None,
+            }
+        })
+        .collect()
+}
+
+/// The row retention policies: the number of days after the date in a column of a collection
+/// that each row is kept.
+/// 
+/// Parsed from `ZENITHDS_ROW_RETENTION` in the form `collection:column:days`, separated by commas.
+/// Entries that cannot be parsed are ignored.
+pub fn row_retention() -> Vec<(String, String, u64)> {
+    envar_str("ZENITHDS_ROW_RETENTION")
+        .split(',')
+        .filter_map(|s| s.rsplit_once(':'))
+        .filter_map(|(policy, days)| {
+            let (collection, column) = policy.split_once(':')?;
+            match days.trim().parse::<u64>() {
+                Ok(days) if !collection.trim().is_empty() && !column.trim().is_empty() => {
+                    Some((collection.trim().to_string(), column.trim().to_string(), days))
+                },
+                _ => None,
+            }
+        })
+        .collect()
+}
+
+/// Sets the values given on the command line, which override all others.
+/// Should be called once, before anything is configured.
+pub fn set_overrides(
+    values: HashMap<String, String>,
+) {
+    if CLI_VALUES.set(values).is_err() {
+        tracing::warn!("The command line was already read");
+    }
+}
+
+/// Flattens the values in `table` into `values`, naming each by `prefix` and its key in
+/// upper case, so that `port` is `ZENITHDS_PORT` and `client_ca` in `[tls]` is `ZENITHDS_TLS_CLIENT_CA`.
+/// Lists are joined with commas, and `false` is the empty string, which turns a flag off.
+fn flatten(
+    prefix: &str,
+    table: toml::Table,
+    values: &mut HashMap<String, String>,
+) -> Result<(), String> {
+
+    let scalar = |name: &str, value: toml::Value| match value {
+        toml::Value::String(s) => Ok(s),
+        toml::Value::Boolean(b) => Ok(if b { "true".to_string() } else { String::new() }),
+        toml::Value::Integer(_) | toml::Value::Float(_) | toml::Value::Datetime(_) => Ok(value.to_string()),
+        _ => Err(format!("'{}' in the config file cannot be a table or a list of lists", name)),
+    };
+    for (key, value) in table {
+        let name = format!("{}_{}", prefix, key.to_uppercase().replace('-', "_"));
+        match value {
+            toml::Value::Table(table) => flatten(&name, table, values)?,
+            toml::Value::Array(items) => {
+                let items = items.into_iter()
+                    .map(|item| scalar(&name, item))
+                    .collect::<Result<Vec<String>, String>>()?;
+                values.insert(name, items.join(","));
+            },
+            value => {
+                let value = scalar(&name, value)?;
+                values.insert(name, value);
+            },
+        }
+    }
+    Ok(())
+}
+
+/// Reads the config file, so that its values are used for environment variables that are not set.
+/// 
+/// The file is at `path` if given, or else at `ZENITHDS_CONFIG`, or else is `zenithds.toml` in
+/// the working directory if there is one. Returns the path of the file read, if any, or an
+/// error if the file cannot be read or parsed. Should be called once, before anything is configured.
+pub fn load_file(
+    path: Option<String>,
+) -> Result<Option<PathBuf>, String> {
+
+    let path = match path.or_else(|| env::var("ZENITHDS_CONFIG").ok().filter(|p| !p.is_empty())) {
+        Some(path) => PathBuf::from(path),
+        None if Path::new(CONFIG_FILE).is_file() => PathBuf::from(CONFIG_FILE),
+        None => return Ok(None),
+    };
+    let values = parse_file(&path)?;
+    FILE_PATH.set(path.clone()).map_err(|_| "The config file was already read".to_string())?;
+    *FILE_VALUES.write().unwrap_or_else(|e| e.into_inner()) = values;
+    Ok(Some(path))
+}
+
+/// Reads and flattens the config file at `path`.
+fn parse_file(
+    path: &Path,
+) -> Result<HashMap<String, String>, String> {
+
+    let contents = std::fs::read_to_string(path)
+        .map_err(|err| format!("Could not read the config file '{}': {}", path.display(), err))?;
+    let table: toml::Table = contents.parse()
+        .map_err(|err| format!("Could not parse the config file '{}': {}", path.display(), err))?;
+    let mut values = HashMap::new();
+    flatten("ZENITHDS", table, &mut values)?;
+    Ok(values)
+}
+
+/// Returns the path of the config file that was read when the data service started, if any.
+pub fn file_path() -> Option<&'static Path> {
+    FILE_PATH.get().map(|path| path.as_path())
+}
+
+/// Returns when the config file was last modified, if there is one.
+pub fn file_modified() -> Option<SystemTime> {
+    file_path().and_then(|path| std::fs::metadata(path).and_then(|m| m.modified()).ok())
+}
+
+/// The settings changed by reloading the config file.
+pub struct Reloaded {
+    /// Settings that changed and are now in use.
+    pub changed: Vec<String>,
+    /// Settings that changed but keep their old values until the data service is restarted.
+    pub restart_required: Vec<String>,
+}
+
+/// Reads the config file again, replacing the values read before. Settings that are
+/// set on the command line or in the environment are not affected, and settings in
+/// `RESTART_REQUIRED` keep their old values. Returns an error, changing nothing, if the
+/// file cannot be read or parsed, or if no config file was read when the data service started.
+pub fn reload_file() -> Result<Reloaded, String> {
+    let path = file_path().ok_or_else(|| "No config file was read when the data service started".to_string())?;
+    let mut values = parse_file(path)?;
+
+    let mut file_values = FILE_VALUES.write().unwrap_or_else(|e| e.into_inner());
+    let mut names: Vec<String> = file_values.keys().chain(values.keys())
+        .filter(|name| file_values.get(*name) != values.get(*name))
+        // Settings given elsewhere are unaffected by the file.
+        .filter(|name| !CLI_VALUES.get().is_some_and(|cli| cli.contains_key(*name)) && env::var(name).is_err())
+        .cloned()
+        .collect();
+    names.sort();
+    names.dedup();
+
+    let (restart_required, changed): (Vec<String>, Vec<String>) = names.into_iter()
+        .partition(|name| RESTART_REQUIRED.contains(&name.as_str()));
+    for name in &restart_required {
+        match file_values.get(name) {
+            Some(value) => values.insert(name.clone(), value.clone()),
+            None => values.remove(name),
+        };
+    }
+    *file_values = values;
+    Ok(Reloaded { changed, restart_required })
+}
@@ -0,0 +1,92 @@
+use std::sync::{LazyLock, RwLock};
+use axum::http::{
+    header::{AUTHORIZATION, CONTENT_TYPE},
+    HeaderName,
+    HeaderValue,
+    Method,
+};
+use tower_http::cors::{AllowOrigin, CorsLayer};
+use tracing::{info, warn};
+
+use crate::{auth, config, request_id};
+
+
+/// The origins that browsers can make cross-origin requests from.
+enum Origins {
+    Any,
+    List(Vec<HeaderValue>),
+}
+
+/// Parsed when the data service starts, and again when the configuration is reloaded.
+static ORIGINS: LazyLock<RwLock<Origins>> = LazyLock::new(|| RwLock::new(Origins::List(Vec::new())));
+
+
+/// Whether credentials are allowed in cross-origin requests.
+fn credentials() -> bool {
+    !config::envar_str("ZENITHDS_ALLOW_CREDENTIALS").is_empty()
+}
+
+
+/// Parses the origins in `ZENITHDS_ALLOWED_ORIGINS`, separated by commas, or `*` for any origin.
+/// Origins that are not valid are ignored.
+fn origins() -> Result<Origins, String> {
+    let allowed_origins = config::envar_str("ZENITHDS_ALLOWED_ORIGINS");
+    let origins: Vec<&str> = allowed_origins.split(',').map(|s| s.trim()).filter(|s| !s.is_empty()).collect();
+    match origins.contains(&"*") {
+        // Browsers do not send credentials to an API that allows any origin.
+        true if credentials() => Err("ZENITHDS_ALLOW_CREDENTIALS cannot be set when any origin is allowed".to_string()),
+        true => {
+            info!("Access-Control-Allow-Origin: *");
+            Ok(Origins::Any)
+        },
+        false => {
+            let origins: Vec<HeaderValue> = origins.iter()
+                .filter_map(|s| match s.parse::<HeaderValue>() {
+                    Ok(origin) => Some(origin),
+                    Err(_) => {
+                        warn!("Ignoring origin '{}' that is not valid", s);
+                        None
+                    },
+                })
+                .collect();
+            info!("Access-Control-Allow-Origin options: {:?}", origins);
+            Ok(Origins::List(origins))
+        },
+    }
+}
+
+
+/// Whether a browser can make cross-origin requests from `origin`.
+fn allowed(origin: &HeaderValue) -> bool {
+    match &*ORIGINS.read().unwrap_or_else(|e| e.into_inner()) {
+        Origins::Any => true,
+        Origins::List(origins) => origins.contains(origin),
+    }
+}
+
+
+/// Returns the layer that answers cross-origin requests from the allowed origins.
+/// Allowed origins are given back in `Access-Control-Allow-Origin`, so that
+/// they can be changed by reloading the configuration.
+pub fn layer() -> Result<CorsLayer, String> {
+    *ORIGINS.write().unwrap_or_else(|e| e.into_inner()) = origins()?;
+    let credentials = credentials();
+    if credentials {
+        info!("Allowing credentials in cross-origin requests");
+    }
+    Ok(CorsLayer::new()
+        .allow_methods([Method::GET, Method::POST, Method::PUT, Method::DELETE])
+        .allow_headers([CONTENT_TYPE, AUTHORIZATION, HeaderName::from_static(auth::API_KEY_HEADER), HeaderName::from_static(request_id::REQUEST_ID_HEADER)])
+        .expose_headers([HeaderName::from_static(request_id::REQUEST_ID_HEADER)])
+        .allow_origin(AllowOrigin::predicate(|origin, _| allowed(origin)))
+        .allow_credentials(credentials))
+}
+
+
+/// Parses the allowed origins again. If they cannot be used,
+/// returns an error and keeps the origins that were allowed.
+pub fn reload() -> Result<(), String> {
+    let origins = origins()?;
+    *ORIGINS.write().unwrap_or_else(|e| e.into_inner()) = origins;
+    Ok(())
+}
@@ -0,0 +1,92 @@
+use std::sync::LazyLock;
+use aes_gcm::{
+    aead::{Aead, AeadCore, KeyInit, OsRng},
+    Aes256Gcm, Key, Nonce,
+};
+
+use crate::types::error::ZenithError;
+use crate::config;
+
+/// Marks the start of an encrypted file, followed by the nonce and the ciphertext.
+const MAGIC: &[u8] = b"ZDSENC1";
+const NONCE_LENGTH: usize = 12;
+
+
+/// Parses the 256-bit key in `ZENITHDS_ENCRYPTION_KEY`, given as 64 hexadecimal characters.
+/// 
+/// Returns `None` if encryption is not enabled, or an error if the key is malformed.
+fn parse_key() -> Result<Option<Key<Aes256Gcm>>, String> {
+    let hex = config::envar_str("ZENITHDS_ENCRYPTION_KEY");
+    if hex.is_empty() {
+        return Ok(None);
+    }
+    if hex.len() != 64 || !hex.is_ascii() {
+        return Err("The key must be 64 hexadecimal characters".to_string());
+    }
+
+    let mut bytes = [0u8; 32];
+    for (i, byte) in bytes.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&hex[2 * i..2 * i + 2], 16)
+            .map_err(|_| "The key must be 64 hexadecimal characters".to_string())?;
+    }
+    Ok(Some(bytes.into()))
+}
+
+/// Parsed once, when first used. The key is only read when the data service starts (see
+/// `config::RESTART_REQUIRED`), as files written with one key cannot be read with another.
+static KEY: LazyLock<Result<Option<Key<Aes256Gcm>>, String>> = LazyLock::new(parse_key);
+
+
+/// Returns the key files are encrypted with.
+/// 
+/// Returns `None` if encryption is not enabled, or an `EncryptionError` if the key is malformed.
+pub fn key() -> Result<Option<Key<Aes256Gcm>>, ZenithError> {
+    KEY.clone().map_err(ZenithError::EncryptionError)
+}
+
+
+/// Whether `bytes` were written by `encrypt`.
+pub fn is_encrypted(bytes: &[u8]) -> bool {
+    bytes.starts_with(MAGIC)
+}
+
+
+/// Encrypts `bytes` if encryption is enabled, otherwise returns them as they are.
+pub fn encrypt(bytes: Vec<u8>) -> Result<Vec<u8>, ZenithError> {
+    let Some(key) = key()? else {
+        return Ok(bytes);
+    };
+
+    let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+    let ciphertext = Aes256Gcm::new(&key)
+        .encrypt(&nonce, bytes.as_slice())
+        .map_err(|_| ZenithError::EncryptionError("Could not encrypt data".to_string()))?;
+
+    let mut encrypted = Vec::with_capacity(MAGIC.len() + NONCE_LENGTH + ciphertext.len());
+    encrypted.extend_from_slice(MAGIC);
+    encrypted.extend_from_slice(&nonce);
+    encrypted.extend_from_slice(&ciphertext);
+    Ok(encrypted)
+}
+
+
+/// Decrypts `bytes` if they were encrypted, otherwise returns them as they are.
+/// 
+/// Raises an `EncryptionError` if the data is encrypted and the key is
+/// not set, or the data cannot be authenticated with the key.
+pub fn decrypt(bytes: Vec<u8>) -> Result<Vec<u8>, ZenithError> {
+    if !is_encrypted(&bytes) {
+        return Ok(bytes);
+    }
+    let Some(key) = key()? else {
+        return Err(ZenithError::EncryptionError("Data is encrypted, but no key is set".to_string()));
+    };
+    if bytes.len() < MAGIC.len() + NONCE_LENGTH {
+        return Err(ZenithError::EncryptionError("Encrypted data is truncated".to_string()));
+    }
+
+    let (nonce, ciphertext) = bytes[MAGIC.len()..].split_at(NONCE_LENGTH);
+    Aes256Gcm::new(&key)
+        .decrypt(Nonce::from_slice(nonce), ciphertext)
+        .map_err(|_| ZenithError::EncryptionError("Could not decrypt data with the key".to_string()))
+}
@@ -1,47 +1,229 @@
 use std::{
-    path::Path,
-    collections::HashMap,
-    sync::{mpsc, Arc},
+    cell::{Cell, RefCell},
+    io::{Cursor, Read},
+    path::{Path, PathBuf},
+    collections::{HashMap, HashSet},
+    sync::{mpsc, Arc, LazyLock, Mutex},
     thread,
+    time::{Duration, Instant, SystemTime},
 };
 use regex::Regex;
+use tracing::{debug, error};
 
 use crate::types::{
-    query::{CSVData, FileMetadata, Predicate, DataQuery},
-    error::ZenithError,
-    api::{QueryPredicates, CreatePayload},
+    query::{CSVData, FileMetadata, Predicate, DataQuery, IntegrityStatus},
+    error::{Missing, ZenithError},
+    api::{QueryPredicates, CreatePayload, CsvCharacter, Dialect, Keep, RenderOptions, ProblemKind, ValidationProblem, ValidateResponse, OnConflict, ExplainResponse, ExplainedFile, QueryProfile, FileProfile, PhaseTiming},
 };
-use crate::config;
+use crate::{auth::Principal, catalog, config, crypto, disk, open_files::OpenFile, queries::Cancelled, schema, slow_query, storage::{storage, list_data_files}};
+
+
+/// The longest name a collection or file can have, in bytes.
+const MAX_NAME_LENGTH: usize = 255;
+
+/// The header and rows found by a selection.
+pub type Selection = (Vec<String>, Vec<Vec<String>>);
+
+/// One lock per collection, held while files in the collection are being changed.
+static COLLECTION_LOCKS: LazyLock<Mutex<HashMap<String, Arc<Mutex<()>>>>> = LazyLock::new(|| Mutex::new(HashMap::new()));
+
+/// Returns the lock for `collection`. Hold it for the duration of any change to the
+/// files in the collection, so that snapshots always see a consistent set of files.
+fn collection_lock(collection: &str) -> Arc<Mutex<()>> {
+    let mut locks = COLLECTION_LOCKS.lock().unwrap_or_else(|e| e.into_inner());
+    Arc::clone(locks.entry(collection.to_string()).or_default())
+}
+
+
+/// Checks that `name`, the name of a collection or file (given by `kind`), is safe to join onto
+/// a path. It must not be empty or too long, must not start with `.`, which is kept for the
+/// files of the data service, and can only have letters, digits, spaces, and `-_.+@()`, so it
+/// cannot name another directory.
+pub fn validate_name(
+    kind: &str,
+    name: &str,
+) -> Result<(), ZenithError> {
+
+    if name.is_empty() {
+        return Err(ZenithError::QueryError(format!("The {} name is empty", kind)));
+    }
+    if name.len() > MAX_NAME_LENGTH {
+        return Err(ZenithError::QueryError(format!("The {} name is longer than {} bytes", kind, MAX_NAME_LENGTH)));
+    }
+    if name.starts_with('.') {
+        return Err(ZenithError::QueryError(format!("The {} name '{}' cannot start with '.'", kind, name)));
+    }
+    if let Some(c) = name.chars().find(|c| !c.is_alphanumeric() && !" -_.+@()".contains(*c)) {
+        return Err(ZenithError::QueryError(format!("The {} name '{}' cannot contain {:?}", kind, name.escape_default(), c)));
+    }
+    Ok(())
+}
+
+
+/// Returns the path of `collection`, raising a `NotFound` error if it does not exist.
+fn existing_collection_path(
+    collection: &str,
+) -> Result<PathBuf, ZenithError> {
+
+    let path = config::collection_path(collection);
+    match storage().is_dir(&path) {
+        true => Ok(path),
+        false => Err(ZenithError::NotFound(Missing::Collection(collection.to_string()))),
+    }
+}
+
+
+/// Returns the path of `filename` in `collection`, raising a `NotFound`
+/// error naming the collection or the file, whichever does not exist.
+fn existing_file_path(
+    collection: &str,
+    filename: &str,
+) -> Result<PathBuf, ZenithError> {
+
+    let path = existing_collection_path(collection)?.join(filename);
+    match storage().is_file(&path) {
+        true => Ok(path),
+        false => Err(ZenithError::NotFound(Missing::File(collection.to_string(), filename.to_string()))),
+    }
+}
+
+
+/// Returns a new id for an entry in `dir`, based on the current time in
+/// milliseconds. The id is bumped if it is already taken in the directory.
+fn unique_id(dir: &Path) -> String {
+    let mut id = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH)
+        .map(|d| d.as_millis()).unwrap_or(0);
+    while storage().is_file(&dir.join(id.to_string())) || storage().is_dir(&dir.join(id.to_string())) {
+        id += 1;
+    }
+    id.to_string()
+}
+
+
+/// Keeps the current version of `filename` in `collection`, if it exists,
+/// before it is replaced. Returns the id of the version kept.
+fn keep_version(
+    collection: &str,
+    filename: &str,
+) -> Result<Option<String>, ZenithError> {
+
+    let current_path = config::collection_path(collection).join(filename);
+    if !storage().is_file(&current_path) {
+        return Ok(None);
+    }
+    let versions_path = config::versions_path(collection).join(filename);
+    storage().create_dir_all(&versions_path)?;
+    let id = unique_id(&versions_path);
+    storage().link(&current_path, &versions_path.join(&id))?;
+    Ok(Some(id))
+}
+
+
+/// Returns the path to the version of `filename` in `collection` with `version_id`.
+fn version_path(
+    collection: &str,
+    filename: &str,
+    version_id: &str,
+) -> Result<PathBuf, ZenithError> {
+
+    validate_name("collection", collection)?;
+    validate_name("file", filename)?;
+    if version_id.is_empty() {
+        return Err(ZenithError::QueryError("The version id is empty".to_string()));
+    }
+    let path = config::versions_path(collection).join(filename).join(version_id);
+    if !version_id.chars().all(|c| c.is_ascii_digit()) || !storage().is_file(&path) {
+        return Err(ZenithError::NotFound(Missing::Other(format!(
+            "Version '{}' of '{}' in collection '{}' does not exist", version_id, filename, collection
+        ))));
+    }
+    Ok(path)
+}
+
+
+/// Opens the file at `path` for reading, decrypting it if it was encrypted.
+/// 
+/// Unencrypted files are streamed from disk as they are read.
+fn open_file(path: &Path) -> Result<Box<dyn Read + Send>, ZenithError> {
+    let mut file = storage().open(path)?;
+    let mut start = Vec::new();
+    (&mut file).take(64).read_to_end(&mut start)?;
+
+    if crypto::is_encrypted(&start) {
+        file.read_to_end(&mut start)?;
+        Ok(Box::new(Cursor::new(crypto::decrypt(start)?)))
+    }
+    else {
+        Ok(Box::new(Cursor::new(start).chain(file)))
+    }
+}
+
+
+/// Writes `bytes` to the file at `path`, encrypting them if encryption is enabled.
+/// Raises an `InsufficientStorage` error if the volume is too full to write them.
+fn write_file(path: &Path, bytes: Vec<u8>) -> Result<(), ZenithError> {
+    let bytes = crypto::encrypt(bytes)?;
+    disk::ensure_space(path, bytes.len())?;
+    match storage().write(path, &bytes) {
+        Ok(()) => Ok(()),
+        Err(err) if err.kind() == std::io::ErrorKind::StorageFull => {
+            let _ = storage().remove(path);
+            Err(ZenithError::InsufficientStorage(format!("The data volume is full: {}", err)))
+        },
+        Err(err) => Err(err.into()),
+    }
+}
 
 
 /// Read the CSV with `filename` from the `collection`,
 /// returning its header and rows as determined by the `query`.
 /// 
+/// If an `expected_checksum` is given, raises an `IntegrityError`
+/// when the file on disk does not match it, or the checksum of the file since. See `reverify`.
+/// 
 /// The header is automatically set on the first row found that is complete.
 /// Rows before the header and rows with a different length than the header are ignored.
 /// 
 /// Make this function efficient.
 fn read_csv(
-    collection: &str,
-    filename: &str,
+    fm: &FileMetadata,
     query: &Arc<DataQuery>,
+    expected_checksum: Option<&String>,
+    dialect: &Dialect,
 ) -> Result<CSVData, ZenithError> {
 
-    let path = Path::new(config::DATA_PATH).join(collection).join(filename);
-    let mut reader = csv::ReaderBuilder::new()
+    let path = &fm.filepath;
+    // Held until the file has been read.
+    let _open = OpenFile::acquire();
+    // If there is a checksum to verify, the whole file needs to be read first.
+    let source: Box<dyn Read + Send> = match expected_checksum {
+        Some(expected) => {
+            let mut bytes = storage().read(path)?;
+            if catalog::checksum(&bytes) != *expected {
+                bytes = reverify(fm)?;
+            }
+            Box::new(Cursor::new(crypto::decrypt(bytes)?))
+        },
+        None => open_file(path)?,
+    };
+    let mut reader = dialect.reader()
         .has_headers(false)
-        .from_path(path)?;
+        .from_reader(source);
 
     let mut records: Vec<Vec<String>> = Vec::new();
     let mut header: Vec<String> = Vec::new();
+    let mut rows_read = 0;
 
-    for (_i, result) in reader.records().enumerate() {
+    for result in reader.records() {
         // Make this efficient (pass references instead of copying? use structs for specific structure?)
         // For now this will return an error if the result cannot be read.
         let record: Vec<String> = result?
             .into_iter()
             .map(|v| String::from_utf8(Vec::from(v)).unwrap_or_else(|_| String::from("")))
             .collect();
+        if !header.is_empty() {
+            rows_read += 1;
+        }
 
         // Append rows that match the length of the header.
         if !header.is_empty() && header.len() == record.len() {
@@ -62,7 +244,7 @@ fn read_csv(
             }) {
                 // If no fields specified, simply push the record.
                 if query.fields.is_empty() && !record.is_empty() {
-                    records.push(record);
+                    records.push(schema::mask_row(&query.masks, &header, record));
                 }
                 // Otherwise filter the record values needed based on the fields specified.
                 // This needs to be done here because we want to be able to apply
@@ -74,13 +256,17 @@ fn read_csv(
                             .map(|s| s.to_owned())
                             .collect();
                     if !filtered.is_empty() {
-                        records.push(filtered);
+                        let names: Vec<String> = query.fields.iter()
+                            .filter(|field| record_hashmap.contains_key(*field))
+                            .cloned()
+                            .collect();
+                        records.push(schema::mask_row(&query.masks, &names, filtered));
                     }
                 }
             }
         }
         // Set the header automatically on the first record with complete fields.
-        else if header.is_empty() && record.iter().all(|v: &String| v.len() > 0) {
+        else if header.is_empty() && record.iter().all(|v: &String| !v.is_empty()) {
             header = record;
         }
     }
@@ -92,8 +278,9 @@ fn read_csv(
             .map(|field| field.to_owned())
             .collect();
     }
+    let header = schema::mask_header(&query.masks, header);
 
-    Ok(CSVData { header, records })
+    Ok(CSVData { header, records, rows_read })
 }
 
 
@@ -104,6 +291,18 @@ fn list_collection_files(
     filename_regex_predicates: &Vec<Predicate>,
 ) -> Result<Vec<FileMetadata>, ZenithError> {
 
+    list_files_in(collection, &existing_collection_path(collection)?, filename_regex_predicates)
+}
+
+
+/// Returns a list of the files in `path` and their metadata, as files of `collection`,
+/// such as the files of a snapshot of it, filtered by any `filename_regex_predicates`.
+fn list_files_in(
+    collection: &str,
+    path: &Path,
+    filename_regex_predicates: &Vec<Predicate>,
+) -> Result<Vec<FileMetadata>, ZenithError> {
+
     // Compose each regex beforehand.
     let mut regex_predicates = Vec::new();
     for pr in filename_regex_predicates {
@@ -113,32 +312,20 @@ fn list_collection_files(
         }
     }
 
-    let path = Path::new(config::DATA_PATH).join(collection);
-    let files_metadata: Vec<FileMetadata> = std::fs::read_dir(path)?
-        .map(|entry| {
-            match entry {
-                Ok(e) => FileMetadata {
-                    filename: e.file_name().into_string().unwrap_or_else(|_| String::from("")),
-                    collection: String::from(collection),
-                    filepath: e.path(),
-                    size: match e.metadata() {
-                        Ok(m) => m.len(),
-                        Err(_) => 0,
-                    }
-                },
-                Err(_) => FileMetadata {
-                    filename: String::from(""),
-                    collection: String::from(collection),
-                    filepath: "".into(),
-                    size: 0,
-                }
-            }
+    let files_metadata: Vec<FileMetadata> = list_data_files(path)?
+        .into_iter()
+        .map(|e| FileMetadata {
+            filename: e.name,
+            collection: String::from(collection),
+            filepath: e.path,
+            size: e.size,
+            modified: e.modified,
         })
         .filter(|m| {
-            m.filename != "" && m.size > 0
+            !m.filename.is_empty() && m.size > 0
             &&
             regex_predicates.iter().all(|(re, pr)| match re.find(&m.filename) {
-                Some(ma) => pr.satisfied_by(&ma.as_str().to_string()),
+                Some(ma) => pr.satisfied_by(ma.as_str()),
                 None => false,
             })
         })
@@ -175,94 +362,448 @@ fn group_collection_files(
 }
 
 
-/// Throws an error if the `header` is not the same as headers in the `collection`.
+/// Throws an error if the `header` is not the same as the header of the `collection`.
+/// 
+/// The header of the collection is given by its schema if it has one, and
+/// otherwise by the canonical header in its catalog. Any header satisfies
+/// a collection with neither.
 fn satisfies_collection_header(
     collection: &str,
     header: &Vec<String>,
 )-> Result<(), ZenithError> {
 
-    let collection_path = Path::new(config::DATA_PATH).join(collection);
-    let entries: Vec<Result<std::fs::DirEntry, std::io::Error>> = std::fs::read_dir(&collection_path)?
-        .take(3).collect();
+    let expected = match schema::read(collection)? {
+        Some(schema) => schema.header(),
+        None => catalog::read(collection)?.header,
+    };
+    if !expected.is_empty() && expected != *header {
+        return Err(ZenithError::HeaderMismatch {
+            collection: collection.to_string(),
+            expected,
+            found: header.clone(),
+        });
+    }
+
+    Ok(())
+}
+
 
-    for e in entries {
-        let entry = e?;
-        let entry_path = collection_path.join(entry.file_name());
-        let mut reader = csv::ReaderBuilder::new()
+/// Rewrites each of `filenames` in `collection` whose header is `header`, passing the
+/// header and each row of the same length through `rewrite`, along with whether it is
+/// the header. Rows are removed when `rewrite` returns false. Rows of other lengths are
+/// kept as they are.
+/// 
+/// Files are read in the order they are given. Files that are not changed are left alone,
+/// and the current version of each file that is changed is kept. The collection lock must
+/// be held while calling this. Returns the names of the files rewritten.
+fn rewrite_files(
+    collection: &str,
+    filenames: &[String],
+    header: &[String],
+    rewrite: impl Fn(&mut Vec<String>, bool) -> bool,
+) -> Result<Vec<String>, ZenithError> {
+
+    let collection_path = config::collection_path(collection);
+    let dialect = catalog::dialect(collection)?;
+    let mut rewritten = Vec::new();
+    let mut entries = list_data_files(&collection_path)?;
+    entries.retain(|entry| filenames.contains(&entry.name));
+    entries.sort_by_key(|entry| filenames.iter().position(|filename| *filename == entry.name));
+    for entry in entries {
+        let mut bytes = Vec::new();
+        open_file(&entry.path)?.read_to_end(&mut bytes)?;
+        let mut reader = dialect.reader()
             .has_headers(false)
-            .from_path(entry_path)?;
-        let mut entry_header: Vec<String> = Vec::new();
+            .flexible(true)
+            .from_reader(bytes.as_slice());
+        let mut writer = dialect.writer()
+            .flexible(true)
+            .from_writer(Vec::new());
 
+        // The header is found the same way as in `render`.
+        let (mut found, mut changed) = (false, false);
         for result in reader.records() {
-            let record: Vec<String> = result?
+            let mut record: Vec<String> = result?
                 .into_iter()
                 .map(|v| String::from_utf8(Vec::from(v)).unwrap_or_else(|_| String::from("")))
                 .collect();
-
-            if record.iter().all(|v: &String| v.len() > 0) {
-                entry_header = record;
-                break;
+            let original = record.clone();
+            let keep = if !found && record.iter().all(|v| !v.is_empty()) {
+                if record != header {
+                    break;
+                }
+                found = true;
+                rewrite(&mut record, true)
+            }
+            else if record.len() == header.len() {
+                rewrite(&mut record, false)
+            }
+            else {
+                true
+            };
+            changed |= !keep || record != original;
+            if keep {
+                writer.write_record(&record)?;
             }
         }
-
-        if entry_header.len() != header.len() ||
-            entry_header.iter().zip(header).any(|(a, b)| a != b) {
-            return Err(ZenithError::QueryError(format!(
-                "Header {:?} does not match header in collection '{}'",
-                header, &collection
-            )));
+        if !found || !changed {
+            continue;
         }
+
+        let temp_path = collection_path.join(format!(".{}.tmp", entry.name));
+        write_file(&temp_path, writer.into_inner().map_err(|e| e.into_error())?)?;
+        rewritten.push((entry.name, temp_path, entry.path));
+    }
+
+    // Every file is written before any is moved into place, so that a failure
+    // part way through leaves the collection as it was.
+    for (filename, temp_path, path) in &rewritten {
+        keep_version(collection, filename)?;
+        storage().rename(temp_path, path)?;
     }
+    Ok(rewritten.into_iter().map(|(filename, _, _)| filename).collect())
+}
+
+
+/// Records `header` as the canonical header of `collection`, along with the
+/// `rewritten` files, after the header has been changed.
+/// 
+/// The collection lock must be held while calling this.
+fn change_collection_header(
+    collection: &str,
+    rewritten: &[String],
+    header: Vec<String>,
+) -> Result<(), ZenithError> {
 
+    let mut catalog = catalog::update(collection, rewritten)?;
+    if !catalog.files.is_empty() {
+        catalog.header = header;
+        catalog::write(collection, &catalog)?;
+    }
     Ok(())
 }
 
 
-/// Make a selection on `collection` with `predicates`.
+/// The keys of rows being written to a file that are already taken.
+#[derive(Default)]
+struct KeyClashes {
+    /// The rows with the same key as an earlier row being written, by their number.
+    repeated: Vec<(usize, Vec<String>)>,
+    /// The keys of the rows in other files of the collection, by the number of the first row
+    /// being written with the key, and the file the key is in.
+    existing: Vec<(usize, String, Vec<String>)>,
+}
+
+
+/// Finds the keys of the `rows` being written to `filename` in `collection`, numbered as given,
+/// that are repeated in the rows, or are already in other files of the collection, by the key
+/// of its `schema`. Each row must be as long as the header of the schema.
+fn find_key_clashes<'a>(
+    collection: &str,
+    filename: &str,
+    schema: &schema::Schema,
+    rows: impl Iterator<Item = (usize, &'a Vec<String>)>,
+) -> Result<(KeyClashes, HashMap<Vec<String>, usize>), ZenithError> {
+
+    let indices = schema.key_indices();
+    let mut clashes = KeyClashes::default();
+    // The number of the first row with each key.
+    let mut keys = HashMap::new();
+    if indices.is_empty() {
+        return Ok((clashes, keys));
+    }
+    let key_of = |row: &[String]| -> Vec<String> { indices.iter().map(|&i| row[i].clone()).collect() };
+
+    for (row_number, row) in rows {
+        match keys.contains_key(&key_of(row)) {
+            true => clashes.repeated.push((row_number, key_of(row))),
+            false => { keys.insert(key_of(row), row_number); },
+        }
+    }
+
+    let header = schema.header();
+    let dialect = catalog::dialect(collection)?;
+    for entry in list_data_files(&config::collection_path(collection))? {
+        if entry.name == filename {
+            continue;
+        }
+        let mut bytes = Vec::new();
+        open_file(&entry.path)?.read_to_end(&mut bytes)?;
+        let (file_header, file_rows, _) = render(&bytes, &dialect)?;
+        if file_header != header {
+            continue;
+        }
+        clashes.existing.extend(file_rows.iter()
+            .map(|row| key_of(row))
+            .filter_map(|key| keys.get(&key).map(|&row_number| (row_number, entry.name.clone(), key))));
+    }
+    Ok((clashes, keys))
+}
+
+
+/// Checks the `rows` being written to `filename` in `collection` against the key of its
+/// `schema`. Rows with the same key as each other are an error. Rows with the same key as
+/// a row in another file are an error, unless `on_conflict` is `Upsert`, in which case
+/// the rows in the other files are removed.
 /// 
-/// Returns the field names in a header as `Vec<String>` and rows of values as `Vec<Vec<String>>`.
+/// The collection lock must be held while calling this. Returns the names of the files rewritten.
+fn enforce_key(
+    collection: &str,
+    filename: &str,
+    schema: &schema::Schema,
+    rows: &[Vec<String>],
+    first_row_number: usize,
+    on_conflict: OnConflict,
+) -> Result<Vec<String>, ZenithError> {
+
+    let numbered = rows.iter().enumerate().map(|(i, row)| (first_row_number + i, row));
+    let (clashes, keys) = find_key_clashes(collection, filename, schema, numbered)?;
+    if let Some((row_number, key)) = clashes.repeated.first() {
+        return Err(ZenithError::QueryError(format!(
+            "Row {} has the same key {:?} as an earlier row", row_number, key
+        )));
+    }
+    let conflicts = clashes.existing;
+    if conflicts.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    match on_conflict {
+        OnConflict::Reject => {
+            const SHOWN: usize = 10;
+            let mut message = conflicts.iter().take(SHOWN)
+                .map(|(_, filename, key)| format!("key {:?} already exists in '{}'", key, filename))
+                .collect::<Vec<String>>().join("; ");
+            if conflicts.len() > SHOWN {
+                message.push_str(&format!("; and {} more", conflicts.len() - SHOWN));
+            }
+            Err(ZenithError::Conflict(message))
+        },
+        OnConflict::Upsert => {
+            let indices = schema.key_indices();
+            let key_of = |row: &[String]| -> Vec<String> { indices.iter().map(|&i| row[i].clone()).collect() };
+            let mut filenames: Vec<String> = conflicts.into_iter().map(|(_, filename, _)| filename).collect();
+            filenames.dedup();
+            rewrite_files(collection, &filenames, &schema.header(), |row, is_header| {
+                is_header || !keys.contains_key(&key_of(row))
+            })
+        },
+    }
+}
+
+
+/// Puts the columns of a `payload` whose rows were given as objects in the order of the header
+/// of `collection`, if it has one, filling in columns missing from every row with empty values
+/// unless the collection has a schema, which fills them in with their defaults when inserted.
 /// 
-/// Uses threads to divide the search computation. The header will be set
-/// on the first header returned. Therefore, for now, we make the assumption
-/// that all data in the collection has consistent headers. As the rows are
-/// received in nondeterministic order, the order of the rows returned from
-/// this function will vary. One can sort the rows to solve this.
-pub fn select(
+/// The payload is returned as it is if its rows were not given as objects, or it has columns
+/// that are not in the collection, leaving any problems with it to be found when it is inserted.
+pub fn order_keyed_columns(
     collection: &str,
-    predicates: QueryPredicates,
-) -> Result<(Vec<String>, Vec<Vec<String>>), ZenithError> {
+    payload: CreatePayload,
+) -> Result<CreatePayload, ZenithError> {
+
+    if !payload.keyed {
+        return Ok(payload);
+    }
+    validate_name("collection", collection)?;
+    let schema = schema::read(collection)?;
+    let expected = match &schema {
+        Some(schema) => schema.header(),
+        None => catalog::read(collection).map(|catalog| catalog.header).unwrap_or_default(),
+    };
+    let positions: Vec<Option<usize>> = expected.iter().map(|name| payload.header.iter().position(|h| h == name)).collect();
+    let complete = positions.iter().all(|p| p.is_some());
+    if expected.is_empty() || payload.header.iter().any(|h| !expected.contains(h)) || (schema.is_some() && !complete) {
+        return Ok(payload);
+    }
 
-    let query = DataQuery::new(predicates.fields, predicates.predicates)?;
-    let query = Arc::new(query); // drop this at end of function
+    let rows = payload.rows.iter()
+        .map(|row| positions.iter().map(|p| p.map(|i| row[i].clone()).unwrap_or_default()).collect())
+        .collect();
+    Ok(CreatePayload { header: expected, rows, ..payload })
+}
 
-    let files = list_collection_files(collection, &query.filename_regex_predicates)?;
-    let groups = group_collection_files(files, config::envar_usize("ZENITHDS_NUM_WORKERS"));
 
-    let (sender, receiver) = mpsc::channel();
-    let mut threads = Vec::new();
-    let (mut header, mut records): (Vec<String>, Vec<Vec<String>>) = (Vec::new(), Vec::new());
+/// Fills in the columns of `schema` that are missing from the header of `payload`
+/// with their defaults (or empty values), putting the columns in the order of the schema.
+/// 
+/// The payload is returned as it is unless its header is a strict subset of the columns,
+/// leaving any other problems with it to be found when it is checked.
+fn fill_missing_columns(
+    schema: &schema::Schema,
+    payload: CreatePayload,
+) -> CreatePayload {
+
+    let header = if !payload.header.is_empty() {
+        payload.header.clone()
+    } else {
+        match payload.rows.iter().find(|r| r.iter().all(|v: &String| !v.is_empty())) {
+            Some(header) => header.clone(),
+            None => return payload,
+        }
+    };
+    let expected = schema.header();
+    let positions: Vec<Option<usize>> = expected.iter().map(|name| header.iter().position(|h| h == name)).collect();
+    let subset = header.len() < expected.len()
+        && header.iter().enumerate().all(|(i, h)| expected.contains(h) && !header[..i].contains(h));
+    if !subset || payload.rows.iter().any(|row| row.len() != header.len()) {
+        return payload;
+    }
+
+    let fill = |row: &Vec<String>| -> Vec<String> {
+        if *row == header {
+            return expected.clone();
+        }
+        schema.columns.iter().zip(&positions)
+            .map(|(column, position)| match position {
+                Some(i) => row[*i].clone(),
+                None => column.default.clone().unwrap_or_default(),
+            })
+            .collect()
+    };
+    CreatePayload {
+        header: if payload.header.is_empty() { Vec::new() } else { expected.clone() },
+        rows: payload.rows.iter().map(fill).collect(),
+        ..payload
+    }
+}
+
+
+/// Parses a query on `collection` for `fields` with `predicates`, typing its predicates by
+/// the schema of the collection. If a `principal` is given, the columns masked from it
+/// are masked by the query, and a `Forbidden` error is raised if a predicate uses one.
+fn prepare_query(
+    collection: &str,
+    fields: Vec<String>,
+    predicates: Vec<String>,
+    principal: Option<&Principal>,
+) -> Result<DataQuery, ZenithError> {
+
+    validate_name("collection", collection)?;
+    let mut query = DataQuery::new(fields, predicates)?;
+    if let Some(schema) = schema::read(collection)? {
+        query.apply_schema(&schema);
+        if let Some(principal) = principal {
+            query.masks = schema.masks(&principal.roles);
+        }
+    }
+    // Filtering on a masked column would reveal its values.
+    if let Some(predicate) = query.predicates.iter().find(|p| query.masks.contains_key(&p.field)) {
+        return Err(ZenithError::Forbidden(format!("Column '{}' cannot be used in predicates", predicate.field)));
+    }
+    Ok(query)
+}
+
+
+/// Returns the columns masked from `principal` in `collection`, raising a `Forbidden` error if
+/// `predicates` filter on any of them, as `select` does, for rows that are not found by `select`,
+/// such as those from federation nodes.
+pub fn query_masks(
+    collection: &str,
+    predicates: &QueryPredicates,
+    principal: &Principal,
+) -> Result<HashMap<String, schema::MaskAction>, ZenithError> {
+    Ok(prepare_query(collection, predicates.fields.clone(), predicates.predicates.clone(), Some(principal))?.masks)
+}
+
+
+/// Reads the file of `fm` again and verifies it against the checksum in the catalog as it is now,
+/// holding the collection lock, for a file that did not match the checksum it had when the query
+/// started. The file may have been changed since then, such as by an insert or a rollback, which
+/// is not an integrity error. Raises an `IntegrityError` if it still does not match.
+fn reverify(fm: &FileMetadata) -> Result<Vec<u8>, ZenithError> {
+    let (collection, filename) = (&fm.collection, &fm.filename);
+    let lock = collection_lock(collection);
+    let _guard = lock.lock().unwrap_or_else(|e| e.into_inner());
+    let Some(file) = catalog::read(collection)?.files.remove(filename) else {
+        return Err(ZenithError::NotFound(Missing::File(collection.to_string(), filename.to_string())));
+    };
+    let bytes = storage().read(&fm.filepath)?;
+    if catalog::checksum(&bytes) != file.sha256 {
+        return Err(ZenithError::IntegrityError(format!(
+            "'{}' in collection '{}' does not match its checksum", filename, collection
+        )));
+    }
+    Ok(bytes)
+}
+
 
-    let group_sizes: Vec<String> = groups.iter()
-                        .map(|g| g.iter().map(|m| m.size).sum())
-                        .map(|n: u64| format!("{}KB", n / 1000))
-                        .collect();
+/// Returns the checksums of the files in `collection` to verify as they are read,
+/// which are only needed if `ZENITHDS_VERIFY_ON_READ` is set.
+fn checksums(collection: &str) -> Result<HashMap<String, String>, ZenithError> {
+    Ok(match config::envar_str("ZENITHDS_VERIFY_ON_READ").is_empty() {
+        true => HashMap::new(),
+        false => catalog::read(collection)?.files.into_iter().map(|(filename, file)| (filename, file.sha256)).collect(),
+    })
+}
+
+
+/// Returns the size of each of the `groups` of files, for logging.
+fn group_sizes(groups: &[Vec<FileMetadata>]) -> Vec<String> {
+    groups.iter()
+        .map(|g| g.iter().map(|m| m.size).sum())
+        .map(|n: u64| format!("{}KB", n / 1000))
+        .collect()
+}
 
-    println!("SELECT '{}' with {} groups {:?}", &collection, groups.len(), group_sizes);
+
+/// Reads the files in each of the `groups` on a thread of its own with `query`, calling
+/// `receive` with the profile of each file and what was read from it as soon as it is read.
+/// 
+/// Once `stopped` is set, workers stop reading files. It is set if `receive` returns `false`.
+/// 
+/// Files that cannot be read are logged and skipped, except for those that do not match their
+/// checksums, which raise an `IntegrityError` and stop the scan, so that a query does not return
+/// the rows of the other files as though they were all of them.
+fn scan(
+    groups: Vec<Vec<FileMetadata>>,
+    query: &Arc<DataQuery>,
+    checksums: HashMap<String, String>,
+    dialect: Dialect,
+    stopped: &Cancelled,
+    mut receive: impl FnMut(FileProfile, Option<CSVData>) -> bool,
+) -> Result<(), ZenithError> {
+    let (sender, receiver) = mpsc::channel();
+    let mut threads = Vec::new();
+    let checksums = Arc::new(checksums);
 
     for group in groups {
         let sender = sender.clone();
-        let query = Arc::clone(&query);
+        let query = Arc::clone(query);
+        let checksums = Arc::clone(&checksums);
+        let stopped = stopped.clone();
         let join_handle = thread::spawn(move || {
             for fm in group {
-                match read_csv(&fm.collection, &fm.filename, &query) {
+                if stopped.is_set() {
+                    break;
+                }
+                let read_started = Instant::now();
+                let result = read_csv(&fm, &query, checksums.get(&fm.filename), &dialect);
+                let mut profile = FileProfile {
+                    filename: fm.filename.clone(),
+                    rows_read: 0,
+                    rows_matched: 0,
+                    micros: read_started.elapsed().as_micros() as u64,
+                    error: None,
+                };
+                let data = match result {
                     Ok(data) => {
-                        if let Err(err) = sender.send(data) {
-                            eprintln!("read {}/{} send error: {}", &fm.collection, &fm.filename, err);
-                        }
+                        profile.rows_read = data.rows_read;
+                        profile.rows_matched = data.records.len();
+                        Ok(data)
                     },
                     Err(err) => {
-                        eprintln!("read {}/{} read error: {}", &fm.collection, &fm.filename, err);
+                        error!("read {}/{} read error: {}", &fm.collection, &fm.filename, err);
+                        profile.error = Some(err.to_string());
+                        Err(err)
                     }
+                };
+                if let Err(err) = sender.send((profile, data)) {
+                    if !stopped.is_set() {
+                        error!("read {}/{} send error: {}", &fm.collection, &fm.filename, err);
+                    }
+                    break;
                 }
             }
         });
@@ -272,66 +813,735 @@ pub fn select(
     // Need to drop the initial sender here so the receiver will not be waiting for it.
     drop(sender);
 
-    for mut received in receiver {
-        if header.is_empty() {
-            header = received.header;
+    let mut failed = None;
+    for (profile, received) in receiver {
+        let received = match received {
+            Ok(data) => Some(data),
+            Err(err @ ZenithError::IntegrityError(_)) => {
+                failed = Some(err);
+                stopped.set();
+                break;
+            },
+            Err(_) => None,
+        };
+        if !receive(profile, received) {
+            stopped.set();
+            break;
         }
-        records.append(&mut received.records);
     }
 
     for join_handle in threads {
         if let Err(err) = join_handle.join() {
-            eprintln!("Failed to join thread: {:?}", err);
+            error!("Failed to join thread: {:?}", err);
         }
     }
+    failed.map_or(Ok(()), Err)
+}
 
-    drop(query);
 
-    Ok((header, records))
+/// Make a selection on `collection` with `predicates`.
+/// 
+/// Returns the field names in a header as `Vec<String>` and rows of values as `Vec<Vec<String>>`.
+/// 
+/// Uses threads to divide the search computation. The header will be set
+/// on the first header returned. Therefore, for now, we make the assumption
+/// that all data in the collection has consistent headers. As the rows are
+/// received in nondeterministic order, the order of the rows returned from
+/// this function will vary. One can sort the rows to solve this.
+/// 
+/// If a `principal` is given, the columns masked from it by the schema are masked
+/// in the rows returned, and cannot be used in predicates.
+/// 
+/// Once `cancelled` is set, workers stop reading files, and a `Cancelled` error is raised.
+pub fn select(
+    collection: &str,
+    predicates: QueryPredicates,
+    principal: Option<&Principal>,
+    cancelled: &Cancelled,
+) -> Result<(Vec<String>, Vec<Vec<String>>), ZenithError> {
+
+    let (selection, _) = run_select(collection, None, None, predicates, principal, cancelled, false)?;
+    Ok(selection)
 }
 
 
-/// Inserts `payload` into `collection`.
-pub fn insert(
+/// Make a selection like `select`, giving the header and rows matched in each file to `each`
+/// as soon as the file is read, rather than collecting them, so that the first rows can be
+/// sent before every file has been read. As the rows are not held, the memory budget does
+/// not apply. Returns the number of rows matched.
+/// 
+/// Once `cancelled` is set, or `each` returns `false`, workers stop reading files,
+/// and a `Cancelled` error is raised.
+pub fn select_each(
     collection: &str,
-    payload: CreatePayload,
-) -> Result<(), ZenithError> {
+    predicates: QueryPredicates,
+    principal: Option<&Principal>,
+    cancelled: &Cancelled,
+    mut each: impl FnMut(Vec<String>, Vec<Vec<String>>) -> bool,
+) -> Result<usize, ZenithError> {
 
-    if collection.is_empty() || payload.filename.is_empty() {
-        return Err(ZenithError::QueryError("Payload collection or filename is empty".to_string()));
-    }
+    let started = Instant::now();
+    let QueryPredicates { fields, predicates } = predicates;
+    let query = Arc::new(prepare_query(collection, fields.clone(), predicates.clone(), principal)?);
+    let files = list_collection_files(collection, &query.filename_regex_predicates)?;
+    let files_scanned = files.len();
+    let checksums = checksums(collection)?;
+    let dialect = catalog::dialect(collection)?;
+    let groups = group_collection_files(files, config::envar_usize("ZENITHDS_NUM_WORKERS"));
 
-    // If no header is provided, we can allow inserting a raw set of rows,
-    // but we must first find a header in the rows.
-    let header = if !payload.header.is_empty() {
-        Some(&payload.header)
-    } else {
-        payload.rows.iter().find(|r| r.iter().all(|v: &String| v.len() > 0))
-    };
+    debug!("SELECT '{}' with {} groups {:?}, streamed", &collection, groups.len(), group_sizes(&groups));
 
-    match header {
-        Some(header) => {
-            // Make sure the length of each given row matches the length of the given header.
-            if payload.rows.iter().any(|row| row.len() != header.len()) {
-                return Err(ZenithError::QueryError(format!("Row length does not match header length {}", header.len())));
-            }
-            // Check the payload header to make sure it will work in this collection.
-            satisfies_collection_header(collection, header)?;
+    let mut rows = 0;
+    scan(groups, &query, checksums, dialect, cancelled, |_, received| match received {
+        Some(received) => {
+            rows += received.records.len();
+            each(received.header, received.records)
         },
-        None => { return Err(ZenithError::QueryError("Header cannot be found".to_string())); }
-    }
+        None => true,
+    })?;
 
-    // Write the data to the collection.
-    let insert_path = Path::new(config::DATA_PATH).join(collection).join(&payload.filename);
-    let mut writer = csv::WriterBuilder::new().from_path(insert_path)?;
-    if !payload.header.is_empty() {
-        writer.write_record(&payload.header)?;
-    }
-    for row in payload.rows {
-        writer.write_record(row)?;
+    if cancelled.is_set() {
+        return Err(ZenithError::Cancelled(format!("The query on collection '{}' was cancelled", collection)));
     }
-
-    Ok(())
+    slow_query::record(collection, &fields, &predicates, files_scanned, rows, started.elapsed());
+    Ok(rows)
+}
+
+
+/// Approximates the bytes of memory that a `row` takes up.
+fn row_bytes(row: &[String]) -> usize {
+    std::mem::size_of::<Vec<String>>() + row.iter().map(|v| std::mem::size_of::<String>() + v.len()).sum::<usize>()
+}
+
+
+/// Make a selection like `select`, also returning a profile of how it was run:
+/// the rows read and matched in each file scanned, and how long each phase took.
+pub fn select_profiled(
+    collection: &str,
+    predicates: QueryPredicates,
+    principal: Option<&Principal>,
+    cancelled: &Cancelled,
+) -> Result<(Selection, QueryProfile), ZenithError> {
+
+    let (selection, profile) = run_select(collection, None, None, predicates, principal, cancelled, true)?;
+    Ok((selection, profile.unwrap_or_default()))
+}
+
+
+/// Make a selection like `select` on the files of `collection` as they were in the snapshot with
+/// `snapshot_id`, rather than its current files, also returning a profile if it is `profiled`.
+/// See `select_files_in`.
+pub fn select_snapshot(
+    collection: &str,
+    snapshot_id: &str,
+    predicates: QueryPredicates,
+    principal: Option<&Principal>,
+    cancelled: &Cancelled,
+    profiled: bool,
+) -> Result<(Selection, Option<QueryProfile>), ZenithError> {
+
+    let path = existing_snapshot_path(collection, snapshot_id)?;
+    run_select(collection, Some(&path), None, predicates, principal, cancelled, profiled)
+}
+
+
+/// Make a selection like `select` on the files in `path` as files of `collection`, such as the
+/// files of a snapshot or the rows of a materialized view, rather than its current files, also
+/// returning a profile if it is `profiled`. The files are read with the dialect, schema, and masks
+/// of the collection, but are not verified against the checksums in its catalog, which are of its
+/// current files.
+pub fn select_files_in(
+    collection: &str,
+    path: &Path,
+    predicates: QueryPredicates,
+    principal: Option<&Principal>,
+    cancelled: &Cancelled,
+    profiled: bool,
+) -> Result<(Selection, Option<QueryProfile>), ZenithError> {
+
+    run_select(collection, Some(path), None, predicates, principal, cancelled, profiled)
+}
+
+
+/// Make a selection like `select` on only the file `filename` in `collection`, such as a file
+/// that was just created, raising a `NotFound` error if it does not exist.
+pub fn select_file(
+    collection: &str,
+    filename: &str,
+    predicates: QueryPredicates,
+    principal: Option<&Principal>,
+    cancelled: &Cancelled,
+) -> Result<Selection, ZenithError> {
+
+    validate_name("file", filename)?;
+    existing_file_path(collection, filename)?;
+    let (selection, _) = run_select(collection, None, Some(filename), predicates, principal, cancelled, false)?;
+    Ok(selection)
+}
+
+
+/// Runs a selection for `select`, `select_profiled`, `select_files_in`, and `select_file`, on the
+/// files in `path` if it is given, or only the file `filename`, returning a profile if it is `profiled`.
+#[allow(clippy::too_many_arguments)]
+fn run_select(
+    collection: &str,
+    path: Option<&Path>,
+    filename: Option<&str>,
+    predicates: QueryPredicates,
+    principal: Option<&Principal>,
+    cancelled: &Cancelled,
+    profiled: bool,
+) -> Result<(Selection, Option<QueryProfile>), ZenithError> {
+
+    let started = Instant::now();
+    let mut phases = Vec::new();
+    let mut phase = Instant::now();
+    let mut end_phase = |name: &str| {
+        phases.push(PhaseTiming { phase: name.to_string(), micros: phase.elapsed().as_micros() as u64 });
+        phase = Instant::now();
+    };
+
+    let QueryPredicates { fields, predicates } = predicates;
+    let query = Arc::new(prepare_query(collection, fields.clone(), predicates.clone(), principal)?); // drop this at end of function
+    end_phase("prepare");
+
+    let files_path = match path {
+        Some(path) => path.to_path_buf(),
+        None => existing_collection_path(collection)?,
+    };
+    let mut files = list_files_in(collection, &files_path, &query.filename_regex_predicates)?;
+    if let Some(filename) = filename {
+        files.retain(|f| f.filename == filename);
+    }
+    let files_scanned = files.len();
+    // Listing every file to count those pruned is only worth it when profiling.
+    let files_pruned = match profiled && !query.filename_regex_predicates.is_empty() {
+        true => list_files_in(collection, &files_path, &Vec::new())?.len().saturating_sub(files_scanned),
+        false => 0,
+    };
+    let checksums = match path {
+        Some(_) => HashMap::new(),
+        None => checksums(collection)?,
+    };
+    let dialect = catalog::dialect(collection)?;
+    let groups = group_collection_files(files, config::envar_usize("ZENITHDS_NUM_WORKERS"));
+    end_phase("list");
+
+    let (mut header, mut records): (Vec<String>, Vec<Vec<String>>) = (Vec::new(), Vec::new());
+    let mut file_profiles = Vec::new();
+
+    debug!("SELECT '{}' with {} groups {:?}", &collection, groups.len(), group_sizes(&groups));
+
+    // Set when the query is abandoned, so that workers stop reading files.
+    let stopped = cancelled.clone();
+    let mut over_budget = false;
+    let budget = config::envar_usize("ZENITHDS_QUERY_MEMORY_BUDGET");
+    let mut bytes = 0;
+
+    scan(groups, &query, checksums, dialect, &stopped, |profile, received| {
+        file_profiles.push(profile);
+        if let Some(mut received) = received {
+            if header.is_empty() {
+                header = received.header;
+            }
+            if budget > 0 {
+                bytes += received.records.iter().map(|row| row_bytes(row)).sum::<usize>();
+                if bytes > budget {
+                    over_budget = true;
+                    return false;
+                }
+            }
+            records.append(&mut received.records);
+        }
+        true
+    })?;
+
+    drop(query);
+    end_phase("scan");
+
+    if over_budget {
+        return Err(ZenithError::ResultTooLarge(format!(
+            "The rows matched in collection '{}' take more than the {} bytes each query can use. Narrow the query with predicates or fields",
+            collection, budget
+        )));
+    }
+    if stopped.is_set() {
+        return Err(ZenithError::Cancelled(format!("The query on collection '{}' was cancelled", collection)));
+    }
+
+    slow_query::record(collection, &fields, &predicates, files_scanned, records.len(), started.elapsed());
+    let profile = profiled.then(|| {
+        file_profiles.sort_by(|a, b| a.filename.cmp(&b.filename));
+        QueryProfile { files_scanned, files_pruned, files: file_profiles, phases }
+    });
+    Ok(((header, records), profile))
+}
+
+
+/// Returns the columns of `collection` that `principal` can see, with the types their values
+/// are compared as. They are the columns of its schema if it has one, or the canonical header
+/// in its catalog, as strings, if it does not. Masked columns are strings, and hidden columns
+/// are left out.
+pub fn columns(
+    collection: &str,
+    principal: Option<&Principal>,
+) -> Result<Vec<(String, schema::ColumnType)>, ZenithError> {
+
+    validate_name("collection", collection)?;
+    let Some(schema) = schema::read(collection)? else {
+        return Ok(catalog::read(collection)?.header.into_iter().map(|name| (name, schema::ColumnType::String)).collect());
+    };
+    let masks = principal.map(|p| schema.masks(&p.roles)).unwrap_or_default();
+    Ok(schema.columns.into_iter()
+        .filter(|column| masks.get(&column.name) != Some(&schema::MaskAction::Hide))
+        .map(|column| match masks.contains_key(&column.name) {
+            true => (column.name, schema::ColumnType::String),
+            false => (column.name, column.column_type),
+        })
+        .collect())
+}
+
+
+/// Describes how `select` would run a query on `collection` with `predicates`, without
+/// running it: the predicates as they were parsed, the files that would be scanned and
+/// those pruned by filename predicates, how the files are grouped across workers, and
+/// the bytes and rows (as recorded in the catalog) that would be read.
+pub fn explain(
+    collection: &str,
+    predicates: QueryPredicates,
+    principal: Option<&Principal>,
+) -> Result<ExplainResponse, ZenithError> {
+
+    let query = prepare_query(collection, predicates.fields, predicates.predicates, principal)?;
+    let files = list_collection_files(collection, &query.filename_regex_predicates)?;
+    let pruned = list_collection_files(collection, &Vec::new())?
+        .into_iter()
+        .map(|fm| fm.filename)
+        .filter(|filename| !files.iter().any(|fm| fm.filename == *filename))
+        .collect();
+    let catalog = catalog::read(collection)?;
+
+    let explained: Vec<ExplainedFile> = files.iter()
+        .map(|fm| ExplainedFile {
+            filename: fm.filename.clone(),
+            size: fm.size,
+            rows: catalog.files.get(&fm.filename).map(|f| f.rows),
+        })
+        .collect();
+    let bytes = explained.iter().map(|f| f.size).sum();
+    let rows = explained.iter().map(|f| f.rows).sum();
+    let groups = group_collection_files(files, config::envar_usize("ZENITHDS_NUM_WORKERS"))
+        .into_iter()
+        .map(|group| group.into_iter().map(|fm| fm.filename).collect())
+        .collect();
+
+    Ok(ExplainResponse {
+        fields: query.fields,
+        predicates: query.predicates.iter().map(|p| p.explain()).collect(),
+        filename_predicates: query.filename_regex_predicates.iter().map(|p| p.explain()).collect(),
+        files: explained,
+        pruned,
+        groups,
+        bytes,
+        rows,
+    })
+}
+
+
+/// Merges the `other_header` and `other_rows` from another source into the `header` and `rows`.
+/// 
+/// Fields are matched by name. Fields only found in the other source are added to the end
+/// of the header, and rows that do not have a value for a field are given the empty string.
+pub fn merge(
+    header: &mut Vec<String>,
+    rows: &mut Vec<Vec<String>>,
+    other_header: Vec<String>,
+    other_rows: Vec<Vec<String>>,
+) {
+    let positions: Vec<usize> = other_header.into_iter()
+        .map(|field| match header.iter().position(|f| *f == field) {
+            Some(i) => i,
+            None => {
+                header.push(field);
+                header.len() - 1
+            }
+        })
+        .collect();
+
+    for row in rows.iter_mut() {
+        row.resize(header.len(), String::new());
+    }
+    for other_row in other_rows {
+        let mut row = vec![String::new(); header.len()];
+        for (i, value) in positions.iter().zip(other_row) {
+            row[*i] = value;
+        }
+        rows.push(row);
+    }
+}
+
+
+/// Converts `rows` to JSON, giving the values in each column the type in `types`.
+/// Values in columns without a type are given as strings. See `ColumnType::to_json`.
+pub fn to_json(
+    types: &[schema::ColumnType],
+    rows: Vec<Vec<String>>,
+) -> Vec<Vec<serde_json::Value>> {
+
+    rows.into_iter()
+        .map(|row| row.into_iter().enumerate()
+            .map(|(i, value)| match types.get(i) {
+                Some(column_type) => column_type.to_json(&value),
+                None => serde_json::Value::String(value),
+            })
+            .collect())
+        .collect()
+}
+
+
+/// Converts `rows` to newline-delimited JSON, with each row as an object keyed by the `header`,
+/// and its values typed by `types` as with `to_json`.
+pub fn to_ndjson(
+    header: &[String],
+    types: &[schema::ColumnType],
+    rows: &[Vec<String>],
+) -> Result<Vec<u8>, ZenithError> {
+
+    let mut bytes = Vec::new();
+    for row in rows {
+        let object: serde_json::Map<String, serde_json::Value> = header.iter().zip(row)
+            .enumerate()
+            .map(|(i, (name, value))| (name.clone(), match types.get(i) {
+                Some(column_type) => column_type.to_json(value),
+                None => serde_json::Value::String(value.clone()),
+            }))
+            .collect();
+        serde_json::to_writer(&mut bytes, &object)?;
+        bytes.push(b'\n');
+    }
+    Ok(bytes)
+}
+
+
+/// Escapes `value` if a spreadsheet would run it as a formula, which is when it starts
+/// with `=`, `+`, `-`, `@`, a tab, or a carriage return, by putting `'` in front of it.
+/// Numbers, such as `-1`, are left as they are.
+pub fn escape_formula(
+    value: String,
+) -> String {
+
+    let formula = value.starts_with(['=', '+', '-', '@', '\t', '\r']);
+    if formula && value.parse::<f64>().is_err() {
+        format!("'{}", value)
+    }
+    else {
+        value
+    }
+}
+
+
+/// Writes `header` and `rows` as CSV. If `escape_formulas` is set,
+/// values are escaped so they are not run as formulas. See `escape_formula`.
+pub fn to_csv(
+    header: Vec<String>,
+    rows: Vec<Vec<String>>,
+    escape_formulas: bool,
+) -> Result<Vec<u8>, ZenithError> {
+
+    let mut writer = csv::WriterBuilder::new().flexible(true).from_writer(Vec::new());
+    for row in std::iter::once(header).filter(|h| !h.is_empty()).chain(rows) {
+        match escape_formulas {
+            true => writer.write_record(row.into_iter().map(escape_formula))?,
+            false => writer.write_record(row)?,
+        }
+    }
+    Ok(writer.into_inner().map_err(|e| e.into_error())?)
+}
+
+
+/// Writes the `selection` made on `collection` to `filename` in the directory at `path`, such as the
+/// directory of a materialized view, in the dialect of the collection, so that it can be read as its
+/// files are with `select_files_in`. The file is replaced once it has been written in full.
+pub fn write_selection(
+    collection: &str,
+    path: &Path,
+    filename: &str,
+    selection: Selection,
+) -> Result<(), ZenithError> {
+
+    let (header, rows) = selection;
+    let mut writer = catalog::dialect(collection)?.writer().flexible(true).from_writer(Vec::new());
+    for row in std::iter::once(header).filter(|h| !h.is_empty()).chain(rows) {
+        writer.write_record(row)?;
+    }
+    let bytes = writer.into_inner().map_err(|e| e.into_error())?;
+    storage().create_dir_all(path)?;
+    let temp_path = path.join(format!(".{}.tmp", filename));
+    write_file(&temp_path, bytes)?;
+    storage().rename(&temp_path, &path.join(filename))?;
+    Ok(())
+}
+
+
+/// Inserts `payload` into `collection`.
+pub fn insert(
+    collection: &str,
+    payload: CreatePayload,
+) -> Result<(), ZenithError> {
+
+    insert_partial(collection, payload).map(|_| ())
+}
+
+
+/// Inserts `payload` into `collection`, as `insert` does. If `payload.partial` is set, the rows
+/// with problems that `validate` would find are left out instead, and the problems are returned.
+/// Problems with the payload as a whole, such as a header that does not match, are still errors.
+pub fn insert_partial(
+    collection: &str,
+    payload: CreatePayload,
+) -> Result<Vec<ValidationProblem>, ZenithError> {
+
+    validate_name("collection", collection)?;
+    validate_name("file", &payload.filename)?;
+    existing_collection_path(collection)?;
+
+    let lock = collection_lock(collection);
+    let _guard = lock.lock().unwrap_or_else(|e| e.into_inner());
+
+    let schema = schema::read(collection)?;
+    let mut payload = match &schema {
+        Some(schema) => fill_missing_columns(schema, payload),
+        None => payload,
+    };
+    let rejected = match payload.partial {
+        true => reject_rows(collection, schema.as_ref(), &mut payload)?,
+        false => Vec::new(),
+    };
+
+    // If no header is provided, we can allow inserting a raw set of rows,
+    // but we must first find a header in the rows.
+    let header = if !payload.header.is_empty() {
+        Some(&payload.header)
+    } else {
+        payload.rows.iter().find(|r| r.iter().all(|v: &String| !v.is_empty()))
+    };
+
+    let mut upserted = Vec::new();
+    match header {
+        Some(header) => {
+            // Make sure the length of each given row matches the length of the given header.
+            if payload.rows.iter().any(|row| row.len() != header.len()) {
+                return Err(ZenithError::QueryError(format!("Row length does not match header length {}", header.len())));
+            }
+            // Check the payload header to make sure it will work in this collection.
+            satisfies_collection_header(collection, header)?;
+            // Rows up to and including a header found in the rows are not values.
+            let first = match payload.rows.iter().position(|r| r == header) {
+                Some(i) if payload.header.is_empty() => i + 1,
+                _ => 0,
+            };
+            match schema {
+                // Check each value against the type of its column, and the key against the collection.
+                Some(schema) => {
+                    let errors = schema.check_rows(&payload.rows[first..], first);
+                    if !errors.is_empty() {
+                        return Err(ZenithError::QueryError(schema::describe_errors(&errors)));
+                    }
+                    upserted = enforce_key(
+                        collection, &payload.filename, &schema, &payload.rows[first..], first, payload.on_conflict
+                    )?;
+                },
+                // Give the collection a schema from the first rows inserted, if enabled.
+                None if !config::envar_str("ZENITHDS_INFER_SCHEMA").is_empty() => {
+                    schema::write(collection, &schema::infer(header, &payload.rows[first..]))?;
+                },
+                None => (),
+            }
+        },
+        None => { return Err(ZenithError::QueryError("Header cannot be found".to_string())); }
+    }
+
+    // A collection takes the dialect it is given while it has no files, and keeps it after.
+    let mut collection_catalog = catalog::read(collection)?;
+    let dialect = match collection_catalog.files.is_empty() {
+        true => collection_catalog.dialect.with(payload.delimiter, payload.quote),
+        false => collection_catalog.dialect,
+    };
+    if dialect != collection_catalog.dialect {
+        collection_catalog.dialect = dialect;
+        catalog::write(collection, &collection_catalog)?;
+    }
+
+    // Write the data to a temporary file first, and then move it into place.
+    // Replacing the file (rather than truncating it) leaves any snapshot
+    // hard linked to a previous version of the file untouched.
+    let collection_path = config::collection_path(collection);
+    let insert_path = collection_path.join(&payload.filename);
+    let temp_path = collection_path.join(format!(".{}.tmp", payload.filename));
+    let mut writer = dialect.writer().from_writer(Vec::new());
+    if !payload.header.is_empty() {
+        writer.write_record(&payload.header)?;
+    }
+    for row in payload.rows {
+        writer.write_record(row)?;
+    }
+    let bytes = writer.into_inner().map_err(|e| e.into_error())?;
+    write_file(&temp_path, bytes)?;
+    keep_version(collection, &payload.filename)?;
+    storage().rename(&temp_path, &insert_path)?;
+    upserted.push(payload.filename);
+    catalog::update(collection, &upserted)?;
+
+    Ok(rejected)
+}
+
+
+/// Removes the rows of `payload` with problems found by `find_row_problems` from it, returning
+/// the problems. Nothing is removed if it has no header, or a header that does not match the
+/// collection, as the payload as a whole cannot be inserted. The collection lock must be held.
+fn reject_rows(
+    collection: &str,
+    schema: Option<&schema::Schema>,
+    payload: &mut CreatePayload,
+) -> Result<Vec<ValidationProblem>, ZenithError> {
+
+    let Some((header, first)) = find_header(payload) else {
+        return Ok(Vec::new());
+    };
+    match satisfies_collection_header(collection, header) {
+        Ok(()) => (),
+        Err(ZenithError::HeaderMismatch { .. }) => return Ok(Vec::new()),
+        Err(err) => return Err(err),
+    }
+    let problems = find_row_problems(collection, payload, schema, header, first)?;
+    let rejected: HashSet<usize> = problems.iter().filter_map(|p| p.row).collect();
+    let mut i = 0;
+    payload.rows.retain(|_| {
+        i += 1;
+        !rejected.contains(&(i - 1))
+    });
+    Ok(problems)
+}
+
+
+fn problem(kind: ProblemKind, row: Option<usize>, message: String) -> ValidationProblem {
+    ValidationProblem { kind, row, column: None, value: None, message }
+}
+
+
+/// Returns the header of `payload`, or if it has none, the first of its rows with a value in
+/// every field, along with the position of the first row after the header in its rows.
+fn find_header(payload: &CreatePayload) -> Option<(&Vec<String>, usize)> {
+    if !payload.header.is_empty() {
+        return Some((&payload.header, 0));
+    }
+    let i = payload.rows.iter().position(|r| r.iter().all(|v: &String| !v.is_empty()))?;
+    Some((&payload.rows[i], i + 1))
+}
+
+
+/// Finds the problems with the rows of `payload` being inserted into `collection` under `header`,
+/// as `insert` would: rows that are not as long as the header, and if the collection has a
+/// `schema` that the header matches, values that are not valid for it, and keys that are repeated,
+/// or already in other files unless `on_conflict` is `Upsert`. Rows before `first` are only
+/// checked for their length. The collection lock must be held while calling this.
+fn find_row_problems(
+    collection: &str,
+    payload: &CreatePayload,
+    schema: Option<&schema::Schema>,
+    header: &[String],
+    first: usize,
+) -> Result<Vec<ValidationProblem>, ZenithError> {
+
+    let mut problems: Vec<ValidationProblem> = payload.rows.iter().enumerate()
+        .filter(|(_, row)| row.len() != header.len())
+        .map(|(i, row)| problem(ProblemKind::RowLength, Some(i), format!(
+            "Row {} has {} values, but the header has {}", i, row.len(), header.len()
+        )))
+        .collect();
+    let Some(schema) = schema else {
+        return Ok(problems);
+    };
+
+    // Values and keys can only be checked in rows as long as the header.
+    let rows = payload.rows.iter().enumerate()
+        .skip(first)
+        .filter(|(_, row)| row.len() == header.len());
+    problems.extend(rows.clone()
+        .flat_map(|(i, row)| schema.check_row(i, row))
+        .map(|error| ValidationProblem {
+            kind: ProblemKind::InvalidValue,
+            row: Some(error.row),
+            message: error.to_string(),
+            column: Some(error.column),
+            value: Some(error.value),
+        }));
+    let (clashes, _) = find_key_clashes(collection, &payload.filename, schema, rows)?;
+    problems.extend(clashes.repeated.into_iter()
+        .map(|(i, key)| problem(ProblemKind::RepeatedKey, Some(i), format!(
+            "Row {} has the same key {:?} as an earlier row", i, key
+        ))));
+    if payload.on_conflict == OnConflict::Reject {
+        problems.extend(clashes.existing.into_iter()
+            .map(|(i, filename, key)| problem(ProblemKind::ExistingKey, Some(i), format!(
+                "Row {} has key {:?}, which already exists in '{}'", i, key, filename
+            ))));
+    }
+    Ok(problems)
+}
+
+
+/// How many problems `validate` reports, of all it finds.
+const REPORTED_PROBLEMS: usize = 1000;
+
+
+/// Checks `payload` as `insert` would before inserting it into `collection`, without writing
+/// anything, returning every problem found: no header, a header that does not match the
+/// collection, rows that are not as long as the header, values that are not valid for the
+/// schema of the collection, and keys that are repeated, or already in other files unless
+/// `on_conflict` is `Upsert`. The number of rows that would be inserted is also returned.
+pub fn validate(
+    collection: &str,
+    payload: CreatePayload,
+) -> Result<ValidateResponse, ZenithError> {
+
+    validate_name("collection", collection)?;
+    validate_name("file", &payload.filename)?;
+    existing_collection_path(collection)?;
+
+    let lock = collection_lock(collection);
+    let _guard = lock.lock().unwrap_or_else(|e| e.into_inner());
+
+    let schema = schema::read(collection)?;
+    let payload = match &schema {
+        Some(schema) => fill_missing_columns(schema, payload),
+        None => payload,
+    };
+    let Some((header, first)) = find_header(&payload) else {
+        let problems = vec![problem(ProblemKind::NoHeader, None, "Header cannot be found".to_string())];
+        return Ok(ValidateResponse { valid: false, rows: 0, total_problems: problems.len(), problems });
+    };
+
+    let mut problems = Vec::new();
+    let matches_header = match satisfies_collection_header(collection, header) {
+        Ok(()) => true,
+        Err(err @ ZenithError::HeaderMismatch { .. }) => {
+            problems.push(problem(ProblemKind::HeaderMismatch, None, err.to_string()));
+            false
+        },
+        Err(err) => return Err(err),
+    };
+    let schema = schema.as_ref().filter(|_| matches_header);
+    problems.extend(find_row_problems(collection, &payload, schema, header, first)?);
+
+    let total_problems = problems.len();
+    problems.truncate(REPORTED_PROBLEMS);
+    Ok(ValidateResponse {
+        valid: total_problems == 0,
+        rows: payload.rows.len() - first,
+        problems,
+        total_problems,
+    })
 }
 
 
@@ -341,29 +1551,917 @@ pub fn delete(
     filename: &str,
 ) -> Result<(), ZenithError> {
 
-    if filename.is_empty() || collection.is_empty() {
-        return Err(ZenithError::QueryError("The filename or collection is empty".to_string()));
+    validate_name("collection", collection)?;
+    validate_name("file", filename)?;
+    let lock = collection_lock(collection);
+    let _guard = lock.lock().unwrap_or_else(|e| e.into_inner());
+
+    let delete_path = existing_file_path(collection, filename)?;
+    storage().remove(&delete_path)?;
+    catalog::update(collection, &[filename.to_string()])?;
+    Ok(())
+}
+
+
+/// Deletes the files in `collection` that were last modified more than `max_age` ago.
+/// 
+/// Returns the names of the files that were removed.
+pub fn expire(
+    collection: &str,
+    max_age: Duration,
+) -> Result<Vec<String>, ZenithError> {
+
+    validate_name("collection", collection)?;
+    let lock = collection_lock(collection);
+    let _guard = lock.lock().unwrap_or_else(|e| e.into_inner());
+
+    let now = SystemTime::now();
+    let mut removed = Vec::new();
+
+    for fm in list_collection_files(collection, &Vec::new())? {
+        // Files with a modification time in the future are left alone.
+        if now.duration_since(fm.modified).is_ok_and(|age| age > max_age) {
+            storage().remove(&fm.filepath)?;
+            removed.push(fm.filename);
+        }
+    }
+    if !removed.is_empty() {
+        catalog::update(collection, &removed)?;
+    }
+
+    Ok(removed)
+}
+
+
+/// Removes the rows of `collection` whose date in `column` is more than `max_age` ago, in all
+/// of its files with its header. Rows whose value in the column is empty, or cannot be parsed
+/// as a date (see `schema::parse_date`), are kept. Dates without a time zone are taken as UTC.
+/// 
+/// Returns the number of rows removed, and the names of the files rewritten.
+pub fn expire_rows(
+    collection: &str,
+    column: &str,
+    max_age: Duration,
+) -> Result<(usize, Vec<String>), ZenithError> {
+
+    validate_name("collection", collection)?;
+    existing_collection_path(collection)?;
+
+    let lock = collection_lock(collection);
+    let _guard = lock.lock().unwrap_or_else(|e| e.into_inner());
+
+    let header = match schema::read(collection)? {
+        Some(schema) => schema.header(),
+        None => catalog::read(collection)?.header,
+    };
+    let index = header.iter().position(|name| name == column).ok_or_else(|| ZenithError::NotFound(Missing::Other(format!(
+        "Column '{}' does not exist in collection '{}'", column, collection
+    ))))?;
+    // A period too long to subtract from the time now expires nothing.
+    let cutoff = chrono::Duration::from_std(max_age).ok()
+        .and_then(|age| chrono::Utc::now().naive_utc().checked_sub_signed(age))
+        .unwrap_or(chrono::NaiveDateTime::MIN);
+
+    let filenames: Vec<String> = list_data_files(&config::collection_path(collection))?
+        .into_iter().map(|entry| entry.name).collect();
+    let removed = Cell::new(0);
+    let rewritten = rewrite_files(collection, &filenames, &header, |row, is_header| {
+        let expired = !is_header && schema::parse_date(&row[index]).is_some_and(|date| date < cutoff);
+        if expired {
+            removed.set(removed.get() + 1);
+        }
+        !expired
+    })?;
+    catalog::update(collection, &rewritten)?;
+
+    Ok((removed.get(), rewritten))
+}
+
+
+/// Takes a point-in-time snapshot of the files in `collection`.
+/// 
+/// Files are hard linked into a new directory under `config::snapshots_path(collection)`,
+/// or copied if they cannot be linked (for example, across devices).
+/// Changes to the collection wait until the snapshot is complete.
+/// 
+/// Returns the snapshot id and the number of files in the snapshot.
+pub fn snapshot(
+    collection: &str,
+) -> Result<(String, usize), ZenithError> {
+
+    validate_name("collection", collection)?;
+
+    let lock = collection_lock(collection);
+    let _guard = lock.lock().unwrap_or_else(|e| e.into_inner());
+
+    let collection_path = config::collection_path(collection);
+    let entries = list_data_files(&collection_path)?;
+
+    let snapshots_path = config::snapshots_path(collection);
+    let id = unique_id(&snapshots_path);
+    let snapshot_path = snapshots_path.join(&id);
+    storage().create_dir_all(&snapshot_path)?;
+
+    for entry in &entries {
+        storage().link(&entry.path, &snapshot_path.join(&entry.name))?;
+    }
+
+    Ok((id, entries.len()))
+}
+
+
+/// Returns the directory of the snapshot of `collection` with `snapshot_id`,
+/// raising a `NotFound` error if there is no such snapshot.
+fn existing_snapshot_path(
+    collection: &str,
+    snapshot_id: &str,
+) -> Result<PathBuf, ZenithError> {
+
+    if snapshot_id.is_empty() {
+        return Err(ZenithError::QueryError("The snapshot id is empty".to_string()));
+    }
+    let snapshot_path = config::snapshots_path(collection).join(snapshot_id);
+    if !snapshot_id.chars().all(|c| c.is_ascii_digit()) || !storage().is_dir(&snapshot_path) {
+        return Err(ZenithError::NotFound(Missing::Other(format!(
+            "Snapshot '{}' of collection '{}' does not exist", snapshot_id, collection
+        ))));
+    }
+    Ok(snapshot_path)
+}
+
+
+/// Returns the id of the snapshot of `collection` that `as_of` refers to: the latest snapshot
+/// taken at or before it, given as milliseconds since the Unix epoch or an RFC 3339 timestamp.
+/// As snapshot ids are the times they were taken, giving a snapshot id refers to that snapshot.
+/// Raises a `NotFound` error if no snapshot was taken by then.
+pub fn snapshot_as_of(
+    collection: &str,
+    as_of: &str,
+) -> Result<String, ZenithError> {
+
+    validate_name("collection", collection)?;
+    let time: u128 = match as_of.parse() {
+        Ok(millis) => millis,
+        Err(_) => chrono::DateTime::parse_from_rfc3339(as_of)
+            .ok()
+            .and_then(|datetime| u128::try_from(datetime.timestamp_millis()).ok())
+            .ok_or_else(|| ZenithError::QueryError(format!(
+                "'{}' is not a snapshot id, or a timestamp in milliseconds or RFC 3339", as_of
+            )))?,
+    };
+    let snapshots_path = config::snapshots_path(collection);
+    let snapshots = match storage().is_dir(&snapshots_path) {
+        true => storage().list(&snapshots_path)?,
+        false => Vec::new(),
+    };
+    snapshots.into_iter()
+        .filter(|entry| !entry.is_file)
+        .filter_map(|entry| entry.name.parse::<u128>().ok())
+        .filter(|id| *id <= time)
+        .max()
+        .map(|id| id.to_string())
+        .ok_or_else(|| ZenithError::NotFound(Missing::Other(format!(
+            "No snapshot of collection '{}' was taken by '{}'", collection, as_of
+        ))))
+}
+
+
+/// Restores `collection` to the state captured in the snapshot with `snapshot_id`.
+/// 
+/// The collection is created if it does not exist. Files in the snapshot replace
+/// the current files of the same name, and files not in the snapshot are deleted.
+/// 
+/// Returns the number of files restored.
+pub fn restore(
+    collection: &str,
+    snapshot_id: &str,
+) -> Result<usize, ZenithError> {
+
+    validate_name("collection", collection)?;
+    let snapshot_path = existing_snapshot_path(collection, snapshot_id)?;
+
+    let lock = collection_lock(collection);
+    let _guard = lock.lock().unwrap_or_else(|e| e.into_inner());
+
+    let collection_path = config::collection_path(collection);
+    storage().create_dir_all(&collection_path)?;
+
+    let entries = list_data_files(&snapshot_path)?;
+
+    // Link (or copy) each file to a temporary file first, and then move it
+    // into place, so the snapshot is never modified through the collection.
+    let mut restored = HashSet::new();
+    for entry in &entries {
+        let temp_path = collection_path.join(format!(".{}.tmp", entry.name));
+        let _ = storage().remove(&temp_path);
+        storage().link(&entry.path, &temp_path)?;
+        storage().rename(&temp_path, &collection_path.join(&entry.name))?;
+        restored.insert(entry.name.clone());
+    }
+
+    // Remove any files created after the snapshot was taken.
+    let mut changed: Vec<String> = restored.iter().cloned().collect();
+    for entry in list_data_files(&collection_path)? {
+        if !restored.contains(&entry.name) {
+            storage().remove(&entry.path)?;
+            changed.push(entry.name);
+        }
+    }
+    catalog::update(collection, &changed)?;
+
+    Ok(entries.len())
+}
+
+
+/// Lists the previous versions of `filename` in `collection`,
+/// returning the id and size of each, from oldest to newest.
+pub fn versions(
+    collection: &str,
+    filename: &str,
+) -> Result<Vec<(String, u64)>, ZenithError> {
+
+    validate_name("collection", collection)?;
+    validate_name("file", filename)?;
+    let versions_path = config::versions_path(collection).join(filename);
+    if !storage().is_dir(&versions_path) {
+        return Ok(Vec::new());
+    }
+
+    let mut versions: Vec<(String, u64)> = storage().list(&versions_path)?
+        .into_iter()
+        .map(|entry| (entry.name, entry.size))
+        .collect();
+    versions.sort_by_key(|(id, _)| id.parse::<u128>().unwrap_or(0));
+
+    Ok(versions)
+}
+
+
+/// Lists the files in `collection`, returning the name and size of each.
+pub fn files(
+    collection: &str,
+) -> Result<Vec<(String, u64)>, ZenithError> {
+
+    validate_name("collection", collection)?;
+    let mut files: Vec<(String, u64)> = list_collection_files(collection, &Vec::new())?
+        .into_iter()
+        .map(|fm| (fm.filename, fm.size))
+        .collect();
+    files.sort();
+    Ok(files)
+}
+
+
+/// Reads `filename` in `collection`, rendering it as CSV data. See `render`.
+#[allow(clippy::type_complexity)]
+pub fn read(
+    collection: &str,
+    filename: &str,
+) -> Result<(Vec<String>, Vec<Vec<String>>, Vec<Vec<String>>), ZenithError> {
+
+    validate_name("collection", collection)?;
+    validate_name("file", filename)?;
+    let path = existing_file_path(collection, filename)?;
+    let mut bytes = Vec::new();
+    open_file(&path)?.read_to_end(&mut bytes)?;
+    render(&bytes, &catalog::dialect(collection)?)
+}
+
+
+/// Reads the version of `filename` in `collection` with `version_id`,
+/// rendering it as CSV data. See `render`.
+#[allow(clippy::type_complexity)]
+pub fn read_version(
+    collection: &str,
+    filename: &str,
+    version_id: &str,
+) -> Result<(Vec<String>, Vec<Vec<String>>, Vec<Vec<String>>), ZenithError> {
+
+    let path = version_path(collection, filename, version_id)?;
+    let mut bytes = Vec::new();
+    open_file(&path)?.read_to_end(&mut bytes)?;
+    render(&bytes, &catalog::dialect(collection)?)
+}
+
+
+/// Rolls `filename` in `collection` back to the version with `version_id`.
+/// 
+/// The current file is kept as a new version, so a rollback can be undone.
+pub fn rollback(
+    collection: &str,
+    filename: &str,
+    version_id: &str,
+) -> Result<(), ZenithError> {
+
+    let path = version_path(collection, filename, version_id)?;
+
+    let lock = collection_lock(collection);
+    let _guard = lock.lock().unwrap_or_else(|e| e.into_inner());
+
+    let collection_path = config::collection_path(collection);
+    let temp_path = collection_path.join(format!(".{}.tmp", filename));
+    let _ = storage().remove(&temp_path);
+    storage().link(&path, &temp_path)?;
+    keep_version(collection, filename)?;
+    storage().rename(&temp_path, &collection_path.join(filename))?;
+    catalog::update(collection, &[filename.to_string()])?;
+
+    Ok(())
+}
+
+
+/// Returns the number of rows in `filename` in `collection`, as recorded
+/// in the catalog, or `None` if the file is not in the catalog.
+pub fn file_rows(
+    collection: &str,
+    filename: &str,
+) -> Result<Option<usize>, ZenithError> {
+
+    validate_name("collection", collection)?;
+    validate_name("file", filename)?;
+    Ok(catalog::read(collection)?.files.get(filename).map(|f| f.rows))
+}
+
+
+/// Returns the schema of `collection`, if it has one.
+pub fn schema(
+    collection: &str,
+) -> Result<Option<schema::Schema>, ZenithError> {
+
+    validate_name("collection", collection)?;
+    schema::read(collection)
+}
+
+
+/// Sets the `schema` of `collection`, replacing any previous schema.
+/// 
+/// If the collection has files, the columns of the schema must match their header.
+pub fn set_schema(
+    collection: &str,
+    schema: &schema::Schema,
+) -> Result<(), ZenithError> {
+
+    validate_name("collection", collection)?;
+    schema.validate()?;
+
+    let lock = collection_lock(collection);
+    let _guard = lock.lock().unwrap_or_else(|e| e.into_inner());
+
+    let catalog = catalog::read(collection)?;
+    if !catalog.header.is_empty() && catalog.header != schema.header() {
+        return Err(ZenithError::QueryError(format!(
+            "Schema columns {:?} do not match header {:?} in collection '{}'",
+            schema.header(), catalog.header, collection
+        )));
+    }
+    schema::write(collection, schema)
+}
+
+
+/// Infers a schema for `collection` from the values in all of its files. See `schema::infer`.
+pub fn infer_schema(
+    collection: &str,
+) -> Result<schema::Schema, ZenithError> {
+
+    let (header, rows) = select(collection, QueryPredicates { fields: Vec::new(), predicates: Vec::new() }, None, &Cancelled::default())?;
+    if header.is_empty() {
+        return Err(ZenithError::QueryError(format!("No header found in collection '{}'", collection)));
+    }
+    Ok(schema::infer(&header, &rows))
+}
+
+
+/// Removes the schema of `collection`, if it has one.
+pub fn remove_schema(
+    collection: &str,
+) -> Result<(), ZenithError> {
+
+    validate_name("collection", collection)?;
+
+    let lock = collection_lock(collection);
+    let _guard = lock.lock().unwrap_or_else(|e| e.into_inner());
+
+    schema::remove(collection)
+}
+
+
+/// Adds `column` to the end of the header of `collection`, filling it in with
+/// its default in every row of the files that have the header.
+/// 
+/// The column is also added to the schema of the collection, if it has one.
+/// Returns the names of the files rewritten.
+pub fn add_column(
+    collection: &str,
+    column: &schema::Column,
+) -> Result<Vec<String>, ZenithError> {
+
+    validate_name("collection", collection)?;
+    if column.name.is_empty() {
+        return Err(ZenithError::QueryError("The column name is empty".to_string()));
+    }
+    column.validate()?;
+    let default = column.default.as_deref().unwrap_or("");
+    if column.required && default.is_empty() {
+        return Err(ZenithError::QueryError(format!("Required column '{}' needs a default", column.name)));
+    }
+
+    let lock = collection_lock(collection);
+    let _guard = lock.lock().unwrap_or_else(|e| e.into_inner());
+
+    let mut schema = schema::read(collection)?;
+    let header = match &schema {
+        Some(schema) => schema.header(),
+        None => catalog::read(collection)?.header,
+    };
+    if header.is_empty() {
+        return Err(ZenithError::QueryError(format!(
+            "Collection '{}' has no header to add a column to", collection
+        )));
+    }
+    if header.contains(&column.name) {
+        return Err(ZenithError::Conflict(format!(
+            "Column '{}' already exists in collection '{}'", column.name, collection
+        )));
+    }
+
+    let filenames: Vec<String> = list_data_files(&config::collection_path(collection))?
+        .into_iter().map(|entry| entry.name).collect();
+    let rewritten = rewrite_files(collection, &filenames, &header, |row, is_header| {
+        row.push(if is_header { column.name.clone() } else { default.to_string() });
+        true
+    })?;
+
+    change_collection_header(collection, &rewritten, [header, vec![column.name.clone()]].concat())?;
+    if let Some(schema) = &mut schema {
+        schema.columns.push(column.clone());
+        schema::write(collection, schema)?;
+    }
+
+    Ok(rewritten)
+}
+
+
+/// Renames the column `from` to `to` in `collection`, rewriting the header of every
+/// file that has the header of the collection. The column is also renamed in the
+/// schema of the collection, if it has one. Returns the names of the files rewritten.
+pub fn rename_column(
+    collection: &str,
+    from: &str,
+    to: &str,
+) -> Result<Vec<String>, ZenithError> {
+
+    validate_name("collection", collection)?;
+    if from.is_empty() || to.is_empty() {
+        return Err(ZenithError::QueryError("The column name is empty".to_string()));
+    }
+
+    let lock = collection_lock(collection);
+    let _guard = lock.lock().unwrap_or_else(|e| e.into_inner());
+
+    let mut schema = schema::read(collection)?;
+    let header = match &schema {
+        Some(schema) => schema.header(),
+        None => catalog::read(collection)?.header,
+    };
+    let Some(index) = header.iter().position(|name| name == from) else {
+        return Err(ZenithError::NotFound(Missing::Other(format!(
+            "Column '{}' does not exist in collection '{}'", from, collection
+        ))));
+    };
+    if header.iter().any(|name| name == to) {
+        return Err(ZenithError::Conflict(format!(
+            "Column '{}' already exists in collection '{}'", to, collection
+        )));
+    }
+
+    let filenames: Vec<String> = list_data_files(&config::collection_path(collection))?
+        .into_iter().map(|entry| entry.name).collect();
+    let rewritten = rewrite_files(collection, &filenames, &header, |row, is_header| {
+        if is_header {
+            row[index] = to.to_string();
+        }
+        true
+    })?;
+
+    let mut renamed = header;
+    renamed[index] = to.to_string();
+    change_collection_header(collection, &rewritten, renamed)?;
+    if let Some(schema) = &mut schema {
+        schema.columns[index].name = to.to_string();
+        for name in schema.key.iter_mut().filter(|name| *name == from) {
+            *name = to.to_string();
+        }
+        schema::write(collection, schema)?;
+    }
+
+    Ok(rewritten)
+}
+
+
+/// Removes `column` from `collection`, rewriting every file that has the header of
+/// the collection without it. The column is also removed from the schema of the
+/// collection, if it has one. Returns the names of the files rewritten.
+pub fn drop_column(
+    collection: &str,
+    column: &str,
+) -> Result<Vec<String>, ZenithError> {
+
+    validate_name("collection", collection)?;
+    if column.is_empty() {
+        return Err(ZenithError::QueryError("The column name is empty".to_string()));
+    }
+
+    let lock = collection_lock(collection);
+    let _guard = lock.lock().unwrap_or_else(|e| e.into_inner());
+
+    let mut schema = schema::read(collection)?;
+    let header = match &schema {
+        Some(schema) => schema.header(),
+        None => catalog::read(collection)?.header,
+    };
+    let Some(index) = header.iter().position(|name| name == column) else {
+        return Err(ZenithError::NotFound(Missing::Other(format!(
+            "Column '{}' does not exist in collection '{}'", column, collection
+        ))));
+    };
+    if header.len() == 1 {
+        return Err(ZenithError::QueryError(format!(
+            "Column '{}' is the only column in collection '{}'", column, collection
+        )));
+    }
+    if schema.as_ref().is_some_and(|schema| schema.key.iter().any(|name| name == column)) {
+        return Err(ZenithError::QueryError(format!(
+            "Column '{}' is part of the key of collection '{}'", column, collection
+        )));
+    }
+
+    let filenames: Vec<String> = list_data_files(&config::collection_path(collection))?
+        .into_iter().map(|entry| entry.name).collect();
+    let rewritten = rewrite_files(collection, &filenames, &header, |row, _| {
+        row.remove(index);
+        true
+    })?;
+
+    let mut remaining = header;
+    remaining.remove(index);
+    change_collection_header(collection, &rewritten, remaining)?;
+    if let Some(schema) = &mut schema {
+        schema.columns.remove(index);
+        schema::write(collection, schema)?;
+    }
+
+    Ok(rewritten)
+}
+
+
+/// Removes the rows of `collection` that are duplicates of other rows, in all of its files with
+/// its header, comparing only the values of `columns`, or of every column if none are given.
+/// The first of each set of duplicates is kept, in the order of the names of the files and
+/// the order of the rows in them, or the last if `keep` is `Last`.
+/// 
+/// Returns the number of rows removed, and the names of the files rewritten.
+pub fn dedupe(
+    collection: &str,
+    columns: &[String],
+    keep: Keep,
+) -> Result<(usize, Vec<String>), ZenithError> {
+
+    validate_name("collection", collection)?;
+    existing_collection_path(collection)?;
+
+    let lock = collection_lock(collection);
+    let _guard = lock.lock().unwrap_or_else(|e| e.into_inner());
+
+    let header = match schema::read(collection)? {
+        Some(schema) => schema.header(),
+        None => catalog::read(collection)?.header,
+    };
+    let mut indices = Vec::new();
+    for column in columns {
+        match header.iter().position(|name| name == column) {
+            Some(index) => indices.push(index),
+            None => return Err(ZenithError::NotFound(Missing::Other(format!(
+                "Column '{}' does not exist in collection '{}'", column, collection
+            )))),
+        }
+    }
+    let key_of = |row: &[String]| -> Vec<String> {
+        match indices.is_empty() {
+            true => row.to_vec(),
+            false => indices.iter().map(|&i| row[i].clone()).collect(),
+        }
+    };
+
+    let mut filenames: Vec<String> = list_data_files(&config::collection_path(collection))?
+        .into_iter().map(|entry| entry.name).collect();
+    filenames.sort();
+    // How many times each key is yet to be seen, when the last is kept. They are counted
+    // with rows as `rewrite_files` sees them, without changing any, so that no file is written.
+    let remaining: RefCell<HashMap<Vec<String>, usize>> = RefCell::new(HashMap::new());
+    if keep == Keep::Last {
+        rewrite_files(collection, &filenames, &header, |row, is_header| {
+            if !is_header {
+                *remaining.borrow_mut().entry(key_of(row)).or_default() += 1;
+            }
+            true
+        })?;
+    }
+    let seen: RefCell<HashSet<Vec<String>>> = RefCell::new(HashSet::new());
+    let removed = Cell::new(0);
+    let rewritten = rewrite_files(collection, &filenames, &header, |row, is_header| {
+        if is_header {
+            return true;
+        }
+        let kept = match keep {
+            Keep::First => seen.borrow_mut().insert(key_of(row)),
+            Keep::Last => {
+                let mut remaining = remaining.borrow_mut();
+                let count = remaining.entry(key_of(row)).or_default();
+                *count = count.saturating_sub(1);
+                *count == 0
+            },
+        };
+        if !kept {
+            removed.set(removed.get() + 1);
+        }
+        kept
+    })?;
+    catalog::update(collection, &rewritten)?;
+
+    Ok((removed.get(), rewritten))
+}
+
+
+/// Rebuilds the catalog of `collection` from the files in it, replacing the current catalog.
+/// See `catalog::build`.
+/// 
+/// Returns the new catalog, the names of the files that were `added`, `removed`,
+/// or `changed` compared to the previous catalog, and the names of the files
+/// that do not have the canonical header.
+#[allow(clippy::type_complexity)]
+pub fn rebuild_catalog(
+    collection: &str,
+) -> Result<(catalog::Catalog, Vec<String>, Vec<String>, Vec<String>, Vec<String>), ZenithError> {
+
+    validate_name("collection", collection)?;
+
+    let lock = collection_lock(collection);
+    let _guard = lock.lock().unwrap_or_else(|e| e.into_inner());
+
+    let previous = catalog::read(collection)?;
+    let (rebuilt, mismatched) = catalog::build(collection)?;
+
+    let added = rebuilt.files.keys()
+        .filter(|f| !previous.files.contains_key(*f)).cloned().collect();
+    let removed = previous.files.keys()
+        .filter(|f| !rebuilt.files.contains_key(*f)).cloned().collect();
+    let changed = rebuilt.files.iter()
+        .filter(|(f, file)| previous.files.get(*f).is_some_and(|p| p.sha256 != file.sha256 || p.rows != file.rows))
+        .map(|(f, _)| f.clone()).collect();
+
+    catalog::write(collection, &rebuilt)?;
+    Ok((rebuilt, added, removed, changed, mismatched))
+}
+
+
+/// Verifies the files in `collection` against the checksums in its catalog.
+/// 
+/// Returns the status of each file. Files that were added or changed outside
+/// the data service are `Untracked` or `Modified`, and files that were removed
+/// outside the data service are `Missing`.
+pub fn verify(
+    collection: &str,
+) -> Result<Vec<(String, IntegrityStatus)>, ZenithError> {
+
+    validate_name("collection", collection)?;
+
+    let lock = collection_lock(collection);
+    let _guard = lock.lock().unwrap_or_else(|e| e.into_inner());
+
+    let mut checksums: HashMap<String, String> = catalog::read(collection)?.files
+        .into_iter().map(|(filename, file)| (filename, file.sha256)).collect();
+    let mut statuses = Vec::new();
+    for entry in list_data_files(&config::collection_path(collection))? {
+        let status = match checksums.remove(&entry.name) {
+            Some(expected) if expected == catalog::checksum(&storage().read(&entry.path)?) => IntegrityStatus::Ok,
+            Some(_) => IntegrityStatus::Modified,
+            None => IntegrityStatus::Untracked,
+        };
+        statuses.push((entry.name, status));
+    }
+    for (filename, _) in checksums {
+        statuses.push((filename, IntegrityStatus::Missing));
+    }
+    statuses.sort_by(|a, b| a.0.cmp(&b.0));
+
+    Ok(statuses)
+}
+
+
+/// What `register` did with a file that was changed in a collection by another process.
+pub enum Registration {
+    /// The file is as the catalog records it, such as when the data service wrote it itself.
+    Unchanged,
+    /// The file was recorded in the catalog with its `rows`, replacing a file with `previous_rows`,
+    /// if there was one. If it is not valid in the collection, and was not rejected, it has a `problem`.
+    Recorded { previous_rows: Option<usize>, rows: usize, problem: Option<String> },
+    /// The file was removed, and was forgotten by the catalog.
+    Forgotten { previous_rows: usize },
+    /// The file was not valid in the collection for the `problem`, and was moved into `.rejected`.
+    Rejected { previous_rows: Option<usize>, problem: String },
+}
+
+
+/// Writes the catalog of `collection` as it is built from its files now, if it does not have
+/// one yet, so that `register` can tell the files changed after it from those already there.
+pub fn ensure_catalog(collection: &str) -> Result<(), ZenithError> {
+    validate_name("collection", collection)?;
+    let lock = collection_lock(collection);
+    let _guard = lock.lock().unwrap_or_else(|e| e.into_inner());
+    if !storage().is_file(&config::collection_path(collection).join(catalog::CATALOG_FILENAME)) {
+        catalog::update(collection, &[])?;
+    }
+    Ok(())
+}
+
+
+/// Checks that the `bytes` of a file are valid in `collection`: that they have a header, the
+/// header of the collection, if it has one, rows as long as the header, and values of the types
+/// of the schema of the collection, if it has one.
+fn check_file(
+    collection: &str,
+    bytes: &[u8],
+    dialect: &Dialect,
+) -> Result<(), ZenithError> {
+
+    let (header, rows, removed) = render(bytes, dialect)?;
+    if header.is_empty() {
+        return Err(ZenithError::QueryError("Header cannot be found".to_string()));
+    }
+    satisfies_collection_header(collection, &header)?;
+    if !removed.is_empty() {
+        return Err(ZenithError::QueryError(format!("{} rows do not match header length {}", removed.len(), header.len())));
+    }
+    if let Some(schema) = schema::read(collection)? {
+        let errors = schema.check_rows(&rows, 0);
+        if !errors.is_empty() {
+            return Err(ZenithError::QueryError(schema::describe_errors(&errors)));
+        }
     }
-    let delete_path = Path::new(config::DATA_PATH).join(collection).join(filename);
-    std::fs::remove_file(delete_path)?;
     Ok(())
 }
 
 
-/// Renders `bytes` as CSV data, returning the `header`, `rows`, and any `removed` records.
+/// Records `filename` in the catalog of `collection` after it was added, changed, or removed by
+/// another process, checking that it is valid in the collection. If it is not and `reject` is
+/// set, it is moved into `.rejected` in the collection, replacing any file rejected before
+/// with the same name, instead of being recorded.
+pub fn register(
+    collection: &str,
+    filename: &str,
+    reject: bool,
+) -> Result<Registration, ZenithError> {
+
+    validate_name("collection", collection)?;
+    validate_name("file", filename)?;
+    let lock = collection_lock(collection);
+    let _guard = lock.lock().unwrap_or_else(|e| e.into_inner());
+
+    let collection_path = config::collection_path(collection);
+    let catalogued = storage().is_file(&collection_path.join(catalog::CATALOG_FILENAME));
+    let collection_catalog = match catalogued {
+        true => catalog::read(collection)?,
+        false => {
+            // Each of the files in a new collection is recorded as it is checked.
+            let empty = catalog::Catalog::default();
+            catalog::write(collection, &empty)?;
+            empty
+        },
+    };
+    let recorded = collection_catalog.files.get(filename);
+    let previous_rows = recorded.map(|f| f.rows);
+    let path = collection_path.join(filename);
+    if !storage().is_file(&path) {
+        return Ok(match previous_rows {
+            Some(previous_rows) => {
+                catalog::update(collection, &[filename.to_string()])?;
+                Registration::Forgotten { previous_rows }
+            },
+            None => Registration::Unchanged,
+        });
+    }
+
+    let bytes = storage().read(&path)?;
+    let sha256 = catalog::checksum(&bytes);
+    if recorded.is_some_and(|f| f.sha256 == sha256) {
+        return Ok(Registration::Unchanged);
+    }
+    let problem = check_file(collection, &crypto::decrypt(bytes)?, &collection_catalog.dialect).err().map(|e| e.to_string());
+    match problem {
+        Some(problem) if reject => {
+            let rejected_path = collection_path.join(".rejected");
+            storage().create_dir_all(&rejected_path)?;
+            storage().rename(&path, &rejected_path.join(filename))?;
+            if previous_rows.is_some() {
+                catalog::update(collection, &[filename.to_string()])?;
+            }
+            Ok(Registration::Rejected { previous_rows, problem })
+        },
+        problem => {
+            let updated = catalog::update(collection, &[filename.to_string()])?;
+            let rows = updated.files.get(filename).map(|f| f.rows).unwrap_or_default();
+            Ok(Registration::Recorded { previous_rows, rows, problem })
+        },
+    }
+}
+
+
+/// The delimiters that `sniff_delimiter` chooses between, in the order they are preferred.
+const SNIFFED_DELIMITERS: [u8; 4] = [b',', b'\t', b';', b'|'];
+
+/// How many records `sniff_delimiter` reads with each delimiter.
+const SNIFFED_RECORDS: usize = 20;
+
+
+/// Returns the delimiter that splits the first records of `bytes`, quoted by `quote`, most
+/// consistently: into the same number of fields, more than one, in the most records. A comma
+/// is returned if no delimiter splits them.
+pub fn sniff_delimiter(bytes: &[u8], quote: CsvCharacter) -> CsvCharacter {
+    let mut best = (b',', 0, 0);
+    for delimiter in SNIFFED_DELIMITERS {
+        let mut reader = Dialect { delimiter: CsvCharacter(delimiter), quote }.reader()
+            .has_headers(false)
+            .flexible(true)
+            .from_reader(bytes);
+        // How many records have each number of fields.
+        let mut lengths: HashMap<usize, usize> = HashMap::new();
+        for record in reader.byte_records().take(SNIFFED_RECORDS) {
+            match record {
+                Ok(record) if record.len() > 1 => *lengths.entry(record.len()).or_default() += 1,
+                Ok(_) => (),
+                Err(_) => break,
+            }
+        }
+        if let Some((fields, records)) = lengths.into_iter().max_by_key(|&(fields, records)| (records, fields)) {
+            if (records, fields) > (best.1, best.2) {
+                best = (delimiter, records, fields);
+            }
+        }
+    }
+    CsvCharacter(best.0)
+}
+
+
+/// Renders `bytes` as CSV data in `dialect`, returning the `header`, `rows`, and any `removed` records.
+#[allow(clippy::type_complexity)]
 pub fn render(
-    bytes: &[u8]
+    bytes: &[u8],
+    dialect: &Dialect,
 ) -> Result<(Vec<String>, Vec<Vec<String>>, Vec<Vec<String>>), ZenithError> {
 
-    let mut reader = csv::ReaderBuilder::new()
+    render_with(bytes, dialect, &RenderOptions::default())
+}
+
+
+/// Returns `bytes` after the first `lines` lines.
+pub fn skip_lines(bytes: &[u8], lines: usize) -> &[u8] {
+    if lines == 0 {
+        return bytes;
+    }
+    let start = bytes.iter()
+        .enumerate()
+        .filter(|(_, b)| **b == b'\n')
+        .nth(lines - 1)
+        .map_or(bytes.len(), |(i, _)| i + 1);
+    &bytes[start..]
+}
+
+
+/// Renders `bytes` as CSV data in `dialect`, as `render` does, after skipping the first
+/// `options.skip_rows` lines. The header is the record at `options.header_row`, if it is given,
+/// with the records before it removed, and otherwise the first record with a value in every field.
+/// No more than `options.max_rows` rows are returned, and records after them are not read.
+#[allow(clippy::type_complexity)]
+pub fn render_with(
+    bytes: &[u8],
+    dialect: &Dialect,
+    options: &RenderOptions,
+) -> Result<(Vec<String>, Vec<Vec<String>>, Vec<Vec<String>>), ZenithError> {
+
+    // Lines are skipped before they are read, as a preamble may not have as many fields as the header.
+    let mut reader = dialect.reader()
         .has_headers(false)
-        .from_reader(bytes);
+        .flexible(options.header_row.is_some())
+        .from_reader(skip_lines(bytes, options.skip_rows));
 
     let mut records: Vec<Vec<String>> = Vec::new();
     let mut header: Vec<String> = Vec::new();
     let mut removed: Vec<Vec<String>> = Vec::new();
 
-    for result in reader.records() {
+    for (i, result) in reader.records().enumerate() {
+        if options.max_rows.is_some_and(|max_rows| records.len() >= max_rows) {
+            break;
+        }
         let record: Vec<String> = result?
             .into_iter()
             .map(|v| String::from_utf8(Vec::from(v)).unwrap_or_else(|_| String::from("")))
@@ -373,8 +2471,11 @@ pub fn render(
         if !header.is_empty() && header.len() == record.len() {
             records.push(record);
         }
-        // Set the header automatically on the first record with complete fields.
-        else if header.is_empty() && record.iter().all(|v: &String| v.len() > 0) {
+        // Set the header on the record given, or automatically on the first record with complete fields.
+        else if header.is_empty() && match options.header_row {
+            Some(header_row) => i == header_row,
+            None => record.iter().all(|v: &String| !v.is_empty()),
+        } {
             header = record;
         }
         else {
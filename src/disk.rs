@@ -0,0 +1,39 @@
+use std::path::Path;
+use tracing::warn;
+
+use crate::types::error::ZenithError;
+use crate::config;
+
+
+/// Returns an `InsufficientStorage` error if writing `bytes` to the file at `path` would fill the
+/// volume it is on past `ZENITHDS_DISK_HIGH_WATERMARK`, as a percentage of its space, so that
+/// writes are rejected before the volume is full rather than failing part of the way through.
+/// Nothing is checked if the watermark is `0`, files are stored in memory, or the space on the
+/// volume cannot be found.
+pub fn ensure_space(
+    path: &Path,
+    bytes: usize,
+) -> Result<(), ZenithError> {
+
+    let watermark = config::envar_usize("ZENITHDS_DISK_HIGH_WATERMARK");
+    if watermark == 0 || config::envar_str("ZENITHDS_STORAGE") == "memory" {
+        return Ok(());
+    }
+    let Some(stats) = path.parent().and_then(|dir| fs4::statvfs(dir).ok()) else {
+        return Ok(());
+    };
+    // As with `df`, space reserved for the superuser is not counted.
+    let used = stats.total_space().saturating_sub(stats.free_space());
+    let capacity = used + stats.available_space();
+    if capacity == 0 {
+        return Ok(());
+    }
+    let percent = (used + bytes as u64) as f64 / capacity as f64 * 100.0;
+    if percent > watermark.min(100) as f64 {
+        warn!("Rejected a write of {} bytes to '{}', which would fill the volume to {:.1}%", bytes, path.display(), percent);
+        return Err(ZenithError::InsufficientStorage(format!(
+            "The data volume would be {:.1}% full, over the high watermark of {}%", percent, watermark
+        )));
+    }
+    Ok(())
+}
@@ -0,0 +1,184 @@
+use std::{convert::Infallible, time::Instant};
+
+use axum::response::{sse::{Event, KeepAlive, Sse}, IntoResponse, Response};
+use futures_util::StreamExt;
+use serde_json::json;
+use tokio::sync::mpsc;
+use tracing::{info, warn};
+
+use crate::auth::Principal;
+use crate::schema::ColumnType;
+use crate::types::{api::QueryPredicates, error::{Missing, ZenithError}};
+use crate::{config, db, limit, queries, remote, request_id};
+
+/// The most events kept for a client that has not been sent them yet. The scan
+/// waits for the client once this many are waiting, rather than holding every row.
+const CAPACITY: usize = 16;
+
+
+/// The header and rows of a query, as they are turned into events.
+struct Events {
+    collection: String,
+    typed: bool,
+    /// The header sent, once it has been.
+    header: Option<Vec<String>>,
+    /// The types of the values in each column, chosen when the header is sent.
+    types: Vec<ColumnType>,
+    rows: usize,
+}
+
+impl Events {
+    /// Returns the events for `rows` with `header`, following a `header` event if it has not been
+    /// sent yet, with `rows` events of up to `STREAM_CHUNK_ROWS` rows each. Rows with a different
+    /// header, such as from a federation node, have their values put in the columns of the header sent,
+    /// leaving out the columns it does not have.
+    fn events(&mut self, header: Vec<String>, rows: Vec<Vec<String>>) -> Result<Vec<Event>, ZenithError> {
+        let mut events = Vec::new();
+        let sent = match &self.header {
+            Some(sent) => sent,
+            None => {
+                self.types = crate::column_types(&self.collection, &header, &rows, self.typed)?;
+                events.push(Event::default().event("header").data(json!(header).to_string()));
+                &*self.header.insert(header.clone())
+            },
+        };
+        let rows = match *sent == header {
+            true => rows,
+            false => {
+                let positions: Vec<Option<usize>> = sent.iter().map(|name| header.iter().position(|h| h == name)).collect();
+                rows.into_iter()
+                    .map(|row| positions.iter().map(|p| p.and_then(|i| row.get(i).cloned()).unwrap_or_default()).collect())
+                    .collect()
+            },
+        };
+        self.rows += rows.len();
+        for chunk in rows.chunks(crate::STREAM_CHUNK_ROWS) {
+            let data = json!(db::to_json(&self.types, chunk.to_vec())).to_string();
+            events.push(Event::default().event("rows").data(data));
+        }
+        Ok(events)
+    }
+}
+
+
+/// Runs a query on `collection` with `predicates` for `principal`, sending the header and rows
+/// as server-sent events as each file is read, so that the first rows arrive before the whole
+/// collection has been scanned. Values are typed as with JSON if `typed` is set.
+///
+/// The first event is a `header` with the names of the fields. Each `rows` event has up to
+/// `STREAM_CHUNK_ROWS` rows, and the last event is `end`, with the number of `rows` sent, or
+/// `error`, with a `message` and `code`, if the query could not finish. Errors before the first
+/// event are returned as errors instead. The query holds its `slot`, and can be cancelled by its
+/// `running` id, until the last event is sent or the client goes away.
+///
+/// If federation nodes are configured, the rows of each node are sent after the local rows.
+pub async fn query(
+    collection: String,
+    predicates: QueryPredicates,
+    principal: Principal,
+    typed: bool,
+    slot: limit::SlotGuard,
+    running: queries::Running,
+) -> Result<Response, ZenithError> {
+
+    let (sender, mut receiver) = mpsc::channel::<Result<Event, ZenithError>>(CAPACITY);
+    tokio::spawn(request_id::inherit(async move {
+        let _slot = slot;
+        let started = Instant::now();
+        let result = run(&collection, predicates, principal, typed, &running, &sender).await;
+        let event = match result {
+            Ok(rows) => {
+                info!("Sent {} rows from collection '{}' as events in {:.2?}", rows, collection, started.elapsed());
+                Ok(Event::default().event("end").data(json!({ "rows": rows }).to_string()))
+            },
+            Err(err) => {
+                warn!("The query on collection '{}' sent as events was unsuccessful: {}", collection, err);
+                Err(err)
+            },
+        };
+        let _ = sender.send(event).await;
+    }));
+
+    // Errors found before anything is sent get a response of their own.
+    let first = match receiver.recv().await {
+        Some(Ok(event)) => event,
+        Some(Err(err)) => return Err(err),
+        None => return Err(ZenithError::Cancelled("The query was cancelled".to_string())),
+    };
+    let rest = futures_util::stream::unfold(receiver, |mut receiver| async move {
+        receiver.recv().await.map(|event| (event, receiver))
+    });
+    let events = futures_util::stream::once(async move { Ok(first) })
+        .chain(rest)
+        .map(|event| Ok::<Event, Infallible>(event.unwrap_or_else(|err| {
+            Event::default().event("error").data(json!({ "message": err.to_string(), "code": err.code() }).to_string())
+        })));
+    Ok(Sse::new(events).keep_alive(KeepAlive::default()).into_response())
+}
+
+
+/// Scans `collection` and then any federation nodes, sending the events for the rows matched
+/// with `sender` as they are found. Returns the number of rows sent.
+async fn run(
+    collection: &str,
+    predicates: QueryPredicates,
+    principal: Principal,
+    typed: bool,
+    running: &queries::Running,
+    sender: &mpsc::Sender<Result<Event, ZenithError>>,
+) -> Result<usize, ZenithError> {
+
+    let nodes = config::federation_nodes();
+    let mut events = Events { collection: collection.to_string(), typed, header: None, types: Vec::new(), rows: 0 };
+    let (mut events, scanned) = {
+        let (collection, predicates, cancelled, sender) = (collection.to_string(), predicates.clone(), running.cancelled().clone(), sender.clone());
+        request_id::spawn_blocking(move || {
+            let mut failed = None;
+            let scanned = db::select_each(&collection, predicates, Some(&principal), &cancelled, |header, rows| {
+                match events.events(header, rows) {
+                    // The client has gone if the events cannot be sent.
+                    Ok(batch) => batch.into_iter().all(|event| sender.blocking_send(Ok(event)).is_ok()),
+                    Err(err) => {
+                        failed = Some(err);
+                        false
+                    },
+                }
+            });
+            (events, failed.map(Err).unwrap_or(scanned))
+        }).await
+    };
+    match scanned {
+        Ok(_) => (),
+        // The coordinator does not need to hold any of the collection itself.
+        Err(ZenithError::NotFound(Missing::Collection(_))) if !nodes.is_empty() => (),
+        Err(err) => return Err(err),
+    }
+
+    let mut node_queries = tokio::task::JoinSet::new();
+    for node in nodes {
+        let (collection, predicates) = (collection.to_string(), predicates.clone());
+        node_queries.spawn(request_id::inherit(async move { remote::federated_select(&node, &collection, &predicates).await }));
+    }
+    while let Some(result) = node_queries.join_next().await {
+        let (node_header, node_rows) = match result {
+            Ok(result) => result?,
+            Err(err) => {
+                warn!("Failed to join federated query: {:?}", err);
+                return Err(ZenithError::RemoteError("A federated query did not complete".to_string()));
+            },
+        };
+        for event in events.events(node_header, node_rows)? {
+            if sender.send(Ok(event)).await.is_err() {
+                return Err(ZenithError::Cancelled(format!("The query on collection '{}' was cancelled", collection)));
+            }
+        }
+    }
+
+    // A query that matched no files still sends its header, empty.
+    if events.header.is_none() {
+        for event in events.events(Vec::new(), Vec::new())? {
+            let _ = sender.send(Ok(event)).await;
+        }
+    }
+    Ok(events.rows)
+}
@@ -0,0 +1,264 @@
+use std::{pin::Pin, sync::Arc};
+
+use arrow_array::{
+    ArrayRef, BooleanArray, Float64Array, Int64Array, RecordBatch, RecordBatchOptions, StringArray, TimestampMillisecondArray,
+};
+use arrow_flight::{
+    encode::FlightDataEncoderBuilder, error::FlightError, flight_descriptor::DescriptorType, flight_service_server::FlightService,
+    Action, ActionType, Criteria, Empty, FlightData, FlightDescriptor, FlightEndpoint, FlightInfo, HandshakeRequest,
+    HandshakeResponse, PollInfo, PutResult, SchemaAsIpc, SchemaResult, Ticket,
+};
+use arrow_ipc::writer::IpcWriteOptions;
+use arrow_schema::{DataType, Field, Schema, TimeUnit};
+use futures_util::{Stream, StreamExt};
+use tonic::{Request, Response, Status, Streaming};
+use tracing::info;
+
+use crate::auth::{Principal, Scope};
+use crate::grpc::{self, proto};
+use crate::schema::{self, ColumnType};
+use crate::types::{api::{FlightTicket, Priority}, error::ZenithError};
+use crate::{db, request_id, storage};
+
+type FlightStream<T> = Pin<Box<dyn Stream<Item = Result<T, Status>> + Send>>;
+
+
+/// Returns the ticket given as JSON in `bytes`, raising a `QueryError` if it is not one.
+fn parse_ticket(bytes: &[u8]) -> Result<FlightTicket, ZenithError> {
+    serde_json::from_slice(bytes).map_err(|err| ZenithError::QueryError(format!("The ticket is not a valid query: {}", err)))
+}
+
+
+/// Returns the ticket of the query that `descriptor` describes: a path with the name of a
+/// collection, for all of its rows, or a command with a ticket as JSON.
+fn descriptor_ticket(descriptor: &FlightDescriptor) -> Result<FlightTicket, ZenithError> {
+    match (descriptor.r#type(), descriptor.path.as_slice()) {
+        (DescriptorType::Path, [collection]) => Ok(FlightTicket { collection: collection.clone(), ..FlightTicket::default() }),
+        (DescriptorType::Cmd, _) => parse_ticket(&descriptor.cmd),
+        _ => Err(ZenithError::QueryError("A flight is described by the name of a collection, or a ticket".to_string())),
+    }
+}
+
+
+/// Returns the types of the columns in `header`, as given by the `columns` of their collection.
+/// Columns that are not among them, such as those only found on federation nodes, are strings.
+fn types(header: &[String], columns: &[(String, ColumnType)]) -> Vec<ColumnType> {
+    header.iter()
+        .map(|name| columns.iter().find(|(column, _)| column == name).map(|(_, column_type)| *column_type).unwrap_or_default())
+        .collect()
+}
+
+
+/// Returns the Arrow schema of rows with a field for each column of `header`, of the type in
+/// `types`. Fields of any type but `string` can be null, for empty values.
+fn arrow_schema(header: &[String], types: &[ColumnType]) -> Schema {
+    let fields: Vec<Field> = header.iter().zip(types)
+        .map(|(name, column_type)| match column_type {
+            ColumnType::String => Field::new(name, DataType::Utf8, false),
+            ColumnType::Int => Field::new(name, DataType::Int64, true),
+            ColumnType::Float => Field::new(name, DataType::Float64, true),
+            ColumnType::Bool => Field::new(name, DataType::Boolean, true),
+            ColumnType::Date => Field::new(name, DataType::Timestamp(TimeUnit::Millisecond, None), true),
+        })
+        .collect();
+    Schema::new(fields)
+}
+
+
+/// Returns `rows` as a record batch of `schema`, whose columns have `types`. Values that cannot
+/// be parsed as the type of their column are null.
+fn record_batch(schema: &Arc<Schema>, types: &[ColumnType], rows: &[Vec<String>]) -> Result<RecordBatch, FlightError> {
+    let columns = types.iter().enumerate()
+        .map(|(i, column_type)| {
+            let values = rows.iter().map(move |row| row.get(i).map(String::as_str).unwrap_or_default());
+            let array: ArrayRef = match column_type {
+                ColumnType::String => Arc::new(StringArray::from_iter_values(values)),
+                ColumnType::Int => Arc::new(values.map(|v| v.parse::<i64>().ok()).collect::<Int64Array>()),
+                ColumnType::Float => Arc::new(values.map(|v| v.parse::<f64>().ok()).collect::<Float64Array>()),
+                ColumnType::Bool => Arc::new(values.map(schema::parse_bool).collect::<BooleanArray>()),
+                ColumnType::Date => Arc::new(values
+                    .map(|v| schema::parse_date(v).map(|d| d.and_utc().timestamp_millis()))
+                    .collect::<TimestampMillisecondArray>()),
+            };
+            array
+        })
+        .collect();
+    let options = RecordBatchOptions::new().with_row_count(Some(rows.len()));
+    Ok(RecordBatch::try_new_with_options(Arc::clone(schema), columns, &options)?)
+}
+
+
+/// Returns the flight of `ticket` for `principal`, described by `descriptor`, with the schema of
+/// the rows it returns and a single endpoint to get them from this data service.
+fn flight_info(ticket: &FlightTicket, descriptor: FlightDescriptor, principal: &Principal) -> Result<FlightInfo, ZenithError> {
+    let columns = db::columns(&ticket.collection, Some(principal))?;
+    let header = match ticket.fields.is_empty() {
+        true => columns.iter().map(|(name, _)| name.clone()).collect(),
+        false => ticket.fields.clone(),
+    };
+    let schema = arrow_schema(&header, &types(&header, &columns));
+    Ok(FlightInfo::new()
+        .try_with_schema(&schema)
+        .map_err(|err| ZenithError::QueryError(format!("Arrow schema cannot be made: {}", err)))?
+        .with_descriptor(descriptor)
+        .with_endpoint(FlightEndpoint::new().with_ticket(Ticket::new(serde_json::to_vec(ticket)?)))
+        .with_ordered(true))
+}
+
+
+/// The Arrow Flight service, served alongside the gRPC API. Each collection is a flight, and
+/// `DoGet` runs a query as `StreamQuery` does, returning its rows as Arrow record batches.
+pub struct Service;
+
+#[tonic::async_trait]
+impl FlightService for Service {
+    type HandshakeStream = FlightStream<HandshakeResponse>;
+
+    async fn handshake(
+        &self,
+        _request: Request<Streaming<HandshakeRequest>>,
+    ) -> Result<Response<Self::HandshakeStream>, Status> {
+        Err(Status::unimplemented("Give an API key or token in the authorization metadata instead"))
+    }
+
+    type ListFlightsStream = FlightStream<FlightInfo>;
+
+    async fn list_flights(
+        &self,
+        request: Request<Criteria>,
+    ) -> Result<Response<Self::ListFlightsStream>, Status> {
+
+        request_id::scope(grpc::id(&request), async move {
+            let principal = grpc::authorize(&request, Scope::Read).await?;
+            let flights = request_id::spawn_blocking(move || {
+                storage::list_collections()?.into_iter()
+                    .map(|collection| {
+                        let descriptor = FlightDescriptor::new_path(vec![collection.clone()]);
+                        flight_info(&FlightTicket { collection, ..FlightTicket::default() }, descriptor, &principal)
+                    })
+                    .collect::<Result<Vec<FlightInfo>, ZenithError>>()
+            }).await?;
+            let stream: Self::ListFlightsStream = Box::pin(futures_util::stream::iter(flights.into_iter().map(Ok)));
+            Ok(Response::new(stream))
+        }).await
+    }
+
+    async fn get_flight_info(
+        &self,
+        request: Request<FlightDescriptor>,
+    ) -> Result<Response<FlightInfo>, Status> {
+
+        request_id::scope(grpc::id(&request), async move {
+            let principal = grpc::authorize(&request, Scope::Read).await?;
+            let descriptor = request.into_inner();
+            let ticket = descriptor_ticket(&descriptor)?;
+            let info = request_id::spawn_blocking(move || flight_info(&ticket, descriptor, &principal)).await?;
+            Ok(Response::new(info))
+        }).await
+    }
+
+    async fn poll_flight_info(
+        &self,
+        _request: Request<FlightDescriptor>,
+    ) -> Result<Response<PollInfo>, Status> {
+        Err(Status::unimplemented("Flights are not long-running, so use GetFlightInfo instead"))
+    }
+
+    async fn get_schema(
+        &self,
+        request: Request<FlightDescriptor>,
+    ) -> Result<Response<SchemaResult>, Status> {
+
+        request_id::scope(grpc::id(&request), async move {
+            let principal = grpc::authorize(&request, Scope::Read).await?;
+            let descriptor = request.into_inner();
+            let ticket = descriptor_ticket(&descriptor)?;
+            let info = request_id::spawn_blocking(move || flight_info(&ticket, descriptor, &principal)).await?;
+            let schema = info.try_decode_schema().map_err(FlightError::from)?;
+            let result = SchemaAsIpc::new(&schema, &IpcWriteOptions::default()).try_into().map_err(FlightError::from)?;
+            Ok(Response::new(result))
+        }).await
+    }
+
+    type DoGetStream = FlightStream<FlightData>;
+
+    async fn do_get(
+        &self,
+        request: Request<Ticket>,
+    ) -> Result<Response<Self::DoGetStream>, Status> {
+
+        request_id::scope(grpc::id(&request), async move {
+            let principal = grpc::authorize(&request, Scope::Read).await?;
+            let ticket = parse_ticket(&request.into_inner().ticket)?;
+            info!("Received an Arrow Flight request to query collection '{}'", ticket.collection);
+            let columns = {
+                let (collection, principal) = (ticket.collection.clone(), principal.clone());
+                // The coordinator of a federated query does not need to hold the collection itself.
+                request_id::spawn_blocking(move || db::columns(&collection, Some(&principal))).await.unwrap_or_default()
+            };
+            let query = proto::QueryRequest {
+                collection: ticket.collection,
+                fields: ticket.fields,
+                predicates: ticket.predicates,
+                page: ticket.page.map(|p| p as u64),
+                per_page: ticket.per_page.map(|p| p as u64),
+                priority: match ticket.priority.unwrap_or_default() {
+                    Priority::Interactive => proto::Priority::Interactive,
+                    Priority::Batch => proto::Priority::Batch,
+                } as i32,
+            };
+            let (selection, range) = grpc::select(principal, query, true).await?;
+            info!("Streaming {} fields and {}/{} rows", selection.0.len(), range.len(), selection.1.len());
+
+            let types = types(&selection.0, &columns);
+            let schema = Arc::new(arrow_schema(&selection.0, &types));
+            let end = range.end;
+            let batches = {
+                let schema = Arc::clone(&schema);
+                range.step_by(crate::STREAM_CHUNK_ROWS)
+                    .map(move |start| record_batch(&schema, &types, &selection.1[start..end.min(start + crate::STREAM_CHUNK_ROWS)]))
+            };
+            let stream: Self::DoGetStream = Box::pin(FlightDataEncoderBuilder::new()
+                .with_schema(schema)
+                .build(futures_util::stream::iter(batches))
+                .map(|data| data.map_err(Status::from)));
+            Ok(Response::new(stream))
+        }).await
+    }
+
+    type DoPutStream = FlightStream<PutResult>;
+
+    async fn do_put(
+        &self,
+        _request: Request<Streaming<FlightData>>,
+    ) -> Result<Response<Self::DoPutStream>, Status> {
+        Err(Status::unimplemented("Flights cannot be written, so use Create instead"))
+    }
+
+    type DoExchangeStream = FlightStream<FlightData>;
+
+    async fn do_exchange(
+        &self,
+        _request: Request<Streaming<FlightData>>,
+    ) -> Result<Response<Self::DoExchangeStream>, Status> {
+        Err(Status::unimplemented("Flights cannot be exchanged"))
+    }
+
+    type DoActionStream = FlightStream<arrow_flight::Result>;
+
+    async fn do_action(
+        &self,
+        _request: Request<Action>,
+    ) -> Result<Response<Self::DoActionStream>, Status> {
+        Err(Status::unimplemented("There are no actions"))
+    }
+
+    type ListActionsStream = FlightStream<ActionType>;
+
+    async fn list_actions(
+        &self,
+        _request: Request<Empty>,
+    ) -> Result<Response<Self::ListActionsStream>, Status> {
+        Ok(Response::new(Box::pin(futures_util::stream::empty())))
+    }
+}
@@ -0,0 +1,166 @@
+use std::{collections::HashMap, sync::Arc};
+
+use async_graphql::{
+    dynamic::{Field, FieldFuture, FieldValue, InputValue, Object, Schema, TypeRef},
+    Value,
+};
+use tracing::error;
+
+use crate::auth::Principal;
+use crate::schema::ColumnType;
+use crate::types::{api::{QueryParameters, QueryPredicates}, error::ZenithError};
+use crate::{db, queries, storage};
+
+
+/// A column of a collection, as a field of the type of its rows.
+struct Column {
+    field: String,
+    name: String,
+    column_type: ColumnType,
+}
+
+/// A row of a collection, with the value of each field selected.
+type Row = HashMap<String, Value>;
+
+
+/// Returns `name` as a valid GraphQL name, which has only letters, digits, and `_`, and does
+/// not start with a digit. Other characters are replaced by `_`.
+fn graphql_name(name: &str) -> String {
+    let mut graphql: String = name.chars().map(|c| if c.is_ascii_alphanumeric() { c } else { '_' }).collect();
+    if !graphql.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_') {
+        graphql.insert(0, '_');
+    }
+    graphql
+}
+
+
+/// Returns `name` as a GraphQL name that is not in `taken`, adding `_` until it is not,
+/// or `None` if it starts with `__`, which GraphQL keeps for introspection.
+fn unique_name(name: &str, taken: &mut Vec<String>) -> Option<String> {
+    let mut unique = graphql_name(name);
+    if unique.starts_with("__") {
+        return None;
+    }
+    while taken.contains(&unique) {
+        unique.push('_');
+    }
+    taken.push(unique.clone());
+    Some(unique)
+}
+
+
+/// Returns the GraphQL error for `error`, hiding the details of errors in the data service.
+fn graphql_error(error: ZenithError) -> async_graphql::Error {
+    match error {
+        ZenithError::PredicateError(_) | ZenithError::QueryError(_) | ZenithError::NotFound(_) | ZenithError::Conflict(_)
+            | ZenithError::Forbidden(_) | ZenithError::PayloadTooLarge(_)
+            | ZenithError::TooManyRequests(_) | ZenithError::ResultTooLarge(_) | ZenithError::ServiceUnavailable(_)
+            | ZenithError::RemoteError(_) | ZenithError::Cancelled(_) => async_graphql::Error::new(error.to_string()),
+        _ => {
+            error!("{error}");
+            async_graphql::Error::new("Something went wrong")
+        },
+    }
+}
+
+
+/// Returns the columns of `collection` that `principal` can see, as fields. See `db::columns`.
+fn columns(collection: &str, principal: &Principal) -> Result<Vec<Column>, ZenithError> {
+    let mut taken = Vec::new();
+    Ok(db::columns(collection, Some(principal))?.into_iter()
+        .filter_map(|(name, column_type)| unique_name(&name, &mut taken).map(|field| Column { field, name, column_type }))
+        .collect())
+}
+
+
+/// Returns the field of the `Query` type for `collection`, which returns its rows as `row_type`.
+/// Its `predicates` are the predicates of a query, and its `page` and `perPage` choose the page
+/// of rows returned. The fields selected from the rows are the fields of the query.
+fn collection_field(name: &str, row_type: &str, collection: String, columns: Arc<Vec<Column>>) -> Field {
+    Field::new(name, TypeRef::named_nn_list_nn(row_type), move |ctx| {
+        let (collection, columns) = (collection.clone(), Arc::clone(&columns));
+        FieldFuture::new(async move {
+            let principal = ctx.data::<Principal>()?.clone();
+            let cancelled = ctx.data::<queries::Cancelled>()?;
+            let fields = ctx.field().selection_set()
+                .filter_map(|selected| columns.iter().find(|c| c.field == selected.name()))
+                .map(|column| column.name.clone())
+                .collect();
+            let predicates = match ctx.args.get("predicates") {
+                Some(predicates) => predicates.deserialize::<Option<Vec<String>>>()?.unwrap_or_default(),
+                None => Vec::new(),
+            };
+            let query = QueryParameters {
+                page: ctx.args.get("page").map(|page| page.deserialize::<Option<usize>>()).transpose()?.flatten(),
+                per_page: ctx.args.get("perPage").map(|per_page| per_page.deserialize::<Option<usize>>()).transpose()?.flatten(),
+                ..QueryParameters::default()
+            };
+
+            let ((header, rows), _) = crate::run_query(&collection, QueryPredicates { fields, predicates }, principal, cancelled, false)
+                .await
+                .map_err(graphql_error)?;
+            let positions: Vec<Option<&Column>> = header.iter().map(|name| columns.iter().find(|c| c.name == *name)).collect();
+            let page = crate::page_range(rows.len(), &query).map(|range| &rows[range]).unwrap_or_default();
+            let rows = page.iter().map(|row| {
+                let row: Row = positions.iter().zip(row)
+                    .filter_map(|(column, value)| column.map(|c| (c.field.clone(), Value::from_json(c.column_type.to_json(value)).unwrap_or_default())))
+                    .collect();
+                FieldValue::owned_any(row)
+            });
+            Ok(Some(FieldValue::list(rows)))
+        })
+    })
+    .argument(InputValue::new("predicates", TypeRef::named_nn_list(TypeRef::STRING)))
+    .argument(InputValue::new("page", TypeRef::named(TypeRef::INT)))
+    .argument(InputValue::new("perPage", TypeRef::named(TypeRef::INT)))
+}
+
+
+/// Builds the GraphQL schema of the collections that `principal` can see. The `Query` type has
+/// a field for each collection, returning its rows as a type with a field for each column.
+/// Collections without any columns are left out, as are collections and columns whose names
+/// cannot be made into GraphQL names.
+pub fn build(principal: &Principal) -> Result<Schema, ZenithError> {
+    let mut query = Object::new("Query")
+        .field(Field::new("collections", TypeRef::named_nn_list_nn(TypeRef::STRING), |_| {
+            FieldFuture::new(async move {
+                let collections = storage::list_collections().map_err(|err| graphql_error(err.into()))?;
+                Ok(Some(FieldValue::list(collections.into_iter().map(FieldValue::value))))
+            })
+        }));
+    let mut types = Vec::new();
+    let mut taken = vec!["collections".to_string()];
+
+    for collection in storage::list_collections()? {
+        let columns = columns(&collection, principal)?;
+        if columns.is_empty() {
+            continue;
+        }
+        let Some(name) = unique_name(&collection, &mut taken) else {
+            continue;
+        };
+        let row_type = format!("{}Row", name);
+        let mut object = Object::new(&row_type);
+        for column in &columns {
+            let field = column.field.clone();
+            let column_type = match column.column_type {
+                ColumnType::Int => TypeRef::INT,
+                ColumnType::Float => TypeRef::FLOAT,
+                ColumnType::Bool => TypeRef::BOOLEAN,
+                ColumnType::String | ColumnType::Date => TypeRef::STRING,
+            };
+            object = object.field(Field::new(&column.field, TypeRef::named(column_type), move |ctx| {
+                let value = ctx.parent_value.downcast_ref::<Row>().and_then(|row| row.get(&field)).cloned();
+                FieldFuture::from_value(value)
+            }));
+        }
+        query = query.field(collection_field(&name, &row_type, collection, Arc::new(columns)));
+        types.push(object);
+    }
+
+    types.into_iter()
+        .fold(Schema::build("Query", None, None), |schema, object| schema.register(object))
+        .register(query)
+        .finish()
+        .map_err(|err| ZenithError::QueryError(format!("GraphQL schema cannot be built: {}", err)))
+}
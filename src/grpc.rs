@@ -0,0 +1,229 @@
+use std::{net::SocketAddr, pin::Pin, sync::Arc};
+
+use axum::extract::{Extension, Json, Path};
+use futures_util::Stream;
+use tonic::{transport::Server, Request, Response, Status};
+use tracing::{info, warn, error};
+
+use crate::auth::{Principal, Scope};
+use crate::types::{api::{CreatePayload, OnConflict, Priority, QueryParameters, QueryPredicates}, error::ZenithError};
+use crate::{acl, auth, config, db, flight, limit, queries, request_id, shutdown};
+
+/// The messages and service generated from `proto/zenithds.proto`.
+pub mod proto {
+    tonic::include_proto!("zenithds.v1");
+}
+
+use arrow_flight::flight_service_server::FlightServiceServer;
+use proto::zenith_ds_server::{ZenithDs, ZenithDsServer};
+
+
+impl From<ZenithError> for Status {
+    fn from(error: ZenithError) -> Self {
+        match error {
+            ZenithError::PredicateError(_) | ZenithError::QueryError(_) | ZenithError::HeaderMismatch { .. } => {
+                Status::invalid_argument(error.to_string())
+            },
+            ZenithError::NotFound(_) => Status::not_found(error.to_string()),
+            ZenithError::Conflict(_) => Status::failed_precondition(error.to_string()),
+            ZenithError::Unauthorized(_) => Status::unauthenticated(error.to_string()),
+            ZenithError::Forbidden(_) => Status::permission_denied(error.to_string()),
+            ZenithError::TooManyRequests(_) | ZenithError::ResultTooLarge(_) | ZenithError::InsufficientStorage(_)
+                | ZenithError::PayloadTooLarge(_) => {
+                Status::resource_exhausted(error.to_string())
+            },
+            ZenithError::ServiceUnavailable(_) | ZenithError::RemoteError(_) => Status::unavailable(error.to_string()),
+            ZenithError::Cancelled(_) => Status::cancelled(error.to_string()),
+            _ => {
+                error!("{error}");
+                Status::internal("Something went wrong")
+            },
+        }
+    }
+}
+
+
+/// Returns the principal of a gRPC `request`, as `auth::authenticate` does for the HTTP API,
+/// raising `PermissionDenied` if it does not have `scope`, or its address is not allowed.
+/// The API key or token is given in the `x-api-key` or `authorization` metadata.
+pub async fn authorize<T>(request: &Request<T>, scope: Scope) -> Result<Principal, ZenithError> {
+    if acl::enabled().unwrap_or(true) {
+        match request.remote_addr() {
+            Some(address) if acl::allowed(address.ip()) => (),
+            Some(address) => {
+                warn!("Denied a gRPC request from {}", address.ip());
+                return Err(ZenithError::Forbidden("Requests are not allowed from this address".to_string()));
+            },
+            None => return Err(ZenithError::Forbidden("The address of the request is not known".to_string())),
+        }
+    }
+    if !auth::enabled() {
+        return Ok(Principal::default());
+    }
+    let principal = auth::principal(&request.metadata().clone().into_headers(), None).await?;
+    if principal.scope < scope {
+        return Err(ZenithError::Forbidden("The credentials of the request do not have the scope it needs".to_string()));
+    }
+    Ok(principal)
+}
+
+
+/// Returns the id of a gRPC `request`, given in its `x-request-id` metadata. See `request_id::accept`.
+pub fn id<T>(request: &Request<T>) -> String {
+    request_id::accept(request.metadata().get(request_id::REQUEST_ID_HEADER).and_then(|v| v.to_str().ok()))
+}
+
+
+/// Runs the query in `request` for `principal`, returning the selection and the positions of
+/// the rows to return. Every row is returned if `all` is set and no page is asked for.
+pub async fn select(
+    principal: Principal,
+    request: proto::QueryRequest,
+    all: bool,
+) -> Result<(Arc<db::Selection>, std::ops::Range<usize>), ZenithError> {
+
+    let priority = match request.priority() {
+        proto::Priority::Interactive => Priority::Interactive,
+        proto::Priority::Batch => Priority::Batch,
+    };
+    let _slot = limit::SlotGuard::start(limit::priority(Some(priority), &principal)).await?;
+    let running = queries::Running::start(&request_id::current().unwrap_or_default(), &principal);
+    let predicates = QueryPredicates { fields: request.fields, predicates: request.predicates };
+    let (selection, _) = crate::run_query(&request.collection, predicates, principal, running.cancelled(), false).await?;
+    let query = QueryParameters {
+        page: request.page.map(|p| p as usize),
+        per_page: request.per_page.map(|p| p as usize),
+        ..QueryParameters::default()
+    };
+    let range = match all && query.page.is_none() && query.per_page.is_none() {
+        true => 0..selection.1.len(),
+        false => crate::page_range(selection.1.len(), &query).unwrap_or_default(),
+    };
+    Ok((Arc::new(selection), range))
+}
+
+
+fn rows(rows: &[Vec<String>]) -> Vec<proto::Row> {
+    rows.iter().map(|row| proto::Row { values: row.clone() }).collect()
+}
+
+
+/// The gRPC API, whose calls do what their counterparts in the HTTP API do.
+struct Service;
+
+#[tonic::async_trait]
+impl ZenithDs for Service {
+    async fn query(
+        &self,
+        request: Request<proto::QueryRequest>,
+    ) -> Result<Response<proto::QueryResponse>, Status> {
+
+        request_id::scope(id(&request), async move {
+            let principal = authorize(&request, Scope::Read).await?;
+            let request = request.into_inner();
+            info!("Received a gRPC request to query collection '{}'", request.collection);
+            let (selection, range) = select(principal, request, false).await?;
+            info!("Returned {} fields and {}/{} rows", selection.0.len(), range.len(), selection.1.len());
+            Ok(Response::new(proto::QueryResponse { header: selection.0.clone(), rows: rows(&selection.1[range]) }))
+        }).await
+    }
+
+    type StreamQueryStream = Pin<Box<dyn Stream<Item = Result<proto::QueryChunk, Status>> + Send>>;
+
+    async fn stream_query(
+        &self,
+        request: Request<proto::QueryRequest>,
+    ) -> Result<Response<Self::StreamQueryStream>, Status> {
+
+        request_id::scope(id(&request), async move {
+            let principal = authorize(&request, Scope::Read).await?;
+            let request = request.into_inner();
+            info!("Received a gRPC request to stream a query on collection '{}'", request.collection);
+            let (selection, range) = select(principal, request, true).await?;
+            info!("Streaming {} fields and {}/{} rows", selection.0.len(), range.len(), selection.1.len());
+
+            let (first, end) = (range.start, range.end);
+            // An empty range still has a chunk, for the header.
+            let starts = range.step_by(crate::STREAM_CHUNK_ROWS).chain((first == end).then_some(first));
+            let chunks = starts.map(move |start| Ok(proto::QueryChunk {
+                header: if start == first { selection.0.clone() } else { Vec::new() },
+                rows: rows(&selection.1[start..end.min(start + crate::STREAM_CHUNK_ROWS)]),
+            }));
+            let stream: Self::StreamQueryStream = Box::pin(futures_util::stream::iter(chunks));
+            Ok(Response::new(stream))
+        }).await
+    }
+
+    async fn create(
+        &self,
+        request: Request<proto::CreateRequest>,
+    ) -> Result<Response<proto::CreateResponse>, Status> {
+
+        request_id::scope(id(&request), async move {
+            let principal = authorize(&request, Scope::Write).await?;
+            let request = request.into_inner();
+            let on_conflict = match request.on_conflict() {
+                proto::OnConflict::Reject => OnConflict::Reject,
+                proto::OnConflict::Upsert => OnConflict::Upsert,
+            };
+            let payload = CreatePayload {
+                filename: request.filename,
+                header: request.header,
+                rows: request.rows.into_iter().map(|row| row.values).collect(),
+                on_conflict,
+                delimiter: None,
+                quote: None,
+                partial: request.partial,
+                keyed: false,
+            };
+            let Json(created) = crate::create_csv_v1(Path(request.collection), Extension(principal), Json(payload)).await?;
+            let rejected = created.rejected.into_iter()
+                .map(|problem| proto::RejectedRow {
+                    row: problem.row.unwrap_or_default() as u64,
+                    kind: problem.kind.name().to_string(),
+                    message: problem.message,
+                })
+                .collect();
+            Ok(Response::new(proto::CreateResponse { rows: created.rows as u64, rejected }))
+        }).await
+    }
+
+    async fn delete(
+        &self,
+        request: Request<proto::DeleteRequest>,
+    ) -> Result<Response<proto::DeleteResponse>, Status> {
+
+        request_id::scope(id(&request), async move {
+            let principal = authorize(&request, Scope::Write).await?;
+            let request = request.into_inner();
+            crate::delete_csv_v1(Path((request.collection, request.filename)), Extension(principal)).await?;
+            Ok(Response::new(proto::DeleteResponse {}))
+        }).await
+    }
+}
+
+
+/// Serves the gRPC API and the Arrow Flight service at `config::grpc_address` until the data service is asked to stop.
+/// Nothing is served if `ZENITHDS_GRPC_PORT` is not set.
+pub async fn serve() {
+    if config::envar_usize("ZENITHDS_GRPC_PORT") == 0 {
+        return;
+    }
+    let address = config::grpc_address();
+    let socket: SocketAddr = match address.parse() {
+        Ok(socket) => socket,
+        Err(err) => {
+            error!("Could not serve gRPC on {}: {}", address, err);
+            return;
+        }
+    };
+    info!("Serving gRPC on {}", address);
+    let served = Server::builder()
+        .add_service(ZenithDsServer::new(Service))
+        .add_service(FlightServiceServer::new(flight::Service))
+        .serve_with_shutdown(socket, shutdown::requested())
+        .await;
+    if let Err(err) = served {
+        error!("Could not serve gRPC on {}: {}", address, err);
+    }
+}
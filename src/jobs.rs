@@ -0,0 +1,159 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, LazyLock, Mutex},
+    time::{Duration, Instant, SystemTime},
+};
+
+use crate::auth::{Principal, Scope};
+use crate::db::Selection;
+use crate::queries::Cancelled;
+use crate::types::{api::{JobState, JobStatus}, error::{Missing, ZenithError}};
+use crate::{config, request_id};
+
+
+/// A query job, with the principal that submitted it and its result once it is done.
+struct Job {
+    principal: String,
+    status: JobStatus,
+    result: Option<Arc<Selection>>,
+    cancelled: Cancelled,
+    /// When the job is forgotten, once it has finished.
+    expires: Option<Instant>,
+}
+
+/// The query jobs submitted, by their id.
+static JOBS: LazyLock<Mutex<HashMap<String, Job>>> = LazyLock::new(|| Mutex::new(HashMap::new()));
+
+
+/// Returns the time now, in milliseconds since the Unix epoch.
+fn now() -> u64 {
+    SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).map(|d| d.as_millis() as u64).unwrap_or(0)
+}
+
+
+/// Forgets the jobs that finished more than `ZENITHDS_JOB_TTL` seconds ago, with their results.
+fn expire(jobs: &mut HashMap<String, Job>) {
+    let now = Instant::now();
+    jobs.retain(|_, job| job.expires.is_none_or(|expires| expires > now));
+}
+
+
+/// Forgets the jobs that finished more than `ZENITHDS_JOB_TTL` seconds ago, so that their
+/// results are not kept until another job is submitted or looked at.
+pub fn forget_expired() {
+    expire(&mut JOBS.lock().unwrap_or_else(|e| e.into_inner()));
+}
+
+
+/// Returns the job with `id`, raising a `NotFound` error if there is none, and `Forbidden` if it was
+/// submitted by another principal. A principal can only see its own jobs, unless it can write.
+fn get<'a>(
+    jobs: &'a mut HashMap<String, Job>,
+    id: &str,
+    principal: &Principal,
+) -> Result<&'a mut Job, ZenithError> {
+
+    match jobs.get_mut(id) {
+        Some(job) if principal.scope == Scope::Write || job.principal == principal.name => Ok(job),
+        Some(_) => Err(ZenithError::Forbidden(format!("Job '{}' was submitted by another principal", id))),
+        None => Err(ZenithError::NotFound(Missing::Other(format!("Job '{}' does not exist", id)))),
+    }
+}
+
+
+/// Submits a job to query `collection` for `principal`, returning its status, and the
+/// flag that cancels it, which is given to the workers that run it.
+pub fn submit(collection: &str, principal: &Principal) -> (JobStatus, Cancelled) {
+    let status = JobStatus {
+        job_id: request_id::generate(),
+        collection: collection.to_string(),
+        state: JobState::Queued,
+        submitted: now(),
+        started: None,
+        finished: None,
+        rows: None,
+        error: None,
+    };
+    let cancelled = Cancelled::default();
+    let mut jobs = JOBS.lock().unwrap_or_else(|e| e.into_inner());
+    expire(&mut jobs);
+    jobs.insert(status.job_id.clone(), Job {
+        principal: principal.name.clone(),
+        status: status.clone(),
+        result: None,
+        cancelled: cancelled.clone(),
+        expires: None,
+    });
+    (status, cancelled)
+}
+
+
+/// Records that the job with `id` has started running.
+pub fn started(id: &str) {
+    if let Some(job) = JOBS.lock().unwrap_or_else(|e| e.into_inner()).get_mut(id) {
+        job.status.state = JobState::Running;
+        job.status.started = Some(now());
+    }
+}
+
+
+/// Records that the job with `id` has finished with `result`, which is kept
+/// for `ZENITHDS_JOB_TTL` seconds. Nothing is kept if the job was removed.
+pub fn finished(id: &str, result: Result<Selection, ZenithError>) {
+    let mut jobs = JOBS.lock().unwrap_or_else(|e| e.into_inner());
+    let Some(job) = jobs.get_mut(id) else {
+        return;
+    };
+    job.status.finished = Some(now());
+    job.expires = Some(Instant::now() + Duration::from_secs(config::envar_usize("ZENITHDS_JOB_TTL") as u64));
+    match result {
+        Ok(selection) => {
+            job.status.state = JobState::Done;
+            job.status.rows = Some(selection.1.len());
+            job.result = Some(Arc::new(selection));
+        },
+        Err(ZenithError::Cancelled(_)) => job.status.state = JobState::Cancelled,
+        Err(err) => {
+            job.status.state = JobState::Failed;
+            job.status.error = Some(err.to_string());
+        },
+    }
+}
+
+
+/// Returns the status of the job with `id`.
+pub fn status(id: &str, principal: &Principal) -> Result<JobStatus, ZenithError> {
+    let mut jobs = JOBS.lock().unwrap_or_else(|e| e.into_inner());
+    expire(&mut jobs);
+    Ok(get(&mut jobs, id, principal)?.status.clone())
+}
+
+
+/// Returns the header and rows found by the job with `id`, raising a `Conflict` error if it
+/// is not done. Only the principal that submitted the job can see them, as they are
+/// masked by its roles.
+pub fn result(id: &str, principal: &Principal) -> Result<Arc<Selection>, ZenithError> {
+    let mut jobs = JOBS.lock().unwrap_or_else(|e| e.into_inner());
+    expire(&mut jobs);
+    let job = get(&mut jobs, id, principal)?;
+    if job.principal != principal.name {
+        return Err(ZenithError::Forbidden(format!("Job '{}' was submitted by another principal", id)));
+    }
+    match &job.result {
+        Some(result) => Ok(Arc::clone(result)),
+        None => Err(ZenithError::Conflict(format!(
+            "Job '{}' is {}, not done", id, format!("{:?}", job.status.state).to_lowercase()
+        ))),
+    }
+}
+
+
+/// Cancels the job with `id` if it has not finished, and forgets it with its result.
+pub fn remove(id: &str, principal: &Principal) -> Result<JobStatus, ZenithError> {
+    let mut jobs = JOBS.lock().unwrap_or_else(|e| e.into_inner());
+    let job = get(&mut jobs, id, principal)?;
+    job.cancelled.set();
+    let status = job.status.clone();
+    jobs.remove(id);
+    Ok(status)
+}
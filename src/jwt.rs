@@ -0,0 +1,179 @@
+use std::{
+    sync::LazyLock,
+    time::{Duration, Instant},
+};
+use jsonwebtoken::{
+    decode, decode_header, DecodingKey, Validation,
+    jwk::{JwkSet, KeyAlgorithm},
+    AlgorithmFamily,
+};
+use tokio::sync::RwLock;
+
+use crate::types::error::ZenithError;
+use crate::{auth::{Principal, Scope}, config};
+
+/// How long a fetched key set is used before it is fetched again.
+const KEY_SET_TTL: Duration = Duration::from_secs(3600);
+/// The least time between fetches of the key set when a token names a key not in it.
+const KEY_SET_MIN_REFRESH: Duration = Duration::from_secs(60);
+
+
+/// The key set of the issuer, and when it was fetched.
+static KEY_SET: LazyLock<RwLock<Option<(Instant, JwkSet)>>> = LazyLock::new(|| RwLock::new(None));
+
+
+/// Whether bearer tokens can be JWTs from an issuer, set in `ZENITHDS_JWT_ISSUER`.
+pub fn enabled() -> bool {
+    !config::envar_str("ZENITHDS_JWT_ISSUER").is_empty()
+}
+
+
+/// Returns the URL of the key set of the issuer. This is `ZENITHDS_JWT_JWKS_URL` if it
+/// is set, and otherwise the `jwks_uri` in the OpenID configuration of the issuer.
+async fn key_set_url(
+    client: &reqwest::Client,
+) -> Result<String, ZenithError> {
+
+    let url = config::envar_str("ZENITHDS_JWT_JWKS_URL");
+    if !url.is_empty() {
+        return Ok(url);
+    }
+    let issuer = config::envar_str("ZENITHDS_JWT_ISSUER");
+    let discovery = format!("{}/.well-known/openid-configuration", issuer.trim_end_matches('/'));
+    let configuration: serde_json::Value = client.get(&discovery).send().await
+        .and_then(|r| r.error_for_status())
+        .map_err(|err| ZenithError::RemoteError(format!("{}: {}", discovery, err)))?
+        .json().await
+        .map_err(|err| ZenithError::RemoteError(format!("{}: {}", discovery, err)))?;
+    match configuration.get("jwks_uri").and_then(|v| v.as_str()) {
+        Some(url) => Ok(url.to_string()),
+        None => Err(ZenithError::RemoteError(format!("{}: No jwks_uri in the OpenID configuration", discovery))),
+    }
+}
+
+
+/// Fetches the key set of the issuer, replacing the one kept.
+async fn fetch_key_set() -> Result<JwkSet, ZenithError> {
+    let client = reqwest::Client::builder()
+        .timeout(Duration::from_secs(config::envar_usize("ZENITHDS_REPLICA_TIMEOUT") as u64))
+        .build()
+        .map_err(|err| ZenithError::RemoteError(err.to_string()))?;
+    let url = key_set_url(&client).await?;
+    let key_set: JwkSet = client.get(&url).send().await
+        .and_then(|r| r.error_for_status())
+        .map_err(|err| ZenithError::RemoteError(format!("{}: {}", url, err)))?
+        .json().await
+        .map_err(|err| ZenithError::RemoteError(format!("{}: {}", url, err)))?;
+
+    *KEY_SET.write().await = Some((Instant::now(), key_set.clone()));
+    Ok(key_set)
+}
+
+
+/// Returns the key set of the issuer, fetching it if it has not been fetched recently.
+/// If `kid` is not in the key set kept, it is fetched again, in case the keys were rotated.
+async fn key_set(
+    kid: &str,
+) -> Result<JwkSet, ZenithError> {
+
+    if let Some((fetched, key_set)) = KEY_SET.read().await.as_ref() {
+        let stale = fetched.elapsed() > KEY_SET_TTL;
+        let rotated = key_set.find(kid).is_none() && fetched.elapsed() > KEY_SET_MIN_REFRESH;
+        if !stale && !rotated {
+            return Ok(key_set.clone());
+        }
+    }
+    fetch_key_set().await
+}
+
+
+/// Returns the roles in the `claims` of a token. They are found in the claim named by
+/// `ZENITHDS_JWT_ROLES_CLAIM`, which can be a path through objects separated by dots,
+/// and can be a list of strings or a string of roles separated by spaces.
+fn roles(
+    claims: &serde_json::Value,
+) -> Vec<String> {
+
+    let path = config::envar_str("ZENITHDS_JWT_ROLES_CLAIM");
+    let claim = path.split('.').try_fold(claims, |value, name| value.get(name));
+    match claim {
+        Some(serde_json::Value::Array(values)) => values.iter()
+            .filter_map(|v| v.as_str()).map(|v| v.to_string()).collect(),
+        Some(serde_json::Value::String(value)) => value.split_whitespace().map(|v| v.to_string()).collect(),
+        _ => Vec::new(),
+    }
+}
+
+
+/// Returns the scope given to a role in `ZENITHDS_JWT_ROLES`, in the form
+/// `role:scope` separated by commas, where the scope is `read` or `write`.
+fn role_scope(
+    role: &str,
+) -> Option<Scope> {
+
+    config::envar_str("ZENITHDS_JWT_ROLES")
+        .split(',')
+        .filter_map(|entry| entry.trim().rsplit_once(':'))
+        .filter(|(name, _)| *name == role)
+        .filter_map(|(_, scope)| match scope {
+            "read" => Some(Scope::Read),
+            "write" => Some(Scope::Write),
+            _ => None,
+        })
+        .reduce(|a, b| if a > b { a } else { b })
+}
+
+
+/// Validates the JWT `token` against the key set of the issuer, returning a principal
+/// with its roles and the widest scope given to any of them.
+///
+/// The token must be signed with a public key in the key set, not be expired, and have
+/// the issuer in `ZENITHDS_JWT_ISSUER`, and the audience in `ZENITHDS_JWT_AUDIENCE`, if set.
+pub async fn principal(
+    token: &str,
+) -> Result<Principal, ZenithError> {
+
+    let invalid = |err: jsonwebtoken::errors::Error| ZenithError::Unauthorized(format!("The token is not valid: {}", err));
+
+    let header = decode_header(token).map_err(invalid)?;
+    let kid = header.kid.clone().unwrap_or_default();
+    let key_set = key_set(&kid).await?;
+    let jwk = match header.kid.as_deref() {
+        Some(kid) => key_set.find(kid),
+        None if key_set.keys.len() == 1 => key_set.keys.first(),
+        None => None,
+    };
+    let Some(jwk) = jwk else {
+        return Err(ZenithError::Unauthorized("The token was not signed with a known key".to_string()));
+    };
+    let key = DecodingKey::from_jwk(jwk).map_err(invalid)?;
+    // Only public keys are accepted, and the algorithm must be the one given for the key.
+    let key_algorithm = jwk.common.key_algorithm;
+    if key.family() == AlgorithmFamily::Hmac || key_algorithm.is_some_and(|a| a != KeyAlgorithm::from(header.alg)) {
+        return Err(ZenithError::Unauthorized("The token was not signed with an accepted algorithm".to_string()));
+    }
+
+    let mut validation = Validation::new(header.alg);
+    validation.set_issuer(&[config::envar_str("ZENITHDS_JWT_ISSUER")]);
+    validation.set_required_spec_claims(&["exp", "iss"]);
+    let audience = config::envar_str("ZENITHDS_JWT_AUDIENCE");
+    if audience.is_empty() {
+        validation.validate_aud = false;
+    }
+    else {
+        validation.set_audience(&[audience]);
+        validation.set_required_spec_claims(&["exp", "iss", "aud"]);
+    }
+    let claims = decode::<serde_json::Value>(token, &key, &validation).map_err(invalid)?.claims;
+
+    let roles = roles(&claims);
+    let scope = roles.iter()
+        .filter_map(|role| role_scope(role))
+        .reduce(|a, b| if a > b { a } else { b })
+        .ok_or_else(|| ZenithError::Forbidden("The token has no role that is given access".to_string()))?;
+    let name = match claims.get("sub").and_then(|v| v.as_str()) {
+        Some(subject) => format!("jwt:{}", subject),
+        None => "jwt".to_string(),
+    };
+    Ok(Principal { name, scope, roles })
+}
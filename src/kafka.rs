@@ -0,0 +1,310 @@
+use std::{
+    collections::BTreeMap,
+    path::PathBuf,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+use rskafka::client::{
+    error::{Error as KafkaError, ProtocolError},
+    partition::{OffsetAt, PartitionClient, UnknownTopicHandling},
+    ClientBuilder,
+};
+use serde_json::{json, Value};
+use tracing::{debug, error, info, warn};
+
+use crate::audit::{self, AuditEntry};
+use crate::auth::Principal;
+use crate::storage::storage;
+use crate::types::{api::CreatePayload, error::ZenithError};
+use crate::{changes, config, db, request_id, shutdown};
+
+/// The file in a collection directory holding the offset to consume each partition from.
+pub const OFFSETS_FILENAME: &str = ".kafka-offsets.json";
+
+/// The most bytes of records fetched from a partition at once.
+const MAX_FETCH_BYTES: i32 = 50_000_000;
+
+/// The longest a fetch waits for records to arrive, so that batches are added on time.
+const MAX_FETCH_WAIT: Duration = Duration::from_secs(1);
+
+/// How long to wait before trying again after the brokers cannot be reached, or a batch
+/// cannot be added to its collection.
+const RETRY_DELAY: Duration = Duration::from_secs(5);
+
+
+/// The offsets to consume from, by topic and partition, for the topics added to a collection.
+type Offsets = BTreeMap<String, BTreeMap<i32, i64>>;
+
+/// Held while the offsets of a collection are changed, as more than one topic can be added to it.
+static OFFSETS_LOCK: Mutex<()> = Mutex::new(());
+
+
+fn offsets_path(collection: &str) -> PathBuf {
+    config::collection_path(collection).join(OFFSETS_FILENAME)
+}
+
+
+/// Reads the offset to consume `partition` of `topic` from, for `collection`, if it has one.
+fn read_offset(collection: &str, topic: &str, partition: i32) -> Result<Option<i64>, ZenithError> {
+    let path = offsets_path(collection);
+    if !storage().is_file(&path) {
+        return Ok(None);
+    }
+    let offsets: Offsets = serde_json::from_slice(&storage().read(&path)?)?;
+    Ok(offsets.get(topic).and_then(|partitions| partitions.get(&partition)).copied())
+}
+
+
+/// Records `offset` as the offset to consume `partition` of `topic` from, for `collection`.
+fn write_offset(collection: &str, topic: &str, partition: i32, offset: i64) -> Result<(), ZenithError> {
+    let _guard = OFFSETS_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let path = offsets_path(collection);
+    let mut offsets: Offsets = match storage().is_file(&path) {
+        true => serde_json::from_slice(&storage().read(&path)?)?,
+        false => Offsets::new(),
+    };
+    offsets.entry(topic.to_string()).or_default().insert(partition, offset);
+    let temp_path = path.with_extension("json.tmp");
+    storage().write(&temp_path, &serde_json::to_vec_pretty(&offsets)?)?;
+    storage().rename(&temp_path, &path)?;
+    Ok(())
+}
+
+
+/// The records read from a partition that have not been added to its collection yet.
+struct Batch {
+    /// The offset of the first record read into the batch.
+    first_offset: i64,
+    /// The offset after the last record read into the batch, including those skipped.
+    next_offset: i64,
+    /// The values of the records, as JSON objects.
+    values: Vec<Value>,
+    /// When the first record was read into the batch, if one has been.
+    started: Option<Instant>,
+}
+
+impl Batch {
+    fn new(offset: i64) -> Self {
+        Batch { first_offset: offset, next_offset: offset, values: Vec::new(), started: None }
+    }
+}
+
+
+/// Consumes the topics in `ZENITHDS_KAFKA_TOPICS` from the brokers in `ZENITHDS_KAFKA_BROKERS`,
+/// if both are set, adding the records of each partition to the collection of its topic until
+/// the data service is asked to stop.
+pub async fn consume() {
+    let (brokers, topics) = (config::kafka_brokers(), config::kafka_topics());
+    if brokers.is_empty() || topics.is_empty() {
+        return;
+    }
+    info!("Consuming Kafka topics {:?} from {:?}", topics, brokers);
+
+    let client = loop {
+        let connected = tokio::select! {
+            connected = ClientBuilder::new(brokers.clone()).client_id("zenithds").build() => connected,
+            _ = shutdown::requested() => return,
+        };
+        match connected {
+            Ok(client) => break client,
+            Err(err) => {
+                warn!("Could not connect to the Kafka brokers {:?}: {}", brokers, err);
+                tokio::select! {
+                    _ = tokio::time::sleep(RETRY_DELAY) => (),
+                    _ = shutdown::requested() => return,
+                }
+            },
+        }
+    };
+    let listed = loop {
+        match client.list_topics().await {
+            Ok(listed) => break listed,
+            Err(err) => {
+                warn!("Could not list the Kafka topics: {}", err);
+                tokio::select! {
+                    _ = tokio::time::sleep(RETRY_DELAY) => (),
+                    _ = shutdown::requested() => return,
+                }
+            },
+        }
+    };
+
+    for (topic, collection) in topics {
+        let Some(found) = listed.iter().find(|t| t.name == topic) else {
+            warn!("Kafka topic '{}' does not exist, so it is not consumed", topic);
+            continue;
+        };
+        for &partition in &found.partitions {
+            match client.partition_client(topic.clone(), partition, UnknownTopicHandling::Retry).await {
+                Ok(partition_client) => shutdown::spawn(consume_partition(partition_client, collection.clone())),
+                Err(err) => error!("Could not consume partition {} of Kafka topic '{}': {}", partition, topic, err),
+            }
+        }
+    }
+}
+
+
+/// Consumes the partition of `client` into `collection`, a batch at a time, from the
+/// offset recorded for it, or its earliest record. The batch read so far is added when
+/// the data service is asked to stop.
+async fn consume_partition(client: PartitionClient, collection: String) {
+    let (topic, partition) = (client.topic().to_string(), client.partition());
+    let batch_rows = config::envar_usize("ZENITHDS_KAFKA_BATCH_ROWS").max(1);
+    let batch_time = Duration::from_secs(config::envar_usize("ZENITHDS_KAFKA_BATCH_SECONDS") as u64);
+
+    let mut offset = match read_offset(&collection, &topic, partition) {
+        Ok(Some(offset)) => offset,
+        Ok(None) => match earliest_offset(&client).await {
+            Some(offset) => offset,
+            None => return,
+        },
+        Err(err) => {
+            error!("Could not read the offsets of Kafka topic '{}' in collection '{}': {}", topic, collection, err);
+            return;
+        },
+    };
+    debug!("Consuming partition {} of Kafka topic '{}' from offset {}", partition, topic, offset);
+
+    let mut batch = Batch::new(offset);
+    loop {
+        let wait = match batch.started {
+            Some(started) => batch_time.saturating_sub(started.elapsed()),
+            None => batch_time,
+        }.min(MAX_FETCH_WAIT);
+        let fetched = tokio::select! {
+            fetched = client.fetch_records(offset, 1..MAX_FETCH_BYTES, wait.as_millis() as i32) => Some(fetched),
+            _ = shutdown::requested() => None,
+        };
+        match fetched {
+            Some(Ok((records, _))) => {
+                // Records after a full batch are fetched again for the next one.
+                for record in records {
+                    if batch.values.len() >= batch_rows {
+                        break;
+                    }
+                    if batch.started.is_none() {
+                        batch.started = Some(Instant::now());
+                    }
+                    match record.record.value.as_deref().map(serde_json::from_slice::<Value>) {
+                        Some(Ok(value)) if value.is_object() => batch.values.push(value),
+                        _ => warn!("Skipped record {} in partition {} of Kafka topic '{}', which is not a JSON object",
+                            record.offset, partition, topic),
+                    }
+                    offset = record.offset + 1;
+                    batch.next_offset = offset;
+                }
+            },
+            Some(Err(KafkaError::ServerError { protocol_error: ProtocolError::OffsetOutOfRange, .. })) => {
+                // The records from the offset were removed by the retention of the topic, or it was recreated.
+                let Some(earliest) = earliest_offset(&client).await else {
+                    return;
+                };
+                warn!("Offset {} of partition {} of Kafka topic '{}' is gone, so consuming from {}", offset, partition, topic, earliest);
+                (offset, batch.next_offset) = (earliest, earliest);
+            },
+            Some(Err(err)) => {
+                warn!("Could not fetch from partition {} of Kafka topic '{}': {}", partition, topic, err);
+                tokio::select! {
+                    _ = tokio::time::sleep(RETRY_DELAY) => (),
+                    _ = shutdown::requested() => (),
+                }
+            },
+            None => {
+                add_batch(&collection, &topic, partition, &batch).await;
+                return;
+            },
+        }
+
+        let full = batch.values.len() >= batch_rows;
+        let due = batch.started.is_some_and(|started| started.elapsed() >= batch_time);
+        if full || due {
+            while !add_batch(&collection, &topic, partition, &batch).await {
+                tokio::select! {
+                    _ = tokio::time::sleep(RETRY_DELAY) => (),
+                    _ = shutdown::requested() => return,
+                }
+            }
+            batch = Batch::new(offset);
+        }
+    }
+}
+
+
+/// Returns the earliest offset of the partition of `client`, waiting until it can be found,
+/// or `None` if the data service is asked to stop first.
+async fn earliest_offset(client: &PartitionClient) -> Option<i64> {
+    loop {
+        let found = tokio::select! {
+            found = client.get_offset(OffsetAt::Earliest) => found,
+            _ = shutdown::requested() => return None,
+        };
+        match found {
+            Ok(offset) => return Some(offset),
+            Err(err) => {
+                warn!("Could not find the earliest offset of partition {} of Kafka topic '{}': {}", client.partition(), client.topic(), err);
+                tokio::select! {
+                    _ = tokio::time::sleep(RETRY_DELAY) => (),
+                    _ = shutdown::requested() => return None,
+                }
+            },
+        }
+    }
+}
+
+
+/// Adds the records in `batch`, from `partition` of `topic`, to `collection` as a new file,
+/// and records the offset after them. Returns whether it should not be tried again: a batch
+/// the collection rejects is skipped, so that it does not hold up the rest of the topic.
+async fn add_batch(collection: &str, topic: &str, partition: i32, batch: &Batch) -> bool {
+    if batch.next_offset == batch.first_offset {
+        return true;
+    }
+    let filename = format!("{}-{}-{}.csv", topic, partition, batch.first_offset);
+    let added = {
+        let (collection, filename, values) = (collection.to_string(), filename.clone(), batch.values.clone());
+        let detail = format!("offsets {} to {} of partition {} of topic '{}'", batch.first_offset, batch.next_offset - 1, partition, topic);
+        request_id::spawn_blocking(move || match values.is_empty() {
+            true => Ok(0),
+            false => insert(&collection, filename, values, detail),
+        }).await
+    };
+    match added {
+        Ok(rows) => info!("Added {} records from Kafka topic '{}' to collection '{}'", rows, topic, collection),
+        Err(err @ (ZenithError::QueryError(_) | ZenithError::PredicateError(_) | ZenithError::HeaderMismatch { .. }
+            | ZenithError::Conflict(_))) => {
+            error!("Skipped offsets {} to {} of partition {} of Kafka topic '{}', which collection '{}' rejected: {}",
+                batch.first_offset, batch.next_offset - 1, partition, topic, collection, err);
+        },
+        Err(err) => {
+            warn!("Could not add records from Kafka topic '{}' to collection '{}': {}", topic, collection, err);
+            return false;
+        },
+    }
+    if let Err(err) = write_offset(collection, topic, partition, batch.next_offset) {
+        // The batch is added again, to the same file, if the data service restarts.
+        error!("Could not record offset {} of Kafka topic '{}' in collection '{}': {}", batch.next_offset, topic, collection, err);
+    }
+    true
+}
+
+
+/// Inserts the records with `values` into `collection` as `filename`, as they
+/// would be by `create`, returning the number of rows added.
+fn insert(collection: &str, filename: String, values: Vec<Value>, detail: String) -> Result<usize, ZenithError> {
+    let payload: CreatePayload = serde_json::from_value(json!({ "filename": filename, "rows": values }))
+        .map_err(|err| ZenithError::QueryError(format!("Invalid records: {}", err)))?;
+    let payload = db::order_keyed_columns(collection, payload)?;
+    let rows = payload.rows.len();
+    let previous_rows = db::file_rows(collection, &filename).ok().flatten();
+    let contents = changes::watched().then(|| (payload.header.clone(), payload.rows.clone()));
+    db::insert(collection, payload)?;
+
+    let kafka = Principal { name: "kafka".to_string(), ..Principal::default() };
+    let entry = AuditEntry::new(&kafka, "ingest", collection)
+        .filename(&filename)
+        .rows(previous_rows, Some(rows))
+        .detail(detail);
+    changes::publish(&entry, contents);
+    audit::record(entry);
+    Ok(rows)
+}
@@ -0,0 +1,1928 @@
+//! ZenithDS, a data service for querying collections of CSV files.
+//!
+//! The `zenithds` binary serves the API with `serve`. Other applications can do the same,
+//! mount the router from `build_router` in their own, or use `db` and `types` directly.
+
+use axum::{
+    body::Bytes,
+    http::{HeaderMap, StatusCode, header::{ACCEPT, CONTENT_DISPOSITION, CONTENT_TYPE}},
+    extract::{Extension, Json, Path, Query, ws::WebSocketUpgrade},
+    response::IntoResponse,
+    routing::{get, post, delete},
+    middleware,
+    Router,
+};
+use tower_http::decompression::RequestDecompressionLayer;
+use tracing::{info, warn, error};
+use std::{
+    collections::{HashMap, HashSet},
+    net::SocketAddr,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+pub mod types;
+pub mod config;
+pub mod db;
+pub mod crypto;
+pub mod remote;
+pub mod storage;
+pub mod catalog;
+pub mod schema;
+pub mod auth;
+pub mod jwt;
+pub mod limit;
+pub mod tls;
+pub mod audit;
+pub mod acl;
+pub mod request_id;
+pub mod slow_query;
+pub mod access_log;
+pub mod cli;
+pub mod shutdown;
+pub mod cors;
+pub mod logging;
+pub mod reload;
+pub mod listen;
+pub mod disk;
+pub mod open_files;
+pub mod queries;
+pub mod jobs;
+pub mod results;
+pub mod xlsx;
+pub mod avro;
+pub mod grpc;
+pub mod graphql;
+pub mod postgres;
+pub mod flight;
+pub mod odata;
+pub mod changes;
+pub mod changelog;
+pub mod webhooks;
+pub mod events;
+pub mod kafka;
+pub mod watcher;
+pub mod stats;
+pub mod views;
+pub mod saved_queries;
+pub mod schedules;
+pub mod maintenance;
+pub mod triggers;
+pub mod windows;
+pub mod replication;
+#[cfg(feature = "client")]
+pub mod client;
+
+/// The number of rows serialized at a time when streaming NDJSON or CSV.
+const STREAM_CHUNK_ROWS: usize = 1000;
+
+/// How often jobs and result sets that have expired are forgotten, if nothing else forgets them first.
+const EXPIRY_INTERVAL: Duration = Duration::from_secs(60);
+
+use crate::audit::AuditEntry;
+use crate::maintenance::Timing;
+use crate::auth::Principal;
+use crate::types::{
+    error::{Missing, ZenithError},
+    query::IntegrityStatus,
+    api::*,
+};
+
+
+/// Builds the router of the API, nested under `ZENITHDS_PREFIX`, with its middleware: request
+/// ids, access logs, CORS, IP filtering, rate limits, and authentication. It can be served on its
+/// own, or merged into a larger application, which must be served with `ConnectInfo<SocketAddr>`
+/// for clients to be told apart by their IP address. Returns an error if the CORS settings are not valid.
+pub fn build_router() -> Result<Router, String> {
+    let api_routes_v1 = Router::new()
+        // Bodies sent with `Content-Encoding: gzip` or `zstd` are decompressed as they are read.
+        .route("/render", post(render_csv_v1).layer(RequestDecompressionLayer::new()))
+        .route("/create/{collection}", post(create_csv_v1).layer(RequestDecompressionLayer::new()))
+        .route("/validate/{collection}", post(validate_csv_v1).layer(RequestDecompressionLayer::new()))
+        .route("/import/{collection}", post(import_csv_v1))
+        .route("/upload/{collection}/{filename}", post(upload_csv_v1).layer(RequestDecompressionLayer::new()))
+        .route("/delete/{collection}/{filename}", delete(delete_csv_v1))
+        .route("/files/{collection}", get(list_files_v1))
+        .route("/export/{collection}/{filename}", get(export_csv_v1))
+        .route("/replicate/{collection}", post(replicate_collection_v1))
+        .route("/query/{collection}", post(query_post_v1))
+        .route("/queries/{id}", delete(cancel_query_v1))
+        .route("/queries/saved", get(list_saved_queries_v1))
+        .route("/queries/saved/{name}", get(get_saved_query_v1).put(save_query_v1).delete(remove_saved_query_v1))
+        .route("/queries/saved/{name}/run", post(run_saved_query_v1))
+        .route("/schedules", get(list_schedules_v1).post(create_schedule_v1))
+        .route("/schedules/{id}", get(get_schedule_v1).delete(remove_schedule_v1))
+        .route("/schedules/{id}/run", post(run_schedule_v1))
+        .route("/jobs/query/{collection}", post(submit_query_job_v1))
+        .route("/jobs/{id}", get(get_job_v1).delete(delete_job_v1))
+        .route("/jobs/{id}/result", get(get_job_result_v1))
+        .route("/results/{id}", get(get_result_set_v1).delete(delete_result_set_v1))
+        .route("/explain/{collection}", post(explain_query_v1))
+        .route("/profile/{collection}", post(profile_collection_v1))
+        .route("/top/{collection}", post(top_values_v1))
+        .route("/histogram/{collection}", post(histogram_v1))
+        .route("/graphql", post(graphql_v1))
+        .route("/odata", get(odata_service_v1))
+        .route("/odata/$metadata", get(odata_metadata_v1))
+        .route("/odata/{name}", get(odata_entities_v1))
+        .route("/changes", get(change_log_v1))
+        .route("/ws/changes/{collection}", get(watch_changes_v1))
+        .route("/snapshot/{collection}", post(snapshot_collection_v1))
+        .route("/restore/{collection}/{snapshot_id}", post(restore_collection_v1))
+        .route("/versions/{collection}/{filename}", get(list_versions_v1))
+        .route("/versions/{collection}/{filename}/{version_id}", get(get_version_v1))
+        .route("/rollback/{collection}/{filename}/{version_id}", post(rollback_version_v1))
+        .route("/dedupe/{collection}", post(dedupe_collection_v1))
+        .route("/triggers/{collection}", get(list_triggers_v1).post(create_trigger_v1))
+        .route("/triggers/{collection}/{id}", delete(remove_trigger_v1))
+        .route("/views", get(list_views_v1).post(create_view_v1))
+        .route("/views/{name}", get(get_view_v1).delete(remove_view_v1))
+        .route("/views/{name}/refresh", post(refresh_view_v1))
+        .route("/views/{name}/query", post(query_view_v1))
+        .route("/verify/{collection}", post(verify_collection_v1))
+        .route("/admin/rebuild-catalog/{collection}", post(rebuild_catalog_v1))
+        .route("/admin/rename-column/{collection}", post(rename_column_v1))
+        .route("/admin/drop-column/{collection}", post(drop_column_v1))
+        .route("/admin/audit", get(audit_log_v1))
+        .route("/admin/webhooks/{collection}", get(list_webhooks_v1).post(add_webhook_v1))
+        .route("/admin/webhooks/{collection}/{id}", delete(remove_webhook_v1))
+        .route("/admin/webhooks/{collection}/{id}/deliveries", get(list_deliveries_v1))
+        .route("/admin/config", get(get_config_v1))
+        .route("/admin/reload", post(reload_config_v1))
+        .route("/admin/maintenance", get(list_maintenance_tasks_v1))
+        .route("/admin/maintenance/{name}/run", post(run_maintenance_task_v1))
+        .route("/schema/{collection}", get(get_schema_v1).put(set_schema_v1).delete(remove_schema_v1))
+        .route("/schema/{collection}/infer", post(infer_schema_v1))
+        .route("/schema/{collection}/columns", post(add_column_v1))
+        // Requests are limited by the principal they are authenticated as.
+        .route_layer(middleware::from_fn(limit::limit))
+        // Every route but the health check needs an API key, if any are configured.
+        .route_layer(middleware::from_fn(auth::authenticate))
+        // Changes made by requests replicated from a peer are not replicated again.
+        .route_layer(middleware::from_fn(replication::mark))
+        .route("/", get(root));
+
+    Ok(Router::new()
+        .nest(config::prefix("v1").as_str(), api_routes_v1)
+        .layer(middleware::from_fn(acl::filter))
+        .layer(cors::layer()?)
+        .layer(middleware::from_fn(access_log::log))
+        .layer(middleware::from_fn(request_id::propagate)))
+}
+
+
+/// Starts the tasks the data service runs alongside its API: the maintenance tasks, which are
+/// retention of files and rows, expiring jobs and result sets, scheduled queries, and config reloads, then Kafka
+/// consumers, the file watcher, and the gRPC and PostgreSQL listeners, each if it is configured.
+/// `serve` starts them itself, so this is only needed when the router is served some other way.
+pub fn spawn_background_tasks() {
+    enforce_retention();
+    enforce_row_retention();
+    maintenance::register("expire_jobs", Timing::Interval(EXPIRY_INTERVAL), |_| async {
+        jobs::forget_expired();
+        Ok(())
+    });
+    maintenance::register("expire_result_sets", Timing::Interval(EXPIRY_INTERVAL), |_| async {
+        results::forget_expired();
+        Ok(())
+    });
+    maintenance::register("run_schedules", Timing::Aligned(Duration::from_secs(60)), schedules::run_due);
+    reload::watch();
+    shutdown::spawn(kafka::consume());
+    tokio::spawn(watcher::watch());
+    tokio::spawn(grpc::serve());
+    tokio::spawn(postgres::serve());
+}
+
+
+/// Checks the settings, then serves the API and starts the background tasks until the data service
+/// is asked to stop with `SIGINT` or `SIGTERM`, waiting for requests and tasks to finish. Returns
+/// an error if the settings are not valid, or the API cannot be served. Settings are read from the
+/// environment, and any overrides or config file loaded first with `config`.
+pub async fn serve() -> Result<(), String> {
+    match config::envar_str("ZENITHDS_STORAGE").as_str() {
+        "filesystem" => {
+            info!("Keeping collections in '{}'", config::data_path().display());
+            for (collection, path) in config::collection_paths() {
+                info!("Keeping collection '{}' in '{}'", collection, path.display());
+            }
+        },
+        "memory" => info!("Storing files in memory. They will be lost when the data service stops"),
+        other => return Err(format!("Unknown storage '{}'", other)),
+    }
+    let tls_config = match tls::enabled() {
+        // A reverse proxy in front of a Unix socket terminates TLS itself.
+        true if listen::unix_socket() => return Err("TLS cannot be used with a Unix socket".to_string()),
+        true => {
+            let tls_config = tls::server_config().map_err(|err| err.to_string())?;
+            info!("Serving HTTPS");
+            if tls::client_auth_enabled() {
+                info!("Accepting client certificates");
+            }
+            Some(tls_config)
+        },
+        false => None,
+    };
+    if auth::enabled() {
+        info!("Requiring API keys, tokens, or client certificates");
+    }
+    if acl::enabled()? {
+        info!("Allowing clients by IP address");
+    }
+    if crypto::key().map_err(|err| err.to_string())?.is_some() {
+        info!("Encrypting files at rest");
+    }
+
+    let app = build_router()?;
+    spawn_background_tasks();
+    tokio::spawn(shutdown::listen());
+
+    let listener = listen::bind().await
+        .map_err(|err| format!("Could not establish server on {}: {}", listen::address(), err))?;
+    info!("Establish listener on {}", listen::address());
+    // Once asked to stop, the server stops accepting connections and waits for
+    // requests to finish, until they are cut off at the deadline.
+    let server = async {
+        match (listener, &tls_config) {
+            (listen::Listener::Tcp(listener), Some(tls_config)) => {
+                let config = axum_server::tls_rustls::RustlsConfig::from_config(Arc::clone(tls_config));
+                let handle = axum_server::Handle::new();
+                let stopping = handle.clone();
+                tokio::spawn(async move {
+                    shutdown::requested().await;
+                    stopping.graceful_shutdown(None);
+                });
+                match listener.into_std().and_then(axum_server::from_tcp) {
+                    Ok(server) => server.handle(handle).acceptor(tls::ClientCertAcceptor::new(config)).serve(app.into_make_service_with_connect_info::<SocketAddr>()).await,
+                    Err(err) => Err(err),
+                }
+            },
+            (listen::Listener::Tcp(listener), None) => axum::serve(listener, app.into_make_service_with_connect_info::<SocketAddr>())
+                .with_graceful_shutdown(shutdown::requested())
+                .await,
+            // Clients on a Unix socket do not have an IP address.
+            #[cfg(unix)]
+            (listen::Listener::Unix(listener), _) => axum::serve(listener, app.into_make_service())
+                .with_graceful_shutdown(shutdown::requested())
+                .await,
+        }
+    };
+    let served = tokio::select! {
+        served = server => served,
+        _ = shutdown::deadline() => {
+            warn!("Requests were still running after the drain timeout");
+            Ok(())
+        },
+    };
+    listen::unbind();
+    shutdown::finish().await;
+    served.map_err(|_| format!("Could not create server on {}", listen::address()))
+}
+
+
+/// Periodically deletes files older than the retention period configured for their collection,
+/// every `ZENITHDS_RETENTION_INTERVAL` seconds, as the maintenance task `retention`. A run that
+/// cannot expire the files of a collection fails with the last error, after trying the others.
+fn enforce_retention() {
+    let policies = config::retention();
+    if policies.is_empty() {
+        return;
+    }
+    info!("Retention periods in days: {:?}", policies);
+
+    let period = config::envar_usize("ZENITHDS_RETENTION_INTERVAL").max(1) as u64;
+    maintenance::register("retention", Timing::Interval(Duration::from_secs(period)), move |_| {
+        let policies = policies.clone();
+        async move {
+            let mut result = Ok(());
+            for (collection, days) in policies {
+                let expired = {
+                    let collection = collection.clone();
+                    request_id::spawn_blocking(move || db::expire(&collection, Duration::from_secs(days.saturating_mul(24 * 60 * 60)))).await
+                };
+                match expired {
+                    Ok(removed) => {
+                        if !removed.is_empty() {
+                            info!("Expired {} files in collection '{}': {:?}", removed.len(), collection, removed);
+                        }
+                        let retention = Principal { name: "retention".to_string(), ..Principal::default() };
+                        for filename in &removed {
+                            let entry = AuditEntry::new(&retention, "expire", &collection).filename(filename);
+                            changes::publish(&entry, None);
+                            audit::record(entry);
+                        }
+                    },
+                    Err(err) => {
+                        warn!("Could not expire files in collection '{}': {}", collection, err);
+                        result = Err(err);
+                    }
+                }
+            }
+            result
+        }
+    });
+}
+
+/// Periodically removes rows whose date in a column is older than the retention period configured
+/// for it, every `ZENITHDS_RETENTION_INTERVAL` seconds, as the maintenance task `row_retention`,
+/// rewriting the files they are in. A run that cannot expire the rows of a collection fails with
+/// the last error, after trying the others.
+fn enforce_row_retention() {
+    let policies = config::row_retention();
+    if policies.is_empty() {
+        return;
+    }
+    info!("Row retention periods in days: {:?}", policies);
+
+    let period = config::envar_usize("ZENITHDS_RETENTION_INTERVAL").max(1) as u64;
+    maintenance::register("row_retention", Timing::Interval(Duration::from_secs(period)), move |_| {
+        let policies = policies.clone();
+        async move {
+            let mut result = Ok(());
+            for (collection, column, days) in policies {
+                let expired = {
+                    let (collection, column) = (collection.clone(), column.clone());
+                    request_id::spawn_blocking(move || db::expire_rows(&collection, &column, Duration::from_secs(days.saturating_mul(24 * 60 * 60)))).await
+                };
+                match expired {
+                    Ok((removed, rewritten)) => {
+                        if rewritten.is_empty() {
+                            continue;
+                        }
+                        info!("Expired {} rows in collection '{}', rewriting {:?}", removed, collection, rewritten);
+                        let retention = Principal { name: "retention".to_string(), ..Principal::default() };
+                        let entry = AuditEntry::new(&retention, "expire_rows", &collection)
+                            .detail(format!("{} rows older than {} days in '{}', rewriting {} files", removed, days, column, rewritten.len()));
+                        changes::publish(&entry, None);
+                        audit::record(entry);
+                    },
+                    Err(err) => {
+                        warn!("Could not expire rows in collection '{}': {}", collection, err);
+                        result = Err(err);
+                    }
+                }
+            }
+            result
+        }
+    });
+}
+
+async fn root() -> &'static str {
+    "Welcome to ZenithDS"
+}
+
+
+/// The content types of bodies that `render` accepts.
+const RENDER_CONTENT_TYPES: [&str; 4] = ["text/csv", "text/plain", "text/tab-separated-values", "application/csv"];
+
+
+/// Renders a request `body` as CSV data, returning
+/// a `header`, `rows`,and any `removed` records.
+async fn render_csv_v1(
+    Query(params): Query<RenderParameters>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<Json<RenderResponse>, ZenithError> {
+    // A body without a content type is taken to be CSV.
+    if let Some(content_type) = headers.get(CONTENT_TYPE) {
+        let essence = content_type.to_str().unwrap_or_default()
+            .split(';').next().unwrap_or_default()
+            .trim().to_lowercase();
+        if !RENDER_CONTENT_TYPES.contains(&essence.as_str()) {
+            return Err(ZenithError::UnsupportedMediaType(format!(
+                "The body must be CSV or plain text ({}), not '{}'", RENDER_CONTENT_TYPES.join(", "), essence
+            )));
+        }
+    }
+    let quote = params.quote.unwrap_or(Dialect::default().quote);
+    let delimiter = params.delimiter.unwrap_or_else(|| db::sniff_delimiter(db::skip_lines(&body[..], params.skip_rows), quote));
+    let dialect = Dialect { delimiter, quote };
+    let options = RenderOptions { skip_rows: params.skip_rows, header_row: params.header_row, max_rows: params.max_rows };
+    let (header, rows, removed) = match db::render_with(&body[..], &dialect, &options) {
+        Ok(rendered) => rendered,
+        Err(ZenithError::CSVError(err)) => return Err(ZenithError::QueryError(format!("The body is not valid CSV: {}", err))),
+        Err(err) => return Err(err),
+    };
+    if let (Some(header_row), true) = (params.header_row, header.is_empty()) {
+        return Err(ZenithError::QueryError(format!(
+            "The body has {} records after the skipped lines, so record {} cannot be the header", removed.len(), header_row
+        )));
+    }
+    if header.is_empty() {
+        let found = match removed.len() {
+            0 => "The body is empty".to_string(),
+            n => format!("No record in the body ({} found) has a value in every field", n),
+        };
+        return Err(ZenithError::QueryError(format!("{}, so a header cannot be found", found)));
+    }
+    Ok(Json( RenderResponse { header, rows, removed } ))
+}
+
+
+/// Creates or overwrites a CSV as `filename` in
+/// the `collection` with a given `header` and `rows`,
+/// returning the number of `rows` created and any `rejected`.
+async fn create_csv_v1(
+    Path(collection): Path<String>,
+    Extension(principal): Extension<Principal>,
+    Json(payload): Json<CreatePayload>,
+) -> Result<Json<CreateResponse>, ZenithError> {
+
+    info!("Received a request to create '{}' in collection '{}', with a header of length {} and {} rows",
+        payload.filename, collection, payload.header.len(), payload.rows.len());
+    // Peers are sent the rows in the order of the collection, as arrays.
+    let payload = db::order_keyed_columns(&collection, payload)?;
+    let mut replica = payload.clone();
+    let previous_rows = db::file_rows(&collection, &payload.filename).ok().flatten();
+    match db::insert_partial(&collection, payload) {
+        Ok(rejected) => {
+            info!("Inserted in collection '{}', rejecting {} rows", collection, rejected.len());
+            // Peers are sent only the rows created.
+            let rejected_rows: HashSet<usize> = rejected.iter().filter_map(|problem| problem.row).collect();
+            let mut i = 0;
+            replica.rows.retain(|_| {
+                i += 1;
+                !rejected_rows.contains(&(i - 1))
+            });
+            replica.partial = false;
+            let entry = AuditEntry::new(&principal, "create", &collection)
+                .filename(&replica.filename)
+                .rows(previous_rows, Some(replica.rows.len()));
+            changes::publish(&entry, changes::watched().then(|| (replica.header.clone(), replica.rows.clone())));
+            audit::record(entry);
+            Ok(Json( CreateResponse { rows: replica.rows.len(), rejected } ))
+        },
+        Err(err) => {
+            warn!("The request to create in collection '{}' was unsuccessful", collection);
+            Err(err)
+        }
+    }
+}
+
+
+/// Checks a `header` and `rows` as `create` would before creating them as `filename` in the
+/// `collection`, returning the problems found, without creating anything.
+async fn validate_csv_v1(
+    Path(collection): Path<String>,
+    Json(payload): Json<CreatePayload>,
+) -> Result<Json<ValidateResponse>, ZenithError> {
+
+    info!("Received a request to validate '{}' in collection '{}', with a header of length {} and {} rows",
+        payload.filename, collection, payload.header.len(), payload.rows.len());
+    let payload = db::order_keyed_columns(&collection, payload)?;
+    let report = db::validate(&collection, payload)?;
+    info!("Validated {} rows in collection '{}', with {} problems", report.rows, collection, report.total_problems);
+    Ok(Json(report))
+}
+
+
+/// Downloads a CSV or workbook from a `url` and creates it as `filename` in the `collection`.
+/// If no `filename` is given, the last segment of the URL path is used.
+async fn import_csv_v1(
+    Path(collection): Path<String>,
+    Extension(principal): Extension<Principal>,
+    Json(payload): Json<ImportPayload>,
+) -> Result<Json<ImportResponse>, ZenithError> {
+
+    let filename = match payload.filename {
+        Some(filename) => filename,
+        None => payload.url.split(['?', '#']).next().unwrap_or("")
+            .rsplit('/').next().unwrap_or("").to_string(),
+    };
+    info!("Received a request to import '{}' as '{}' in collection '{}'", payload.url, filename, collection);
+
+    let bytes = remote::download(&payload.url).await?;
+    let detail = format!("from '{}'", payload.url);
+    let source = FileSource { sheet: payload.sheet, delimiter: payload.delimiter, quote: payload.quote };
+    insert_file(&collection, &principal, "import", filename, &bytes, source, payload.on_conflict, Some(detail))
+}
+
+
+/// Creates the CSV or workbook sent as the request `body` as `filename` in the `collection`.
+async fn upload_csv_v1(
+    Path((collection, filename)): Path<(String, String)>,
+    Extension(principal): Extension<Principal>,
+    Query(params): Query<UploadParameters>,
+    body: Bytes,
+) -> Result<Json<ImportResponse>, ZenithError> {
+
+    info!("Received a request to upload '{}' in collection '{}', of {} bytes", filename, collection, body.len());
+    let source = FileSource { sheet: params.sheet, delimiter: params.delimiter, quote: params.quote };
+    insert_file(&collection, &principal, "upload", filename, &body, source, params.on_conflict, None)
+}
+
+
+/// How to read a file that is imported or uploaded.
+struct FileSource {
+    /// The sheet to read, if the file is a workbook.
+    sheet: Option<String>,
+    /// The delimiter and quote of the file, if they are not those of the collection.
+    delimiter: Option<CsvCharacter>,
+    quote: Option<CsvCharacter>,
+}
+
+
+/// Returns `filename` with an `.avro` extension replaced by `.csv`.
+fn avro_csv_name(filename: &str) -> String {
+    match filename.strip_suffix(".avro") {
+        Some(stem) => format!("{}.csv", stem),
+        None => filename.to_string(),
+    }
+}
+
+
+/// Renders the `bytes` of a CSV, of the sheet of a workbook, or of the records of an Avro file,
+/// and creates them as `filename` in the `collection`. A workbook or Avro file is stored as CSV,
+/// with its extension replaced by `.csv`.
+/// The file is stored in the dialect of the collection, or in its own if the collection has no files.
+#[allow(clippy::too_many_arguments)]
+fn insert_file(
+    collection: &str,
+    principal: &Principal,
+    action: &str,
+    filename: String,
+    bytes: &[u8],
+    source: FileSource,
+    on_conflict: OnConflict,
+    detail: Option<String>,
+) -> Result<Json<ImportResponse>, ZenithError> {
+
+    let workbook = xlsx::is_workbook(bytes) || xlsx::is_workbook_name(&filename);
+    let (filename, dialect, (header, rows, removed)) = match workbook {
+        _ if avro::is_avro(bytes) => {
+            let (header, rows) = avro::read(bytes)?;
+            (avro_csv_name(&filename), None, (header, rows, Vec::new()))
+        },
+        true => {
+            let csv = xlsx::to_csv(bytes, source.sheet.as_deref())?;
+            (xlsx::csv_name(&filename), None, db::render(&csv, &Dialect::default())?)
+        },
+        false => {
+            let dialect = catalog::dialect(collection)?.with(source.delimiter, source.quote);
+            (filename, Some(dialect), db::render(bytes, &dialect)?)
+        },
+    };
+    if header.is_empty() {
+        return Err(ZenithError::QueryError(format!("Header cannot be found in '{}'", filename)));
+    }
+    let (row_count, removed_count) = (rows.len(), removed.len());
+
+    let payload = CreatePayload {
+        filename: filename.clone(), header, rows, on_conflict,
+        delimiter: dialect.map(|d| d.delimiter), quote: dialect.map(|d| d.quote), partial: false, keyed: false,
+    };
+    let previous_rows = db::file_rows(collection, &filename).ok().flatten();
+    let contents = changes::watched().then(|| (payload.header.clone(), payload.rows.clone()));
+    match db::insert(collection, payload) {
+        Ok(()) => {
+            info!("Inserted {} rows in collection '{}', removing {}", row_count, collection, removed_count);
+            let mut entry = AuditEntry::new(principal, action, collection)
+                .filename(&filename)
+                .rows(previous_rows, Some(row_count));
+            if let Some(detail) = detail {
+                entry = entry.detail(detail);
+            }
+            changes::publish(&entry, contents);
+            audit::record(entry);
+            Ok(Json( ImportResponse { filename, rows: row_count, removed: removed_count } ))
+        },
+        Err(err) => {
+            warn!("The request to {} in collection '{}' was unsuccessful", action, collection);
+            Err(err)
+        }
+    }
+}
+
+
+/// Deletes a CSV as `filename` from the `collection`.
+async fn delete_csv_v1(
+    Path((collection, filename)): Path<(String, String)>,
+    Extension(principal): Extension<Principal>,
+) -> Result<(), ZenithError> {
+
+    info!("Received a request to delete '{}' in collection '{}'", filename, collection);
+    let previous_rows = db::file_rows(&collection, &filename).ok().flatten();
+    match db::delete(&collection, &filename) {
+        Ok(()) => {
+            info!("Deleted '{}' in collection '{}'", filename, collection);
+            let entry = AuditEntry::new(&principal, "delete", &collection)
+                .filename(&filename)
+                .rows(previous_rows, None);
+            changes::publish(&entry, None);
+            audit::record(entry);
+            Ok(())
+        },
+        Err(err) => {
+            warn!("The request to delete in collection '{}' was unsuccessful", collection);
+            Err(err)
+        }
+    }
+}
+
+
+/// Lists the `files` in the `collection`, with the `filename` and `size` of each.
+async fn list_files_v1(
+    Path(collection): Path<String>,
+) -> Result<Json<FilesResponse>, ZenithError> {
+
+    let files = db::files(&collection)?
+        .into_iter()
+        .map(|(filename, size)| FileSummary { filename, size })
+        .collect();
+    Ok(Json( FilesResponse { files } ))
+}
+
+
+/// Downloads `filename` in the `collection` as CSV, with the columns the principal cannot
+/// see masked. If `escape_formulas` is set, or `ZENITHDS_ESCAPE_FORMULAS` is set and it
+/// is not turned off, values that a spreadsheet would run as formulas are escaped.
+/// With `format=avro`, it is downloaded as an Avro file instead.
+async fn export_csv_v1(
+    Path((collection, filename)): Path<(String, String)>,
+    Query(parameters): Query<ExportParameters>,
+    Extension(principal): Extension<Principal>,
+) -> Result<impl IntoResponse, ZenithError> {
+
+    let (header, rows, _) = db::read(&collection, &filename)?;
+    let masks = schema::read(&collection)?.map(|s| s.masks(&principal.roles)).unwrap_or_default();
+    let rows: Vec<Vec<String>> = rows.into_iter().map(|row| schema::mask_row(&masks, &header, row)).collect();
+    let header = schema::mask_header(&masks, header);
+
+    if parameters.format.unwrap_or_default() == ExportFormat::Avro {
+        // Masked columns are strings, as their values are no longer of the type of the column.
+        let schema = schema::read(&collection)?;
+        let types: Vec<schema::ColumnType> = header.iter()
+            .map(|name| match (&schema, masks.contains_key(name)) {
+                (Some(schema), false) => schema.columns.iter().find(|c| c.name == *name).map(|c| c.column_type).unwrap_or_default(),
+                _ => schema::ColumnType::String,
+            })
+            .collect();
+        let bytes = avro::write(&collection, &header, &types, &rows)?;
+        let stem = filename.strip_suffix(".csv").unwrap_or(&filename);
+        let disposition = format!("attachment; filename=\"{}.avro\"", stem);
+        return Ok(([(CONTENT_TYPE, "application/avro".to_string()), (CONTENT_DISPOSITION, disposition)], bytes));
+    }
+
+    let escape_formulas = parameters.escape_formulas
+        .unwrap_or(!config::envar_str("ZENITHDS_ESCAPE_FORMULAS").is_empty());
+    let bytes = db::to_csv(header, rows, escape_formulas)?;
+    let disposition = format!("attachment; filename=\"{}\"", filename);
+    Ok(([(CONTENT_TYPE, "text/csv".to_string()), (CONTENT_DISPOSITION, disposition)], bytes))
+}
+
+
+/// Brings each replica peer up to date with the `collection`, by sending every
+/// file in the collection and deleting any files the peer has that it does not.
+async fn replicate_collection_v1(
+    Path(collection): Path<String>,
+) -> Result<Json<ReplicateResponse>, ZenithError> {
+
+    info!("Received a request to replicate collection '{}'", collection);
+    // Raises a `NotFound` error if the collection does not exist.
+    db::files(&collection)?;
+    Ok(Json( ReplicateResponse { peers: replication::collection(&collection).await } ))
+}
+
+
+/// Runs a query on `collection` with `predicates` for `principal`, returning a `header`
+/// and `rows`, and with `debug`, a profile of how it was run. Stops if `cancelled` is set.
+/// 
+/// If federation nodes are configured, the query is also run on each
+/// node, and their rows are merged with the rows found locally.
+async fn run_query(
+    collection: &str,
+    predicates: QueryPredicates,
+    principal: Principal,
+    cancelled: &queries::Cancelled,
+    debug: bool,
+) -> Result<(db::Selection, Option<QueryProfile>), ZenithError> {
+
+    let nodes = config::federation_nodes();
+    // The rows from nodes are masked here, as the collection may have no files here to be queried.
+    let masks = match nodes.is_empty() {
+        true => HashMap::new(),
+        false => {
+            let (collection, predicates, principal) = (collection.to_string(), predicates.clone(), principal.clone());
+            request_id::spawn_blocking(move || db::query_masks(&collection, &predicates, &principal)).await?
+        },
+    };
+    let selected = {
+        let (collection, predicates, cancelled) = (collection.to_string(), predicates.clone(), cancelled.clone());
+        request_id::spawn_blocking(move || match debug {
+            true => db::select_profiled(&collection, predicates, Some(&principal), &cancelled).map(|(s, p)| (s, Some(p))),
+            false => db::select(&collection, predicates, Some(&principal), &cancelled).map(|s| (s, None)),
+        }).await
+    };
+    let ((mut header, mut rows), mut profile) = match selected {
+        Ok(result) => result,
+        // The coordinator does not need to hold any of the collection itself.
+        Err(ZenithError::NotFound(Missing::Collection(_))) if !nodes.is_empty() => ((Vec::new(), Vec::new()), debug.then(QueryProfile::default)),
+        Err(err) => return Err(err),
+    };
+    if nodes.is_empty() {
+        return Ok(((header, rows), profile));
+    }
+
+    let started = Instant::now();
+    let mut node_queries = tokio::task::JoinSet::new();
+    for node in nodes {
+        let (collection, predicates) = (collection.to_string(), predicates.clone());
+        node_queries.spawn(request_id::inherit(async move { remote::federated_select(&node, &collection, &predicates).await }));
+    }
+    while let Some(result) = node_queries.join_next().await {
+        match result {
+            Ok(Ok((node_header, node_rows))) => {
+                let node_rows = node_rows.into_iter().map(|row| schema::mask_row(&masks, &node_header, row)).collect();
+                db::merge(&mut header, &mut rows, schema::mask_header(&masks, node_header), node_rows);
+            },
+            Ok(Err(err)) => {
+                warn!("Federated query on collection '{}' was unsuccessful: {}", collection, err);
+                return Err(err);
+            },
+            Err(err) => {
+                error!("Failed to join federated query: {:?}", err);
+                return Err(ZenithError::RemoteError("A federated query did not complete".to_string()));
+            }
+        }
+    }
+    if let Some(profile) = &mut profile {
+        profile.phases.push(PhaseTiming { phase: "federation".to_string(), micros: started.elapsed().as_micros() as u64 });
+    }
+    Ok(((header, rows), profile))
+}
+
+
+/// Runs a query on `collection` like `run_query`, on its files as they were in the latest
+/// snapshot taken by `as_of` (see `db::snapshot_as_of`), so that the query returns the same
+/// rows however the collection has changed since. Federation nodes are not queried.
+async fn run_snapshot_query(
+    collection: &str,
+    as_of: &str,
+    predicates: QueryPredicates,
+    principal: Principal,
+    cancelled: &queries::Cancelled,
+    debug: bool,
+) -> Result<(db::Selection, Option<QueryProfile>), ZenithError> {
+
+    let (collection, as_of, cancelled) = (collection.to_string(), as_of.to_string(), cancelled.clone());
+    request_id::spawn_blocking(move || {
+        let snapshot_id = db::snapshot_as_of(&collection, &as_of)?;
+        info!("Querying collection '{}' as of snapshot '{}'", collection, snapshot_id);
+        db::select_snapshot(&collection, &snapshot_id, predicates, Some(&principal), &cancelled, debug)
+    }).await
+}
+
+
+/// Returns the types that the values in each column of `header` are given as, in the page of
+/// `rows` from `collection` returned. Values are given as strings, unless they are `typed` by the
+/// schema of the collection, or by the types inferred from the rows if it has no schema.
+fn column_types(
+    collection: &str,
+    header: &[String],
+    rows: &[Vec<String>],
+    typed: bool,
+) -> Result<Vec<schema::ColumnType>, ZenithError> {
+
+    Ok(match typed {
+        true => match schema::read(collection)? {
+            Some(schema) => header.iter()
+                .map(|name| schema.columns.iter().find(|c| c.name == *name).map(|c| c.column_type).unwrap_or_default())
+                .collect(),
+            None => schema::infer(header, rows).columns.into_iter().map(|c| c.column_type).collect(),
+        },
+        false => Vec::new(),
+    })
+}
+
+
+/// Returns the positions of the page of `rows` asked for by the `page` and `per_page`
+/// of the `query`, if there is one.
+fn page_range(rows: usize, query: &QueryParameters) -> Option<std::ops::Range<usize>> {
+    let per_page = query.per_page.unwrap_or_else(|| config::envar_usize("ZENITHDS_DEFAULT_PAGE_SIZE")).max(1);
+    let start = query.page.unwrap_or_else(|| config::envar_usize("ZENITHDS_DEFAULT_PAGE")).checked_mul(per_page)?;
+    (start < rows).then(|| start..rows.min(start.saturating_add(per_page)))
+}
+
+
+/// Returns the page of `rows` asked for by the `page` and `per_page` of the `query`, if there is one.
+fn page<'a>(rows: &'a [Vec<String>], query: &QueryParameters) -> Option<&'a [Vec<String>]> {
+    page_range(rows.len(), query).map(|range| &rows[range])
+}
+
+
+/// Streams the rows of `selection` in `range` as `content_type`, serializing them a chunk
+/// at a time with `serialize`, which is given the header, the rows, and whether they are first.
+fn stream_response(
+    selection: Arc<db::Selection>,
+    range: std::ops::Range<usize>,
+    content_type: &'static str,
+    serialize: impl Fn(&[String], &[Vec<String>], bool) -> Result<Vec<u8>, ZenithError> + Send + 'static,
+) -> axum::response::Response {
+
+    let (first, end) = (range.start, range.end);
+    // An empty range still has a chunk, for the header of a CSV.
+    let starts = range.step_by(STREAM_CHUNK_ROWS).chain((first == end).then_some(first));
+    let chunks = starts.map(move |start| {
+        let rows = &selection.1[start..end.min(start + STREAM_CHUNK_ROWS)];
+        serialize(&selection.0, rows, start == first)
+            .map(Bytes::from)
+            .map_err(|err| std::io::Error::other(err.to_string()))
+    });
+    let body = axum::body::Body::from_stream(futures_util::stream::iter(chunks));
+    ([(CONTENT_TYPE, content_type)], body).into_response()
+}
+
+
+/// Queries a `collection` based on `predicates`,
+/// returning a `header` and `rows`.
+/// 
+/// If federation nodes are configured, the query is also run on each
+/// node, and their rows are merged with the rows found locally.
+/// 
+/// With `debug=true`, a profile of how the query was run is returned with the rows.
+/// With `priority=batch`, the query waits for interactive queries when the data service is busy.
+/// With `store=true`, every row is stored, to page through as a result set.
+/// With `format=ndjson`, every row is streamed as a JSON object on its own line.
+/// With `format=csv`, the header and every row are streamed as CSV.
+/// With `format=sse`, the header and every row are sent as server-sent events as each file is read.
+/// Without `format`, the format is chosen by the `Accept` header, falling back to JSON.
+async fn query_post_v1(
+    Path(collection): Path<String>,
+    Query(query): Query<QueryParameters>,
+    Extension(principal): Extension<Principal>,
+    headers: HeaderMap,
+    Json(payload): Json<QueryPayload>,
+) -> Result<axum::response::Response, ZenithError> {
+
+    let now = Instant::now();
+    let predicates = QueryPredicates { fields: payload.fields, predicates: payload.predicates };
+    let _slot = limit::SlotGuard::start(limit::priority(query.priority, &principal)).await?;
+    // The query can be cancelled by its request id until it is done.
+    let running = queries::Running::start(&request_id::current().unwrap_or_default(), &principal);
+
+    // A node in a federation returns all of its own rows to the coordinator.
+    if headers.contains_key(remote::FEDERATED_HEADER) {
+        let selected = {
+            let (collection, cancelled) = (collection.clone(), running.cancelled().clone());
+            request_id::spawn_blocking(move || db::select(&collection, predicates, Some(&principal), &cancelled)).await
+        };
+        let (header, rows) = selected?;
+        info!("Returned {} fields and {} rows to coordinator in {:.2?}", header.len(), rows.len(), now.elapsed());
+        return Ok(Json( QueryResponse { header, rows: db::to_json(&[], rows), ..Default::default() } ).into_response());
+    }
+
+    // The format asked for takes precedence over the `Accept` header.
+    let format = query.format.unwrap_or_else(|| {
+        headers.get(ACCEPT).and_then(|accept| accept.to_str().ok()).map(ResponseFormat::negotiate).unwrap_or_default()
+    });
+    // Rows are sent as events as each file is read, rather than once every file has been.
+    if format == ResponseFormat::Sse {
+        if query.store.unwrap_or(false) || query.debug.unwrap_or(false) || query.as_of.is_some() || !payload.windows.is_empty() {
+            return Err(ZenithError::QueryError("Queries sent as events cannot be stored, profiled, run on a snapshot, or have windows".to_string()));
+        }
+        info!("Sending the rows of collection '{}' as events", collection);
+        return events::query(collection, predicates, principal, query.typed.unwrap_or(false), _slot, running).await;
+    }
+
+    let (selection, mut profile) = match &query.as_of {
+        Some(as_of) => run_snapshot_query(&collection, as_of, predicates, principal.clone(), running.cancelled(), query.debug.unwrap_or(false)).await?,
+        None => run_query(&collection, predicates, principal.clone(), running.cancelled(), query.debug.unwrap_or(false)).await?,
+    };
+    // Windows are computed over every row found, before it is stored or paged.
+    let selection = match payload.windows.is_empty() {
+        true => selection,
+        false => {
+            let started = Instant::now();
+            let windows = payload.windows;
+            let selection = request_id::spawn_blocking(move || windows::apply(selection, &windows)).await?;
+            if let Some(profile) = &mut profile {
+                profile.phases.push(PhaseTiming { phase: "windows".to_string(), micros: started.elapsed().as_micros() as u64 });
+            }
+            selection
+        },
+    };
+    // Stored, if asked for, so that later pages do not run the query again.
+    let (result_set, selection) = match query.store.unwrap_or(false) {
+        true => {
+            let (id, selection) = results::store(&collection, &principal, selection);
+            (Some(id), selection)
+        },
+        false => (None, Arc::new(selection)),
+    };
+    let (header, rows) = (selection.0.clone(), &selection.1);
+    let total_rows = result_set.as_ref().map(|_| rows.len());
+    let mut phase = Instant::now();
+    let mut end_phase = |profile: &mut Option<QueryProfile>, name: &str| {
+        if let Some(profile) = profile {
+            profile.phases.push(PhaseTiming { phase: name.to_string(), micros: phase.elapsed().as_micros() as u64 });
+        }
+        phase = Instant::now();
+    };
+
+    let types = column_types(&collection, &header, rows, query.typed.unwrap_or(false))?;
+    end_phase(&mut profile, "types");
+
+    // Every row is streamed as NDJSON or CSV, unless a page is asked for.
+    if format != ResponseFormat::Json {
+        let range = match query.page.is_some() || query.per_page.is_some() {
+            true => page_range(rows.len(), &query).unwrap_or_default(),
+            false => 0..rows.len(),
+        };
+        info!("Returning {} fields and {}/{} rows as {:?} in {:.2?}", header.len(), range.len(), rows.len(), format, now.elapsed());
+        let content_type = format.media_types()[0];
+        if format == ResponseFormat::Ndjson {
+            return Ok(stream_response(selection, range, content_type, move |header, rows, _| {
+                db::to_ndjson(header, &types, rows)
+            }));
+        }
+        let escape_formulas = query.escape_formulas
+            .unwrap_or(!config::envar_str("ZENITHDS_ESCAPE_FORMULAS").is_empty());
+        return Ok(stream_response(selection, range, content_type, move |header, rows, first| {
+            let header = if first { header.to_vec() } else { Vec::new() };
+            db::to_csv(header, rows.to_vec(), escape_formulas)
+        }));
+    }
+
+    match page(rows, &query) {
+        Some(paged_rows) => {
+            info!("Returned {} fields and {}/{} rows in {:.2?}", header.len(), paged_rows.len(), rows.len(), now.elapsed());
+            let rows = db::to_json(&types, paged_rows.to_owned());
+            end_phase(&mut profile, "page");
+            Ok(Json( QueryResponse { header, rows, profile, result_set, total_rows } ).into_response())
+        },
+        None => {
+            info!("No rows in {:.2?}", now.elapsed());
+            end_phase(&mut profile, "page");
+            Ok(Json( QueryResponse::<serde_json::Value> { header, rows: vec![], profile, result_set, total_rows } ).into_response())
+        }
+    }
+}
+
+
+/// Submits a job to query a `collection` based on `predicates`, returning its
+/// `job_id` at once. The job runs in the background, at batch priority unless
+/// `priority=interactive` is given, and its rows are fetched once it is done.
+async fn submit_query_job_v1(
+    Path(collection): Path<String>,
+    Query(query): Query<QueryParameters>,
+    Extension(principal): Extension<Principal>,
+    Json(predicates): Json<QueryPredicates>,
+) -> Result<(StatusCode, Json<JobStatus>), ZenithError> {
+
+    info!("Received a request to submit a query job on collection '{}'", collection);
+    let priority = limit::priority(Some(query.priority.unwrap_or(Priority::Batch)), &principal);
+    let (status, cancelled) = jobs::submit(&collection, &principal);
+    let id = status.job_id.clone();
+    tokio::spawn(request_id::inherit(async move {
+        let _slot = limit::SlotGuard::wait(priority).await;
+        jobs::started(&id);
+        let now = Instant::now();
+        let result = run_query(&collection, predicates, principal, &cancelled, false).await;
+        match &result {
+            Ok(((header, rows), _)) => info!("Job '{}' found {} fields and {} rows in {:.2?}", id, header.len(), rows.len(), now.elapsed()),
+            Err(err) => warn!("Job '{}' on collection '{}' was unsuccessful: {}", id, collection, err),
+        }
+        jobs::finished(&id, result.map(|(selection, _)| selection));
+    }));
+    info!("Submitted job '{}'", status.job_id);
+    Ok((StatusCode::ACCEPTED, Json( status )))
+}
+
+
+/// Returns the status of the query job with `id`.
+async fn get_job_v1(
+    Path(id): Path<String>,
+    Extension(principal): Extension<Principal>,
+) -> Result<Json<JobStatus>, ZenithError> {
+
+    Ok(Json( jobs::status(&id, &principal)? ))
+}
+
+
+/// Returns a page of the `header` and `rows` found by the query job with `id`, once it is done.
+/// Takes `page`, `per_page`, and `typed` as with `query`.
+async fn get_job_result_v1(
+    Path(id): Path<String>,
+    Query(query): Query<QueryParameters>,
+    Extension(principal): Extension<Principal>,
+) -> Result<Json<QueryResponse<serde_json::Value>>, ZenithError> {
+
+    let result = jobs::result(&id, &principal)?;
+    let collection = jobs::status(&id, &principal)?.collection;
+    let (header, rows) = (&result.0, &result.1);
+    let types = column_types(&collection, header, rows, query.typed.unwrap_or(false))?;
+    let rows = page(rows, &query).map(|rows| db::to_json(&types, rows.to_owned())).unwrap_or_default();
+    Ok(Json( QueryResponse { header: header.clone(), rows, total_rows: Some(result.1.len()), ..Default::default() } ))
+}
+
+
+/// Returns a page of the `header` and `rows` of the stored result set with `id`, from a query
+/// made with `store=true`. Takes `page`, `per_page`, and `typed` as with `query`.
+async fn get_result_set_v1(
+    Path(id): Path<String>,
+    Query(query): Query<QueryParameters>,
+    Extension(principal): Extension<Principal>,
+) -> Result<Json<QueryResponse<serde_json::Value>>, ZenithError> {
+
+    let (collection, result) = results::get(&id, &principal)?;
+    let (header, rows) = (&result.0, &result.1);
+    let types = column_types(&collection, header, rows, query.typed.unwrap_or(false))?;
+    let paged = page(rows, &query).map(|rows| db::to_json(&types, rows.to_owned())).unwrap_or_default();
+    Ok(Json( QueryResponse {
+        header: header.clone(),
+        rows: paged,
+        profile: None,
+        result_set: Some(id),
+        total_rows: Some(rows.len()),
+    } ))
+}
+
+
+/// Removes the stored result set with `id` before it expires.
+async fn delete_result_set_v1(
+    Path(id): Path<String>,
+    Extension(principal): Extension<Principal>,
+) -> Result<(), ZenithError> {
+
+    results::remove(&id, &principal)
+}
+
+
+/// Cancels the query job with `id`, if it is still running, and removes it with its result.
+async fn delete_job_v1(
+    Path(id): Path<String>,
+    Extension(principal): Extension<Principal>,
+) -> Result<(), ZenithError> {
+
+    info!("Received a request to remove job '{}'", id);
+    match jobs::remove(&id, &principal) {
+        Ok(status) => {
+            info!("Removed job '{}', which was {:?}", id, status.state);
+            Ok(())
+        },
+        Err(err) => {
+            warn!("The request to remove job '{}' was unsuccessful", id);
+            Err(err)
+        }
+    }
+}
+
+
+/// Cancels the queries running with the request id `id`, so that their workers stop
+/// reading files. The queries themselves get a `Cancelled` error.
+async fn cancel_query_v1(
+    Path(id): Path<String>,
+    Extension(principal): Extension<Principal>,
+) -> Result<(), ZenithError> {
+
+    info!("Received a request to cancel query '{}'", id);
+    match queries::cancel(&id, &principal) {
+        Ok(cancelled) => {
+            info!("Cancelled {} queries with id '{}'", cancelled, id);
+            Ok(())
+        },
+        Err(err) => {
+            warn!("The request to cancel query '{}' was unsuccessful", id);
+            Err(err)
+        }
+    }
+}
+
+
+/// Lists the saved `queries`, in order of their names.
+async fn list_saved_queries_v1() -> Result<Json<SavedQueriesResponse>, ZenithError> {
+    Ok(Json( SavedQueriesResponse { queries: saved_queries::list()? } ))
+}
+
+
+/// Returns the query saved as `name`, with its parameters.
+async fn get_saved_query_v1(
+    Path(name): Path<String>,
+) -> Result<Json<SavedQuery>, ZenithError> {
+
+    Ok(Json( saved_queries::get(&name)? ))
+}
+
+
+/// Saves a query on a collection as `name`, replacing any query saved as it, so that it can be
+/// run by its name with values for the `{{placeholders}}` in its predicates.
+async fn save_query_v1(
+    Path(name): Path<String>,
+    Extension(principal): Extension<Principal>,
+    Json(payload): Json<SavedQueryPayload>,
+) -> Result<Json<SavedQuery>, ZenithError> {
+
+    info!("Received a request to save query '{}'", name);
+    let query = saved_queries::save(&name, payload)?;
+    audit::record(AuditEntry::new(&principal, "save_query", &query.collection)
+        .detail(format!("'{}'", query.name)));
+    Ok(Json( query ))
+}
+
+
+/// Removes the query saved as `name`.
+async fn remove_saved_query_v1(
+    Path(name): Path<String>,
+    Extension(principal): Extension<Principal>,
+) -> Result<Json<SavedQuery>, ZenithError> {
+
+    info!("Received a request to remove saved query '{}'", name);
+    let query = saved_queries::remove(&name)?;
+    audit::record(AuditEntry::new(&principal, "remove_query", &query.collection)
+        .detail(format!("'{}'", query.name)));
+    Ok(Json( query ))
+}
+
+
+/// Runs the query saved as `name`, with the values of its `parameters` in its predicates,
+/// returning a page of the rows found, as `query` does. See `saved_queries::bind`.
+async fn run_saved_query_v1(
+    Path(name): Path<String>,
+    Query(query): Query<QueryParameters>,
+    Extension(principal): Extension<Principal>,
+    payload: Option<Json<RunQueryPayload>>,
+) -> Result<Json<QueryResponse<serde_json::Value>>, ZenithError> {
+
+    info!("Received a request to run saved query '{}'", name);
+    let Json(payload) = payload.unwrap_or_default();
+    let (collection, predicates) = saved_queries::bind(&name, &payload.parameters)?;
+    let _slot = limit::SlotGuard::start(limit::priority(query.priority, &principal)).await?;
+    let running = queries::Running::start(&request_id::current().unwrap_or_default(), &principal);
+    let ((header, rows), profile) = run_query(&collection, predicates, principal.clone(), running.cancelled(), query.debug.unwrap_or(false)).await?;
+    let types = column_types(&collection, &header, &rows, query.typed.unwrap_or(false))?;
+    let paged = page(&rows, &query).map(|rows| db::to_json(&types, rows.to_owned())).unwrap_or_default();
+    Ok(Json( QueryResponse { header, rows: paged, profile, result_set: None, total_rows: None } ))
+}
+
+
+/// Lists the `schedules` of saved queries, oldest first.
+async fn list_schedules_v1() -> Result<Json<SchedulesResponse>, ZenithError> {
+    Ok(Json( SchedulesResponse { schedules: schedules::list()? } ))
+}
+
+
+/// Returns the schedule with `id`, with how it last ran.
+async fn get_schedule_v1(
+    Path(id): Path<String>,
+) -> Result<Json<Schedule>, ZenithError> {
+
+    Ok(Json( schedules::get(&id)? ))
+}
+
+
+/// Schedules a saved query to run on a cron expression, writing the rows it finds to
+/// a collection or sending them to a URL each time. See `schedules::create`.
+async fn create_schedule_v1(
+    Extension(principal): Extension<Principal>,
+    Json(payload): Json<SchedulePayload>,
+) -> Result<Json<Schedule>, ZenithError> {
+
+    info!("Received a request to schedule query '{}' at '{}'", payload.query, payload.cron);
+    let schedule = schedules::create(payload)?;
+    info!("Created schedule '{}'", schedule.schedule_id);
+    let target = schedule.collection.clone().unwrap_or_default();
+    audit::record(AuditEntry::new(&principal, "create_schedule", &target)
+        .detail(format!("'{}' of query '{}' at '{}'", schedule.schedule_id, schedule.query, schedule.cron)));
+    Ok(Json( schedule ))
+}
+
+
+/// Removes the schedule with `id`.
+async fn remove_schedule_v1(
+    Path(id): Path<String>,
+    Extension(principal): Extension<Principal>,
+) -> Result<Json<Schedule>, ZenithError> {
+
+    info!("Received a request to remove schedule '{}'", id);
+    let schedule = schedules::remove(&id)?;
+    let target = schedule.collection.clone().unwrap_or_default();
+    audit::record(AuditEntry::new(&principal, "remove_schedule", &target)
+        .detail(format!("'{}' of query '{}'", schedule.schedule_id, schedule.query)));
+    Ok(Json( schedule ))
+}
+
+
+/// Runs the schedule with `id` now, as it would run on its schedule, returning it with how it ran.
+async fn run_schedule_v1(
+    Path(id): Path<String>,
+) -> Result<Json<Schedule>, ZenithError> {
+
+    info!("Received a request to run schedule '{}'", id);
+    let schedule = schedules::get(&id)?;
+    schedules::run(schedule, chrono::Utc::now()).await?;
+    Ok(Json( schedules::get(&id)? ))
+}
+
+
+/// Explains how a query on a `collection` with `predicates` would be run, without running it.
+async fn explain_query_v1(
+    Path(collection): Path<String>,
+    Extension(principal): Extension<Principal>,
+    Json(predicates): Json<QueryPredicates>,
+) -> Result<Json<ExplainResponse>, ZenithError> {
+
+    Ok(Json( db::explain(&collection, predicates, Some(&principal))? ))
+}
+
+
+/// Profiles the columns of a `collection`, in the rows matched by any `predicates`, or only
+/// the `fields` given. See `stats::profile`.
+async fn profile_collection_v1(
+    Path(collection): Path<String>,
+    Extension(principal): Extension<Principal>,
+    payload: Option<Json<ProfilePayload>>,
+) -> Result<Json<ProfileResponse>, ZenithError> {
+
+    info!("Received a request to profile collection '{}'", collection);
+    let Json(payload) = payload.unwrap_or_default();
+    let _slot = limit::SlotGuard::start(limit::priority(None, &principal)).await?;
+    let running = queries::Running::start(&request_id::current().unwrap_or_default(), &principal);
+    let cancelled = running.cancelled().clone();
+    let predicates = QueryPredicates { fields: payload.fields, predicates: payload.predicates };
+    let profile = request_id::spawn_blocking(move || stats::profile(&collection, predicates, &payload.percentiles, &principal, &cancelled)).await?;
+    Ok(Json( profile ))
+}
+
+
+/// Returns the most frequent values of a `column` of a `collection`, in the rows
+/// matched by any `predicates`, with their counts. See `stats::top_values`.
+async fn top_values_v1(
+    Path(collection): Path<String>,
+    Extension(principal): Extension<Principal>,
+    Json(payload): Json<TopValuesPayload>,
+) -> Result<Json<TopValuesResponse>, ZenithError> {
+
+    info!("Received a request for the top values of column '{}' in collection '{}'", payload.column, collection);
+    let _slot = limit::SlotGuard::start(limit::priority(None, &principal)).await?;
+    let running = queries::Running::start(&request_id::current().unwrap_or_default(), &principal);
+    let cancelled = running.cancelled().clone();
+    let limit = payload.limit.unwrap_or(stats::TOP_VALUES);
+    let top = request_id::spawn_blocking(move || {
+        stats::top_values(&collection, &payload.column, payload.predicates, limit, &principal, &cancelled)
+    }).await?;
+    Ok(Json( top ))
+}
+
+
+/// Returns a histogram of the numbers in a `column` of a `collection`, in the rows matched
+/// by any `predicates`, in a number of `buckets` or buckets of a `width`. See `stats::histogram`.
+async fn histogram_v1(
+    Path(collection): Path<String>,
+    Extension(principal): Extension<Principal>,
+    Json(payload): Json<HistogramPayload>,
+) -> Result<Json<HistogramResponse>, ZenithError> {
+
+    info!("Received a request for a histogram of column '{}' in collection '{}'", payload.column, collection);
+    let buckets = match (payload.buckets, payload.width) {
+        (Some(_), Some(_)) => return Err(ZenithError::QueryError("Give either a number of buckets or a width, not both".to_string())),
+        (_, Some(width)) => stats::Buckets::Width(width),
+        (buckets, None) => stats::Buckets::Count(buckets.unwrap_or(stats::HISTOGRAM_BUCKETS)),
+    };
+    let _slot = limit::SlotGuard::start(limit::priority(None, &principal)).await?;
+    let running = queries::Running::start(&request_id::current().unwrap_or_default(), &principal);
+    let cancelled = running.cancelled().clone();
+    let histogram = request_id::spawn_blocking(move || {
+        stats::histogram(&collection, &payload.column, payload.predicates, buckets, &principal, &cancelled)
+    }).await?;
+    Ok(Json( histogram ))
+}
+
+
+/// Runs a GraphQL `request` on the collections that the principal can see, returning
+/// its `data` and any `errors`. See `graphql::build` for the schema it is run on.
+async fn graphql_v1(
+    Extension(principal): Extension<Principal>,
+    Json(request): Json<async_graphql::Request>,
+) -> Result<Json<async_graphql::Response>, ZenithError> {
+
+    info!("Received a GraphQL request");
+    let _slot = limit::SlotGuard::start(limit::priority(None, &principal)).await?;
+    let running = queries::Running::start(&request_id::current().unwrap_or_default(), &principal);
+    let schema = {
+        let principal = principal.clone();
+        request_id::spawn_blocking(move || graphql::build(&principal)).await?
+    };
+    let response = schema.execute(request.data(principal).data(running.cancelled().clone())).await;
+    Ok(Json( response ))
+}
+
+
+/// Returns the OData service document, which lists an entity set for each collection
+/// that the principal can see. See `odata::entity_sets`.
+async fn odata_service_v1(
+    Extension(principal): Extension<Principal>,
+    headers: HeaderMap,
+) -> Result<axum::response::Response, ZenithError> {
+
+    let sets = request_id::spawn_blocking(move || odata::entity_sets(&principal)).await?;
+    let document = odata::service_document(&odata::service_root(&headers), &sets);
+    Ok(odata::response(odata::JSON, document.to_string()))
+}
+
+
+/// Returns the OData metadata document, which describes the entity type of each entity set.
+async fn odata_metadata_v1(
+    Extension(principal): Extension<Principal>,
+) -> Result<axum::response::Response, ZenithError> {
+
+    let sets = request_id::spawn_blocking(move || odata::entity_sets(&principal)).await?;
+    Ok(odata::response(odata::XML, odata::metadata(&sets)))
+}
+
+
+/// Returns the entities of the entity set `name`, the rows of its collection, as asked for
+/// by the OData query options `$select`, `$filter`, `$orderby`, `$top`, `$skip`, and `$count`.
+async fn odata_entities_v1(
+    Path(name): Path<String>,
+    Query(parameters): Query<ODataParameters>,
+    Extension(principal): Extension<Principal>,
+    headers: HeaderMap,
+) -> Result<axum::response::Response, ZenithError> {
+
+    info!("Received an OData request for entity set '{}'", name);
+    let sets = {
+        let principal = principal.clone();
+        request_id::spawn_blocking(move || odata::entity_sets(&principal)).await?
+    };
+    let set = sets.into_iter()
+        .find(|set| set.name == name)
+        .ok_or_else(|| ZenithError::NotFound(Missing::Other(format!("Entity set '{}' does not exist", name))))?;
+    let _slot = limit::SlotGuard::start(limit::priority(None, &principal)).await?;
+    let running = queries::Running::start(&request_id::current().unwrap_or_default(), &principal);
+    let entities = odata::entities(&set, parameters, &odata::service_root(&headers), principal, running.cancelled()).await?;
+    Ok(odata::response(odata::JSON, entities.to_string()))
+}
+
+
+/// Sends the changes to the files in the `collection` over a WebSocket, as they are made. With
+/// `rows=true`, changes that create a file also send its header and rows. See `changes::forward`.
+async fn watch_changes_v1(
+    Path(collection): Path<String>,
+    Query(parameters): Query<ChangesParameters>,
+    Extension(principal): Extension<Principal>,
+    socket: WebSocketUpgrade,
+) -> Result<axum::response::Response, ZenithError> {
+
+    info!("Received a request to watch changes in collection '{}'", collection);
+    // The collection must exist, so that a misspelled name is not watched forever.
+    db::files(&collection)?;
+    let with_rows = parameters.rows.unwrap_or(false);
+    Ok(socket.on_upgrade(move |socket| changes::forward(socket, collection, with_rows, principal)))
+}
+
+
+/// Takes a point-in-time snapshot of the files in
+/// the `collection`, returning the `snapshot_id`.
+async fn snapshot_collection_v1(
+    Path(collection): Path<String>,
+) -> Result<Json<SnapshotResponse>, ZenithError> {
+
+    info!("Received a request to snapshot collection '{}'", collection);
+    match db::snapshot(&collection) {
+        Ok((snapshot_id, files)) => {
+            info!("Took snapshot '{}' of {} files in collection '{}'", snapshot_id, files, collection);
+            Ok(Json( SnapshotResponse { snapshot_id, files } ))
+        },
+        Err(err) => {
+            warn!("The request to snapshot collection '{}' was unsuccessful", collection);
+            Err(err)
+        }
+    }
+}
+
+
+/// Restores the `collection` from the snapshot with `snapshot_id`,
+/// replacing its files, or recreating it if it does not exist.
+async fn restore_collection_v1(
+    Path((collection, snapshot_id)): Path<(String, String)>,
+    Extension(principal): Extension<Principal>,
+) -> Result<Json<SnapshotResponse>, ZenithError> {
+
+    info!("Received a request to restore collection '{}' from snapshot '{}'", collection, snapshot_id);
+    match db::restore(&collection, &snapshot_id) {
+        Ok(files) => {
+            info!("Restored {} files in collection '{}' from snapshot '{}'", files, collection, snapshot_id);
+            let entry = AuditEntry::new(&principal, "restore", &collection)
+                .detail(format!("{} files from snapshot '{}'", files, snapshot_id));
+            changes::publish(&entry, None);
+            audit::record(entry);
+            Ok(Json( SnapshotResponse { snapshot_id, files } ))
+        },
+        Err(err) => {
+            warn!("The request to restore collection '{}' was unsuccessful", collection);
+            Err(err)
+        }
+    }
+}
+
+
+/// Removes duplicate rows from the `collection`, comparing the `columns` given or every column,
+/// and keeping the first or last of each, returning the number of rows `removed` and the files `rewritten`.
+async fn dedupe_collection_v1(
+    Path(collection): Path<String>,
+    Extension(principal): Extension<Principal>,
+    Json(payload): Json<DedupePayload>,
+) -> Result<Json<DedupeResponse>, ZenithError> {
+
+    info!("Received a request to dedupe collection '{}'", collection);
+    let deduped = {
+        let collection = collection.clone();
+        request_id::spawn_blocking(move || db::dedupe(&collection, &payload.columns, payload.keep)).await
+    };
+    match deduped {
+        Ok((removed, rewritten)) => {
+            info!("Removed {} duplicate rows from collection '{}', rewriting {} files", removed, collection, rewritten.len());
+            let entry = AuditEntry::new(&principal, "dedupe", &collection)
+                .detail(format!("{} rows, rewriting {} files", removed, rewritten.len()));
+            changes::publish(&entry, None);
+            audit::record(entry);
+            Ok(Json( DedupeResponse { removed, rewritten } ))
+        },
+        Err(err) => {
+            warn!("The request to dedupe collection '{}' was unsuccessful", collection);
+            Err(err)
+        }
+    }
+}
+
+
+/// Lists the materialized `views`, in order of their names.
+async fn list_views_v1() -> Result<Json<ViewsResponse>, ZenithError> {
+    Ok(Json( ViewsResponse { views: views::list()? } ))
+}
+
+
+/// Returns the materialized view `name`, with when its rows were last refreshed.
+async fn get_view_v1(
+    Path(name): Path<String>,
+) -> Result<Json<View>, ZenithError> {
+
+    Ok(Json( views::get(&name)? ))
+}
+
+
+/// Defines a materialized view of the rows of a collection matched by a query, and materializes
+/// them, so that they can be queried without scanning the collection. See `views::create`.
+async fn create_view_v1(
+    Extension(principal): Extension<Principal>,
+    Json(payload): Json<ViewPayload>,
+) -> Result<Json<View>, ZenithError> {
+
+    info!("Received a request to create view '{}' of collection '{}'", payload.name, payload.collection);
+    let _slot = limit::SlotGuard::start(limit::priority(None, &principal)).await?;
+    let view = request_id::spawn_blocking(move || views::create(payload)).await?;
+    info!("Created view '{}' with {} rows", view.name, view.rows);
+    audit::record(AuditEntry::new(&principal, "create_view", &view.collection)
+        .detail(format!("'{}' with {} rows", view.name, view.rows)));
+    Ok(Json( view ))
+}
+
+
+/// Refreshes the rows of the materialized view `name` from its collection.
+async fn refresh_view_v1(
+    Path(name): Path<String>,
+    Extension(principal): Extension<Principal>,
+) -> Result<Json<View>, ZenithError> {
+
+    info!("Received a request to refresh view '{}'", name);
+    let _slot = limit::SlotGuard::start(limit::priority(None, &principal)).await?;
+    let view = request_id::spawn_blocking(move || views::refresh(&name)).await?;
+    info!("Refreshed view '{}' with {} rows", view.name, view.rows);
+    audit::record(AuditEntry::new(&principal, "refresh_view", &view.collection)
+        .detail(format!("'{}' with {} rows", view.name, view.rows)));
+    Ok(Json( view ))
+}
+
+
+/// Removes the materialized view `name` and its rows.
+async fn remove_view_v1(
+    Path(name): Path<String>,
+    Extension(principal): Extension<Principal>,
+) -> Result<Json<View>, ZenithError> {
+
+    info!("Received a request to remove view '{}'", name);
+    let view = request_id::spawn_blocking(move || views::remove(&name)).await?;
+    audit::record(AuditEntry::new(&principal, "remove_view", &view.collection)
+        .detail(format!("'{}'", view.name)));
+    Ok(Json( view ))
+}
+
+
+/// Queries the rows of the materialized view `name` with `predicates`, as `query` does a
+/// collection, returning a page of the rows matched. See `views::query`.
+async fn query_view_v1(
+    Path(name): Path<String>,
+    Query(query): Query<QueryParameters>,
+    Extension(principal): Extension<Principal>,
+    Json(predicates): Json<QueryPredicates>,
+) -> Result<Json<QueryResponse<serde_json::Value>>, ZenithError> {
+
+    info!("Received a request to query view '{}'", name);
+    let _slot = limit::SlotGuard::start(limit::priority(query.priority, &principal)).await?;
+    let running = queries::Running::start(&request_id::current().unwrap_or_default(), &principal);
+    let cancelled = running.cancelled().clone();
+    let debug = query.debug.unwrap_or(false);
+    let (collection, (header, rows), profile) = request_id::spawn_blocking(move || {
+        views::query(&name, predicates, &principal, &cancelled, debug)
+    }).await?;
+    let types = column_types(&collection, &header, &rows, query.typed.unwrap_or(false))?;
+    let paged = page(&rows, &query).map(|rows| db::to_json(&types, rows.to_owned())).unwrap_or_default();
+    Ok(Json( QueryResponse { header, rows: paged, profile, result_set: None, total_rows: None } ))
+}
+
+
+/// Lists the previous versions of `filename` in the `collection`.
+async fn list_versions_v1(
+    Path((collection, filename)): Path<(String, String)>,
+) -> Result<Json<VersionsResponse>, ZenithError> {
+
+    let versions = db::versions(&collection, &filename)?
+        .into_iter()
+        .map(|(version_id, size)| FileVersion { version_id, size })
+        .collect();
+    Ok(Json( VersionsResponse { filename, versions } ))
+}
+
+
+/// Renders the version of `filename` in the `collection` with `version_id`,
+/// returning a `header`, `rows`, and any `removed` records.
+async fn get_version_v1(
+    Path((collection, filename, version_id)): Path<(String, String, String)>,
+    Extension(principal): Extension<Principal>,
+) -> Result<Json<RenderResponse>, ZenithError> {
+
+    let (header, rows, removed) = db::read_version(&collection, &filename, &version_id)?;
+    // Mask the columns the principal cannot see. Records that were removed
+    // cannot be matched up with the columns, so they are left out.
+    let masks = schema::read(&collection)?.map(|s| s.masks(&principal.roles)).unwrap_or_default();
+    if masks.is_empty() {
+        return Ok(Json( RenderResponse { header, rows, removed } ));
+    }
+    let rows = rows.into_iter().map(|row| schema::mask_row(&masks, &header, row)).collect();
+    Ok(Json( RenderResponse { header: schema::mask_header(&masks, header), rows, removed: Vec::new() } ))
+}
+
+
+/// Rolls back `filename` in the `collection` to the version with `version_id`.
+async fn rollback_version_v1(
+    Path((collection, filename, version_id)): Path<(String, String, String)>,
+    Extension(principal): Extension<Principal>,
+) -> Result<(), ZenithError> {
+
+    info!("Received a request to roll back '{}' in collection '{}' to version '{}'", filename, collection, version_id);
+    let previous_rows = db::file_rows(&collection, &filename).ok().flatten();
+    match db::rollback(&collection, &filename, &version_id) {
+        Ok(()) => {
+            info!("Rolled back '{}' in collection '{}' to version '{}'", filename, collection, version_id);
+            let entry = AuditEntry::new(&principal, "rollback", &collection)
+                .filename(&filename)
+                .rows(previous_rows, db::file_rows(&collection, &filename).ok().flatten())
+                .detail(format!("to version '{}'", version_id));
+            changes::publish(&entry, None);
+            audit::record(entry);
+            Ok(())
+        },
+        Err(err) => {
+            warn!("The request to roll back in collection '{}' was unsuccessful", collection);
+            Err(err)
+        }
+    }
+}
+
+
+/// Verifies the files in the `collection` against their recorded checksums,
+/// returning the number of files `verified` and any `problems` found.
+async fn verify_collection_v1(
+    Path(collection): Path<String>,
+) -> Result<Json<VerifyResponse>, ZenithError> {
+
+    let statuses = db::verify(&collection)?;
+    let verified = statuses.iter().filter(|(_, status)| *status == IntegrityStatus::Ok).count();
+    let problems: Vec<FileIntegrity> = statuses.into_iter()
+        .filter(|(_, status)| *status != IntegrityStatus::Ok)
+        .map(|(filename, status)| FileIntegrity { filename, status })
+        .collect();
+
+    info!("Verified {} files in collection '{}', with {} problems", verified, collection, problems.len());
+    Ok(Json( VerifyResponse { verified, problems } ))
+}
+
+
+/// Rebuilds the catalog of the `collection` from its files, returning the new `catalog`
+/// and the files `added`, `removed`, `changed`, or `mismatched` with the canonical header.
+async fn rebuild_catalog_v1(
+    Path(collection): Path<String>,
+) -> Result<Json<RebuildCatalogResponse>, ZenithError> {
+
+    info!("Received a request to rebuild the catalog of collection '{}'", collection);
+    match db::rebuild_catalog(&collection) {
+        Ok((catalog, added, removed, changed, mismatched)) => {
+            info!("Rebuilt the catalog of collection '{}' with {} files: {} added, {} removed, {} changed, {} mismatched",
+                collection, catalog.files.len(), added.len(), removed.len(), changed.len(), mismatched.len());
+            Ok(Json( RebuildCatalogResponse { catalog, added, removed, changed, mismatched } ))
+        },
+        Err(err) => {
+            warn!("The request to rebuild the catalog of collection '{}' was unsuccessful", collection);
+            Err(err)
+        }
+    }
+}
+
+
+/// Renames a column `from` one name `to` another in the `collection`,
+/// rewriting the header of each of its files.
+async fn rename_column_v1(
+    Path(collection): Path<String>,
+    Extension(principal): Extension<Principal>,
+    Json(payload): Json<RenameColumnPayload>,
+) -> Result<Json<SchemaChangeResponse>, ZenithError> {
+
+    info!("Received a request to rename column '{}' to '{}' in collection '{}'", payload.from, payload.to, collection);
+    match db::rename_column(&collection, &payload.from, &payload.to) {
+        Ok(rewritten) => {
+            info!("Renamed column '{}' to '{}' in collection '{}', rewriting {} files", payload.from, payload.to, collection, rewritten.len());
+            let entry = AuditEntry::new(&principal, "rename_column", &collection)
+                .detail(format!("'{}' to '{}', rewriting {} files", payload.from, payload.to, rewritten.len()));
+            changes::publish(&entry, None);
+            audit::record(entry);
+            Ok(Json( SchemaChangeResponse { schema: schema::read(&collection)?, rewritten } ))
+        },
+        Err(err) => {
+            warn!("The request to rename column '{}' in collection '{}' was unsuccessful", payload.from, collection);
+            Err(err)
+        }
+    }
+}
+
+
+/// Removes a `column` from the `collection`, rewriting each of its files without it.
+async fn drop_column_v1(
+    Path(collection): Path<String>,
+    Extension(principal): Extension<Principal>,
+    Json(payload): Json<DropColumnPayload>,
+) -> Result<Json<SchemaChangeResponse>, ZenithError> {
+
+    info!("Received a request to drop column '{}' from collection '{}'", payload.column, collection);
+    match db::drop_column(&collection, &payload.column) {
+        Ok(rewritten) => {
+            info!("Dropped column '{}' from collection '{}', rewriting {} files", payload.column, collection, rewritten.len());
+            let entry = AuditEntry::new(&principal, "drop_column", &collection)
+                .detail(format!("'{}', rewriting {} files", payload.column, rewritten.len()));
+            changes::publish(&entry, None);
+            audit::record(entry);
+            Ok(Json( SchemaChangeResponse { schema: schema::read(&collection)?, rewritten } ))
+        },
+        Err(err) => {
+            warn!("The request to drop column '{}' from collection '{}' was unsuccessful", payload.column, collection);
+            Err(err)
+        }
+    }
+}
+
+
+/// Lists the `triggers` on the `collection`, oldest first, with how they last fired.
+async fn list_triggers_v1(
+    Path(collection): Path<String>,
+) -> Result<Json<TriggersResponse>, ZenithError> {
+
+    Ok(Json( TriggersResponse { triggers: triggers::list(&collection)? } ))
+}
+
+
+/// Creates a trigger on the `collection`, which is fired after each file created in it, writing
+/// rows to another collection or sending them to a URL. See `triggers::create`.
+async fn create_trigger_v1(
+    Path(collection): Path<String>,
+    Extension(principal): Extension<Principal>,
+    Json(payload): Json<TriggerPayload>,
+) -> Result<Json<Trigger>, ZenithError> {
+
+    info!("Received a request to add a trigger to collection '{}'", collection);
+    let trigger = triggers::create(&collection, payload, &principal)?;
+    info!("Added trigger '{}' to collection '{}'", trigger.trigger_id, collection);
+    let destination = trigger.target.as_deref().or(trigger.url.as_deref()).unwrap_or_default();
+    audit::record(AuditEntry::new(&principal, "add_trigger", &collection)
+        .detail(format!("'{}' to {}", trigger.trigger_id, destination)));
+    Ok(Json( trigger ))
+}
+
+
+/// Removes the trigger with the `id` from the `collection`.
+async fn remove_trigger_v1(
+    Path((collection, id)): Path<(String, String)>,
+    Extension(principal): Extension<Principal>,
+) -> Result<Json<Trigger>, ZenithError> {
+
+    info!("Received a request to remove trigger '{}' from collection '{}'", id, collection);
+    let trigger = triggers::remove(&collection, &id)?;
+    let destination = trigger.target.as_deref().or(trigger.url.as_deref()).unwrap_or_default();
+    audit::record(AuditEntry::new(&principal, "remove_trigger", &collection)
+        .detail(format!("'{}' to {}", trigger.trigger_id, destination)));
+    Ok(Json( trigger ))
+}
+
+
+/// Lists the `webhooks` registered on the `collection`, without their secrets.
+async fn list_webhooks_v1(
+    Path(collection): Path<String>,
+) -> Result<Json<WebhooksResponse>, ZenithError> {
+
+    Ok(Json( WebhooksResponse { webhooks: webhooks::list(&collection)? } ))
+}
+
+
+/// Registers a webhook that is sent a signed `POST` on each change to the files of the
+/// `collection`, or only those with the `actions` given. Returns it with its `secret`,
+/// which is only shown here.
+async fn add_webhook_v1(
+    Path(collection): Path<String>,
+    Extension(principal): Extension<Principal>,
+    Json(payload): Json<WebhookPayload>,
+) -> Result<Json<Webhook>, ZenithError> {
+
+    info!("Received a request to add a webhook to collection '{}'", collection);
+    let webhook = webhooks::register(&collection, payload)?;
+    info!("Added webhook '{}' to collection '{}'", webhook.webhook_id, collection);
+    audit::record(AuditEntry::new(&principal, "add_webhook", &collection)
+        .detail(format!("'{}' to {}", webhook.webhook_id, webhook.url)));
+    Ok(Json( webhook ))
+}
+
+
+/// Removes the webhook with the `id` from the `collection`.
+async fn remove_webhook_v1(
+    Path((collection, id)): Path<(String, String)>,
+    Extension(principal): Extension<Principal>,
+) -> Result<Json<Webhook>, ZenithError> {
+
+    info!("Received a request to remove webhook '{}' from collection '{}'", id, collection);
+    let webhook = webhooks::remove(&collection, &id)?;
+    audit::record(AuditEntry::new(&principal, "remove_webhook", &collection)
+        .detail(format!("'{}' to {}", webhook.webhook_id, webhook.url)));
+    Ok(Json( webhook ))
+}
+
+
+/// Lists the recent `deliveries` to the webhook with the `id` on the `collection`, oldest first.
+async fn list_deliveries_v1(
+    Path((collection, id)): Path<(String, String)>,
+) -> Result<Json<DeliveriesResponse>, ZenithError> {
+
+    Ok(Json( DeliveriesResponse { deliveries: webhooks::deliveries(&collection, &id)? } ))
+}
+
+
+/// Returns the `changes` in the change log after the offset `since` that match the
+/// `collection`, if given, up to a `limit`, with the `offset` to read the next changes from.
+async fn change_log_v1(
+    Query(parameters): Query<ChangeLogParameters>,
+) -> Result<Json<ChangeLogResponse>, ZenithError> {
+
+    let (changes, offset) = changelog::read(&parameters)?;
+    Ok(Json( ChangeLogResponse { changes, offset } ))
+}
+
+
+/// Returns the `entries` in the audit log that match the `collection`, `filename`,
+/// `principal`, and `action`, if given, made `since` a time, up to a `limit`.
+async fn audit_log_v1(
+    Query(parameters): Query<AuditParameters>,
+) -> Result<Json<AuditResponse>, ZenithError> {
+
+    let entries = audit::read(&parameters)?;
+    Ok(Json( AuditResponse { entries } ))
+}
+
+
+/// Returns the config `file` read, if any, and every setting with the value in use,
+/// where it is from, and whether it can only be changed by a restart.
+async fn get_config_v1() -> Json<ConfigResponse> {
+
+    Json( ConfigResponse {
+        file: config::file_path().map(|path| path.display().to_string()),
+        settings: config::resolved(),
+    } )
+}
+
+
+/// Reads the config file again and applies the settings that changed,
+/// returning the settings `changed`, those that need a restart, and any `errors`.
+async fn reload_config_v1() -> Result<Json<ReloadResponse>, ZenithError> {
+
+    info!("Received a request to reload the config file");
+    match reload::reload() {
+        Ok(response) => Ok(Json(response)),
+        Err(err) => {
+            warn!("The request to reload the config file was unsuccessful: {}", err);
+            Err(err)
+        }
+    }
+}
+
+
+/// Lists the maintenance tasks the data service runs in the background, with how they have run.
+async fn list_maintenance_tasks_v1() -> Json<MaintenanceResponse> {
+    Json( MaintenanceResponse { tasks: maintenance::list() } )
+}
+
+
+/// Runs the maintenance task `name` at once, returning its status before it runs.
+async fn run_maintenance_task_v1(
+    Path(name): Path<String>,
+) -> Result<Json<MaintenanceTask>, ZenithError> {
+
+    info!("Received a request to run maintenance task '{}'", name);
+    Ok(Json( maintenance::run(&name)? ))
+}
+
+
+/// Returns the schema of the `collection`, or `null` if it does not have one.
+async fn get_schema_v1(
+    Path(collection): Path<String>,
+) -> Result<Json<Option<schema::Schema>>, ZenithError> {
+
+    Ok(Json( db::schema(&collection)? ))
+}
+
+
+/// Sets the schema of the `collection` to the given `columns`,
+/// each with a `name` and a `type`.
+async fn set_schema_v1(
+    Path(collection): Path<String>,
+    Extension(principal): Extension<Principal>,
+    Json(schema): Json<schema::Schema>,
+) -> Result<(), ZenithError> {
+
+    info!("Received a request to set the schema of collection '{}' with {} columns", collection, schema.columns.len());
+    match db::set_schema(&collection, &schema) {
+        Ok(()) => {
+            info!("Set the schema of collection '{}'", collection);
+            audit::record(AuditEntry::new(&principal, "set_schema", &collection)
+                .detail(format!("{} columns", schema.columns.len())));
+            Ok(())
+        },
+        Err(err) => {
+            warn!("The request to set the schema of collection '{}' was unsuccessful", collection);
+            Err(err)
+        }
+    }
+}
+
+
+/// Removes the schema of the `collection`.
+async fn remove_schema_v1(
+    Path(collection): Path<String>,
+    Extension(principal): Extension<Principal>,
+) -> Result<(), ZenithError> {
+
+    info!("Received a request to remove the schema of collection '{}'", collection);
+    db::remove_schema(&collection)?;
+    audit::record(AuditEntry::new(&principal, "remove_schema", &collection));
+    Ok(())
+}
+
+
+/// Infers a schema for the `collection` from the values in its files.
+/// If `save` is set, the schema is also set as the schema of the collection.
+async fn infer_schema_v1(
+    Path(collection): Path<String>,
+    Query(parameters): Query<InferParameters>,
+    Extension(principal): Extension<Principal>,
+) -> Result<Json<schema::Schema>, ZenithError> {
+
+    let schema = db::infer_schema(&collection)?;
+    info!("Inferred a schema for collection '{}': {:?}", collection, schema.columns);
+    if parameters.save.unwrap_or(false) {
+        db::set_schema(&collection, &schema)?;
+        info!("Set the schema of collection '{}'", collection);
+        audit::record(AuditEntry::new(&principal, "set_schema", &collection)
+            .detail(format!("{} columns, inferred", schema.columns.len())));
+    }
+    Ok(Json( schema ))
+}
+
+
+/// Adds a column with a `name` and a `type` to the `collection`, filling it in
+/// with its `default` value in the files that already exist.
+async fn add_column_v1(
+    Path(collection): Path<String>,
+    Extension(principal): Extension<Principal>,
+    Json(column): Json<schema::Column>,
+) -> Result<Json<SchemaChangeResponse>, ZenithError> {
+
+    info!("Received a request to add column '{}' to collection '{}'", column.name, collection);
+    match db::add_column(&collection, &column) {
+        Ok(rewritten) => {
+            info!("Added column '{}' to collection '{}', rewriting {} files", column.name, collection, rewritten.len());
+            let entry = AuditEntry::new(&principal, "add_column", &collection)
+                .detail(format!("'{}', rewriting {} files", column.name, rewritten.len()));
+            changes::publish(&entry, None);
+            audit::record(entry);
+            Ok(Json( SchemaChangeResponse { schema: schema::read(&collection)?, rewritten } ))
+        },
+        Err(err) => {
+            warn!("The request to add column '{}' to collection '{}' was unsuccessful", column.name, collection);
+            Err(err)
+        }
+    }
+}
@@ -0,0 +1,289 @@
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    sync::{LazyLock, Mutex},
+    time::{Duration, Instant},
+};
+use tokio::sync::Notify;
+use axum::{
+    extract::{ConnectInfo, MatchedPath, Request},
+    middleware::Next,
+    response::Response,
+};
+
+use crate::types::{api::Priority, error::ZenithError};
+use crate::{auth::{self, Principal}, config};
+
+/// The most clients kept track of before idle ones are forgotten.
+const MAX_CLIENTS: usize = 10_000;
+
+
+/// The requests a client can still make, refilled over time.
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+/// The request bucket of each client.
+static BUCKETS: LazyLock<Mutex<HashMap<String, Bucket>>> = LazyLock::new(|| Mutex::new(HashMap::new()));
+
+/// The number of queries each client has running.
+static QUERIES: LazyLock<Mutex<HashMap<String, usize>>> = LazyLock::new(|| Mutex::new(HashMap::new()));
+
+/// The number of queries running and waiting to run, across all clients.
+static SLOTS: Mutex<Slots> = Mutex::new(Slots { running: 0, running_batch: 0, waiting: 0, waiting_interactive: 0 });
+
+/// Notified when a query finishes, to wake the queries waiting to run.
+static FINISHED: Notify = Notify::const_new();
+
+
+/// Identifies the client that made `request` by the principal it was authenticated as, or by its
+/// IP address if authentication is not enabled, so that a client cannot get another bucket by
+/// giving its key another way, or by giving keys that are not valid. Requests are authenticated
+/// first (see `auth::authenticate`), and principals are named without their keys.
+fn client(request: &Request) -> String {
+    match request.extensions().get::<Principal>() {
+        Some(principal) if auth::enabled() => format!("principal:{}", principal.name),
+        _ => match request.extensions().get::<ConnectInfo<SocketAddr>>() {
+            Some(ConnectInfo(address)) => format!("ip:{}", address.ip()),
+            None => "unknown".to_string(),
+        },
+    }
+}
+
+
+/// Takes a request from the bucket of `client`, which holds up to `burst` requests and
+/// is refilled with `rate` requests per second. Returns false if the bucket is empty.
+fn take(client: &str, rate: f64, burst: f64) -> bool {
+    let mut buckets = BUCKETS.lock().unwrap_or_else(|e| e.into_inner());
+    let now = Instant::now();
+    if buckets.len() >= MAX_CLIENTS {
+        // Clients whose buckets have refilled are no different from new ones.
+        buckets.retain(|_, b| b.tokens + now.duration_since(b.updated).as_secs_f64() * rate < burst);
+    }
+    if buckets.len() >= MAX_CLIENTS {
+        // Otherwise, such as when many new clients have each made a request, the clients idle
+        // the longest are forgotten, so that there are never more than `MAX_CLIENTS`.
+        let mut idle: Vec<(Instant, String)> = buckets.iter().map(|(c, b)| (b.updated, c.clone())).collect();
+        idle.sort();
+        for (_, client) in idle.into_iter().take(MAX_CLIENTS / 10) {
+            buckets.remove(&client);
+        }
+    }
+
+    let bucket = buckets.entry(client.to_string()).or_insert(Bucket { tokens: burst, updated: now });
+    bucket.tokens = (bucket.tokens + now.duration_since(bucket.updated).as_secs_f64() * rate).min(burst);
+    bucket.updated = now;
+    if bucket.tokens < 1.0 {
+        return false;
+    }
+    bucket.tokens -= 1.0;
+    true
+}
+
+
+/// Counts a running query for a client until it is dropped.
+struct QueryGuard(String);
+
+impl QueryGuard {
+    /// Starts a query for `client`, unless it already has `max` queries running.
+    fn start(client: &str, max: usize) -> Option<QueryGuard> {
+        let mut queries = QUERIES.lock().unwrap_or_else(|e| e.into_inner());
+        let running = queries.entry(client.to_string()).or_insert(0);
+        if *running >= max {
+            return None;
+        }
+        *running += 1;
+        Some(QueryGuard(client.to_string()))
+    }
+}
+
+impl Drop for QueryGuard {
+    fn drop(&mut self) {
+        let mut queries = QUERIES.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(running) = queries.get_mut(&self.0) {
+            *running -= 1;
+            if *running == 0 {
+                queries.remove(&self.0);
+            }
+        }
+    }
+}
+
+
+/// The queries running and waiting to run.
+struct Slots {
+    running: usize,
+    running_batch: usize,
+    waiting: usize,
+    waiting_interactive: usize,
+}
+
+
+/// Counts a query as running, across all clients, until it is dropped.
+pub struct SlotGuard(Priority);
+
+impl SlotGuard {
+    /// Starts a query once fewer than `ZENITHDS_MAX_QUERIES` are running and, for a batch query, fewer
+    /// than `ZENITHDS_MAX_BATCH_QUERIES` batch queries are running and no interactive queries are
+    /// waiting. Until then, the query waits in a queue of up to `ZENITHDS_QUERY_QUEUE` queries,
+    /// for up to `ZENITHDS_QUERY_QUEUE_TIMEOUT` seconds. A limit of `0` is no limit.
+    ///
+    /// Returns a `TooManyRequests` error if the queue is full, and a `ServiceUnavailable`
+    /// error if the query waited too long.
+    pub async fn start(priority: Priority) -> Result<SlotGuard, ZenithError> {
+        let timeout = Duration::from_secs(config::envar_usize("ZENITHDS_QUERY_QUEUE_TIMEOUT") as u64);
+        Self::acquire(priority, Some((config::envar_usize("ZENITHDS_QUERY_QUEUE"), timeout))).await
+    }
+
+    /// Starts a query as `start` does, but waits for as long as it takes, outside of the queue.
+    /// Use this for queries that no client is waiting on, such as jobs.
+    pub async fn wait(priority: Priority) -> SlotGuard {
+        match Self::acquire(priority, None).await {
+            Ok(slot) => slot,
+            Err(_) => unreachable!("Queries waiting outside of the queue do not give up"),
+        }
+    }
+
+    /// Starts a query at `priority`, waiting in a queue of the given size for up to the given
+    /// time, if there is one.
+    async fn acquire(priority: Priority, queue: Option<(usize, Duration)>) -> Result<SlotGuard, ZenithError> {
+        let max = config::envar_usize("ZENITHDS_MAX_QUERIES");
+        let max_batch = config::envar_usize("ZENITHDS_MAX_BATCH_QUERIES");
+        let deadline = queue.map(|(_, timeout)| (tokio::time::Instant::now() + timeout, timeout));
+        // Stops counting the query as waiting when it starts, or when it gives up.
+        let mut waiting: Option<WaitGuard> = None;
+        loop {
+            // Registered before the slots are checked, so that a query finishing in between is not missed.
+            let finished = FINISHED.notified();
+            {
+                let mut slots = SLOTS.lock().unwrap_or_else(|e| e.into_inner());
+                let free = max == 0 || slots.running < max;
+                let startable = match priority {
+                    Priority::Interactive => free,
+                    Priority::Batch => free
+                        && (max_batch == 0 || slots.running_batch < max_batch)
+                        && slots.waiting_interactive == 0,
+                };
+                if startable {
+                    slots.running += 1;
+                    if priority == Priority::Batch {
+                        slots.running_batch += 1;
+                    }
+                    break;
+                }
+                if waiting.is_none() {
+                    if let Some((size, _)) = queue {
+                        if slots.waiting >= size {
+                            return Err(ZenithError::TooManyRequests(format!("{} queries waiting to run", size)));
+                        }
+                        slots.waiting += 1;
+                    }
+                    if priority == Priority::Interactive {
+                        slots.waiting_interactive += 1;
+                    }
+                    waiting = Some(WaitGuard { priority, queued: queue.is_some() });
+                }
+            }
+            match deadline {
+                Some((deadline, timeout)) => if tokio::time::timeout_at(deadline, finished).await.is_err() {
+                    return Err(ZenithError::ServiceUnavailable(format!("Waited {:?} for other queries to finish", timeout)));
+                },
+                None => finished.await,
+            }
+        }
+        drop(waiting);
+        Ok(SlotGuard(priority))
+    }
+}
+
+impl Drop for SlotGuard {
+    fn drop(&mut self) {
+        let mut slots = SLOTS.lock().unwrap_or_else(|e| e.into_inner());
+        slots.running -= 1;
+        if self.0 == Priority::Batch {
+            slots.running_batch -= 1;
+        }
+        drop(slots);
+        FINISHED.notify_waiters();
+    }
+}
+
+
+/// Counts a query as waiting to run until it is dropped, including when
+/// the client goes away while it waits.
+struct WaitGuard {
+    priority: Priority,
+    /// Whether the query is counted in the queue.
+    queued: bool,
+}
+
+impl Drop for WaitGuard {
+    fn drop(&mut self) {
+        let mut slots = SLOTS.lock().unwrap_or_else(|e| e.into_inner());
+        if self.queued {
+            slots.waiting -= 1;
+        }
+        if self.priority == Priority::Interactive {
+            slots.waiting_interactive -= 1;
+            drop(slots);
+            // Batch queries may have been held back for this one.
+            FINISHED.notify_waiters();
+        }
+    }
+}
+
+
+/// Returns the priority a query is run at: batch if it `requested` it, or if the `principal` has
+/// one of the roles in `ZENITHDS_BATCH_ROLES`, and otherwise interactive. A principal with a batch
+/// role cannot run interactive queries, so that long exports cannot be run ahead of dashboards.
+pub fn priority(requested: Option<Priority>, principal: &Principal) -> Priority {
+    let batch_roles = config::envar_str("ZENITHDS_BATCH_ROLES");
+    let batch_role = batch_roles.split(',')
+        .map(|r| r.trim())
+        .any(|r| !r.is_empty() && principal.roles.iter().any(|role| role == r));
+    match batch_role {
+        true => Priority::Batch,
+        false => requested.unwrap_or_default(),
+    }
+}
+
+
+/// Limits each client to `ZENITHDS_RATE_LIMIT` requests per second, in bursts of up to
+/// `ZENITHDS_RATE_BURST` requests (or the rate, if not set), and to `ZENITHDS_MAX_CLIENT_QUERIES`
+/// queries at once.
+/// A limit of `0` is no limit. Requests over a limit get a `TooManyRequests` error.
+/// Queries across all clients are limited by `SlotGuard`, once their priority is known.
+pub async fn limit(
+    request: Request,
+    next: Next,
+) -> Result<Response, ZenithError> {
+
+    let rate = config::envar_usize("ZENITHDS_RATE_LIMIT");
+    let max_queries = config::envar_usize("ZENITHDS_MAX_CLIENT_QUERIES");
+    if rate == 0 && max_queries == 0 {
+        return Ok(next.run(request).await);
+    }
+
+    let client = client(&request);
+    if rate > 0 {
+        let burst = match config::envar_usize("ZENITHDS_RATE_BURST") {
+            0 => rate,
+            burst => burst,
+        };
+        if !take(&client, rate as f64, burst as f64) {
+            return Err(ZenithError::TooManyRequests(format!("More than {} requests per second", rate)));
+        }
+    }
+
+    let is_query = request.extensions().get::<MatchedPath>()
+        .is_some_and(|p| p.as_str().ends_with("/query/{collection}"));
+    let _guard = match is_query && max_queries > 0 {
+        true => match QueryGuard::start(&client, max_queries) {
+            Some(guard) => Some(guard),
+            None => return Err(ZenithError::TooManyRequests(format!("More than {} queries at once", max_queries))),
+        },
+        false => None,
+    };
+    Ok(next.run(request).await)
+}
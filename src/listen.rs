@@ -0,0 +1,101 @@
+use std::{io, sync::OnceLock};
+use listenfd::ListenFd;
+use tokio::net::TcpListener;
+#[cfg(unix)]
+use tokio::net::UnixListener;
+use tracing::warn;
+
+use crate::{config, tls};
+
+
+/// What the socket passed by systemd is bound to, if the data service was given one.
+static ACTIVATED: OnceLock<String> = OnceLock::new();
+
+
+/// The socket the data service accepts connections on.
+pub enum Listener {
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix(UnixListener),
+}
+
+
+/// Whether the data service listens on a Unix socket, which is when `ZENITHDS_UNIX_SOCKET` is set.
+pub fn unix_socket() -> bool {
+    !config::envar_str("ZENITHDS_UNIX_SOCKET").is_empty()
+}
+
+
+/// Returns what the data service listens on, for logs.
+pub fn address() -> String {
+    if let Some(activated) = ACTIVATED.get() {
+        return format!("{} (passed by systemd)", activated);
+    }
+    match unix_socket() {
+        true => config::envar_str("ZENITHDS_UNIX_SOCKET"),
+        false => config::address(),
+    }
+}
+
+
+/// Takes the socket passed by systemd with socket activation (in `LISTEN_FDS`), if any.
+/// Only the first socket is used.
+fn activated() -> io::Result<Option<Listener>> {
+    let mut fds = ListenFd::from_env();
+    if fds.len() == 0 {
+        return Ok(None);
+    }
+    if fds.len() > 1 {
+        warn!("Only listening on the first of the {} sockets passed by systemd", fds.len());
+    }
+    if let Ok(Some(listener)) = fds.take_tcp_listener(0) {
+        let _ = ACTIVATED.set(listener.local_addr()?.to_string());
+        listener.set_nonblocking(true)?;
+        return Ok(Some(Listener::Tcp(TcpListener::from_std(listener)?)));
+    }
+    #[cfg(unix)]
+    if let Ok(Some(listener)) = fds.take_unix_listener(0) {
+        if tls::enabled() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "TLS cannot be used with a Unix socket"));
+        }
+        let path = listener.local_addr()?.as_pathname().map(|p| p.display().to_string());
+        let _ = ACTIVATED.set(path.unwrap_or_else(|| "a Unix socket".to_string()));
+        listener.set_nonblocking(true)?;
+        return Ok(Some(Listener::Unix(UnixListener::from_std(listener)?)));
+    }
+    Err(io::Error::new(io::ErrorKind::InvalidInput, "The socket passed by systemd is not a TCP or Unix stream socket"))
+}
+
+
+/// Binds the socket the data service listens on. This is the socket passed by systemd, if
+/// it was started by socket activation, or else the Unix socket at `ZENITHDS_UNIX_SOCKET`
+/// if it is set, or otherwise the TCP address from `config::address`. A socket left at the
+/// path by a data service that did not stop cleanly is removed first.
+pub async fn bind() -> io::Result<Listener> {
+    if let Some(listener) = activated()? {
+        return Ok(listener);
+    }
+    if !unix_socket() {
+        return Ok(Listener::Tcp(TcpListener::bind(config::address()).await?));
+    }
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::FileTypeExt;
+        let path = config::envar_str("ZENITHDS_UNIX_SOCKET");
+        if std::fs::symlink_metadata(&path).is_ok_and(|m| m.file_type().is_socket()) {
+            std::fs::remove_file(&path)?;
+        }
+        Ok(Listener::Unix(UnixListener::bind(&path)?))
+    }
+    #[cfg(not(unix))]
+    Err(io::Error::new(io::ErrorKind::Unsupported, "Unix sockets are not supported on this platform"))
+}
+
+
+/// Removes the Unix socket once the data service has stopped listening on it,
+/// unless it was passed by systemd, which owns it.
+pub fn unbind() {
+    if unix_socket() && ACTIVATED.get().is_none() {
+        let _ = std::fs::remove_file(config::envar_str("ZENITHDS_UNIX_SOCKET"));
+    }
+}
@@ -0,0 +1,47 @@
+use std::{io::IsTerminal, sync::OnceLock};
+use tracing::warn;
+use tracing_subscriber::{fmt, layer::SubscriberExt, reload, util::SubscriberInitExt, EnvFilter, Registry};
+
+use crate::config;
+
+
+/// Changes the levels that are logged at, when the configuration is reloaded.
+static FILTER: OnceLock<reload::Handle<EnvFilter, Registry>> = OnceLock::new();
+
+
+/// Parses the levels in `ZENITHDS_LOG`, returning an error with them if they are not valid.
+fn filter() -> Result<EnvFilter, String> {
+    let levels = config::envar_str("ZENITHDS_LOG");
+    EnvFilter::try_new(&levels).map_err(|err| format!("Log levels '{}' are not valid: {}", levels, err))
+}
+
+
+/// Sets up logging at the levels in `ZENITHDS_LOG`, such as `info` or
+/// `info,zenithds::db=debug`, as text, or as JSON if `ZENITHDS_LOG_FORMAT` is `json`.
+pub fn init() {
+    let (filter, invalid) = match filter() {
+        Ok(filter) => (filter, None),
+        Err(err) => (EnvFilter::new("info"), Some(err)),
+    };
+    let (filter, handle) = reload::Layer::new(filter);
+    let _ = FILTER.set(handle);
+    let registry = tracing_subscriber::registry().with(filter);
+    match config::envar_str("ZENITHDS_LOG_FORMAT").as_str() {
+        "json" => registry.with(fmt::layer().json()).init(),
+        _ => registry.with(fmt::layer().with_ansi(std::io::stdout().is_terminal())).init(),
+    }
+    if let Some(err) = invalid {
+        warn!("{}. Logging at info instead", err);
+    }
+}
+
+
+/// Parses the levels in `ZENITHDS_LOG` again. If they are not valid,
+/// returns an error and keeps logging at the levels in use.
+pub fn reload() -> Result<(), String> {
+    let filter = filter()?;
+    match FILTER.get() {
+        Some(handle) => handle.reload(filter).map_err(|err| format!("Could not change the log levels: {}", err)),
+        None => Ok(()),
+    }
+}
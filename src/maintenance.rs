@@ -0,0 +1,158 @@
+use std::{
+    collections::BTreeMap,
+    future::Future,
+    sync::{Arc, LazyLock, Mutex},
+    time::Duration,
+};
+use chrono::{DateTime, Utc};
+use tokio::sync::Notify;
+use tracing::{info, warn};
+
+use crate::shutdown;
+use crate::types::{
+    api::{MaintenanceState, MaintenanceTask},
+    error::{Missing, ZenithError},
+};
+
+
+/// When a maintenance task runs.
+#[derive(Clone, Copy)]
+pub enum Timing {
+    /// When it is registered, then each time the duration has passed since its last run started.
+    Interval(Duration),
+    /// At the start of each multiple of the duration since the Unix epoch, such as each minute.
+    Aligned(Duration),
+}
+
+impl Timing {
+    fn period(&self) -> Duration {
+        match self {
+            Timing::Interval(period) | Timing::Aligned(period) => (*period).max(Duration::from_secs(1)),
+        }
+    }
+}
+
+
+/// A maintenance task, with how it has run, and the signal that runs it at once.
+struct Task {
+    status: MaintenanceTask,
+    trigger: Arc<Notify>,
+}
+
+/// The maintenance tasks registered, by their names.
+static TASKS: LazyLock<Mutex<BTreeMap<String, Task>>> = LazyLock::new(|| Mutex::new(BTreeMap::new()));
+
+
+fn not_found(name: &str) -> ZenithError {
+    ZenithError::NotFound(Missing::Other(format!("Maintenance task '{}' does not exist", name)))
+}
+
+
+/// Changes the status of the task `name` with `update`.
+fn update(name: &str, update: impl FnOnce(&mut MaintenanceTask)) {
+    if let Some(task) = TASKS.lock().unwrap_or_else(|e| e.into_inner()).get_mut(name) {
+        update(&mut task.status);
+    }
+}
+
+
+/// Registers the maintenance task `name`, which runs `task` with the time it is run for, at the
+/// times given by `timing`, until the data service is asked to stop. Each run finishes before the
+/// next starts, and a run in progress when the data service is asked to stop is waited for. The
+/// error a run fails with is logged and kept in the status of the task, and the task runs again
+/// at its next time.
+pub fn register<F, Fut>(name: &str, timing: Timing, task: F)
+where
+    F: Fn(DateTime<Utc>) -> Fut + Send + 'static,
+    Fut: Future<Output = Result<(), ZenithError>> + Send,
+{
+    let name = name.to_string();
+    let period = timing.period();
+    let trigger = Arc::new(Notify::new());
+    TASKS.lock().unwrap_or_else(|e| e.into_inner()).insert(name.clone(), Task {
+        status: MaintenanceTask {
+            name: name.clone(),
+            period: period.as_secs(),
+            aligned: matches!(timing, Timing::Aligned(_)),
+            state: MaintenanceState::Idle,
+            runs: 0,
+            failures: 0,
+            last_started: None,
+            last_finished: None,
+            last_error: None,
+            next_run: None,
+        },
+        trigger: Arc::clone(&trigger),
+    });
+    info!("Running maintenance task '{}' every {:?}", name, period);
+
+    let period_millis = period.as_millis() as i64;
+    shutdown::spawn(async move {
+        let mut last: Option<DateTime<Utc>> = None;
+        loop {
+            let now = Utc::now();
+            let next = match timing {
+                Timing::Interval(_) => last.map_or(now, |last| last + period),
+                Timing::Aligned(_) => {
+                    let next = (now.timestamp_millis() / period_millis + 1) * period_millis;
+                    DateTime::from_timestamp_millis(next).unwrap_or(now)
+                },
+            };
+            update(&name, |status| status.next_run = Some(next.timestamp_millis() as u64));
+            let wait = (next - now).to_std().unwrap_or_default();
+            let triggered = tokio::select! {
+                _ = tokio::time::sleep(wait) => false,
+                _ = trigger.notified() => true,
+                _ = shutdown::requested() => return,
+            };
+            let time = match (triggered, timing) {
+                (true, _) | (false, Timing::Interval(_)) => Utc::now(),
+                // The time run for is the start of the period, even if the timer fired a little early or late.
+                (false, Timing::Aligned(_)) => {
+                    let time = (Utc::now().timestamp_millis() + period_millis / 2) / period_millis * period_millis;
+                    let time = DateTime::from_timestamp_millis(time).unwrap_or_else(Utc::now);
+                    if last == Some(time) {
+                        continue;
+                    }
+                    time
+                },
+            };
+            last = Some(time);
+
+            update(&name, |status| {
+                status.state = MaintenanceState::Running;
+                status.last_started = Some(Utc::now().timestamp_millis() as u64);
+                status.next_run = None;
+            });
+            let result = task(time).await;
+            if let Err(err) = &result {
+                warn!("Maintenance task '{}' failed: {}", name, err);
+            }
+            update(&name, |status| {
+                status.state = MaintenanceState::Idle;
+                status.runs += 1;
+                status.last_finished = Some(Utc::now().timestamp_millis() as u64);
+                status.last_error = result.err().map(|err| err.to_string());
+                if status.last_error.is_some() {
+                    status.failures += 1;
+                }
+            });
+        }
+    });
+}
+
+
+/// Lists the maintenance tasks, in order of their names, with how they have run.
+pub fn list() -> Vec<MaintenanceTask> {
+    TASKS.lock().unwrap_or_else(|e| e.into_inner()).values().map(|task| task.status.clone()).collect()
+}
+
+
+/// Runs the maintenance task `name` at once, for the time now, or as soon as the run in progress
+/// finishes. Returns its status, raising a `NotFound` error if it does not exist.
+pub fn run(name: &str) -> Result<MaintenanceTask, ZenithError> {
+    let tasks = TASKS.lock().unwrap_or_else(|e| e.into_inner());
+    let task = tasks.get(name).ok_or_else(|| not_found(name))?;
+    task.trigger.notify_one();
+    Ok(task.status.clone())
+}
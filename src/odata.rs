@@ -0,0 +1,458 @@
+use std::cmp::Ordering;
+
+use axum::{
+    http::{header::{CONTENT_TYPE, HOST}, HeaderMap, HeaderName},
+    response::{IntoResponse, Response},
+};
+use chrono::SecondsFormat;
+use serde_json::{json, Map, Value};
+
+use crate::auth::Principal;
+use crate::schema::{self, ColumnType};
+use crate::types::{api::{ODataParameters, QueryPredicates}, error::ZenithError};
+use crate::{config, db, queries, storage, tls};
+
+/// The namespace of the entity types in the metadata document.
+const NAMESPACE: &str = "ZenithDS";
+
+/// The content type of the JSON responses, which only give the metadata needed to read them.
+pub const JSON: &str = "application/json;odata.metadata=minimal";
+
+/// The content type of the metadata document.
+pub const XML: &str = "application/xml";
+
+
+/// A column of a collection, as a property of the entity type of its rows.
+struct Property {
+    name: String,
+    column: String,
+    column_type: ColumnType,
+}
+
+/// A collection, as an entity set with a property for each of its columns.
+pub struct EntitySet {
+    pub name: String,
+    collection: String,
+    properties: Vec<Property>,
+    /// The properties that identify an entity, if the schema of the collection has a key.
+    key: Vec<String>,
+}
+
+impl EntitySet {
+    /// Returns the position of the property called `name`, raising a `QueryError` if there is none.
+    fn position(&self, name: &str) -> Result<usize, ZenithError> {
+        self.properties.iter()
+            .position(|p| p.name == name)
+            .ok_or_else(|| ZenithError::QueryError(format!("Property '{}' does not exist on entity set '{}'", name, self.name)))
+    }
+}
+
+
+/// Returns `name` as a valid OData name, which has only letters, digits, and `_`, and does
+/// not start with a digit. Other characters are replaced by `_`.
+fn odata_name(name: &str) -> String {
+    let mut odata: String = name.chars().map(|c| if c.is_ascii_alphanumeric() { c } else { '_' }).collect();
+    if !odata.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_') {
+        odata.insert(0, '_');
+    }
+    odata
+}
+
+
+/// Returns `name` as an OData name that is not in `taken`, adding `_` until it is not.
+fn unique_name(name: &str, taken: &mut Vec<String>) -> String {
+    let mut unique = odata_name(name);
+    while taken.contains(&unique) {
+        unique.push('_');
+    }
+    taken.push(unique.clone());
+    unique
+}
+
+
+/// Returns the entity sets of the collections that `principal` can see, with the columns it
+/// can see as properties. See `db::columns`. Collections without any columns are left out.
+pub fn entity_sets(principal: &Principal) -> Result<Vec<EntitySet>, ZenithError> {
+    let mut sets = Vec::new();
+    let mut taken = Vec::new();
+    for collection in storage::list_collections()? {
+        let mut names = Vec::new();
+        let properties: Vec<Property> = db::columns(&collection, Some(principal))?.into_iter()
+            .map(|(column, column_type)| Property { name: unique_name(&column, &mut names), column, column_type })
+            .collect();
+        if properties.is_empty() {
+            continue;
+        }
+        // A masked column cannot identify an entity, as its values may no longer be distinct.
+        let key = match schema::read(&collection)? {
+            Some(schema) => {
+                let masks = schema.masks(&principal.roles);
+                match schema.key.iter().all(|column| !masks.contains_key(column)) {
+                    true => schema.key.iter()
+                        .filter_map(|column| properties.iter().find(|p| p.column == *column).map(|p| p.name.clone()))
+                        .collect(),
+                    false => Vec::new(),
+                }
+            },
+            None => Vec::new(),
+        };
+        sets.push(EntitySet { name: unique_name(&collection, &mut taken), collection, properties, key });
+    }
+    Ok(sets)
+}
+
+
+/// Returns the URL of the OData service, at the host that the request with `headers` was sent to.
+pub fn service_root(headers: &HeaderMap) -> String {
+    let scheme = if tls::enabled() { "https" } else { "http" };
+    let host = headers.get(HOST).and_then(|v| v.to_str().ok()).map(str::to_string).unwrap_or_else(config::address);
+    format!("{}://{}{}/odata", scheme, host, config::prefix("v1"))
+}
+
+
+/// Returns `body` as a response of `content_type`, giving the version of OData it follows.
+pub fn response(content_type: &'static str, body: String) -> Response {
+    ([(CONTENT_TYPE, content_type), (HeaderName::from_static("odata-version"), "4.0")], body).into_response()
+}
+
+
+/// Returns the service document, which lists the entity `sets` of the service at `root`.
+pub fn service_document(root: &str, sets: &[EntitySet]) -> Value {
+    let sets: Vec<Value> = sets.iter()
+        .map(|set| json!({"name": set.name, "kind": "EntitySet", "url": set.name}))
+        .collect();
+    json!({"@odata.context": format!("{}/$metadata", root), "value": sets})
+}
+
+
+/// Returns the metadata document, which describes the entity type of each of the entity `sets`
+/// in CSDL. Properties of any type but `Edm.String` can be null, for empty values.
+pub fn metadata(sets: &[EntitySet]) -> String {
+    let mut types = String::new();
+    let mut container = String::new();
+    for set in sets {
+        types.push_str(&format!("      <EntityType Name=\"{}Row\">\n", set.name));
+        if !set.key.is_empty() {
+            types.push_str("        <Key>\n");
+            for name in &set.key {
+                types.push_str(&format!("          <PropertyRef Name=\"{}\"/>\n", name));
+            }
+            types.push_str("        </Key>\n");
+        }
+        for property in &set.properties {
+            let edm_type = match property.column_type {
+                ColumnType::String => "Edm.String",
+                ColumnType::Int => "Edm.Int64",
+                ColumnType::Float => "Edm.Double",
+                ColumnType::Bool => "Edm.Boolean",
+                ColumnType::Date => "Edm.DateTimeOffset",
+            };
+            let nullable = property.column_type != ColumnType::String && !set.key.contains(&property.name);
+            types.push_str(&format!("        <Property Name=\"{}\" Type=\"{}\" Nullable=\"{}\"/>\n", property.name, edm_type, nullable));
+        }
+        types.push_str("      </EntityType>\n");
+        container.push_str(&format!("        <EntitySet Name=\"{}\" EntityType=\"{}.{}Row\"/>\n", set.name, NAMESPACE, set.name));
+    }
+    format!(
+        "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n\
+        <edmx:Edmx Version=\"4.0\" xmlns:edmx=\"http://docs.oasis-open.org/odata/ns/edmx\">\n  \
+        <edmx:DataServices>\n    \
+        <Schema Namespace=\"{NAMESPACE}\" xmlns=\"http://docs.oasis-open.org/odata/ns/edm\">\n\
+        {types}      \
+        <EntityContainer Name=\"Container\">\n\
+        {container}      \
+        </EntityContainer>\n    \
+        </Schema>\n  \
+        </edmx:DataServices>\n\
+        </edmx:Edmx>\n"
+    )
+}
+
+
+/// A token of a `$filter` expression.
+#[derive(Debug, PartialEq)]
+enum Token {
+    /// A name, operator, number, date, `true`, `false`, or `null`.
+    Word(String),
+    /// A string, without its quotes.
+    Text(String),
+    /// `(`, `)`, or `,`.
+    Symbol(char),
+}
+
+/// Returns a `PredicateError` for `message` about a `$filter` expression.
+fn filter_error(message: &str) -> ZenithError {
+    ZenithError::PredicateError(format!("{} in $filter", message))
+}
+
+/// Splits a `$filter` expression into tokens. Strings are quoted with `'`, which is written `''` inside them.
+fn tokenize(filter: &str) -> Result<Vec<Token>, ZenithError> {
+    let mut tokens = Vec::new();
+    let mut chars = filter.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => (),
+            '(' | ')' | ',' => tokens.push(Token::Symbol(c)),
+            '\'' => {
+                let mut text = String::new();
+                loop {
+                    match chars.next() {
+                        Some('\'') if chars.peek() == Some(&'\'') => {
+                            chars.next();
+                            text.push('\'');
+                        },
+                        Some('\'') => break,
+                        Some(c) => text.push(c),
+                        None => return Err(filter_error("A string is not closed")),
+                    }
+                }
+                tokens.push(Token::Text(text));
+            },
+            c => {
+                let mut word = c.to_string();
+                while let Some(&c) = chars.peek() {
+                    if c.is_whitespace() || "(),'".contains(c) {
+                        break;
+                    }
+                    word.push(c);
+                    chars.next();
+                }
+                tokens.push(Token::Word(word));
+            },
+        }
+    }
+    Ok(tokens)
+}
+
+/// Parses a `$filter` expression on an entity set into the predicates of a query.
+struct Filter<'a> {
+    tokens: Vec<Token>,
+    position: usize,
+    set: &'a EntitySet,
+}
+
+impl Filter<'_> {
+    fn next(&mut self) -> Option<&Token> {
+        self.position += 1;
+        self.tokens.get(self.position - 1)
+    }
+
+    fn expect(&mut self, symbol: char) -> Result<(), ZenithError> {
+        match self.next() {
+            Some(Token::Symbol(s)) if *s == symbol => Ok(()),
+            _ => Err(filter_error(&format!("Expected '{}'", symbol))),
+        }
+    }
+
+    /// Returns the column of the property named by the next token.
+    fn column(&mut self) -> Result<String, ZenithError> {
+        match self.next() {
+            Some(Token::Word(name)) => {
+                let name = name.clone();
+                let position = self.set.position(&name)?;
+                Ok(self.set.properties[position].column.clone())
+            },
+            other => Err(filter_error(&format!("Expected a property, not {:?}", other))),
+        }
+    }
+
+    /// Parses conditions joined by `and`, adding a predicate for each to `predicates`.
+    fn conditions(&mut self, predicates: &mut Vec<String>) -> Result<(), ZenithError> {
+        loop {
+            self.condition(predicates)?;
+            match self.tokens.get(self.position) {
+                Some(Token::Word(and)) if and == "and" => self.position += 1,
+                _ => return Ok(()),
+            }
+        }
+    }
+
+    /// Parses a comparison of a property to a value, `contains(property, 'text')`, or
+    /// conditions in parentheses.
+    fn condition(&mut self, predicates: &mut Vec<String>) -> Result<(), ZenithError> {
+        let is_call = self.tokens.get(self.position + 1) == Some(&Token::Symbol('('));
+        match self.tokens.get(self.position) {
+            Some(Token::Symbol('(')) => {
+                self.position += 1;
+                self.conditions(predicates)?;
+                match self.tokens.get(self.position) {
+                    Some(Token::Word(word)) => Err(filter_error(&format!("'{}' is not supported", word))),
+                    _ => self.expect(')'),
+                }
+            },
+            Some(Token::Word(function)) if function == "contains" && is_call => {
+                self.position += 2;
+                let column = self.column()?;
+                self.expect(',')?;
+                let text = match self.next() {
+                    Some(Token::Text(text)) => text.clone(),
+                    _ => return Err(filter_error("Expected a string in contains")),
+                };
+                self.expect(')')?;
+                predicates.push(format!("{} CONTAINS {}", column, text));
+                Ok(())
+            },
+            Some(Token::Word(function)) if is_call => Err(filter_error(&format!("Function '{}' is not supported", function))),
+            _ => {
+                let column = self.column()?;
+                let op = match self.next() {
+                    Some(Token::Word(op)) => match op.as_str() {
+                        "eq" => "==",
+                        "ne" => "!=",
+                        "lt" => "<",
+                        "gt" => ">",
+                        "le" => "<=",
+                        "ge" => ">=",
+                        _ => return Err(filter_error(&format!("Operator '{}' is not supported", op))),
+                    },
+                    other => return Err(filter_error(&format!("Expected an operator, not {:?}", other))),
+                };
+                let value = match self.next() {
+                    Some(Token::Text(text)) => text.clone(),
+                    // Empty values are null.
+                    Some(Token::Word(null)) if null == "null" => String::new(),
+                    Some(Token::Word(value)) => value.clone(),
+                    other => return Err(filter_error(&format!("Expected a value, not {:?}", other))),
+                };
+                predicates.push(format!("{} {} {}", column, op, value));
+                Ok(())
+            },
+        }
+    }
+}
+
+/// Returns the predicates of a query for the `$filter` expression `filter` on `set`. Only
+/// comparisons (`eq`, `ne`, `lt`, `gt`, `le`, `ge`) of a property to a value, and `contains`,
+/// can be used, and only joined by `and`.
+fn predicates(filter: &str, set: &EntitySet) -> Result<Vec<String>, ZenithError> {
+    let mut parser = Filter { tokens: tokenize(filter)?, position: 0, set };
+    let mut predicates = Vec::new();
+    if parser.tokens.is_empty() {
+        return Ok(predicates);
+    }
+    parser.conditions(&mut predicates)?;
+    match parser.tokens.get(parser.position) {
+        Some(Token::Word(word)) => Err(filter_error(&format!("'{}' is not supported", word))),
+        Some(token) => Err(filter_error(&format!("Unexpected {:?}", token))),
+        None => Ok(predicates),
+    }
+}
+
+
+/// Returns the properties to order entities by in `$orderby`, and whether each is descending.
+fn order(orderby: &str, set: &EntitySet) -> Result<Vec<(usize, bool)>, ZenithError> {
+    orderby.split(',')
+        .map(|item| {
+            let mut parts = item.split_whitespace();
+            let name = parts.next().unwrap_or_default();
+            let descending = match (parts.next(), parts.next()) {
+                (None | Some("asc"), None) => false,
+                (Some("desc"), None) => true,
+                _ => return Err(ZenithError::QueryError(format!("'{}' in $orderby is not a property and direction", item.trim()))),
+            };
+            Ok((set.position(name)?, descending))
+        })
+        .collect()
+}
+
+
+/// Returns `value` as JSON of the type of its property. Empty values, and values that cannot be
+/// parsed as the type, are `null`, except of `Edm.String` properties. Dates are given in RFC 3339.
+fn entity_value(column_type: ColumnType, value: &str) -> Value {
+    match column_type {
+        ColumnType::String => Value::String(value.to_string()),
+        ColumnType::Date => schema::parse_date(value)
+            .map(|d| Value::String(d.and_utc().to_rfc3339_opts(SecondsFormat::AutoSi, true)))
+            .unwrap_or(Value::Null),
+        _ => match column_type.to_json(value) {
+            Value::String(_) => Value::Null,
+            typed => typed,
+        },
+    }
+}
+
+
+/// Returns the entities of `set` asked for by the query options in `parameters`, for
+/// `principal`, as a response of the service at `root`. The query is stopped if `cancelled`
+/// is set. Entities are ordered by `$orderby` before `$skip` and `$top` are applied.
+pub async fn entities(
+    set: &EntitySet,
+    parameters: ODataParameters,
+    root: &str,
+    principal: Principal,
+    cancelled: &queries::Cancelled,
+) -> Result<Value, ZenithError> {
+
+    let selected: Vec<usize> = match parameters.select.as_deref().map(str::trim) {
+        None | Some("") | Some("*") => (0..set.properties.len()).collect(),
+        Some(select) => select.split(',')
+            .map(|name| set.position(name.trim()))
+            .collect::<Result<_, _>>()?,
+    };
+    let order = match parameters.orderby.as_deref() {
+        Some(orderby) if !orderby.trim().is_empty() => order(orderby, set)?,
+        _ => Vec::new(),
+    };
+    let predicates = match parameters.filter.as_deref() {
+        Some(filter) => predicates(filter, set)?,
+        None => Vec::new(),
+    };
+
+    // Properties that entities are ordered by are queried too, even if they are not selected.
+    let mut queried: Vec<usize> = selected.clone();
+    for (property, _) in &order {
+        if !queried.contains(property) {
+            queried.push(*property);
+        }
+    }
+    let fields = queried.iter().map(|p| set.properties[*p].column.clone()).collect();
+    let ((header, mut rows), _) = crate::run_query(&set.collection, QueryPredicates { fields, predicates }, principal, cancelled, false).await?;
+    let position = |property: usize| header.iter().position(|name| *name == set.properties[property].column);
+
+    let order: Vec<(Option<usize>, ColumnType, bool)> = order.into_iter()
+        .map(|(property, descending)| (position(property), set.properties[property].column_type, descending))
+        .collect();
+    if !order.is_empty() {
+        rows.sort_by(|a, b| {
+            order.iter()
+                .filter_map(|(i, column_type, descending)| i.map(|i| (i, column_type, descending)))
+                .map(|(i, column_type, descending)| {
+                    let ordering = column_type.compare(&a[i], &b[i]);
+                    if *descending { ordering.reverse() } else { ordering }
+                })
+                .find(|ordering| ordering.is_ne())
+                .unwrap_or(Ordering::Equal)
+        });
+    }
+
+    let total = rows.len();
+    let start = parameters.skip.unwrap_or_default().min(total);
+    let end = parameters.top.map_or(total, |top| start.saturating_add(top).min(total));
+    let columns: Vec<(&Property, Option<usize>)> = selected.iter().map(|p| (&set.properties[*p], position(*p))).collect();
+    let values: Vec<Value> = rows[start..end].iter()
+        .map(|row| {
+            let entity: Map<String, Value> = columns.iter()
+                .map(|(property, i)| {
+                    let value = i.and_then(|i| row.get(i)).map(|v| entity_value(property.column_type, v)).unwrap_or(Value::Null);
+                    (property.name.clone(), value)
+                })
+                .collect();
+            Value::Object(entity)
+        })
+        .collect();
+
+    let context = match parameters.select.as_deref().map(str::trim) {
+        None | Some("") | Some("*") => format!("{}/$metadata#{}", root, set.name),
+        Some(_) => {
+            let names: Vec<&str> = columns.iter().map(|(property, _)| property.name.as_str()).collect();
+            format!("{}/$metadata#{}({})", root, set.name, names.join(","))
+        },
+    };
+    let mut response = Map::new();
+    response.insert("@odata.context".to_string(), Value::String(context));
+    if parameters.count.unwrap_or(false) {
+        response.insert("@odata.count".to_string(), Value::from(total));
+    }
+    response.insert("value".to_string(), Value::Array(values));
+    Ok(Value::Object(response))
+}
@@ -0,0 +1,41 @@
+use std::sync::{Condvar, LazyLock, Mutex};
+
+use crate::config;
+
+
+/// The number of files open to be read by queries.
+static OPEN: LazyLock<Mutex<usize>> = LazyLock::new(|| Mutex::new(0));
+
+/// Notified when a file is closed, to wake a worker waiting to open one.
+static CLOSED: Condvar = Condvar::new();
+
+
+/// Counts a file open to be read until it is dropped.
+pub struct OpenFile(bool);
+
+impl OpenFile {
+    /// Waits until fewer than `ZENITHDS_MAX_OPEN_FILES` files are open to be read across all
+    /// queries, then counts another, so that queries over collections with thousands of files
+    /// do not run out of file descriptors. Files stored in memory are not counted, and
+    /// nothing is waited for if the limit is `0`.
+    pub fn acquire() -> OpenFile {
+        let max = config::envar_usize("ZENITHDS_MAX_OPEN_FILES");
+        if max == 0 || config::envar_str("ZENITHDS_STORAGE") == "memory" {
+            return OpenFile(false);
+        }
+        let open = OPEN.lock().unwrap_or_else(|e| e.into_inner());
+        let mut open = CLOSED.wait_while(open, |open| *open >= max).unwrap_or_else(|e| e.into_inner());
+        *open += 1;
+        OpenFile(true)
+    }
+}
+
+impl Drop for OpenFile {
+    fn drop(&mut self) {
+        if self.0 {
+            let mut open = OPEN.lock().unwrap_or_else(|e| e.into_inner());
+            *open -= 1;
+            CLOSED.notify_one();
+        }
+    }
+}
@@ -0,0 +1,816 @@
+use std::{fmt::Debug, net::SocketAddr, sync::Arc};
+
+use async_trait::async_trait;
+use axum::http::{HeaderMap, HeaderValue, header::AUTHORIZATION};
+use futures_util::{Sink, SinkExt};
+use pgwire::api::{
+    auth::{self, DefaultServerParameterProvider, StartupHandler},
+    portal::{Format, Portal},
+    query::{ExtendedQueryHandler, SimpleQueryHandler},
+    results::{DataRowEncoder, DescribePortalResponse, DescribeStatementResponse, FieldInfo, QueryResponse, Response, Tag},
+    stmt::{NoopQueryParser, StoredStatement},
+    ClientInfo, PgWireConnectionState, PgWireServerHandlers, PidSecretKeyGenerator, RandomPidSecretKeyGenerator, Type,
+};
+use pgwire::error::{ErrorInfo, PgWireError, PgWireResult};
+use pgwire::messages::{startup::Authentication, PgWireBackendMessage, PgWireFrontendMessage};
+use tokio::net::TcpListener;
+use tracing::{debug, info, warn, error};
+
+use crate::auth::{Principal, Scope};
+use crate::schema::{self, ColumnType};
+use crate::types::{api::QueryPredicates, error::{Missing, ZenithError}, query::DataQuery};
+use crate::{acl, config, db, limit, queries, request_id, shutdown, storage};
+
+/// The schema that collections are tables in.
+const SCHEMA: &str = "public";
+
+
+/// Returns the PostgreSQL error for `error`, with the closest SQLSTATE code,
+/// hiding the details of errors in the data service.
+fn pg_error(error: ZenithError) -> PgWireError {
+    let code = match error {
+        ZenithError::PredicateError(_) => "42601",
+        ZenithError::QueryError(_) => "22023",
+        ZenithError::NotFound(Missing::Collection(_)) => "42P01",
+        ZenithError::NotFound(_) => "42704",
+        ZenithError::Conflict(_) => "23505",
+        ZenithError::PayloadTooLarge(_) => "54000",
+        ZenithError::Unauthorized(_) => "28P01",
+        ZenithError::Forbidden(_) => "42501",
+        ZenithError::TooManyRequests(_) | ZenithError::ResultTooLarge(_) => "53000",
+        ZenithError::ServiceUnavailable(_) => "57P03",
+        ZenithError::RemoteError(_) => "58000",
+        ZenithError::Cancelled(_) => "57014",
+        _ => {
+            error!("{error}");
+            return user_error("XX000", "Something went wrong".to_string());
+        },
+    };
+    user_error(code, error.to_string())
+}
+
+
+fn user_error(code: &str, message: String) -> PgWireError {
+    PgWireError::UserError(Box::new(ErrorInfo::new("ERROR".to_string(), code.to_string(), message)))
+}
+
+
+fn unsupported(what: &str) -> PgWireError {
+    user_error("0A000", format!("{} is not supported", what))
+}
+
+
+/// A token of a SQL statement.
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    /// A keyword or an identifier that is not quoted.
+    Word(String),
+    /// An identifier in double quotes.
+    Quoted(String),
+    /// A string in single quotes.
+    Text(String),
+    Number(String),
+    Symbol(String),
+}
+
+
+/// Splits `sql` into tokens, leaving out comments.
+fn tokenize(sql: &str) -> PgWireResult<Vec<Token>> {
+    let mut tokens = Vec::new();
+    let mut chars = sql.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c.is_whitespace() {
+            continue;
+        }
+        if c == '-' && chars.peek() == Some(&'-') {
+            chars.by_ref().find(|c| *c == '\n');
+        }
+        else if c.is_alphabetic() || c == '_' {
+            let mut word = c.to_string();
+            while let Some(c) = chars.next_if(|c| c.is_alphanumeric() || *c == '_' || *c == '$') {
+                word.push(c);
+            }
+            tokens.push(Token::Word(word));
+        }
+        else if c.is_ascii_digit() {
+            let mut number = c.to_string();
+            while let Some(c) = chars.next_if(|c| c.is_ascii_digit() || *c == '.') {
+                number.push(c);
+            }
+            tokens.push(Token::Number(number));
+        }
+        else if c == '\'' || c == '"' {
+            // A quote is escaped by doubling it.
+            let mut text = String::new();
+            loop {
+                match chars.next() {
+                    Some(q) if q == c && chars.peek() == Some(&c) => {
+                        text.push(c);
+                        chars.next();
+                    },
+                    Some(q) if q == c => break,
+                    Some(other) => text.push(other),
+                    None => return Err(user_error("42601", "Unterminated quoted string or identifier".to_string())),
+                }
+            }
+            tokens.push(if c == '\'' { Token::Text(text) } else { Token::Quoted(text) });
+        }
+        else {
+            let mut symbol = c.to_string();
+            if let Some(next) = chars.next_if(|next| matches!((c, next), ('<', '=' | '>') | ('>', '=') | ('!', '='))) {
+                symbol.push(next);
+            }
+            tokens.push(Token::Symbol(symbol));
+        }
+    }
+    Ok(tokens)
+}
+
+
+/// An identifier, which matches a name exactly if it is quoted, and regardless of case if not.
+#[derive(Debug)]
+struct Identifier {
+    name: String,
+    quoted: bool,
+}
+
+impl Identifier {
+    /// Returns the name in `names` that the identifier refers to. An unquoted identifier
+    /// refers to the name it equals, if there is one, or else to the name it equals regardless of case.
+    fn resolve<'a>(&self, names: impl Iterator<Item = &'a str> + Clone) -> Option<&'a str> {
+        let mut exact = names.clone().filter(|name| *name == self.name);
+        match (exact.next(), self.quoted) {
+            (Some(name), _) => Some(name),
+            (None, true) => None,
+            (None, false) => names.into_iter().find(|name| name.eq_ignore_ascii_case(&self.name)),
+        }
+    }
+}
+
+
+/// A table that can be queried.
+#[derive(Debug)]
+enum Table {
+    Collection(Identifier),
+    /// `information_schema.tables`, which lists the collections.
+    Tables,
+    /// `information_schema.columns`, which lists the columns of each collection.
+    Columns,
+}
+
+
+/// A query on a table, such as `SELECT a, b FROM sales WHERE region = 'west' LIMIT 10`.
+#[derive(Debug)]
+struct Select {
+    table: Table,
+    /// The columns returned, or every column if empty.
+    columns: Vec<Identifier>,
+    /// The conditions that each row must meet, as a column, an operator, and a value.
+    conditions: Vec<(Identifier, &'static str, String)>,
+    limit: Option<usize>,
+    offset: usize,
+}
+
+
+/// A SQL statement, as it is run by the listener.
+#[derive(Debug)]
+enum Statement {
+    Select(Select),
+    /// A query of constant values without a table, such as `SELECT 1` or `SELECT version()`.
+    Values(Vec<(String, String)>),
+    /// A statement that does nothing here, such as `SET` or `BEGIN`, with the tag it is answered with.
+    Ignored(&'static str),
+}
+
+
+/// Reads a SQL statement from its tokens.
+struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        token
+    }
+
+    /// Takes the next token if it is the `keyword`.
+    fn keyword(&mut self, keyword: &str) -> bool {
+        let found = matches!(self.peek(), Some(Token::Word(word)) if word.eq_ignore_ascii_case(keyword));
+        self.position += found as usize;
+        found
+    }
+
+    /// Takes the next token if it is the `symbol`.
+    fn symbol(&mut self, symbol: &str) -> bool {
+        let found = matches!(self.peek(), Some(Token::Symbol(s)) if s == symbol);
+        self.position += found as usize;
+        found
+    }
+
+    fn identifier(&mut self) -> PgWireResult<Identifier> {
+        match self.next() {
+            Some(Token::Word(name)) => Ok(Identifier { name, quoted: false }),
+            Some(Token::Quoted(name)) => Ok(Identifier { name, quoted: true }),
+            other => Err(user_error("42601", format!("Expected an identifier, found {:?}", other))),
+        }
+    }
+
+    /// Reads an identifier that may be qualified, as in `schema.table`, returning its parts.
+    fn qualified(&mut self) -> PgWireResult<Vec<Identifier>> {
+        let mut parts = vec![self.identifier()?];
+        while self.symbol(".") {
+            parts.push(self.identifier()?);
+        }
+        Ok(parts)
+    }
+
+    fn count(&mut self) -> PgWireResult<usize> {
+        match self.next() {
+            Some(Token::Number(n)) => n.parse().map_err(|_| user_error("22023", format!("'{}' is not a count of rows", n))),
+            Some(Token::Word(all)) if all.eq_ignore_ascii_case("all") => Ok(usize::MAX),
+            other => Err(user_error("42601", format!("Expected a count of rows, found {:?}", other))),
+        }
+    }
+
+    /// Reads a constant value: a string, a number, or `true`, `false`, or `null`.
+    fn value(&mut self) -> PgWireResult<String> {
+        let negative = self.symbol("-");
+        match self.next() {
+            Some(Token::Number(n)) if negative => Ok(format!("-{}", n)),
+            Some(Token::Number(n)) => Ok(n),
+            Some(Token::Text(text)) if !negative => Ok(text),
+            Some(Token::Word(word)) if !negative && ["true", "false"].contains(&word.to_lowercase().as_str()) => Ok(word.to_lowercase()),
+            Some(Token::Word(word)) if !negative && word.eq_ignore_ascii_case("null") => Ok(String::new()),
+            other => Err(user_error("42601", format!("Expected a constant value, found {:?}", other))),
+        }
+    }
+
+    /// Reads the name given to a column with `AS`, if there is one.
+    fn alias(&mut self) -> PgWireResult<Option<String>> {
+        match self.keyword("as") {
+            true => Ok(Some(self.identifier()?.name)),
+            false => Ok(None),
+        }
+    }
+
+    /// Whether the tokens of the statement being read have run out, at the end or at a `;`.
+    fn at_end(&self) -> bool {
+        self.peek().is_none_or(|t| matches!(t, Token::Symbol(s) if s == ";"))
+    }
+
+    fn statement(&mut self) -> PgWireResult<Statement> {
+        let ignored = match self.next() {
+            Some(Token::Word(word)) => match word.to_uppercase().as_str() {
+                "SELECT" => None,
+                // Clients set up their sessions with these, which have no effect on the data service.
+                "SET" => Some("SET"),
+                "RESET" => Some("RESET"),
+                "DISCARD" => Some("DISCARD ALL"),
+                "BEGIN" | "START" => Some("BEGIN"),
+                "COMMIT" | "END" => Some("COMMIT"),
+                "ROLLBACK" | "ABORT" => Some("ROLLBACK"),
+                other => return Err(unsupported(&format!("'{}'", other))),
+            },
+            _ => return Err(user_error("42601", "Expected a statement".to_string())),
+        };
+        if let Some(tag) = ignored {
+            while !self.at_end() {
+                self.position += 1;
+            }
+            return Ok(Statement::Ignored(tag));
+        }
+        let statement = self.select()?;
+        match self.at_end() {
+            true => Ok(statement),
+            false => Err(user_error("42601", format!(
+                "Unexpected {:?}; only SELECT, FROM, WHERE, LIMIT, and OFFSET are supported", self.peek().expect("not at the end")
+            ))),
+        }
+    }
+
+    fn select(&mut self) -> PgWireResult<Statement> {
+        // Without a table, the columns are constants.
+        let mut rest = self.tokens[self.position..].iter().take_while(|t| !matches!(t, Token::Symbol(s) if s == ";"));
+        if !rest.any(|t| matches!(t, Token::Word(w) if w.eq_ignore_ascii_case("from"))) {
+            let mut values = Vec::new();
+            loop {
+                let value = match self.peek() {
+                    Some(Token::Word(function)) if !["true", "false", "null"].contains(&function.to_lowercase().as_str()) => {
+                        let function = function.to_lowercase();
+                        self.next();
+                        if !(self.symbol("(") && self.symbol(")")) {
+                            return Err(unsupported(&format!("Column '{}' without a table", function)));
+                        }
+                        let value = match function.as_str() {
+                            "version" => format!("PostgreSQL 16.0 (ZenithDS {})", env!("CARGO_PKG_VERSION")),
+                            "current_database" => "zenithds".to_string(),
+                            "current_schema" => SCHEMA.to_string(),
+                            _ => return Err(unsupported(&format!("Function '{}'", function))),
+                        };
+                        (function, value)
+                    },
+                    _ => ("?column?".to_string(), self.value()?),
+                };
+                let (name, value) = value;
+                values.push((self.alias()?.unwrap_or(name), value));
+                if !self.symbol(",") {
+                    break;
+                }
+            }
+            return Ok(Statement::Values(values));
+        }
+
+        let mut columns = Vec::new();
+        if !self.symbol("*") {
+            loop {
+                let column = self.qualified()?.pop().expect("a qualified name has a part");
+                if self.alias()?.is_some() {
+                    return Err(unsupported("Renaming columns with AS"));
+                }
+                columns.push(column);
+                if !self.symbol(",") {
+                    break;
+                }
+            }
+        }
+        if !self.keyword("from") {
+            return Err(user_error("42601", "Expected FROM".to_string()));
+        }
+        let mut parts = self.qualified()?;
+        let name = parts.pop().expect("a qualified name has a part");
+        let table = match parts.pop() {
+            Some(schema) if schema.name.eq_ignore_ascii_case("information_schema") => {
+                match name.name.to_lowercase().as_str() {
+                    "tables" => Table::Tables,
+                    "columns" => Table::Columns,
+                    _ => return Err(unsupported(&format!("Table 'information_schema.{}'", name.name))),
+                }
+            },
+            Some(schema) if !schema.name.eq_ignore_ascii_case(SCHEMA) => {
+                return Err(user_error("3F000", format!("Schema '{}' does not exist", schema.name)));
+            },
+            _ => Table::Collection(name),
+        };
+        // A table can be given another name, which columns can be qualified with.
+        let keywords = ["where", "limit", "offset", "order", "group", "having", "join", "inner", "left", "right", "cross", "union"];
+        if matches!(self.peek(), Some(Token::Word(w)) if !keywords.contains(&w.to_lowercase().as_str())) {
+            self.keyword("as");
+            self.identifier()?;
+        }
+
+        let mut conditions = Vec::new();
+        if self.keyword("where") {
+            loop {
+                let column = self.qualified()?.pop().expect("a qualified name has a part");
+                let op = match self.next() {
+                    Some(Token::Symbol(op)) => match op.as_str() {
+                        "=" => "==",
+                        "<>" | "!=" => "!=",
+                        "<" => "<",
+                        ">" => ">",
+                        "<=" => "<=",
+                        ">=" => ">=",
+                        _ => return Err(unsupported(&format!("Operator '{}'", op))),
+                    },
+                    Some(Token::Word(like)) if like.eq_ignore_ascii_case("like") => "LIKE",
+                    other => return Err(unsupported(&format!("{:?} in WHERE", other))),
+                };
+                let value = self.value()?;
+                let condition = match op {
+                    // Only a pattern matching a substring can be run, as `CONTAINS`.
+                    "LIKE" => match value.strip_prefix('%').and_then(|v| v.strip_suffix('%')) {
+                        Some(substring) if !substring.contains(['%', '_']) => (column, "CONTAINS", substring.to_string()),
+                        _ => return Err(unsupported("LIKE with a pattern other than '%text%'")),
+                    },
+                    _ => (column, op, value),
+                };
+                conditions.push(condition);
+                if !self.keyword("and") {
+                    break;
+                }
+            }
+        }
+
+        let (mut limit, mut offset) = (None, 0);
+        loop {
+            if self.keyword("limit") {
+                limit = Some(self.count()?);
+            }
+            else if self.keyword("offset") {
+                offset = self.count()?;
+                self.keyword("rows");
+            }
+            else {
+                break;
+            }
+        }
+        Ok(Statement::Select(Select { table, columns, conditions, limit, offset }))
+    }
+}
+
+
+/// Parses the SQL statements in `sql`, which are separated by `;`.
+fn parse(sql: &str) -> PgWireResult<Vec<Statement>> {
+    let mut parser = Parser { tokens: tokenize(sql)?, position: 0 };
+    let mut statements = Vec::new();
+    loop {
+        while parser.symbol(";") {}
+        if parser.peek().is_none() {
+            return Ok(statements);
+        }
+        statements.push(parser.statement()?);
+    }
+}
+
+
+/// Parses `sql`, which must be a single statement, as in a prepared statement.
+fn parse_one(sql: &str) -> PgWireResult<Statement> {
+    let mut statements = parse(sql)?;
+    match statements.len() {
+        1 => Ok(statements.remove(0)),
+        0 => Ok(Statement::Ignored("")),
+        _ => Err(user_error("42601", "A prepared statement cannot have more than one statement".to_string())),
+    }
+}
+
+
+/// The PostgreSQL type that the values of a column of `column_type` are returned as.
+fn pg_type(column_type: ColumnType) -> Type {
+    match column_type {
+        ColumnType::Int => Type::INT8,
+        ColumnType::Float => Type::FLOAT8,
+        ColumnType::Bool => Type::BOOL,
+        ColumnType::String | ColumnType::Date => Type::TEXT,
+    }
+}
+
+
+/// A table to return, with the name and type of each of its columns.
+type Columns = Vec<(String, ColumnType)>;
+
+
+/// Returns the name of the collection that `identifier` refers to, raising
+/// an `undefined_table` error if there is none.
+fn collection(identifier: &Identifier) -> PgWireResult<String> {
+    let collections = storage::list_collections().map_err(|err| pg_error(err.into()))?;
+    identifier.resolve(collections.iter().map(String::as_str))
+        .map(str::to_string)
+        .ok_or_else(|| user_error("42P01", format!("Relation '{}' does not exist", identifier.name)))
+}
+
+
+/// Returns the columns of the `table` that `principal` can see, and the rows of the table
+/// if it is not a collection, which are made up here rather than read from files.
+fn table(table: &Table, principal: &Principal) -> PgWireResult<(Columns, Option<Vec<Vec<String>>>)> {
+    let text = |names: &[&str]| names.iter().map(|name| (name.to_string(), ColumnType::String)).collect::<Columns>();
+    let collections = || storage::list_collections().map_err(|err| pg_error(err.into()));
+    Ok(match table {
+        Table::Collection(identifier) => (db::columns(&collection(identifier)?, Some(principal)).map_err(pg_error)?, None),
+        Table::Tables => {
+            let rows = collections()?.into_iter()
+                .map(|collection| vec!["zenithds".to_string(), SCHEMA.to_string(), collection, "BASE TABLE".to_string()])
+                .collect();
+            (text(&["table_catalog", "table_schema", "table_name", "table_type"]), Some(rows))
+        },
+        Table::Columns => {
+            let mut rows = Vec::new();
+            for collection in collections()? {
+                for (i, (column, column_type)) in db::columns(&collection, Some(principal)).map_err(pg_error)?.into_iter().enumerate() {
+                    let data_type = match column_type {
+                        ColumnType::Int => "bigint",
+                        ColumnType::Float => "double precision",
+                        ColumnType::Bool => "boolean",
+                        ColumnType::String | ColumnType::Date => "text",
+                    };
+                    rows.push(vec![
+                        "zenithds".to_string(), SCHEMA.to_string(), collection.clone(), column,
+                        (i + 1).to_string(), data_type.to_string(), "YES".to_string(),
+                    ]);
+                }
+            }
+            let mut columns = text(&["table_catalog", "table_schema", "table_name", "column_name"]);
+            columns.push(("ordinal_position".to_string(), ColumnType::Int));
+            columns.extend(text(&["data_type", "is_nullable"]));
+            (columns, Some(rows))
+        },
+    })
+}
+
+
+/// Returns the columns of `table` that `select` returns, and its conditions as predicates,
+/// raising an `undefined_column` error if any of them refer to a column that is not in the table.
+fn resolve(select: &Select, table: &Columns) -> PgWireResult<(Columns, Vec<String>)> {
+    let names = table.iter().map(|(name, _)| name.as_str());
+    let find = |identifier: &Identifier| {
+        identifier.resolve(names.clone())
+            .and_then(|name| table.iter().find(|(n, _)| n == name))
+            .cloned()
+            .ok_or_else(|| user_error("42703", format!("Column '{}' does not exist", identifier.name)))
+    };
+    let columns = match select.columns.is_empty() {
+        true => table.clone(),
+        false => select.columns.iter().map(find).collect::<PgWireResult<Columns>>()?,
+    };
+    let predicates = select.conditions.iter()
+        .map(|(column, op, value)| Ok(format!("{} {} {}", find(column)?.0, op, value)))
+        .collect::<PgWireResult<Vec<String>>>()?;
+    Ok((columns, predicates))
+}
+
+
+/// Describes `columns` as they are returned in `format`.
+fn fields(columns: &Columns, format: &Format) -> Vec<FieldInfo> {
+    columns.iter().enumerate()
+        .map(|(i, (name, column_type))| FieldInfo::new(name.clone(), None, None, pg_type(*column_type), format.format_for(i)))
+        .collect()
+}
+
+
+/// Returns the columns that `statement` returns.
+fn describe(statement: &Statement, principal: &Principal) -> PgWireResult<Columns> {
+    Ok(match statement {
+        Statement::Select(select) => resolve(select, &table(&select.table, principal)?.0)?.0,
+        Statement::Values(values) => values.iter().map(|(name, _)| (name.clone(), ColumnType::String)).collect(),
+        Statement::Ignored(_) => Vec::new(),
+    })
+}
+
+
+/// Encodes `rows` with the values of `columns`, where the rows have the values of `header`.
+/// Empty values in columns that are not strings are `NULL`.
+fn query_response(
+    fields: Vec<FieldInfo>,
+    columns: &[(String, ColumnType)],
+    header: &[String],
+    rows: &[Vec<String>],
+) -> PgWireResult<Response> {
+
+    let positions: Vec<Option<usize>> = columns.iter().map(|(name, _)| header.iter().position(|h| h == name)).collect();
+    let fields = Arc::new(fields);
+    let mut encoder = DataRowEncoder::new(Arc::clone(&fields));
+    let mut data_rows = Vec::with_capacity(rows.len());
+    for row in rows {
+        for ((_, column_type), position) in columns.iter().zip(&positions) {
+            let value = position.and_then(|i| row.get(i)).map(String::as_str).unwrap_or("");
+            match column_type {
+                ColumnType::Int => encoder.encode_field(&value.parse::<i64>().ok())?,
+                ColumnType::Float => encoder.encode_field(&value.parse::<f64>().ok())?,
+                ColumnType::Bool => encoder.encode_field(&schema::parse_bool(value))?,
+                ColumnType::String | ColumnType::Date => encoder.encode_field(&value)?,
+            }
+        }
+        data_rows.push(Ok(encoder.take_row()));
+    }
+    Ok(Response::Query(QueryResponse::new(fields, futures_util::stream::iter(data_rows))))
+}
+
+
+/// Runs `statement` for `principal`, returning the rows it selects as `format`. Queries on
+/// a collection are run as with `query`, and count towards `ZENITHDS_MAX_QUERIES`.
+async fn run(statement: Statement, principal: Principal, format: Format) -> PgWireResult<Response> {
+    let select = match &statement {
+        Statement::Select(select) => select,
+        Statement::Values(values) => {
+            let columns = describe(&statement, &principal)?;
+            let (header, row): (Vec<String>, Vec<String>) = values.iter().cloned().unzip();
+            return query_response(fields(&columns, &format), &columns, &header, &[row]);
+        },
+        Statement::Ignored(tag) => return Ok(Response::Execution(Tag::new(tag))),
+    };
+
+    let (table_columns, table_rows) = table(&select.table, &principal)?;
+    let (columns, predicates) = resolve(select, &table_columns)?;
+    let (header, rows) = match (&select.table, table_rows) {
+        (Table::Collection(identifier), _) => {
+            let collection = collection(identifier)?;
+            info!("Received a PostgreSQL query on collection '{}'", collection);
+            let _slot = limit::SlotGuard::start(limit::priority(None, &principal)).await.map_err(pg_error)?;
+            let running = queries::Running::start(&request_id::current().unwrap_or_default(), &principal);
+            let names = columns.iter().map(|(name, _)| name.clone()).collect();
+            crate::run_query(&collection, QueryPredicates { fields: names, predicates }, principal, running.cancelled(), false)
+                .await
+                .map_err(pg_error)?
+                .0
+        },
+        // The rows of other tables are filtered here.
+        (_, table_rows) => {
+            let header: Vec<String> = table_columns.iter().map(|(name, _)| name.clone()).collect();
+            let query = DataQuery::new(Vec::new(), predicates).map_err(pg_error)?;
+            let rows = table_rows.unwrap_or_default().into_iter()
+                .filter(|row| query.predicates.iter().all(|predicate| {
+                    header.iter().position(|h| *h == predicate.field).is_none_or(|i| predicate.satisfied_by(&row[i]))
+                }))
+                .collect();
+            (header, rows)
+        },
+    };
+    let end = select.limit.map_or(rows.len(), |limit| select.offset.saturating_add(limit).min(rows.len()));
+    let page = rows.get(select.offset..end).unwrap_or_default();
+    info!("Returned {} fields and {}/{} rows", columns.len(), page.len(), rows.len());
+    query_response(fields(&columns, &format), &columns, &header, page)
+}
+
+
+/// Returns the principal that a client connected as. See `Backend::on_startup`.
+fn principal<C: ClientInfo>(client: &C) -> PgWireResult<Principal> {
+    match client.session_extensions().get::<Principal>() {
+        Some(principal) => Ok(principal.as_ref().clone()),
+        None => Err(pg_error(ZenithError::Unauthorized("The connection is not authenticated".to_string()))),
+    }
+}
+
+
+/// Answers clients of the PostgreSQL wire protocol.
+struct Backend {
+    parameters: DefaultServerParameterProvider,
+    pid_generator: RandomPidSecretKeyGenerator,
+    query_parser: Arc<NoopQueryParser>,
+}
+
+
+#[async_trait]
+impl StartupHandler for Backend {
+    /// Asks for a password, which is an API key or token, as with the `authorization` header.
+    /// Clients are allowed or denied by their address as with the HTTP API.
+    async fn on_startup<C>(&self, client: &mut C, message: PgWireFrontendMessage) -> PgWireResult<()>
+    where
+        C: ClientInfo + Sink<PgWireBackendMessage> + Unpin + Send,
+        C::Error: Debug,
+        PgWireError: From<<C as Sink<PgWireBackendMessage>>::Error>,
+    {
+        match message {
+            PgWireFrontendMessage::Startup(ref startup) => {
+                auth::protocol_negotiation(client, startup).await?;
+                auth::save_startup_parameters_to_metadata(client, startup);
+                let address = client.socket_addr().ip();
+                if acl::enabled().unwrap_or(true) && !acl::allowed(address) {
+                    warn!("Denied a PostgreSQL connection from {}", address);
+                    return Err(pg_error(ZenithError::Forbidden("Connections are not allowed from this address".to_string())));
+                }
+                let (pid, secret_key) = self.pid_generator.generate(client);
+                client.set_pid_and_secret_key(pid, secret_key);
+                if !crate::auth::enabled() {
+                    client.session_extensions().insert(Principal::default());
+                    return auth::finish_authentication(client, &self.parameters).await;
+                }
+                client.set_state(PgWireConnectionState::AuthenticationInProgress);
+                client.send(PgWireBackendMessage::Authentication(Authentication::CleartextPassword)).await?;
+            },
+            PgWireFrontendMessage::PasswordMessageFamily(password) => {
+                let password = password.into_password()?.password;
+                let mut headers = HeaderMap::new();
+                if let Ok(value) = HeaderValue::from_str(&format!("Bearer {}", password)) {
+                    headers.insert(AUTHORIZATION, value);
+                }
+                let principal = crate::auth::principal(&headers, None).await.map_err(pg_error)?;
+                if principal.scope < Scope::Read {
+                    return Err(pg_error(ZenithError::Forbidden("The credentials of the connection do not have the scope it needs".to_string())));
+                }
+                client.session_extensions().insert(principal);
+                auth::finish_authentication(client, &self.parameters).await?;
+            },
+            _ => (),
+        }
+        Ok(())
+    }
+}
+
+
+#[async_trait]
+impl SimpleQueryHandler for Backend {
+    async fn do_query<C>(&self, client: &mut C, query: &str) -> PgWireResult<Vec<Response>>
+    where
+        C: ClientInfo + Unpin + Send + Sync,
+    {
+        let principal = principal(client)?;
+        let mut responses = Vec::new();
+        // A simple query can have several statements, each answered in turn.
+        let statements = match parse(query) {
+            Ok(statements) => statements,
+            Err(PgWireError::UserError(error)) => return Ok(vec![Response::Error(error)]),
+            Err(err) => return Err(err),
+        };
+        for statement in statements {
+            let response = request_id::scope(request_id::generate(), run(statement, principal.clone(), Format::UnifiedText)).await;
+            match response {
+                Ok(response) => responses.push(response),
+                Err(PgWireError::UserError(error)) => {
+                    responses.push(Response::Error(error));
+                    break;
+                },
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(responses)
+    }
+}
+
+
+#[async_trait]
+impl ExtendedQueryHandler for Backend {
+    type Statement = String;
+    type QueryParser = NoopQueryParser;
+
+    fn query_parser(&self) -> Arc<Self::QueryParser> {
+        Arc::clone(&self.query_parser)
+    }
+
+    async fn do_query<C>(&self, client: &mut C, portal: &Portal<Self::Statement>, _max_rows: usize) -> PgWireResult<Response>
+    where
+        C: ClientInfo + Unpin + Send + Sync,
+    {
+        if !portal.parameters.is_empty() {
+            return Err(unsupported("A statement with parameters"));
+        }
+        let principal = principal(client)?;
+        let statement = parse_one(&portal.statement.statement)?;
+        request_id::scope(request_id::generate(), run(statement, principal, portal.result_column_format.clone())).await
+    }
+
+    async fn do_describe_statement<C>(&self, client: &mut C, statement: &StoredStatement<Self::Statement>) -> PgWireResult<DescribeStatementResponse>
+    where
+        C: ClientInfo + Unpin + Send + Sync,
+    {
+        let columns = describe(&parse_one(&statement.statement)?, &principal(client)?)?;
+        Ok(DescribeStatementResponse::new(Vec::new(), fields(&columns, &Format::UnifiedText)))
+    }
+
+    async fn do_describe_portal<C>(&self, client: &mut C, portal: &Portal<Self::Statement>) -> PgWireResult<DescribePortalResponse>
+    where
+        C: ClientInfo + Unpin + Send + Sync,
+    {
+        let columns = describe(&parse_one(&portal.statement.statement)?, &principal(client)?)?;
+        Ok(DescribePortalResponse::new(fields(&columns, &portal.result_column_format)))
+    }
+}
+
+
+/// The handlers of a connection, which are all the same `Backend`.
+struct Handlers(Arc<Backend>);
+
+impl PgWireServerHandlers for Handlers {
+    fn simple_query_handler(&self) -> Arc<impl SimpleQueryHandler> {
+        Arc::clone(&self.0)
+    }
+
+    fn extended_query_handler(&self) -> Arc<impl ExtendedQueryHandler> {
+        Arc::clone(&self.0)
+    }
+
+    fn startup_handler(&self) -> Arc<impl StartupHandler> {
+        Arc::clone(&self.0)
+    }
+}
+
+
+/// Serves collections as tables over the PostgreSQL wire protocol at `config::pg_address`,
+/// until the data service is asked to stop. Nothing is served if `ZENITHDS_PG_PORT` is not set.
+pub async fn serve() {
+    if config::envar_usize("ZENITHDS_PG_PORT") == 0 {
+        return;
+    }
+    let address = config::pg_address();
+    let listener = match address.parse::<SocketAddr>() {
+        Ok(socket) => TcpListener::bind(socket).await.map_err(|err| err.to_string()),
+        Err(err) => Err(err.to_string()),
+    };
+    let listener = match listener {
+        Ok(listener) => listener,
+        Err(err) => {
+            error!("Could not serve PostgreSQL on {}: {}", address, err);
+            return;
+        }
+    };
+    info!("Serving PostgreSQL on {}", address);
+    let backend = Arc::new(Backend {
+        parameters: DefaultServerParameterProvider::default(),
+        pid_generator: RandomPidSecretKeyGenerator::default(),
+        query_parser: Arc::new(NoopQueryParser),
+    });
+    let handlers = Arc::new(Handlers(backend));
+    let stopped = shutdown::requested();
+    tokio::pin!(stopped);
+    loop {
+        tokio::select! {
+            accepted = listener.accept() => match accepted {
+                Ok((socket, _)) => {
+                    let handlers = Arc::clone(&handlers);
+                    tokio::spawn(async move {
+                        if let Err(err) = pgwire::tokio::process_socket(socket, None, handlers).await {
+                            debug!("PostgreSQL connection ended with an error: {}", err);
+                        }
+                    });
+                },
+                Err(err) => warn!("Could not accept a PostgreSQL connection: {}", err),
+            },
+            _ = &mut stopped => break,
+        }
+    }
+}
@@ -0,0 +1,92 @@
+use std::{
+    collections::HashMap,
+    sync::{atomic::{AtomicBool, Ordering}, Arc, LazyLock, Mutex},
+};
+
+use crate::auth::{Principal, Scope};
+use crate::types::error::{Missing, ZenithError};
+
+
+/// Set when a query is cancelled, so that the workers scanning its files stop.
+#[derive(Clone, Default)]
+pub struct Cancelled(Arc<AtomicBool>);
+
+impl Cancelled {
+    pub fn is_set(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    pub fn set(&self) {
+        self.0.store(true, Ordering::Relaxed)
+    }
+}
+
+
+/// A query that is running, and the principal that made it.
+struct Query {
+    principal: String,
+    cancelled: Cancelled,
+}
+
+/// The queries running, by their id.
+/// Clients can give the same id to more than one query.
+static RUNNING: LazyLock<Mutex<HashMap<String, Vec<Query>>>> = LazyLock::new(|| Mutex::new(HashMap::new()));
+
+
+/// Counts a query as running until it is dropped. The query is cancelled when it is
+/// dropped, so that a query whose client has gone away does not keep scanning.
+pub struct Running {
+    id: String,
+    cancelled: Cancelled,
+}
+
+impl Running {
+    /// Starts a query with `id` made by `principal`, which can be cancelled until it is dropped.
+    pub fn start(id: &str, principal: &Principal) -> Running {
+        let cancelled = Cancelled::default();
+        RUNNING.lock().unwrap_or_else(|e| e.into_inner())
+            .entry(id.to_string())
+            .or_default()
+            .push(Query { principal: principal.name.clone(), cancelled: cancelled.clone() });
+        Running { id: id.to_string(), cancelled }
+    }
+
+    /// Whether the query has been cancelled, to give to the workers scanning its files.
+    pub fn cancelled(&self) -> &Cancelled {
+        &self.cancelled
+    }
+}
+
+impl Drop for Running {
+    fn drop(&mut self) {
+        self.cancelled.set();
+        let mut running = RUNNING.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(queries) = running.get_mut(&self.id) {
+            queries.retain(|q| !Arc::ptr_eq(&q.cancelled.0, &self.cancelled.0));
+            if queries.is_empty() {
+                running.remove(&self.id);
+            }
+        }
+    }
+}
+
+
+/// Cancels the queries running with `id`, returning how many were cancelled. A principal can
+/// only cancel its own queries, unless it can write. Raises a `NotFound` error if no queries are
+/// running with `id`, and `Forbidden` if they were all made by other principals.
+pub fn cancel(id: &str, principal: &Principal) -> Result<usize, ZenithError> {
+    let running = RUNNING.lock().unwrap_or_else(|e| e.into_inner());
+    let Some(queries) = running.get(id) else {
+        return Err(ZenithError::NotFound(Missing::Other(format!("No query '{}' is running", id))));
+    };
+    let cancellable: Vec<&Query> = queries.iter()
+        .filter(|q| principal.scope == Scope::Write || q.principal == principal.name)
+        .collect();
+    if cancellable.is_empty() {
+        return Err(ZenithError::Forbidden(format!("Query '{}' was made by another principal", id)));
+    }
+    for query in &cancellable {
+        query.cancelled.set();
+    }
+    Ok(cancellable.len())
+}
@@ -0,0 +1,64 @@
+use std::{sync::Mutex, time::Duration};
+use tracing::{info, warn};
+
+use crate::types::{error::ZenithError, api::ReloadResponse};
+use crate::maintenance::{self, Timing};
+use crate::{acl, config, cors, logging};
+
+
+/// Reads the config file again and applies the settings that changed. Most settings are
+/// read whenever they are used, and the IP addresses allowed, the origins allowed, and the
+/// log levels are parsed again. Settings that can only be used when the data service
+/// starts keep their old values, and are returned as `restart_required`.
+///
+/// Returns a `QueryError` if the config file cannot be read or parsed, in which case
+/// nothing is changed. Settings that changed but are not valid are returned as `errors`,
+/// and the values that were in use are kept.
+pub fn reload() -> Result<ReloadResponse, ZenithError> {
+    let reloaded = config::reload_file().map_err(ZenithError::QueryError)?;
+    let errors: Vec<String> = [acl::reload(), cors::reload(), logging::reload()]
+        .into_iter()
+        .filter_map(|result| result.err())
+        .collect();
+
+    info!("Reloaded the config file, changing {:?}", reloaded.changed);
+    if !reloaded.restart_required.is_empty() {
+        warn!("Restart the data service to change {:?}", reloaded.restart_required);
+    }
+    for err in &errors {
+        warn!("{}. Keeping the previous value", err);
+    }
+    Ok(ReloadResponse {
+        changed: reloaded.changed,
+        restart_required: reloaded.restart_required,
+        errors,
+    })
+}
+
+
+/// Reloads the config file whenever it is modified, checking every
+/// `ZENITHDS_CONFIG_WATCH_INTERVAL` seconds, unless that is `0`, as the maintenance task `reload_config`.
+pub fn watch() {
+    let period = config::envar_usize("ZENITHDS_CONFIG_WATCH_INTERVAL");
+    let Some(path) = config::file_path() else {
+        return;
+    };
+    if period == 0 {
+        return;
+    }
+    info!("Watching the config file '{}' for changes", path.display());
+
+    let modified = Mutex::new(config::file_modified());
+    maintenance::register("reload_config", Timing::Interval(Duration::from_secs(period as u64)), move |_| {
+        let now = config::file_modified();
+        let mut modified = modified.lock().unwrap_or_else(|e| e.into_inner());
+        let changed = now.is_some() && now != *modified;
+        *modified = now;
+        async move {
+            match changed {
+                true => reload().map(|_| ()),
+                false => Ok(()),
+            }
+        }
+    });
+}
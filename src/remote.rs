@@ -0,0 +1,332 @@
+use std::{
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+    time::Duration,
+};
+use tracing::warn;
+
+use crate::types::{
+    error::ZenithError,
+    api::{CreatePayload, FileSummary, FilesResponse, QueryPredicates, QueryResponse},
+};
+use crate::{auth, config, request_id};
+
+
+/// The most redirects followed by a request to a URL given by a client.
+const MAX_REDIRECTS: usize = 10;
+
+
+/// Whether `ip` is an address on the internet, rather than a loopback, private, link-local,
+/// shared, or unspecified address, which a URL given by a client should not reach.
+fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, ..] = ip.octets();
+            !(ip.is_loopback() || ip.is_private() || ip.is_link_local() || ip.is_unspecified()
+                || ip.is_broadcast() || ip.is_documentation() || a == 0 || (a == 100 && (64..128).contains(&b)))
+        },
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => is_public(IpAddr::V4(ip)),
+            None => {
+                let first = ip.segments()[0];
+                !(ip.is_loopback() || ip.is_unspecified() || first & 0xfe00 == 0xfc00 || first & 0xffc0 == 0xfe80)
+            },
+        },
+    }
+}
+
+
+/// Whether a URL given by a client can reach `host` at any address, as it is in `ZENITHDS_IMPORT_ALLOWED_HOSTS`.
+fn is_allowed_host(host: &str) -> bool {
+    config::envar_str("ZENITHDS_IMPORT_ALLOWED_HOSTS")
+        .split(',')
+        .any(|allowed| allowed.trim().eq_ignore_ascii_case(host.trim_start_matches('[').trim_end_matches(']')))
+}
+
+
+/// Raised when a URL given by a client would reach an address that is not public.
+#[derive(Debug)]
+struct NotAllowed(String);
+
+impl std::fmt::Display for NotAllowed {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "'{}' is not a public address, and is not in ZENITHDS_IMPORT_ALLOWED_HOSTS", self.0)
+    }
+}
+
+impl std::error::Error for NotAllowed {}
+
+
+/// Checks that `url` is `http` or `https`, and that its host, if it is an address, is public
+/// or allowed. Hosts given by name are checked by `PublicResolver` as they are resolved.
+fn check_url(url: &reqwest::Url) -> Result<(), ZenithError> {
+    if url.scheme() != "http" && url.scheme() != "https" {
+        return Err(ZenithError::QueryError(format!("Unsupported URL scheme '{}'", url.scheme())));
+    }
+    let host = url.host_str()
+        .ok_or_else(|| ZenithError::QueryError(format!("The URL '{}' has no host", url)))?;
+    let Ok(address) = host.trim_start_matches('[').trim_end_matches(']').parse::<IpAddr>() else {
+        return Ok(());
+    };
+    match is_public(address) || is_allowed_host(&address.to_string()) {
+        true => Ok(()),
+        false => Err(ZenithError::QueryError(NotAllowed(address.to_string()).to_string())),
+    }
+}
+
+
+/// Resolves host names like the system does, but fails if any of the addresses of a host are not
+/// public, unless the host is allowed, so that a URL given by a client cannot reach the data
+/// service itself or the network it is on. Checking as the connection is made, rather than
+/// beforehand, means a host cannot resolve to another address by the time it is connected to.
+struct PublicResolver;
+
+impl reqwest::dns::Resolve for PublicResolver {
+    fn resolve(&self, name: reqwest::dns::Name) -> reqwest::dns::Resolving {
+        let host = name.as_str().to_string();
+        Box::pin(async move {
+            let addresses: Vec<SocketAddr> = tokio::net::lookup_host((host.as_str(), 0)).await?.collect();
+            if !is_allowed_host(&host) {
+                if let Some(address) = addresses.iter().find(|address| !is_public(address.ip())) {
+                    return Err(Box::new(NotAllowed(format!("{} ({})", host, address.ip()))) as _);
+                }
+            }
+            Ok(Box::new(addresses.into_iter()) as reqwest::dns::Addrs)
+        })
+    }
+}
+
+
+/// Builds a client for requests to URLs given by clients, which times out after `timeout` seconds,
+/// and only reaches public addresses, or allowed hosts, including after each redirect. Proxies
+/// are not used, as they would reach the addresses themselves.
+fn client(timeout: usize) -> Result<reqwest::Client, ZenithError> {
+    let redirects = reqwest::redirect::Policy::custom(|attempt| {
+        if attempt.previous().len() >= MAX_REDIRECTS {
+            return attempt.error(format!("More than {} redirects", MAX_REDIRECTS));
+        }
+        match check_url(attempt.url()) {
+            Ok(()) => attempt.follow(),
+            Err(err) => attempt.error(err.to_string()),
+        }
+    });
+    reqwest::Client::builder()
+        .timeout(Duration::from_secs(timeout as u64))
+        .redirect(redirects)
+        .dns_resolver(Arc::new(PublicResolver))
+        .no_proxy()
+        .build()
+        .map_err(|err| ZenithError::RemoteError(err.to_string()))
+}
+
+
+/// Returns the error for a request to `url` that failed with `err`, which only says why it failed
+/// in general terms, so that what the remote server or network said is not passed on to clients.
+/// The full error is logged.
+fn failed(url: &reqwest::Url, err: reqwest::Error) -> ZenithError {
+    warn!("The request to '{}' failed: {}", url, err);
+    let mut source = std::error::Error::source(&err);
+    while let Some(cause) = source {
+        if let Some(not_allowed) = cause.downcast_ref::<NotAllowed>() {
+            return ZenithError::QueryError(not_allowed.to_string());
+        }
+        source = cause.source();
+    }
+    ZenithError::RemoteError(match err.status() {
+        Some(status) => format!("'{}' responded with {}", url, status),
+        None if err.is_timeout() => format!("The request to '{}' timed out", url),
+        None if err.is_redirect() => format!("The request to '{}' was redirected to a URL that is not allowed", url),
+        None if err.is_connect() => format!("Could not connect to '{}'", url),
+        None => format!("The request to '{}' failed", url),
+    })
+}
+
+
+/// Parses `url`, which was given by a client, raising a `QueryError` if it is not valid, or not
+/// `http` or `https`, or it is an address that is not public or allowed (see `client`).
+pub fn parse_url(url: &str) -> Result<reqwest::Url, ZenithError> {
+    let parsed = reqwest::Url::parse(url)
+        .map_err(|err| ZenithError::QueryError(format!("Invalid URL '{}': {}", url, err)))?;
+    check_url(&parsed)?;
+    Ok(parsed)
+}
+
+
+/// Downloads the resource at `url`, returning its body as bytes.
+/// 
+/// Only `http` and `https` URLs are allowed, and only public addresses, or hosts in
+/// `ZENITHDS_IMPORT_ALLOWED_HOSTS`, can be reached, including after redirects. The download is
+/// abandoned if it takes longer than `ZENITHDS_IMPORT_TIMEOUT` seconds, or the body is larger
+/// than `ZENITHDS_IMPORT_MAX_BYTES`.
+pub async fn download(
+    url: &str,
+) -> Result<Vec<u8>, ZenithError> {
+
+    let parsed = parse_url(url)?;
+    let max_bytes = config::envar_usize("ZENITHDS_IMPORT_MAX_BYTES");
+    let client = client(config::envar_usize("ZENITHDS_IMPORT_TIMEOUT"))?;
+
+    let mut response = client.get(parsed.clone()).send().await
+        .and_then(|r| r.error_for_status())
+        .map_err(|err| failed(&parsed, err))?;
+
+    // Check the advertised length first, but also count what
+    // actually arrives, in case the length was not given.
+    if response.content_length().is_some_and(|n| n > max_bytes as u64) {
+        return Err(ZenithError::PayloadTooLarge(format!("Body is larger than {} bytes", max_bytes)));
+    }
+    let mut bytes = Vec::new();
+    while let Some(chunk) = response.chunk().await.map_err(|err| failed(&parsed, err))? {
+        if bytes.len() + chunk.len() > max_bytes {
+            return Err(ZenithError::PayloadTooLarge(format!("Body is larger than {} bytes", max_bytes)));
+        }
+        bytes.extend_from_slice(&chunk);
+    }
+
+    Ok(bytes)
+}
+
+
+/// Sends `body` as JSON in a `POST` to `url`, such as the rows found by a scheduled query or trigger,
+/// raising a `RemoteError` if it does not get a `2xx` response within `ZENITHDS_WEBHOOK_TIMEOUT` seconds.
+/// The URL can only reach the addresses `download` can.
+pub async fn post_json(
+    url: &str,
+    body: &serde_json::Value,
+) -> Result<(), ZenithError> {
+
+    let parsed = parse_url(url)?;
+    client(config::envar_usize("ZENITHDS_WEBHOOK_TIMEOUT"))?
+        .post(parsed.clone()).json(body).send().await
+        .and_then(|r| r.error_for_status())
+        .map_err(|err| failed(&parsed, err))?;
+    Ok(())
+}
+
+
+/// Sends `body` in a `POST` to `url` with `headers`, such as a delivery to a webhook, returning the
+/// status of the response, whatever it is. It is abandoned if it takes longer than `timeout`
+/// seconds. The URL can only reach the addresses `download` can, and errors only say why the
+/// request failed in general terms.
+pub async fn post(
+    url: &str,
+    headers: reqwest::header::HeaderMap,
+    body: Vec<u8>,
+    timeout: usize,
+) -> Result<reqwest::StatusCode, ZenithError> {
+
+    let parsed = parse_url(url)?;
+    let response = client(timeout)?
+        .post(parsed.clone()).headers(headers).body(body).send().await
+        .map_err(|err| failed(&parsed, err))?;
+    Ok(response.status())
+}
+
+
+/// Marks requests that were replicated from another instance, so they are not replicated again.
+pub const REPLICATED_HEADER: &str = "x-zenithds-replicated";
+
+/// Builds a client for requests to peers and nodes, which gives
+/// `ZENITHDS_PEER_API_KEY` as the API key of each request, if it is set.
+fn replica_client() -> Result<reqwest::Client, ZenithError> {
+    let mut headers = reqwest::header::HeaderMap::new();
+    let key = config::envar_str("ZENITHDS_PEER_API_KEY");
+    if !key.is_empty() {
+        let value = reqwest::header::HeaderValue::from_str(&key)
+            .map_err(|err| ZenithError::RemoteError(format!("Invalid peer API key: {}", err)))?;
+        headers.insert(auth::API_KEY_HEADER, value);
+    }
+    // Requests to peers are made for the request being handled, so they share its id.
+    if let Some(value) = request_id::current().and_then(|id| reqwest::header::HeaderValue::from_str(&id).ok()) {
+        headers.insert(request_id::REQUEST_ID_HEADER, value);
+    }
+    reqwest::Client::builder()
+        .timeout(Duration::from_secs(config::envar_usize("ZENITHDS_REPLICA_TIMEOUT") as u64))
+        .default_headers(headers)
+        .build()
+        .map_err(|err| ZenithError::RemoteError(err.to_string()))
+}
+
+
+/// Sends a replicated request built by `request` to a peer, raising a `QueryError` if the peer
+/// finds it is not valid, such as a file whose header does not match its collection, and a
+/// `RemoteError` if it fails otherwise.
+async fn send_to_peer(
+    request: reqwest::RequestBuilder,
+) -> Result<reqwest::Response, ZenithError> {
+
+    request
+        .header(REPLICATED_HEADER, "1")
+        .send().await
+        .and_then(|r| r.error_for_status())
+        .map_err(|err| match err.status() {
+            Some(reqwest::StatusCode::UNPROCESSABLE_ENTITY) => ZenithError::QueryError(err.to_string()),
+            _ => ZenithError::RemoteError(err.to_string()),
+        })
+}
+
+
+/// Replicates the creation of a CSV from `payload` in `collection` to the `peer`.
+pub async fn replicate_create(
+    peer: &str,
+    collection: &str,
+    payload: &CreatePayload,
+) -> Result<(), ZenithError> {
+
+    let client = replica_client()?;
+    send_to_peer(client.post(format!("{}/create/{}", peer, collection)).json(payload)).await?;
+    Ok(())
+}
+
+
+/// Replicates the deletion of `filename` in `collection` to the `peer`.
+pub async fn replicate_delete(
+    peer: &str,
+    collection: &str,
+    filename: &str,
+) -> Result<(), ZenithError> {
+
+    let client = replica_client()?;
+    send_to_peer(client.delete(format!("{}/delete/{}/{}", peer, collection, filename))).await?;
+    Ok(())
+}
+
+
+/// Lists the files in `collection` on the `peer`.
+pub async fn peer_files(
+    peer: &str,
+    collection: &str,
+) -> Result<Vec<FileSummary>, ZenithError> {
+
+    let client = replica_client()?;
+    let response = send_to_peer(client.get(format!("{}/files/{}", peer, collection))).await?;
+    let files: FilesResponse = response.json().await
+        .map_err(|err| ZenithError::RemoteError(err.to_string()))?;
+    Ok(files.files)
+}
+
+
+/// Marks queries sent by a federation coordinator. The node answers them from
+/// its own files only, and returns every matching row rather than a page.
+pub const FEDERATED_HEADER: &str = "x-zenithds-federated";
+
+
+/// Queries `collection` on the federation `node` with `predicates`,
+/// returning the header and every matching row on the node.
+pub async fn federated_select(
+    node: &str,
+    collection: &str,
+    predicates: &QueryPredicates,
+) -> Result<(Vec<String>, Vec<Vec<String>>), ZenithError> {
+
+    let client = replica_client()?;
+    let response = client.post(format!("{}/query/{}", node, collection))
+        .header(FEDERATED_HEADER, "1")
+        .json(predicates)
+        .send().await
+        .and_then(|r| r.error_for_status())
+        .map_err(|err| ZenithError::RemoteError(format!("{}: {}", node, err)))?;
+    let result: QueryResponse = response.json().await
+        .map_err(|err| ZenithError::RemoteError(format!("{}: {}", node, err)))?;
+    Ok((result.header, result.rows))
+}
@@ -0,0 +1,161 @@
+use axum::{extract::Request, middleware::Next, response::Response};
+use tracing::{info, warn};
+
+use crate::changes::Change;
+use crate::types::{
+    api::{CreatePayload, OnConflict, PeerReplication},
+    error::{Missing, ZenithError},
+};
+use crate::{catalog, config, db, remote, request_id, shutdown};
+
+
+tokio::task_local! {
+    /// Set while handling a request replicated from a peer, whose changes are not replicated again.
+    static FROM_PEER: bool;
+}
+
+
+/// Marks requests with the `remote::REPLICATED_HEADER` as replicated from a peer while they are
+/// handled, so that the changes they make are not sent back out to the peers of this instance.
+pub async fn mark(
+    request: Request,
+    next: Next,
+) -> Response {
+
+    let from_peer = request.headers().contains_key(remote::REPLICATED_HEADER);
+    FROM_PEER.scope(from_peer, next.run(request)).await
+}
+
+
+/// Sends `change` to each replica peer in the background, unless it was replicated from a peer.
+/// A change to a file sends the file as it is when it is sent, or deletes it from the peer if it
+/// no longer exists, so that a peer ends up with the same file whatever the change was. A change
+/// to a whole collection, such as a restore, brings each peer up to date with the collection, as
+/// `collection` does. Failures are only logged, and can be caught up on with `collection`.
+pub fn trigger(change: &Change) {
+    if FROM_PEER.try_with(|from_peer| *from_peer).unwrap_or(false) {
+        return;
+    }
+    let peers = config::replica_peers();
+    if peers.is_empty() {
+        return;
+    }
+    let (collection, filename) = (change.collection.clone(), change.filename.clone());
+    shutdown::spawn(request_id::inherit(async move {
+        match filename {
+            Some(filename) => {
+                for peer in peers {
+                    if let Err(err) = file(&peer, &collection, &filename).await {
+                        warn!("Could not replicate '{}' in collection '{}' to '{}': {}", filename, collection, peer, err);
+                    }
+                }
+            },
+            None => {
+                collection_to(&collection, peers).await;
+            },
+        }
+    }));
+}
+
+
+/// Sends `filename` in `collection` to the `peer` as it is now, replacing the file the peer has,
+/// or deletes it from the peer if it does not exist.
+async fn file(
+    peer: &str,
+    collection: &str,
+    filename: &str,
+) -> Result<(), ZenithError> {
+
+    let read = {
+        let (collection, filename) = (collection.to_string(), filename.to_string());
+        request_id::spawn_blocking(move || payload(&collection, &filename)).await
+    };
+    match read {
+        Ok(payload) => remote::replicate_create(peer, collection, &payload).await,
+        Err(ZenithError::NotFound(Missing::File(..) | Missing::Collection(_))) => remote::replicate_delete(peer, collection, filename).await,
+        Err(err) => Err(err),
+    }
+}
+
+
+/// Reads `filename` in `collection` as the body of a `create` that replaces it on a peer.
+fn payload(
+    collection: &str,
+    filename: &str,
+) -> Result<CreatePayload, ZenithError> {
+
+    let (header, rows, _) = db::read(collection, filename)?;
+    let dialect = catalog::dialect(collection)?;
+    Ok(CreatePayload {
+        filename: filename.to_string(), header, rows, on_conflict: OnConflict::Upsert,
+        delimiter: Some(dialect.delimiter), quote: Some(dialect.quote), partial: false, keyed: false,
+    })
+}
+
+
+/// Brings each replica peer up to date with the `collection`, by sending every file in the
+/// collection and deleting any files the peer has that it does not. If a peer finds a file is not
+/// valid, such as after the header of the collection changed, every file it has is deleted and
+/// the files are sent again.
+pub async fn collection(
+    collection: &str,
+) -> Vec<PeerReplication> {
+
+    collection_to(collection, config::replica_peers()).await
+}
+
+
+async fn collection_to(
+    collection: &str,
+    peers: Vec<String>,
+) -> Vec<PeerReplication> {
+
+    let mut replications = Vec::new();
+    for peer in peers {
+        let mut replication = PeerReplication { peer: peer.clone(), created: 0, deleted: 0, error: None };
+        let result = match catch_up(&peer, collection, false, &mut replication).await {
+            // A peer whose files have another header, such as after a column was renamed, cannot
+            // take the files of the collection, so its files are removed and sent again.
+            Err(ZenithError::QueryError(_)) => catch_up(&peer, collection, true, &mut replication).await,
+            result => result,
+        };
+
+        match &result {
+            Ok(()) => info!("Replicated collection '{}' to '{}': {} files created, {} deleted", collection, peer, replication.created, replication.deleted),
+            Err(err) => {
+                warn!("Could not replicate collection '{}' to '{}': {}", collection, peer, err);
+                replication.error = Some(err.to_string());
+            },
+        }
+        replications.push(replication);
+    }
+    replications
+}
+
+
+/// Sends every file in `collection` to the `peer`, and deletes the files the peer has that it does
+/// not, or, if `reset`, every file the peer has first, counting them in `replication`.
+async fn catch_up(
+    peer: &str,
+    collection: &str,
+    reset: bool,
+    replication: &mut PeerReplication,
+) -> Result<(), ZenithError> {
+
+    let files = db::files(collection)?;
+    for peer_file in remote::peer_files(peer, collection).await? {
+        if reset || !files.iter().any(|(filename, _)| *filename == peer_file.filename) {
+            remote::replicate_delete(peer, collection, &peer_file.filename).await?;
+            replication.deleted += 1;
+        }
+    }
+    for (filename, _) in &files {
+        let payload = {
+            let (collection, filename) = (collection.to_string(), filename.clone());
+            request_id::spawn_blocking(move || payload(&collection, &filename)).await?
+        };
+        remote::replicate_create(peer, collection, &payload).await?;
+        replication.created += 1;
+    }
+    Ok(())
+}
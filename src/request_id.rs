@@ -0,0 +1,91 @@
+use std::future::Future;
+use aes_gcm::aead::{rand_core::RngCore, OsRng};
+use axum::{
+    extract::Request,
+    http::HeaderValue,
+    middleware::Next,
+    response::Response,
+};
+use tracing::{info_span, Instrument, Span};
+
+/// The header a request id is given in, by the client or in the response.
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+/// The longest request id taken from a client.
+const MAX_REQUEST_ID_LENGTH: usize = 128;
+
+
+tokio::task_local! {
+    /// The id of the request being handled by the current task.
+    static REQUEST_ID: Option<String>;
+}
+
+
+/// Returns the id of the request being handled, if there is one.
+pub fn current() -> Option<String> {
+    REQUEST_ID.try_with(|id| id.clone()).ok().flatten()
+}
+
+
+/// Runs `future` as part of the request being handled, so that it logs and forwards
+/// the same request id. Use this for tasks spawned while handling a request.
+pub fn inherit<F: Future>(future: F) -> impl Future<Output = F::Output> {
+    REQUEST_ID.scope(current(), future.in_current_span())
+}
+
+
+/// Runs `f` on a thread where blocking is allowed, as part of the request being handled, so
+/// that it logs with the same request id. Use this for work, such as scanning the files of a
+/// collection, that would otherwise hold up other requests. A panic in `f` is resumed here.
+pub async fn spawn_blocking<F, T>(f: F) -> T
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    let (id, span) = (current(), Span::current());
+    match tokio::task::spawn_blocking(move || span.in_scope(|| REQUEST_ID.sync_scope(id, f))).await {
+        Ok(result) => result,
+        Err(err) => std::panic::resume_unwind(err.into_panic()),
+    }
+}
+
+
+/// Returns a new random id, for a request or anything else that needs one that cannot be guessed.
+pub fn generate() -> String {
+    let mut bytes = [0u8; 16];
+    OsRng.fill_bytes(&mut bytes);
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+
+/// Returns the id a client `given` for its request, if it is short and printable, or otherwise a new one.
+pub fn accept(given: Option<&str>) -> String {
+    given
+        .filter(|id| !id.is_empty() && id.len() <= MAX_REQUEST_ID_LENGTH && id.chars().all(|c| c.is_ascii_graphic()))
+        .map(|id| id.to_string())
+        .unwrap_or_else(generate)
+}
+
+
+/// Runs `future` as the request with `id`, which is added to every log line for it.
+pub fn scope<F: Future>(id: String, future: F) -> impl Future<Output = F::Output> {
+    let span = info_span!("request", id = %id);
+    REQUEST_ID.scope(Some(id), future).instrument(span)
+}
+
+
+/// Gives each request an id, which is the `X-Request-Id` it was sent with, if it is
+/// short and printable, or otherwise a new one. The id is added to every log line for
+/// the request, to error responses, and to the `X-Request-Id` header of the response.
+pub async fn propagate(
+    request: Request,
+    next: Next,
+) -> Response {
+
+    let id = accept(request.headers().get(REQUEST_ID_HEADER).and_then(|v| v.to_str().ok()));
+    let mut response = scope(id.clone(), next.run(request)).await;
+    if let Ok(value) = HeaderValue::from_str(&id) {
+        response.headers_mut().insert(REQUEST_ID_HEADER, value);
+    }
+    response
+}
@@ -0,0 +1,87 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, LazyLock, Mutex},
+    time::{Duration, Instant},
+};
+
+use crate::auth::Principal;
+use crate::db::Selection;
+use crate::types::error::{Missing, ZenithError};
+use crate::{config, request_id};
+
+
+/// The result of a query, kept so that it can be paged through without running the query again.
+struct ResultSet {
+    principal: String,
+    collection: String,
+    selection: Arc<Selection>,
+    stored: Instant,
+    expires: Instant,
+}
+
+/// The result sets stored, by their id.
+static RESULT_SETS: LazyLock<Mutex<HashMap<String, ResultSet>>> = LazyLock::new(|| Mutex::new(HashMap::new()));
+
+
+/// Forgets the result sets that have expired.
+fn expire(result_sets: &mut HashMap<String, ResultSet>) {
+    let now = Instant::now();
+    result_sets.retain(|_, result_set| result_set.expires > now);
+}
+
+
+/// Forgets the result sets that have expired, so that they are not kept until another is stored or fetched.
+pub fn forget_expired() {
+    expire(&mut RESULT_SETS.lock().unwrap_or_else(|e| e.into_inner()));
+}
+
+
+/// Stores the `selection` made on `collection` for `principal` for `ZENITHDS_RESULT_TTL` seconds,
+/// returning its id and the selection. If `ZENITHDS_MAX_RESULT_SETS` are already stored, the
+/// oldest is forgotten first.
+pub fn store(collection: &str, principal: &Principal, selection: Selection) -> (String, Arc<Selection>) {
+    let id = request_id::generate();
+    let selection = Arc::new(selection);
+    let now = Instant::now();
+    let mut result_sets = RESULT_SETS.lock().unwrap_or_else(|e| e.into_inner());
+    expire(&mut result_sets);
+    let max = config::envar_usize("ZENITHDS_MAX_RESULT_SETS");
+    while max > 0 && result_sets.len() >= max {
+        let Some(oldest) = result_sets.iter().min_by_key(|(_, r)| r.stored).map(|(id, _)| id.clone()) else {
+            break;
+        };
+        result_sets.remove(&oldest);
+    }
+    result_sets.insert(id.clone(), ResultSet {
+        principal: principal.name.clone(),
+        collection: collection.to_string(),
+        selection: Arc::clone(&selection),
+        stored: now,
+        expires: now + Duration::from_secs(config::envar_usize("ZENITHDS_RESULT_TTL") as u64),
+    });
+    (id, selection)
+}
+
+
+/// Returns the collection and selection of the result set with `id`. Raises a `NotFound` error if
+/// there is none, or it has expired, and `Forbidden` if it was stored for another principal.
+/// Only the principal that made the query can see its result, as it is masked by its roles.
+pub fn get(id: &str, principal: &Principal) -> Result<(String, Arc<Selection>), ZenithError> {
+    let mut result_sets = RESULT_SETS.lock().unwrap_or_else(|e| e.into_inner());
+    expire(&mut result_sets);
+    match result_sets.get(id) {
+        Some(result_set) if result_set.principal == principal.name => {
+            Ok((result_set.collection.clone(), Arc::clone(&result_set.selection)))
+        },
+        Some(_) => Err(ZenithError::Forbidden(format!("Result set '{}' belongs to another principal", id))),
+        None => Err(ZenithError::NotFound(Missing::Other(format!("Result set '{}' does not exist or has expired", id)))),
+    }
+}
+
+
+/// Forgets the result set with `id` before it expires.
+pub fn remove(id: &str, principal: &Principal) -> Result<(), ZenithError> {
+    get(id, principal)?;
+    RESULT_SETS.lock().unwrap_or_else(|e| e.into_inner()).remove(id);
+    Ok(())
+}
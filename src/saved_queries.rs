@@ -0,0 +1,169 @@
+use std::{
+    collections::HashMap,
+    path::PathBuf,
+    sync::{LazyLock, Mutex},
+    time::SystemTime,
+};
+use regex::Regex;
+use serde_json::Value;
+
+use crate::storage::storage;
+use crate::types::{
+    api::{QueryPredicates, SavedQuery, SavedQueryPayload},
+    error::{Missing, ZenithError},
+};
+use crate::{config, db};
+
+/// The file in the data path holding the saved queries.
+pub const QUERIES_FILENAME: &str = ".queries.json";
+
+
+/// Held while the saved queries are changed, so that changes are not lost.
+static QUERIES_LOCK: Mutex<()> = Mutex::new(());
+
+/// A placeholder in a predicate, such as `{{region}}`, which is given a value when the query is run.
+static PLACEHOLDER: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"\{\{\s*([A-Za-z_][A-Za-z0-9_]*)\s*\}\}").unwrap());
+
+
+/// Returns the time now, in milliseconds since the Unix epoch.
+fn now() -> u64 {
+    SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).map(|d| d.as_millis() as u64).unwrap_or(0)
+}
+
+
+fn queries_path() -> PathBuf {
+    config::data_path().join(QUERIES_FILENAME)
+}
+
+
+fn not_found(name: &str) -> ZenithError {
+    ZenithError::NotFound(Missing::Other(format!("Saved query '{}' does not exist", name)))
+}
+
+
+/// Reads the saved queries.
+fn read() -> Result<Vec<SavedQuery>, ZenithError> {
+    let path = queries_path();
+    if !storage().is_file(&path) {
+        return Ok(Vec::new());
+    }
+    Ok(serde_json::from_slice(&storage().read(&path)?)?)
+}
+
+
+/// Writes the saved `queries`, replacing those there were.
+///
+/// The queries lock must be held while calling this.
+fn write(queries: &[SavedQuery]) -> Result<(), ZenithError> {
+    let path = queries_path();
+    storage().create_dir_all(&config::data_path())?;
+    let temp_path = path.with_extension("json.tmp");
+    storage().write(&temp_path, &serde_json::to_vec_pretty(queries)?)?;
+    storage().rename(&temp_path, &path)?;
+    Ok(())
+}
+
+
+/// Returns the names of the placeholders in `predicates`, in the order they are first found.
+/// Raises a `QueryError` if a predicate has a `{{` that does not start a placeholder.
+fn placeholders(predicates: &[String]) -> Result<Vec<String>, ZenithError> {
+    let mut names: Vec<String> = Vec::new();
+    for predicate in predicates {
+        let found: Vec<&str> = PLACEHOLDER.captures_iter(predicate).filter_map(|c| c.get(1)).map(|m| m.as_str()).collect();
+        if predicate.matches("{{").count() != found.len() {
+            return Err(ZenithError::QueryError(format!(
+                "The predicate '{}' has a placeholder that is not a name in {{{{ and }}}}", predicate
+            )));
+        }
+        for name in found {
+            if !names.iter().any(|n| n == name) {
+                names.push(name.to_string());
+            }
+        }
+    }
+    Ok(names)
+}
+
+
+/// Lists the saved queries, in order of their names.
+pub fn list() -> Result<Vec<SavedQuery>, ZenithError> {
+    let mut queries = read()?;
+    queries.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(queries)
+}
+
+
+/// Returns the saved query `name`, raising a `NotFound` error if there is none.
+pub fn get(name: &str) -> Result<SavedQuery, ZenithError> {
+    db::validate_name("query", name)?;
+    read()?.into_iter().find(|q| q.name == name).ok_or_else(|| not_found(name))
+}
+
+
+/// Saves the query in `payload` as `name`, replacing any query saved as it, and returns it
+/// with the names of its parameters, which are the placeholders in its predicates.
+pub fn save(name: &str, payload: SavedQueryPayload) -> Result<SavedQuery, ZenithError> {
+    db::validate_name("query", name)?;
+    db::validate_name("collection", &payload.collection)?;
+    let parameters = placeholders(&payload.predicates)?;
+
+    let _guard = QUERIES_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let mut queries = read()?;
+    let created = queries.iter().find(|q| q.name == name).map(|q| q.created).unwrap_or_else(now);
+    let query = SavedQuery {
+        name: name.to_string(),
+        collection: payload.collection,
+        fields: payload.fields,
+        predicates: payload.predicates,
+        description: payload.description,
+        parameters,
+        created,
+        updated: now(),
+    };
+    queries.retain(|q| q.name != name);
+    queries.push(query.clone());
+    write(&queries)?;
+    Ok(query)
+}
+
+
+/// Removes the saved query `name`, returning it. Raises a `NotFound` error if it does not exist.
+pub fn remove(name: &str) -> Result<SavedQuery, ZenithError> {
+    db::validate_name("query", name)?;
+    let _guard = QUERIES_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let mut queries = read()?;
+    let position = queries.iter().position(|q| q.name == name).ok_or_else(|| not_found(name))?;
+    let query = queries.remove(position);
+    write(&queries)?;
+    Ok(query)
+}
+
+
+/// Returns the collection of the saved query `name`, and its fields and predicates with each
+/// placeholder replaced by the value of its parameter in `values`. Strings are given as they
+/// are, and other values as they are written in JSON, with `null` as an empty value.
+/// Raises a `QueryError` if a parameter is not given a value, or a value is given that is not
+/// a parameter of the query.
+pub fn bind(
+    name: &str,
+    values: &HashMap<String, Value>,
+) -> Result<(String, QueryPredicates), ZenithError> {
+
+    let query = get(name)?;
+    if let Some(missing) = query.parameters.iter().find(|p| !values.contains_key(*p)) {
+        return Err(ZenithError::QueryError(format!("The parameter '{}' of query '{}' is not given a value", missing, name)));
+    }
+    if let Some(unknown) = values.keys().find(|v| !query.parameters.contains(v)) {
+        return Err(ZenithError::QueryError(format!("Query '{}' has no parameter '{}'", name, unknown)));
+    }
+
+    let value = |name: &str| match &values[name] {
+        Value::String(s) => s.clone(),
+        Value::Null => String::new(),
+        other => other.to_string(),
+    };
+    let predicates = query.predicates.iter()
+        .map(|predicate| PLACEHOLDER.replace_all(predicate, |c: &regex::Captures| value(&c[1])).into_owned())
+        .collect();
+    Ok((query.collection, QueryPredicates { fields: query.fields, predicates }))
+}
@@ -0,0 +1,314 @@
+use std::{
+    path::PathBuf,
+    sync::Mutex,
+};
+use chrono::{DateTime, Datelike, Timelike, Utc};
+use tracing::{info, warn};
+
+use crate::audit::{self, AuditEntry};
+use crate::auth::Principal;
+use crate::queries::Cancelled;
+use crate::storage::storage;
+use crate::types::{
+    api::{CreatePayload, Priority, Schedule, SchedulePayload},
+    error::{Missing, ZenithError},
+};
+use crate::{changes, config, db, limit, remote, request_id, saved_queries, shutdown};
+
+/// The file in the data path holding the schedules.
+pub const SCHEDULES_FILENAME: &str = ".schedules.json";
+
+
+/// Held while the schedules are changed, so that changes are not lost.
+static SCHEDULES_LOCK: Mutex<()> = Mutex::new(());
+
+
+/// A cron expression: the minutes, hours, days of the month, months, and days of the week
+/// it matches, each as a bit for each value.
+struct Cron {
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    weekdays: u64,
+    /// Whether the days of the month and of the week are `*`. If neither is, a time
+    /// matches if either does, as with other cron implementations.
+    every_day: bool,
+    every_weekday: bool,
+}
+
+impl Cron {
+    /// Parses a cron expression of five fields, the minute, hour, day of the month, month, and day
+    /// of the week (from 0, Sunday, to 7, also Sunday), or one of `@hourly`, `@daily`, `@weekly`,
+    /// `@monthly`, and `@yearly`.
+    fn parse(expression: &str) -> Result<Cron, ZenithError> {
+        let expression = match expression.trim() {
+            "@yearly" | "@annually" => "0 0 1 1 *",
+            "@monthly" => "0 0 1 * *",
+            "@weekly" => "0 0 * * 0",
+            "@daily" | "@midnight" => "0 0 * * *",
+            "@hourly" => "0 * * * *",
+            other => other,
+        };
+        let fields: Vec<&str> = expression.split_whitespace().collect();
+        if fields.len() != 5 {
+            return Err(ZenithError::QueryError(format!(
+                "The cron expression '{}' does not have five fields: minute, hour, day of the month, month, and day of the week", expression
+            )));
+        }
+        let mut weekdays = field(fields[4], 0, 7)?;
+        if weekdays & (1 << 7) != 0 {
+            weekdays |= 1;
+        }
+        Ok(Cron {
+            minutes: field(fields[0], 0, 59)?,
+            hours: field(fields[1], 0, 23)?,
+            days: field(fields[2], 1, 31)?,
+            months: field(fields[3], 1, 12)?,
+            weekdays,
+            every_day: fields[2] == "*",
+            every_weekday: fields[4] == "*",
+        })
+    }
+
+    /// Whether the minute of `time` matches the expression.
+    fn matches(&self, time: &DateTime<Utc>) -> bool {
+        let has = |set: u64, value: u32| set & (1 << value) != 0;
+        let day = has(self.days, time.day());
+        let weekday = has(self.weekdays, time.weekday().num_days_from_sunday());
+        let day_matches = match self.every_day || self.every_weekday {
+            true => day && weekday,
+            false => day || weekday,
+        };
+        has(self.minutes, time.minute()) && has(self.hours, time.hour()) && has(self.months, time.month()) && day_matches
+    }
+}
+
+
+/// Parses a field of a cron expression, with values from `min` to `max`: a list, separated by `,`,
+/// of `*`, values, and ranges such as `1-5`, each of which can be followed by a step such as `/15`.
+/// A value with a step is a range up to `max`. Returns a bit for each value it matches.
+fn field(field: &str, min: u32, max: u32) -> Result<u64, ZenithError> {
+    let invalid = |part: &str| ZenithError::QueryError(format!("'{}' is not a valid cron field with values from {} to {}", part, min, max));
+    let mut set = 0;
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => (range, Some(step.parse::<u32>().ok().filter(|s| *s > 0).ok_or_else(|| invalid(part))?)),
+            None => (part, None),
+        };
+        let value = |s: &str| s.parse::<u32>().ok().filter(|v| (min..=max).contains(v)).ok_or_else(|| invalid(part));
+        let (start, end) = match (range, range.split_once('-')) {
+            ("*", _) => (min, max),
+            (_, Some((start, end))) => (value(start)?, value(end)?),
+            (_, None) if step.is_some() => (value(range)?, max),
+            (_, None) => (value(range)?, value(range)?),
+        };
+        if start > end {
+            return Err(invalid(part));
+        }
+        for v in (start..=end).step_by(step.unwrap_or(1) as usize) {
+            set |= 1 << v;
+        }
+    }
+    Ok(set)
+}
+
+
+fn schedules_path() -> PathBuf {
+    config::data_path().join(SCHEDULES_FILENAME)
+}
+
+
+fn not_found(id: &str) -> ZenithError {
+    ZenithError::NotFound(Missing::Other(format!("Schedule '{}' does not exist", id)))
+}
+
+
+fn scheduler() -> Principal {
+    Principal { name: "scheduler".to_string(), ..Principal::default() }
+}
+
+
+/// Reads the schedules.
+fn read() -> Result<Vec<Schedule>, ZenithError> {
+    let path = schedules_path();
+    if !storage().is_file(&path) {
+        return Ok(Vec::new());
+    }
+    Ok(serde_json::from_slice(&storage().read(&path)?)?)
+}
+
+
+/// Writes the `schedules`, replacing those there were.
+///
+/// The schedules lock must be held while calling this.
+fn write(schedules: &[Schedule]) -> Result<(), ZenithError> {
+    let path = schedules_path();
+    storage().create_dir_all(&config::data_path())?;
+    let temp_path = path.with_extension("json.tmp");
+    storage().write(&temp_path, &serde_json::to_vec_pretty(schedules)?)?;
+    storage().rename(&temp_path, &path)?;
+    Ok(())
+}
+
+
+/// Lists the schedules, oldest first.
+pub fn list() -> Result<Vec<Schedule>, ZenithError> {
+    read()
+}
+
+
+/// Returns the schedule with `id`, raising a `NotFound` error if there is none.
+pub fn get(id: &str) -> Result<Schedule, ZenithError> {
+    read()?.into_iter().find(|s| s.schedule_id == id).ok_or_else(|| not_found(id))
+}
+
+
+/// Schedules the saved query in `payload` to run at the times its cron expression matches,
+/// creating a file of the rows found in its collection, or sending them to its URL, each time.
+/// Raises a `QueryError` if the cron expression is not valid, the query cannot be given its
+/// parameters, or not exactly one of a collection and a URL is given, and a `NotFound` error
+/// if the query or collection does not exist.
+pub fn create(payload: SchedulePayload) -> Result<Schedule, ZenithError> {
+    Cron::parse(&payload.cron)?;
+    saved_queries::bind(&payload.query, &payload.parameters)?;
+    match (&payload.collection, &payload.url) {
+        (Some(collection), None) => {
+            db::validate_name("collection", collection)?;
+            if !storage().is_dir(&config::collection_path(collection)) {
+                return Err(ZenithError::NotFound(Missing::Collection(collection.clone())));
+            }
+        },
+        (None, Some(url)) => {
+            remote::parse_url(url)?;
+        },
+        _ => return Err(ZenithError::QueryError("A schedule needs either a collection or a URL to send its rows to".to_string())),
+    }
+
+    let schedule = Schedule {
+        schedule_id: request_id::generate(),
+        query: payload.query,
+        parameters: payload.parameters,
+        cron: payload.cron,
+        collection: payload.collection,
+        url: payload.url,
+        created: Utc::now().timestamp_millis() as u64,
+        last_run: None,
+        last_rows: None,
+        last_error: None,
+    };
+    let _guard = SCHEDULES_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let mut schedules = read()?;
+    schedules.push(schedule.clone());
+    write(&schedules)?;
+    Ok(schedule)
+}
+
+
+/// Removes the schedule with `id`, returning it. Raises a `NotFound` error if it does not exist.
+pub fn remove(id: &str) -> Result<Schedule, ZenithError> {
+    let _guard = SCHEDULES_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let mut schedules = read()?;
+    let position = schedules.iter().position(|s| s.schedule_id == id).ok_or_else(|| not_found(id))?;
+    let schedule = schedules.remove(position);
+    write(&schedules)?;
+    Ok(schedule)
+}
+
+
+/// Records that the schedule with `id` ran at `time`, finding the number of rows in `result`,
+/// or failing with its error. Schedules removed while they ran are not recorded.
+fn record(id: &str, time: &DateTime<Utc>, result: &Result<usize, ZenithError>) {
+    let _guard = SCHEDULES_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let recorded = read().and_then(|mut schedules| {
+        if let Some(schedule) = schedules.iter_mut().find(|s| s.schedule_id == id) {
+            schedule.last_run = Some(time.timestamp_millis() as u64);
+            (schedule.last_rows, schedule.last_error) = match result {
+                Ok(rows) => (Some(*rows), None),
+                Err(err) => (None, Some(err.to_string())),
+            };
+        }
+        write(&schedules)
+    });
+    if let Err(err) = recorded {
+        warn!("Could not record the run of schedule '{}': {}", id, err);
+    }
+}
+
+
+/// Runs the query of `schedule` as it was scheduled at `time`, at batch priority, and creates a
+/// file of the rows found in its collection, named after the query and the time, or sends them
+/// to its URL, as JSON with the `schedule_id`, `query`, `time`, `header`, and `rows`. Returns the
+/// number of rows found, which is recorded with the schedule.
+pub async fn run(schedule: Schedule, time: DateTime<Utc>) -> Result<usize, ZenithError> {
+    let result = execute(&schedule, &time).await;
+    match &result {
+        Ok(rows) => info!("Ran schedule '{}' of query '{}', finding {} rows", schedule.schedule_id, schedule.query, rows),
+        Err(err) => warn!("Could not run schedule '{}' of query '{}': {}", schedule.schedule_id, schedule.query, err),
+    }
+    record(&schedule.schedule_id, &time, &result);
+    result
+}
+
+
+async fn execute(schedule: &Schedule, time: &DateTime<Utc>) -> Result<usize, ZenithError> {
+    let (collection, predicates) = saved_queries::bind(&schedule.query, &schedule.parameters)?;
+    let _slot = limit::SlotGuard::wait(Priority::Batch).await;
+    let ((header, rows), _) = crate::run_query(&collection, predicates, scheduler(), &Cancelled::default(), false).await?;
+    let found = rows.len();
+
+    if let Some(target) = &schedule.collection {
+        let payload = CreatePayload {
+            filename: format!("{}-{}.csv", schedule.query, time.format("%Y%m%dT%H%M")),
+            header: header.clone(),
+            rows: rows.clone(),
+            on_conflict: Default::default(),
+            delimiter: None,
+            quote: None,
+            partial: false,
+            keyed: false,
+        };
+        let (target, filename) = (target.clone(), payload.filename.clone());
+        let previous_rows = db::file_rows(&target, &filename).ok().flatten();
+        let contents = changes::watched().then(|| (payload.header.clone(), payload.rows.clone()));
+        request_id::spawn_blocking({
+            let target = target.clone();
+            move || db::insert(&target, payload)
+        }).await?;
+        let entry = AuditEntry::new(&scheduler(), "create", &target)
+            .filename(&filename)
+            .rows(previous_rows, Some(found))
+            .detail(format!("schedule '{}'", schedule.schedule_id));
+        changes::publish(&entry, contents);
+        audit::record(entry);
+    }
+    if let Some(url) = &schedule.url {
+        let body = serde_json::json!({
+            "schedule_id": schedule.schedule_id,
+            "query": schedule.query,
+            "time": time.timestamp_millis(),
+            "header": header,
+            "rows": rows,
+        });
+        remote::post_json(url, &body).await?;
+    }
+    Ok(found)
+}
+
+
+/// Runs each schedule whose cron expression matches the minute of `time`, in UTC, in the
+/// background. Schedules are read each time, so that those created or removed since are run or not.
+pub async fn run_due(time: DateTime<Utc>) -> Result<(), ZenithError> {
+    for schedule in read()? {
+        match Cron::parse(&schedule.cron) {
+            Ok(cron) if cron.matches(&time) => {
+                shutdown::spawn(async move {
+                    let _ = run(schedule, time).await;
+                });
+            },
+            Ok(_) => (),
+            Err(err) => warn!("Schedule '{}' cannot be run: {}", schedule.schedule_id, err),
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,386 @@
+use std::{
+    cmp::Ordering,
+    collections::HashMap,
+    path::PathBuf,
+};
+use chrono::{DateTime, NaiveDate, NaiveDateTime};
+use serde::{Deserialize, Serialize};
+
+use crate::types::error::ZenithError;
+use crate::{config, storage::storage};
+
+/// The file in each collection that holds its schema.
+pub const SCHEMA_FILENAME: &str = ".schema.json";
+
+
+/// The type of the values in a column.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum ColumnType {
+    #[default]
+    String,
+    Int,
+    Float,
+    Bool,
+    Date,
+}
+
+/// Parses `value` as a date, or a date and time. Recognizes RFC 3339 timestamps,
+/// `YYYY-MM-DD HH:MM:SS` (with a space or `T`, and optional fractional seconds), and `YYYY-MM-DD`.
+pub fn parse_date(value: &str) -> Option<NaiveDateTime> {
+    if let Ok(datetime) = DateTime::parse_from_rfc3339(value) {
+        return Some(datetime.naive_utc());
+    }
+    for format in ["%Y-%m-%d %H:%M:%S%.f", "%Y-%m-%dT%H:%M:%S%.f"] {
+        if let Ok(datetime) = NaiveDateTime::parse_from_str(value, format) {
+            return Some(datetime);
+        }
+    }
+    NaiveDate::parse_from_str(value, "%Y-%m-%d").ok().and_then(|d| d.and_hms_opt(0, 0, 0))
+}
+
+
+/// Parses `value` as a boolean, ignoring case.
+pub fn parse_bool(value: &str) -> Option<bool> {
+    match value.to_ascii_lowercase().as_str() {
+        "true" => Some(true),
+        "false" => Some(false),
+        _ => None,
+    }
+}
+
+
+impl ColumnType {
+    /// Whether `value` can be parsed as this type.
+    pub fn accepts(&self, value: &str) -> bool {
+        match self {
+            ColumnType::String => true,
+            ColumnType::Int => value.parse::<i64>().is_ok(),
+            ColumnType::Float => value.parse::<f64>().is_ok_and(|f| f.is_finite()),
+            ColumnType::Bool => parse_bool(value).is_some(),
+            ColumnType::Date => parse_date(value).is_some(),
+        }
+    }
+
+    /// Converts `value` to JSON as this type. Empty values are `null`, except in
+    /// `string` columns, and values that cannot be parsed are kept as strings.
+    pub fn to_json(&self, value: &str) -> serde_json::Value {
+        use serde_json::Value;
+        if value.is_empty() && *self != ColumnType::String {
+            return Value::Null;
+        }
+        let typed = match self {
+            ColumnType::Int => value.parse::<i64>().ok().map(Value::from),
+            ColumnType::Float => value.parse::<f64>().ok().and_then(serde_json::Number::from_f64).map(Value::Number),
+            ColumnType::Bool => parse_bool(value).map(Value::Bool),
+            ColumnType::String | ColumnType::Date => None,
+        };
+        typed.unwrap_or_else(|| Value::String(value.to_string()))
+    }
+
+    /// Compares `a` to `b` as values of this type.
+    /// 
+    /// If either value cannot be parsed as this type, they are compared as strings.
+    pub fn compare(&self, a: &str, b: &str) -> Ordering {
+        let typed = match self {
+            ColumnType::String => None,
+            ColumnType::Int => a.parse::<i64>().ok().zip(b.parse::<i64>().ok()).map(|(a, b)| a.cmp(&b)),
+            ColumnType::Float => a.parse::<f64>().ok().zip(b.parse::<f64>().ok()).and_then(|(a, b)| a.partial_cmp(&b)),
+            ColumnType::Bool => parse_bool(a).zip(parse_bool(b)).map(|(a, b)| a.cmp(&b)),
+            ColumnType::Date => parse_date(a).zip(parse_date(b)).map(|(a, b)| a.cmp(&b)),
+        };
+        typed.unwrap_or_else(|| a.cmp(b))
+    }
+}
+
+
+/// Infers the type of each column in `header` from the values in `rows`.
+/// 
+/// A column is given the narrowest type (`bool`, `int`, `float`, then `date`) that
+/// accepts all of its non-empty values, or `string` if there is none. Empty values
+/// are ignored, and a column with no values at all is a `string`.
+pub fn infer(header: &[String], rows: &[Vec<String>]) -> Schema {
+    const CANDIDATES: [ColumnType; 4] = [ColumnType::Bool, ColumnType::Int, ColumnType::Float, ColumnType::Date];
+
+    let columns = header.iter().enumerate()
+        .map(|(i, name)| {
+            let mut candidates = CANDIDATES.to_vec();
+            let mut seen = false;
+            for value in rows.iter().filter_map(|row| row.get(i)).filter(|v| !v.is_empty()) {
+                seen = true;
+                candidates.retain(|t| t.accepts(value));
+                if candidates.is_empty() {
+                    break;
+                }
+            }
+            let column_type = match candidates.first() {
+                Some(t) if seen => *t,
+                _ => ColumnType::String,
+            };
+            Column { name: name.clone(), column_type, required: false, default: None, mask: None }
+        })
+        .collect();
+
+    Schema { columns, key: Vec::new() }
+}
+
+
+/// A column in a schema.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Column {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub column_type: ColumnType,
+    /// Whether every row must have a non-empty value in the column.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub required: bool,
+    /// The value given to the column in rows created without it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub default: Option<String>,
+    /// How the values in the column are hidden from principals without access to them.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mask: Option<Mask>,
+}
+
+
+/// How the values in a masked column are returned.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum MaskAction {
+    /// The column is left out entirely.
+    Hide,
+    /// Each value is replaced with `****`.
+    Redact,
+    /// Each value is replaced with its SHA-256 checksum, so equal values can still be matched up.
+    Hash,
+}
+
+impl MaskAction {
+    /// Masks `value`, returning `None` if the column is hidden.
+    pub fn apply(&self, value: &str) -> Option<String> {
+        match self {
+            MaskAction::Hide => None,
+            MaskAction::Redact => Some("****".to_string()),
+            MaskAction::Hash => Some(crate::catalog::checksum(value.as_bytes())),
+        }
+    }
+}
+
+
+/// Masks a column for every principal, `except` those with any of the given roles.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Mask {
+    pub action: MaskAction,
+    #[serde(default)]
+    pub except: Vec<String>,
+}
+
+
+/// Masks the values in `row`, where the columns are named by `names`, with the
+/// action for each column in `masks`. Hidden columns are left out.
+pub fn mask_row(
+    masks: &HashMap<String, MaskAction>,
+    names: &[String],
+    row: Vec<String>,
+) -> Vec<String> {
+    if masks.is_empty() {
+        return row;
+    }
+    names.iter().zip(row)
+        .filter_map(|(name, value)| match masks.get(name) {
+            Some(action) => action.apply(&value),
+            None => Some(value),
+        })
+        .collect()
+}
+
+
+/// Leaves the hidden columns in `masks` out of `header`.
+pub fn mask_header(
+    masks: &HashMap<String, MaskAction>,
+    header: Vec<String>,
+) -> Vec<String> {
+    header.into_iter().filter(|name| masks.get(name) != Some(&MaskAction::Hide)).collect()
+}
+
+impl Column {
+    /// Raises a `QueryError` if the column has no name, or its default is not valid for its type.
+    pub fn validate(&self) -> Result<(), ZenithError> {
+        if self.name.is_empty() {
+            return Err(ZenithError::QueryError("Schema column has no name".to_string()));
+        }
+        match &self.default {
+            Some(default) if !default.is_empty() && !self.column_type.accepts(default) => {
+                Err(ZenithError::QueryError(format!(
+                    "Default '{}' of column '{}' is not a valid {}", default, self.name, self.column_type.name()
+                )))
+            },
+            _ => Ok(()),
+        }
+    }
+}
+
+/// The explicit schema of a collection. The names of the `columns`
+/// make up the header that every file in the collection must have.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Schema {
+    pub columns: Vec<Column>,
+    /// The names of the columns whose values together identify a row in the collection.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub key: Vec<String>,
+}
+
+impl Schema {
+    /// Returns the names of the columns, in order.
+    pub fn header(&self) -> Vec<String> {
+        self.columns.iter().map(|c| c.name.clone()).collect()
+    }
+
+    /// Raises a `QueryError` if the schema has no columns, a column is not valid
+    /// or is repeated, or the key names a column that does not exist.
+    pub fn validate(&self) -> Result<(), ZenithError> {
+        if self.columns.is_empty() {
+            return Err(ZenithError::QueryError("Schema has no columns".to_string()));
+        }
+        for (i, column) in self.columns.iter().enumerate() {
+            column.validate()?;
+            if self.columns[..i].iter().any(|c| c.name == column.name) {
+                return Err(ZenithError::QueryError(format!("Schema column '{}' is repeated", column.name)));
+            }
+        }
+        for (i, name) in self.key.iter().enumerate() {
+            if !self.columns.iter().any(|c| c.name == *name) {
+                return Err(ZenithError::QueryError(format!("Schema key '{}' is not a column", name)));
+            }
+            if self.key[..i].contains(name) {
+                return Err(ZenithError::QueryError(format!("Schema key '{}' is repeated", name)));
+            }
+        }
+        Ok(())
+    }
+}
+
+
+/// A value in a row that does not satisfy the schema.
+#[derive(Serialize, Clone, Debug)]
+pub struct CellError {
+    pub row: usize,
+    pub column: String,
+    pub value: String,
+    pub reason: String,
+}
+
+impl std::fmt::Display for CellError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "row {}, column '{}': {}", self.row, self.column, self.reason)
+    }
+}
+
+impl ColumnType {
+    /// The name of the type, as it is serialized.
+    pub fn name(&self) -> &'static str {
+        match self {
+            ColumnType::String => "string",
+            ColumnType::Int => "int",
+            ColumnType::Float => "float",
+            ColumnType::Bool => "bool",
+            ColumnType::Date => "date",
+        }
+    }
+}
+
+impl Schema {
+    /// Checks the values in `row` against the type of their column. Empty values are
+    /// only an error in required columns. The row is reported as number `row_number`.
+    pub fn check_row(&self, row_number: usize, row: &[String]) -> Vec<CellError> {
+        self.columns.iter().zip(row)
+            .filter_map(|(column, value)| {
+                let reason = if value.is_empty() {
+                    column.required.then(|| "a value is required".to_string())
+                } else {
+                    (!column.column_type.accepts(value))
+                        .then(|| format!("'{}' is not a valid {}", value, column.column_type.name()))
+                };
+                reason.map(|reason| CellError {
+                    row: row_number,
+                    column: column.name.clone(),
+                    value: value.clone(),
+                    reason,
+                })
+            })
+            .collect()
+    }
+
+    /// Returns the action for each masked column that a principal with `roles` cannot see.
+    pub fn masks(&self, roles: &[String]) -> HashMap<String, MaskAction> {
+        self.columns.iter()
+            .filter_map(|column| column.mask.as_ref().map(|mask| (column, mask)))
+            .filter(|(_, mask)| !mask.except.iter().any(|role| roles.contains(role)))
+            .map(|(column, mask)| (column.name.clone(), mask.action))
+            .collect()
+    }
+
+    /// Returns the positions of the key columns in the header.
+    pub fn key_indices(&self) -> Vec<usize> {
+        self.key.iter()
+            .filter_map(|name| self.columns.iter().position(|c| c.name == *name))
+            .collect()
+    }
+
+    /// Checks each of the `rows` with `check_row`, numbering them from `first_row_number`.
+    pub fn check_rows(&self, rows: &[Vec<String>], first_row_number: usize) -> Vec<CellError> {
+        rows.iter().enumerate()
+            .flat_map(|(i, row)| self.check_row(first_row_number + i, row))
+            .collect()
+    }
+}
+
+
+/// Describes the first few `errors` in a single message.
+pub fn describe_errors(errors: &[CellError]) -> String {
+    const SHOWN: usize = 10;
+    let mut message = errors.iter().take(SHOWN).map(|e| e.to_string()).collect::<Vec<String>>().join("; ");
+    if errors.len() > SHOWN {
+        message.push_str(&format!("; and {} more", errors.len() - SHOWN));
+    }
+    message
+}
+
+
+fn schema_path(collection: &str) -> PathBuf {
+    config::collection_path(collection).join(SCHEMA_FILENAME)
+}
+
+
+/// Reads the schema of `collection`, if it has one.
+pub fn read(collection: &str) -> Result<Option<Schema>, ZenithError> {
+    let path = schema_path(collection);
+    if !storage().is_file(&path) {
+        return Ok(None);
+    }
+    Ok(Some(serde_json::from_slice(&storage().read(&path)?)?))
+}
+
+
+/// Writes the `schema` of `collection`, replacing any previous schema.
+/// 
+/// The collection lock must be held while calling this.
+pub fn write(collection: &str, schema: &Schema) -> Result<(), ZenithError> {
+    let path = schema_path(collection);
+    let temp_path = path.with_extension("json.tmp");
+    storage().write(&temp_path, &serde_json::to_vec_pretty(schema)?)?;
+    storage().rename(&temp_path, &path)?;
+    Ok(())
+}
+
+
+/// Removes the schema of `collection`, if it has one.
+/// 
+/// The collection lock must be held while calling this.
+pub fn remove(collection: &str) -> Result<(), ZenithError> {
+    let path = schema_path(collection);
+    if storage().is_file(&path) {
+        storage().remove(&path)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,94 @@
+use std::{
+    future::Future,
+    io::Write,
+    sync::{LazyLock, OnceLock},
+    time::{Duration, Instant},
+};
+use tokio_util::{sync::CancellationToken, task::TaskTracker};
+use tracing::{info, warn};
+
+use crate::config;
+
+
+/// Cancelled when the data service is asked to stop.
+static SHUTDOWN: LazyLock<CancellationToken> = LazyLock::new(CancellationToken::new);
+
+/// When requests and background tasks must have finished by, once the data service is asked to stop.
+static DEADLINE: OnceLock<Instant> = OnceLock::new();
+
+/// Background tasks, such as replication, that are waited for before the data service stops.
+static TASKS: LazyLock<TaskTracker> = LazyLock::new(TaskTracker::new);
+
+
+/// Waits for `SIGINT` (Ctrl+C) or, on Unix, `SIGTERM`, then asks the data service to stop.
+/// Run this in its own task when the data service starts.
+pub async fn listen() {
+    let interrupt = async {
+        if let Err(err) = tokio::signal::ctrl_c().await {
+            warn!("Could not listen for Ctrl+C: {}", err);
+            std::future::pending::<()>().await;
+        }
+    };
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut signal) => {
+                signal.recv().await;
+            },
+            Err(err) => {
+                warn!("Could not listen for SIGTERM: {}", err);
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = interrupt => (),
+        _ = terminate => (),
+    }
+    let timeout = Duration::from_secs(config::envar_usize("ZENITHDS_DRAIN_TIMEOUT") as u64);
+    let _ = DEADLINE.set(Instant::now() + timeout);
+    info!("Stopping. Waiting up to {:?} for requests and background tasks to finish", timeout);
+    SHUTDOWN.cancel();
+}
+
+
+/// Completes when the data service is asked to stop,
+/// so that it stops accepting connections.
+pub async fn requested() {
+    SHUTDOWN.cancelled().await
+}
+
+
+/// Completes when the drain timeout has passed after the data service was asked to stop,
+/// so that requests still running are cut off.
+pub async fn deadline() {
+    requested().await;
+    if let Some(deadline) = DEADLINE.get() {
+        tokio::time::sleep_until((*deadline).into()).await;
+    }
+}
+
+
+/// Spawns a background task that the data service waits for before it stops.
+pub fn spawn<F>(future: F)
+where
+    F: Future<Output = ()> + Send + 'static,
+{
+    TASKS.spawn(future);
+}
+
+
+/// Waits for background tasks to finish, up to the drain timeout,
+/// and flushes the logs. Call this after the server has stopped.
+pub async fn finish() {
+    TASKS.close();
+    let deadline = DEADLINE.get().copied().unwrap_or_else(Instant::now);
+    if tokio::time::timeout_at(deadline.into(), TASKS.wait()).await.is_err() {
+        warn!("Stopped with {} background tasks still running", TASKS.len());
+    }
+    info!("Stopped");
+    let _ = std::io::stdout().flush();
+}
@@ -0,0 +1,84 @@
+use std::{
+    fs::OpenOptions,
+    io::Write,
+    sync::Mutex,
+    time::{Duration, SystemTime},
+};
+use serde::Serialize;
+use tracing::{warn, error};
+
+use crate::{config, request_id};
+
+
+/// Held while an entry is appended to the slow query log, so that entries are not interleaved.
+static SLOW_QUERY_LOCK: Mutex<()> = Mutex::new(());
+
+
+/// A query that took longer than `ZENITHDS_SLOW_QUERY_MS`.
+#[derive(Serialize, Debug)]
+pub struct SlowQuery {
+    /// When the query finished, in milliseconds since the Unix epoch.
+    pub time: u64,
+    /// The id of the request that made the query, if any (see `request_id`).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
+    pub collection: String,
+    pub fields: Vec<String>,
+    pub predicates: Vec<String>,
+    pub files_scanned: usize,
+    pub rows_returned: usize,
+    pub duration_ms: u64,
+}
+
+
+/// Returns the duration over which a query is slow, or `None` if
+/// `ZENITHDS_SLOW_QUERY_MS` is `0` and slow queries are not logged.
+pub fn threshold() -> Option<Duration> {
+    match config::envar_usize("ZENITHDS_SLOW_QUERY_MS") {
+        0 => None,
+        ms => Some(Duration::from_millis(ms as u64)),
+    }
+}
+
+
+/// Logs a query on `collection` that took `elapsed`, if it is slow.
+/// It is appended to the file at `ZENITHDS_SLOW_QUERY_LOG` as a line of JSON, if set.
+pub fn record(
+    collection: &str,
+    fields: &[String],
+    predicates: &[String],
+    files_scanned: usize,
+    rows_returned: usize,
+    elapsed: Duration,
+) {
+    if threshold().is_none_or(|threshold| elapsed < threshold) {
+        return;
+    }
+    let entry = SlowQuery {
+        time: SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).map(|d| d.as_millis() as u64).unwrap_or(0),
+        request_id: request_id::current(),
+        collection: collection.to_string(),
+        fields: fields.to_vec(),
+        predicates: predicates.to_vec(),
+        files_scanned,
+        rows_returned,
+        duration_ms: elapsed.as_millis() as u64,
+    };
+    warn!("Slow query on collection '{}' took {:.2?}, scanning {} files and returning {} rows with predicates {:?}",
+        entry.collection, elapsed, entry.files_scanned, entry.rows_returned, entry.predicates);
+
+    let path = config::envar_str("ZENITHDS_SLOW_QUERY_LOG");
+    if path.is_empty() {
+        return;
+    }
+    let _guard = SLOW_QUERY_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let result = serde_json::to_string(&entry)
+        .map_err(std::io::Error::other)
+        .and_then(|line| {
+            let mut file = OpenOptions::new().create(true).append(true).open(&path)?;
+            file.write_all(format!("{}\n", line).as_bytes())
+        });
+    if let Err(err) = result {
+        error!("Could not record a slow query in '{}': {}", path, err);
+    }
+}
@@ -0,0 +1,442 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::auth::Principal;
+use crate::queries::Cancelled;
+use crate::schema::{self, ColumnType};
+use crate::types::{
+    api::{Bucket, ColumnProfile, HistogramResponse, PercentileValue, ProfileResponse, QueryPredicates, TopValuesResponse, ValueCount},
+    error::{Missing, ZenithError},
+};
+use crate::db;
+
+/// How many distinct values of each column are given as samples in a profile.
+const SAMPLES: usize = 5;
+
+/// How many values `top_values` returns if no limit is given.
+pub const TOP_VALUES: usize = 10;
+
+/// How many buckets `histogram` divides values into if neither a number of buckets nor a width is given.
+pub const HISTOGRAM_BUCKETS: usize = 10;
+
+/// The most buckets a histogram can have.
+const MAX_BUCKETS: usize = 10_000;
+
+/// How closely a digest follows the distribution of its values: it keeps about this many centroids.
+const COMPRESSION: f64 = 100.0;
+
+/// How many values a digest takes before they are merged into its centroids.
+const DIGEST_BUFFER: usize = 500;
+
+/// The types a column without a schema can be found to have, narrowest first, as in `schema::infer`.
+const CANDIDATES: [ColumnType; 4] = [ColumnType::Bool, ColumnType::Int, ColumnType::Float, ColumnType::Date];
+
+
+/// A summary of the values of a column, built up a batch of rows at a time.
+struct ColumnStats {
+    name: String,
+    values: usize,
+    nulls: usize,
+    distinct: HashSet<String>,
+    /// The types that accept every non-empty value so far, or the type of the column in its schema.
+    candidates: Vec<ColumnType>,
+    /// Whether the type of the column was given by its schema, so that it is kept even where a value does not fit it.
+    typed: bool,
+    /// The smallest and largest value so far as each type that is still a candidate, and as a string.
+    extremes: Vec<(ColumnType, String, String)>,
+    /// The mean of the values that are numbers, and the sum of the squares of their differences
+    /// from it, updated with each number as in Welford's algorithm, for the variance.
+    mean: f64,
+    squares: f64,
+    numbers: usize,
+    digest: Digest,
+    samples: Vec<String>,
+}
+
+impl ColumnStats {
+    fn new(name: &str, column_type: Option<ColumnType>) -> ColumnStats {
+        ColumnStats {
+            name: name.to_string(),
+            values: 0,
+            nulls: 0,
+            distinct: HashSet::new(),
+            candidates: column_type.map_or(CANDIDATES.to_vec(), |t| vec![t]),
+            typed: column_type.is_some(),
+            extremes: Vec::new(),
+            mean: 0.0,
+            squares: 0.0,
+            numbers: 0,
+            digest: Digest::new(),
+            samples: Vec::new(),
+        }
+    }
+
+    fn add(&mut self, value: &str) {
+        self.values += 1;
+        if value.is_empty() {
+            self.nulls += 1;
+            return;
+        }
+        if !self.distinct.contains(value) {
+            if self.samples.len() < SAMPLES {
+                self.samples.push(value.to_string());
+            }
+            self.distinct.insert(value.to_string());
+        }
+        if let Ok(number) = value.parse::<f64>() {
+            if number.is_finite() {
+                self.numbers += 1;
+                let difference = number - self.mean;
+                self.mean += difference / self.numbers as f64;
+                self.squares += difference * (number - self.mean);
+                self.digest.add(number);
+            }
+        }
+
+        if !self.typed {
+            self.candidates.retain(|t| t.accepts(value));
+        }
+        if self.extremes.is_empty() {
+            self.extremes = self.candidates.iter()
+                .chain((!self.candidates.contains(&ColumnType::String)).then_some(&ColumnType::String))
+                .map(|t| (*t, value.to_string(), value.to_string()))
+                .collect();
+            return;
+        }
+        let candidates = &self.candidates;
+        self.extremes.retain(|(t, _, _)| *t == ColumnType::String || candidates.contains(t));
+        for (t, min, max) in &mut self.extremes {
+            if t.compare(value, min).is_lt() {
+                *min = value.to_string();
+            }
+            if t.compare(value, max).is_gt() {
+                *max = value.to_string();
+            }
+        }
+    }
+
+    fn profile(mut self, rows: usize, percentiles: &[f64]) -> ColumnProfile {
+        let seen = self.values > self.nulls;
+        let column_type = match self.candidates.first() {
+            Some(t) if seen => *t,
+            _ => ColumnType::String,
+        };
+        let extremes = self.extremes.into_iter().find(|(t, _, _)| *t == column_type);
+        let numeric = matches!(column_type, ColumnType::Int | ColumnType::Float) && self.numbers > 0;
+        let variance = (numeric && self.numbers > 1).then(|| self.squares / (self.numbers - 1) as f64);
+        // Rows of files without the column count as empty values in it.
+        let nulls = self.nulls + rows.saturating_sub(self.values);
+        ColumnProfile {
+            name: self.name,
+            column_type,
+            nulls,
+            null_rate: if rows == 0 { 0.0 } else { nulls as f64 / rows as f64 },
+            distinct: self.distinct.len(),
+            min: extremes.as_ref().map(|(_, min, _)| min.clone()),
+            max: extremes.map(|(_, _, max)| max),
+            mean: numeric.then_some(self.mean),
+            variance,
+            stddev: variance.map(f64::sqrt),
+            median: numeric.then(|| self.digest.quantile(0.5)),
+            percentiles: match numeric {
+                true => percentiles.iter()
+                    .map(|p| PercentileValue { percentile: *p, value: self.digest.quantile(p / 100.0) })
+                    .collect(),
+                false => Vec::new(),
+            },
+            samples: self.samples,
+        }
+    }
+}
+
+
+/// A t-digest of numbers, from which their quantiles can be estimated without holding them.
+/// Numbers are buffered, and then merged into centroids, each a mean and the number of values
+/// in it, which are kept small near the ends of the distribution so that extreme quantiles are
+/// close, and larger in the middle. Quantiles are estimates, even of a few numbers.
+struct Digest {
+    centroids: Vec<(f64, f64)>,
+    buffer: Vec<f64>,
+    min: f64,
+    max: f64,
+}
+
+impl Digest {
+    fn new() -> Digest {
+        Digest { centroids: Vec::new(), buffer: Vec::new(), min: f64::INFINITY, max: f64::NEG_INFINITY }
+    }
+
+    fn add(&mut self, number: f64) {
+        (self.min, self.max) = (self.min.min(number), self.max.max(number));
+        self.buffer.push(number);
+        if self.buffer.len() >= DIGEST_BUFFER {
+            self.merge();
+        }
+    }
+
+    /// The scale of the digest at quantile `q`: neighbouring centroids are merged
+    /// while the scale at either end of the merged centroid differs by at most 1.
+    fn scale(q: f64) -> f64 {
+        COMPRESSION / (2.0 * std::f64::consts::PI) * (2.0 * q - 1.0).clamp(-1.0, 1.0).asin()
+    }
+
+    fn merge(&mut self) {
+        if self.buffer.is_empty() {
+            return;
+        }
+        let mut centroids = std::mem::take(&mut self.centroids);
+        centroids.extend(self.buffer.drain(..).map(|number| (number, 1.0)));
+        centroids.sort_by(|a, b| a.0.total_cmp(&b.0));
+        let total: f64 = centroids.iter().map(|(_, weight)| weight).sum();
+
+        let mut centroids = centroids.into_iter();
+        let Some(mut current) = centroids.next() else {
+            return;
+        };
+        let mut before = 0.0;
+        let mut limit = Digest::scale(0.0) + 1.0;
+        for (mean, weight) in centroids {
+            if Digest::scale((before + current.1 + weight) / total) <= limit {
+                current.0 += (mean - current.0) * weight / (current.1 + weight);
+                current.1 += weight;
+            }
+            else {
+                before += current.1;
+                self.centroids.push(current);
+                current = (mean, weight);
+                limit = Digest::scale(before / total) + 1.0;
+            }
+        }
+        self.centroids.push(current);
+    }
+
+    /// Estimates the `q` quantile, from 0 to 1, of the numbers added, interpolating between the
+    /// centres of the centroids around it, and the smallest and largest numbers at the ends.
+    /// The digest must have at least one number.
+    fn quantile(&mut self, q: f64) -> f64 {
+        self.merge();
+        let total: f64 = self.centroids.iter().map(|(_, weight)| weight).sum();
+        let target = q * total;
+        // The points to interpolate between, each a number of values below it and a value.
+        let mut previous = (0.0, self.min);
+        let mut below = 0.0;
+        for (mean, weight) in &self.centroids {
+            let centre = (below + weight / 2.0, *mean);
+            if target < centre.0 {
+                let (x0, y0) = previous;
+                return y0 + (centre.1 - y0) * (target - x0) / (centre.0 - x0);
+            }
+            previous = centre;
+            below += weight;
+        }
+        let (x0, y0) = previous;
+        match total > x0 {
+            true => y0 + (self.max - y0) * (target - x0) / (total - x0),
+            false => self.max,
+        }
+    }
+}
+
+
+/// Profiles the columns of `collection` in the rows matched by `predicates`, as they are scanned:
+/// for each column, its type, the number and rate of empty values, the number of distinct values,
+/// the smallest and largest values, and a few sample values, and for a numeric column, its mean,
+/// variance, and standard deviation, and its median and any other `percentiles`, from 0 to 100,
+/// estimated with a t-digest. Columns are typed by the schema of the collection, or by the values
+/// found, as with `schema::infer`. See `db::select_each`.
+pub fn profile(
+    collection: &str,
+    predicates: QueryPredicates,
+    percentiles: &[f64],
+    principal: &Principal,
+    cancelled: &Cancelled,
+) -> Result<ProfileResponse, ZenithError> {
+
+    if percentiles.iter().any(|p| !(0.0..=100.0).contains(p)) {
+        return Err(ZenithError::QueryError("Percentiles must be from 0 to 100".to_string()));
+    }
+
+    let types: HashMap<String, ColumnType> = schema::read(collection)?
+        .map(|schema| schema.columns.into_iter().map(|c| (c.name, c.column_type)).collect())
+        .unwrap_or_default();
+    let mut columns: Vec<ColumnStats> = Vec::new();
+    let mut rows = 0;
+    db::select_each(collection, predicates, Some(principal), cancelled, |header, records| {
+        let positions: Vec<usize> = header.iter()
+            .map(|name| match columns.iter().position(|c| c.name == *name) {
+                Some(position) => position,
+                None => {
+                    columns.push(ColumnStats::new(name, types.get(name).copied()));
+                    columns.len() - 1
+                },
+            })
+            .collect();
+        for record in &records {
+            for (value, position) in record.iter().zip(&positions) {
+                columns[*position].add(value);
+            }
+        }
+        rows += records.len();
+        true
+    })?;
+
+    Ok(ProfileResponse {
+        rows,
+        columns: columns.into_iter().map(|column| column.profile(rows, percentiles)).collect(),
+    })
+}
+
+
+/// Returns the type of `column` in `collection`, as `principal` sees it, raising
+/// a `NotFound` error if it is not a column of the collection that it can see.
+fn column_type(
+    collection: &str,
+    column: &str,
+    principal: &Principal,
+) -> Result<ColumnType, ZenithError> {
+
+    db::columns(collection, Some(principal))?.into_iter()
+        .find(|(name, _)| name == column)
+        .map(|(_, column_type)| column_type)
+        .ok_or_else(|| ZenithError::NotFound(Missing::Other(format!(
+            "Column '{}' does not exist in collection '{}'", column, collection
+        ))))
+}
+
+
+/// Returns the `limit` most frequent values of `column` in the rows of `collection` matched
+/// by `predicates`, with how many times each was found, most frequent first, and values found
+/// as many times in order. The values are counted as the rows are scanned, rather than held.
+pub fn top_values(
+    collection: &str,
+    column: &str,
+    predicates: Vec<String>,
+    limit: usize,
+    principal: &Principal,
+    cancelled: &Cancelled,
+) -> Result<TopValuesResponse, ZenithError> {
+
+    let column_type = column_type(collection, column, principal)?;
+    let mut counts: HashMap<String, usize> = HashMap::new();
+    let mut rows = 0;
+    let predicates = QueryPredicates { fields: vec![column.to_string()], predicates };
+    db::select_each(collection, predicates, Some(principal), cancelled, |header, records| {
+        if let Some(i) = header.iter().position(|name| name == column) {
+            for record in records {
+                if let Some(value) = record.into_iter().nth(i) {
+                    *counts.entry(value).or_default() += 1;
+                    rows += 1;
+                }
+            }
+        }
+        true
+    })?;
+
+    let distinct = counts.len();
+    let mut values: Vec<ValueCount> = counts.into_iter().map(|(value, count)| ValueCount { value, count }).collect();
+    values.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| column_type.compare(&a.value, &b.value)));
+    values.truncate(limit);
+    Ok(TopValuesResponse { column: column.to_string(), rows, distinct, values })
+}
+
+
+/// How the values of a histogram are divided into buckets.
+pub enum Buckets {
+    /// This many buckets of the same width, from the smallest value to the largest.
+    Count(usize),
+    /// Buckets of this width, starting at multiples of it.
+    Width(f64),
+}
+
+
+/// Returns a histogram of the numbers in `column` in the rows of `collection` matched by
+/// `predicates`, with the number of values in each of the `buckets`, from the smallest to the
+/// largest. Empty values, and values that are not numbers, are skipped and counted.
+/// 
+/// Buckets of a given width are counted as the rows are scanned. A number of buckets needs the
+/// smallest and largest values first, so the numbers are held until the scan is done.
+pub fn histogram(
+    collection: &str,
+    column: &str,
+    predicates: Vec<String>,
+    buckets: Buckets,
+    principal: &Principal,
+    cancelled: &Cancelled,
+) -> Result<HistogramResponse, ZenithError> {
+
+    match buckets {
+        Buckets::Count(count) if count == 0 || count > MAX_BUCKETS => {
+            return Err(ZenithError::QueryError(format!("The number of buckets must be from 1 to {}", MAX_BUCKETS)));
+        },
+        Buckets::Width(width) if !(width.is_finite() && width > 0.0) => {
+            return Err(ZenithError::QueryError("The width of the buckets must be a number greater than 0".to_string()));
+        },
+        _ => (),
+    }
+    column_type(collection, column, principal)?;
+
+    let mut numbers: Vec<f64> = Vec::new();
+    // The number of values in each bucket of a given width, by the multiple of the width it starts at.
+    let mut counts: HashMap<i64, usize> = HashMap::new();
+    let (mut min, mut max) = (f64::INFINITY, f64::NEG_INFINITY);
+    let mut skipped = 0;
+    let predicates = QueryPredicates { fields: vec![column.to_string()], predicates };
+    db::select_each(collection, predicates, Some(principal), cancelled, |header, records| {
+        let Some(i) = header.iter().position(|name| name == column) else {
+            return true;
+        };
+        for record in &records {
+            let Some(number) = record.get(i).and_then(|v| v.parse::<f64>().ok()).filter(|n| n.is_finite()) else {
+                skipped += 1;
+                continue;
+            };
+            (min, max) = (min.min(number), max.max(number));
+            match buckets {
+                Buckets::Count(_) => numbers.push(number),
+                Buckets::Width(width) => *counts.entry((number / width).floor() as i64).or_default() += 1,
+            }
+        }
+        true
+    })?;
+
+    let rows = match buckets {
+        Buckets::Count(_) => numbers.len(),
+        Buckets::Width(_) => counts.values().sum(),
+    };
+    let bins = match buckets {
+        _ if rows == 0 => Vec::new(),
+        // Every value is in the one bucket when they are all the same.
+        Buckets::Count(_) if min == max => vec![Bucket { start: min, end: max, count: rows }],
+        Buckets::Count(count) => {
+            let width = (max - min) / count as f64;
+            let mut bins: Vec<Bucket> = (0..count)
+                .map(|b| Bucket { start: min + width * b as f64, end: min + width * (b + 1) as f64, count: 0 })
+                .collect();
+            // The last bucket also holds the largest value.
+            bins[count - 1].end = max;
+            for number in numbers {
+                bins[(((number - min) / width) as usize).min(count - 1)].count += 1;
+            }
+            bins
+        },
+        Buckets::Width(width) => {
+            let (first, last) = ((min / width).floor() as i64, (max / width).floor() as i64);
+            if (last - first) as usize >= MAX_BUCKETS {
+                return Err(ZenithError::QueryError(format!(
+                    "The width of the buckets is too small for values from {} to {}, which would need more than {} buckets", min, max, MAX_BUCKETS
+                )));
+            }
+            (first..=last)
+                .map(|b| Bucket { start: b as f64 * width, end: (b + 1) as f64 * width, count: counts.get(&b).copied().unwrap_or_default() })
+                .collect()
+        },
+    };
+
+    Ok(HistogramResponse {
+        column: column.to_string(),
+        rows,
+        skipped,
+        min: (rows > 0).then_some(min),
+        max: (rows > 0).then_some(max),
+        buckets: bins,
+    })
+}
@@ -0,0 +1,365 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fs::File,
+    io::{self, Cursor, Read},
+    path::{Path, PathBuf},
+    sync::{Arc, LazyLock, Mutex},
+    time::SystemTime,
+};
+
+use crate::config;
+
+
+/// An entry in a directory of the storage.
+pub struct Entry {
+    pub name: String,
+    pub path: PathBuf,
+    pub is_file: bool,
+    pub size: u64,
+    pub modified: SystemTime,
+}
+
+
+/// Where the data service keeps its files.
+/// 
+/// Paths are given in full (for example, under `config::data_path()`), and have
+/// the same meaning regardless of how the files are actually stored.
+pub trait Storage: Send + Sync {
+    /// Lists the entries directly inside the directory at `path`.
+    fn list(&self, path: &Path) -> io::Result<Vec<Entry>>;
+    /// Opens the file at `path` for reading.
+    fn open(&self, path: &Path) -> io::Result<Box<dyn Read + Send>>;
+    /// Creates or truncates the file at `path` with `bytes`. Its directory must exist.
+    fn write(&self, path: &Path, bytes: &[u8]) -> io::Result<()>;
+    /// Moves the file at `from` to `to`, replacing any file at `to`.
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()>;
+    /// Removes the file at `path`.
+    fn remove(&self, path: &Path) -> io::Result<()>;
+    /// Places the file at `from` at `to` as well, without copying it if possible.
+    /// Later changes to one of the files must not affect the other.
+    fn link(&self, from: &Path, to: &Path) -> io::Result<()>;
+    /// Creates the directory at `path`, along with any missing parents.
+    fn create_dir_all(&self, path: &Path) -> io::Result<()>;
+    fn is_file(&self, path: &Path) -> bool;
+    fn is_dir(&self, path: &Path) -> bool;
+
+    /// Reads the whole file at `path`.
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+        let mut bytes = Vec::new();
+        self.open(path)?.read_to_end(&mut bytes)?;
+        Ok(bytes)
+    }
+}
+
+
+/// Stores files on the file system.
+pub struct FsStorage;
+
+impl Storage for FsStorage {
+    fn list(&self, path: &Path) -> io::Result<Vec<Entry>> {
+        std::fs::read_dir(path)?
+            .map(|entry| {
+                let entry = entry?;
+                let metadata = entry.metadata()?;
+                Ok(Entry {
+                    name: entry.file_name().to_string_lossy().to_string(),
+                    path: entry.path(),
+                    is_file: metadata.is_file(),
+                    size: metadata.len(),
+                    modified: metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH),
+                })
+            })
+            .collect()
+    }
+
+    fn open(&self, path: &Path) -> io::Result<Box<dyn Read + Send>> {
+        Ok(Box::new(File::open(path)?))
+    }
+
+    fn write(&self, path: &Path, bytes: &[u8]) -> io::Result<()> {
+        std::fs::write(path, bytes)
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        std::fs::rename(from, to)
+    }
+
+    fn remove(&self, path: &Path) -> io::Result<()> {
+        std::fs::remove_file(path)
+    }
+
+    fn link(&self, from: &Path, to: &Path) -> io::Result<()> {
+        // Hard links are safe to share, because files are always
+        // replaced by renaming, rather than changed in place.
+        if std::fs::hard_link(from, to).is_err() {
+            std::fs::copy(from, to)?;
+        }
+        Ok(())
+    }
+
+    fn create_dir_all(&self, path: &Path) -> io::Result<()> {
+        std::fs::create_dir_all(path)
+    }
+
+    fn is_file(&self, path: &Path) -> bool {
+        path.is_file()
+    }
+
+    fn is_dir(&self, path: &Path) -> bool {
+        path.is_dir()
+    }
+}
+
+
+struct MemFile {
+    bytes: Arc<Vec<u8>>,
+    modified: SystemTime,
+}
+
+#[derive(Default)]
+struct MemState {
+    files: HashMap<PathBuf, MemFile>,
+    dirs: HashSet<PathBuf>,
+}
+
+/// Stores files in memory. Everything is lost when the data service stops.
+/// 
+/// Starts with an empty `DEFAULT_COLLECTION`.
+pub struct MemStorage {
+    state: Mutex<MemState>,
+}
+
+fn not_found(path: &Path) -> io::Error {
+    io::Error::new(io::ErrorKind::NotFound, format!("'{}' does not exist", path.display()))
+}
+
+impl MemStorage {
+    pub fn new() -> MemStorage {
+        let storage = MemStorage { state: Mutex::new(MemState::default()) };
+        let _ = storage.create_dir_all(&config::data_path().join(config::DEFAULT_COLLECTION));
+        storage
+    }
+
+    fn state(&self) -> std::sync::MutexGuard<'_, MemState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl Default for MemStorage {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Storage for MemStorage {
+    fn list(&self, path: &Path) -> io::Result<Vec<Entry>> {
+        let state = self.state();
+        if !state.dirs.contains(path) {
+            return Err(not_found(path));
+        }
+        let name = |p: &Path| p.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default();
+
+        let files = state.files.iter()
+            .filter(|(p, _)| p.parent() == Some(path))
+            .map(|(p, f)| Entry {
+                name: name(p), path: p.clone(), is_file: true,
+                size: f.bytes.len() as u64, modified: f.modified,
+            });
+        let dirs = state.dirs.iter()
+            .filter(|p| p.parent() == Some(path))
+            .map(|p| Entry {
+                name: name(p), path: p.clone(), is_file: false,
+                size: 0, modified: SystemTime::UNIX_EPOCH,
+            });
+        Ok(files.chain(dirs).collect())
+    }
+
+    fn open(&self, path: &Path) -> io::Result<Box<dyn Read + Send>> {
+        match self.state().files.get(path) {
+            Some(f) => Ok(Box::new(Cursor::new(f.bytes.as_ref().clone()))),
+            None => Err(not_found(path)),
+        }
+    }
+
+    fn write(&self, path: &Path, bytes: &[u8]) -> io::Result<()> {
+        let mut state = self.state();
+        if !path.parent().is_some_and(|p| state.dirs.contains(p)) {
+            return Err(not_found(path));
+        }
+        state.files.insert(path.to_path_buf(), MemFile { bytes: Arc::new(bytes.to_vec()), modified: SystemTime::now() });
+        Ok(())
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        let mut state = self.state();
+        if !to.parent().is_some_and(|p| state.dirs.contains(p)) {
+            return Err(not_found(to));
+        }
+        let file = state.files.remove(from).ok_or_else(|| not_found(from))?;
+        state.files.insert(to.to_path_buf(), file);
+        Ok(())
+    }
+
+    fn remove(&self, path: &Path) -> io::Result<()> {
+        self.state().files.remove(path).map(|_| ()).ok_or_else(|| not_found(path))
+    }
+
+    fn link(&self, from: &Path, to: &Path) -> io::Result<()> {
+        let mut state = self.state();
+        if !to.parent().is_some_and(|p| state.dirs.contains(p)) {
+            return Err(not_found(to));
+        }
+        // The contents are never changed in place, so they can be shared.
+        let file = state.files.get(from).ok_or_else(|| not_found(from))?;
+        let linked = MemFile { bytes: Arc::clone(&file.bytes), modified: file.modified };
+        state.files.insert(to.to_path_buf(), linked);
+        Ok(())
+    }
+
+    fn create_dir_all(&self, path: &Path) -> io::Result<()> {
+        let mut state = self.state();
+        for ancestor in path.ancestors().filter(|p| !p.as_os_str().is_empty()) {
+            state.dirs.insert(ancestor.to_path_buf());
+        }
+        Ok(())
+    }
+
+    fn is_file(&self, path: &Path) -> bool {
+        self.state().files.contains_key(path)
+    }
+
+    fn is_dir(&self, path: &Path) -> bool {
+        self.state().dirs.contains(path)
+    }
+}
+
+
+static STORAGE: LazyLock<Box<dyn Storage>> = LazyLock::new(|| {
+    match config::envar_str("ZENITHDS_STORAGE").as_str() {
+        "memory" => Box::new(MemStorage::new()),
+        _ => Box::new(FsStorage),
+    }
+});
+
+/// Returns the storage selected by `ZENITHDS_STORAGE`:
+/// `filesystem` (the default) or `memory`.
+pub fn storage() -> &'static dyn Storage {
+    STORAGE.as_ref()
+}
+
+
+/// Whether a directory `entry` in a collection is a data file.
+/// 
+/// Hidden entries (starting with `.`) are reserved for the data service.
+pub fn is_data_file(entry: &Entry) -> bool {
+    !entry.name.starts_with('.') && entry.is_file
+}
+
+
+/// Lists the data files in the directory at `path`. See `is_data_file`.
+pub fn list_data_files(path: &Path) -> io::Result<Vec<Entry>> {
+    Ok(storage().list(path)?.into_iter().filter(is_data_file).collect())
+}
+
+
+/// Lists the names of the collections, which are the directories in the data path,
+/// and the collections given a directory of their own in `ZENITHDS_COLLECTION_PATHS`.
+pub fn list_collections() -> io::Result<Vec<String>> {
+    let data_path = config::data_path();
+    let mut collections: Vec<String> = match storage().is_dir(&data_path) {
+        true => storage().list(&data_path)?.into_iter()
+            .filter(|entry| !entry.is_file && !entry.name.starts_with('.'))
+            .map(|entry| entry.name)
+            .collect(),
+        false => Vec::new(),
+    };
+    for (collection, path) in config::collection_paths() {
+        if !collections.contains(&collection) && storage().is_dir(&path) {
+            collections.push(collection);
+        }
+    }
+    collections.sort();
+    Ok(collections)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::sync::Once;
+
+    use super::*;
+    use crate::db;
+    use crate::queries::Cancelled;
+    use crate::types::{
+        api::{CreatePayload, QueryPredicates},
+        error::{Missing, ZenithError},
+    };
+
+    static MEMORY: Once = Once::new();
+
+    /// Selects the memory storage, and creates `collection` in it. The storage is selected when it
+    /// is first used, so every test must call this before it uses the storage.
+    fn collection(collection: &str) {
+        MEMORY.call_once(|| {
+            config::set_overrides(HashMap::from([("ZENITHDS_STORAGE".to_string(), "memory".to_string())]));
+        });
+        let path = config::collection_path(collection);
+        storage().create_dir_all(&path).unwrap();
+        assert!(storage().is_dir(&path) && !path.exists(), "The memory storage is not in use");
+    }
+
+    /// Unwraps `result`, failing the test with its error.
+    fn ok<T>(result: Result<T, ZenithError>) -> T {
+        result.unwrap_or_else(|err| panic!("{}", err))
+    }
+
+    fn payload(filename: &str, rows: &[[&str; 2]]) -> CreatePayload {
+        CreatePayload {
+            filename: filename.to_string(),
+            header: vec!["id".to_string(), "name".to_string()],
+            rows: rows.iter().map(|row| row.iter().map(|v| v.to_string()).collect()).collect(),
+            on_conflict: Default::default(),
+            delimiter: None,
+            quote: None,
+            partial: false,
+            keyed: false,
+        }
+    }
+
+    fn select(collection: &str, predicates: &[&str]) -> (Vec<String>, Vec<Vec<String>>) {
+        let predicates = QueryPredicates {
+            fields: Vec::new(),
+            predicates: predicates.iter().map(|p| p.to_string()).collect(),
+        };
+        let (header, mut rows) = ok(db::select(collection, predicates, None, &Cancelled::default()));
+        rows.sort();
+        (header, rows)
+    }
+
+    #[test]
+    fn insert_select_and_delete_in_memory() {
+        collection("mem_crud");
+        ok(db::insert("mem_crud", payload("a.csv", &[["1", "ada"], ["2", "grace"]])));
+        ok(db::insert("mem_crud", payload("b.csv", &[["3", "edsger"]])));
+        assert!(!config::collection_path("mem_crud").exists());
+
+        let (header, rows) = select("mem_crud", &[]);
+        assert_eq!(header, ["id", "name"]);
+        assert_eq!(rows, [["1", "ada"], ["2", "grace"], ["3", "edsger"]]);
+        assert_eq!(select("mem_crud", &["id > 1"]).1, [["2", "grace"], ["3", "edsger"]]);
+
+        ok(db::delete("mem_crud", "a.csv"));
+        assert_eq!(select("mem_crud", &[]).1, [["3", "edsger"]]);
+        ok(db::delete("mem_crud", "b.csv"));
+        assert!(select("mem_crud", &[]).1.is_empty());
+    }
+
+    #[test]
+    fn delete_missing_file_in_memory() {
+        collection("mem_missing");
+        ok(db::insert("mem_missing", payload("a.csv", &[["1", "ada"]])));
+        assert!(matches!(db::delete("mem_missing", "b.csv"), Err(ZenithError::NotFound(Missing::File(..)))));
+        assert_eq!(select("mem_missing", &[]).1, [["1", "ada"]]);
+    }
+}
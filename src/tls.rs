@@ -0,0 +1,136 @@
+use std::{future::Future, io, pin::Pin, sync::Arc};
+use axum_server::{accept::Accept, tls_rustls::{RustlsAcceptor, RustlsConfig}};
+use rustls::{server::WebPkiClientVerifier, RootCertStore, ServerConfig};
+use rustls_pki_types::{
+    pem::PemObject,
+    CertificateDer, PrivateKeyDer,
+};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio_rustls::server::TlsStream;
+use tower_http::add_extension::AddExtension;
+
+use crate::types::error::ZenithError;
+use crate::{auth::Scope, catalog, config};
+
+
+/// Whether the data service terminates TLS itself, which is when
+/// `ZENITHDS_TLS_CERT` and `ZENITHDS_TLS_KEY` are set.
+pub fn enabled() -> bool {
+    !config::envar_str("ZENITHDS_TLS_CERT").is_empty() || !config::envar_str("ZENITHDS_TLS_KEY").is_empty()
+}
+
+
+/// Whether clients can authenticate with a certificate signed by a certificate
+/// authority in `ZENITHDS_TLS_CLIENT_CA`.
+pub fn client_auth_enabled() -> bool {
+    enabled() && !config::envar_str("ZENITHDS_TLS_CLIENT_CA").is_empty()
+}
+
+
+/// The scope of clients that authenticate with a certificate, set in `ZENITHDS_TLS_CLIENT_SCOPE`.
+pub fn client_scope() -> Scope {
+    match config::envar_str("ZENITHDS_TLS_CLIENT_SCOPE").as_str() {
+        "read" => Scope::Read,
+        _ => Scope::Write,
+    }
+}
+
+
+/// Reads the certificates in the PEM file at `path`, of which there must be at least one.
+fn read_certs(path: &str) -> Result<Vec<CertificateDer<'static>>, ZenithError> {
+    let certs = CertificateDer::pem_file_iter(path)
+        .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+        .map_err(|err| ZenithError::TlsError(format!("Could not read certificates from '{}': {}", path, err)))?;
+    if certs.is_empty() {
+        return Err(ZenithError::TlsError(format!("No certificates in '{}'", path)));
+    }
+    Ok(certs)
+}
+
+
+/// Builds the TLS configuration of the server from the certificate chain in the PEM
+/// file at `ZENITHDS_TLS_CERT`, and the private key in the PEM file at `ZENITHDS_TLS_KEY`.
+///
+/// If `ZENITHDS_TLS_CLIENT_CA` is set, clients must give a certificate signed by one of
+/// the certificate authorities in it, unless `ZENITHDS_TLS_CLIENT_OPTIONAL` is set.
+pub fn server_config() -> Result<Arc<ServerConfig>, ZenithError> {
+    let (cert_path, key_path) = (config::envar_str("ZENITHDS_TLS_CERT"), config::envar_str("ZENITHDS_TLS_KEY"));
+    if cert_path.is_empty() || key_path.is_empty() {
+        return Err(ZenithError::TlsError("Both ZENITHDS_TLS_CERT and ZENITHDS_TLS_KEY must be set".to_string()));
+    }
+
+    let certs = read_certs(&cert_path)?;
+    let key = PrivateKeyDer::from_pem_file(&key_path)
+        .map_err(|err| ZenithError::TlsError(format!("Could not read private key from '{}': {}", key_path, err)))?;
+
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    let builder = ServerConfig::builder_with_provider(Arc::clone(&provider))
+        .with_safe_default_protocol_versions()
+        .map_err(|err| ZenithError::TlsError(err.to_string()))?;
+    let builder = match client_auth_enabled() {
+        true => {
+            let ca_path = config::envar_str("ZENITHDS_TLS_CLIENT_CA");
+            let mut roots = RootCertStore::empty();
+            for cert in read_certs(&ca_path)? {
+                roots.add(cert)
+                    .map_err(|err| ZenithError::TlsError(format!("Could not add certificate from '{}': {}", ca_path, err)))?;
+            }
+            let verifier = WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider);
+            let verifier = match config::envar_str("ZENITHDS_TLS_CLIENT_OPTIONAL").is_empty() {
+                true => verifier,
+                false => verifier.allow_unauthenticated(),
+            };
+            let verifier = verifier.build().map_err(|err| ZenithError::TlsError(err.to_string()))?;
+            builder.with_client_cert_verifier(verifier)
+        },
+        false => builder.with_no_client_auth(),
+    };
+    let mut server_config = builder
+        .with_single_cert(certs, key)
+        .map_err(|err| ZenithError::TlsError(err.to_string()))?;
+    server_config.alpn_protocols = vec![b"http/1.1".to_vec()];
+
+    Ok(Arc::new(server_config))
+}
+
+
+/// The certificate a client gave when it connected, which was verified against
+/// `ZENITHDS_TLS_CLIENT_CA`. Added to every request made on the connection.
+#[derive(Clone, Debug)]
+pub struct ClientCertificate {
+    /// The SHA-256 fingerprint of the certificate.
+    pub fingerprint: String,
+}
+
+
+/// Accepts TLS connections, adding the certificate of the client, if it gave one,
+/// to the requests made on the connection.
+#[derive(Clone)]
+pub struct ClientCertAcceptor(RustlsAcceptor);
+
+impl ClientCertAcceptor {
+    pub fn new(config: RustlsConfig) -> Self {
+        ClientCertAcceptor(RustlsAcceptor::new(config))
+    }
+}
+
+impl<I, S> Accept<I, S> for ClientCertAcceptor
+where
+    I: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    S: Send + 'static,
+{
+    type Stream = TlsStream<I>;
+    type Service = AddExtension<S, Option<ClientCertificate>>;
+    type Future = Pin<Box<dyn Future<Output = io::Result<(Self::Stream, Self::Service)>> + Send>>;
+
+    fn accept(&self, stream: I, service: S) -> Self::Future {
+        let accepted = self.0.accept(stream, service);
+        Box::pin(async move {
+            let (stream, service) = accepted.await?;
+            let certificate = stream.get_ref().1.peer_certificates()
+                .and_then(|certs| certs.first())
+                .map(|cert| ClientCertificate { fingerprint: catalog::checksum(cert) });
+            Ok((stream, AddExtension::new(service, certificate)))
+        })
+    }
+}
@@ -0,0 +1,284 @@
+use std::{
+    path::PathBuf,
+    sync::Mutex,
+    time::SystemTime,
+};
+use tracing::{info, warn};
+
+use crate::audit::{self, AuditEntry};
+use crate::auth::{Principal, Scope};
+use crate::changes::Change;
+use crate::queries::Cancelled;
+use crate::storage::storage;
+use crate::types::{
+    api::{CreatePayload, Priority, QueryPredicates, Trigger, TriggerPayload},
+    error::{Missing, ZenithError},
+    query::DataQuery,
+};
+use crate::{changes, config, db, limit, remote, request_id, saved_queries, shutdown};
+
+/// The file in a collection directory holding the triggers on it.
+pub const TRIGGERS_FILENAME: &str = ".triggers.json";
+
+/// The actions that create files in a collection, after which its triggers are fired.
+const INSERT_ACTIONS: [&str; 4] = ["create", "import", "upload", "ingest"];
+
+/// The action recorded for files written by triggers, which do not fire triggers themselves.
+const TRIGGER_ACTION: &str = "trigger";
+
+
+/// Held while the triggers of a collection are changed, so that changes are not lost.
+static TRIGGERS_LOCK: Mutex<()> = Mutex::new(());
+
+
+/// Returns the time now, in milliseconds since the Unix epoch.
+fn now() -> u64 {
+    SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).map(|d| d.as_millis() as u64).unwrap_or(0)
+}
+
+
+fn triggers_path(collection: &str) -> PathBuf {
+    config::collection_path(collection).join(TRIGGERS_FILENAME)
+}
+
+
+fn not_found(collection: &str, id: &str) -> ZenithError {
+    ZenithError::NotFound(Missing::Other(format!("Trigger '{}' does not exist in collection '{}'", id, collection)))
+}
+
+
+fn triggerer() -> Principal {
+    Principal { name: "trigger".to_string(), ..Principal::default() }
+}
+
+
+/// Returns the principal that created `trigger`, which its rows are found as, so that they are
+/// masked as they would be for it.
+fn creator(trigger: &Trigger) -> Principal {
+    Principal { name: trigger.principal.clone(), scope: Scope::Read, roles: trigger.roles.clone() }
+}
+
+
+/// Reads the triggers on `collection`.
+fn read(collection: &str) -> Result<Vec<Trigger>, ZenithError> {
+    let path = triggers_path(collection);
+    if !storage().is_file(&path) {
+        return Ok(Vec::new());
+    }
+    Ok(serde_json::from_slice(&storage().read(&path)?)?)
+}
+
+
+/// Writes the `triggers` on `collection`, replacing those it had, or removes the file if there are none.
+///
+/// The triggers lock must be held while calling this.
+fn write(collection: &str, triggers: &[Trigger]) -> Result<(), ZenithError> {
+    let path = triggers_path(collection);
+    if triggers.is_empty() {
+        return Ok(storage().remove(&path)?);
+    }
+    let temp_path = path.with_extension("json.tmp");
+    storage().write(&temp_path, &serde_json::to_vec_pretty(triggers)?)?;
+    storage().rename(&temp_path, &path)?;
+    Ok(())
+}
+
+
+/// Lists the triggers on `collection`, oldest first.
+pub fn list(collection: &str) -> Result<Vec<Trigger>, ZenithError> {
+    db::validate_name("collection", collection)?;
+    read(collection)
+}
+
+
+/// Creates the trigger in `payload` on `collection` for `principal`, returning it. Its rows are
+/// those of each file created that match its `fields` and `predicates`, or those found by its
+/// saved `query`, masked by the roles of the principal, and are written to its `target` collection or sent to its `url`. Raises a `QueryError` if
+/// not exactly one of a target and a URL is given, the target is the collection itself, the
+/// predicates cannot be parsed, or the query is given predicates or cannot be given its
+/// parameters, and a `NotFound` error if a collection or the query does not exist.
+pub fn create(collection: &str, payload: TriggerPayload, principal: &Principal) -> Result<Trigger, ZenithError> {
+    db::validate_name("collection", collection)?;
+    if !storage().is_dir(&config::collection_path(collection)) {
+        return Err(ZenithError::NotFound(Missing::Collection(collection.to_string())));
+    }
+    match &payload.query {
+        Some(_) if !payload.fields.is_empty() || !payload.predicates.is_empty() => {
+            return Err(ZenithError::QueryError("A trigger that runs a query finds its rows with the query, not with fields or predicates".to_string()));
+        },
+        Some(query) => {
+            saved_queries::bind(query, &payload.parameters)?;
+        },
+        None if !payload.parameters.is_empty() => {
+            return Err(ZenithError::QueryError("A trigger can only be given parameters for its query".to_string()));
+        },
+        None => {
+            DataQuery::new(payload.fields.clone(), payload.predicates.clone())?;
+        },
+    }
+    match (&payload.target, &payload.url) {
+        (Some(target), None) => {
+            db::validate_name("collection", target)?;
+            if target == collection {
+                return Err(ZenithError::QueryError("A trigger cannot write rows to the collection it is on".to_string()));
+            }
+            if !storage().is_dir(&config::collection_path(target)) {
+                return Err(ZenithError::NotFound(Missing::Collection(target.clone())));
+            }
+        },
+        (None, Some(url)) => {
+            remote::parse_url(url)?;
+        },
+        _ => return Err(ZenithError::QueryError("A trigger needs either a target collection or a URL to send its rows to".to_string())),
+    }
+
+    let trigger = Trigger {
+        trigger_id: request_id::generate(),
+        fields: payload.fields,
+        predicates: payload.predicates,
+        query: payload.query,
+        parameters: payload.parameters,
+        target: payload.target,
+        url: payload.url,
+        principal: principal.name.clone(),
+        roles: principal.roles.clone(),
+        created: now(),
+        last_fired: None,
+        last_filename: None,
+        last_rows: None,
+        last_error: None,
+    };
+    let _guard = TRIGGERS_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let mut triggers = read(collection)?;
+    triggers.push(trigger.clone());
+    write(collection, &triggers)?;
+    Ok(trigger)
+}
+
+
+/// Removes the trigger with `id` from `collection`, returning it. Raises a `NotFound` error if it does not exist.
+pub fn remove(collection: &str, id: &str) -> Result<Trigger, ZenithError> {
+    db::validate_name("collection", collection)?;
+    let _guard = TRIGGERS_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let mut triggers = read(collection)?;
+    let position = triggers.iter().position(|t| t.trigger_id == id).ok_or_else(|| not_found(collection, id))?;
+    let trigger = triggers.remove(position);
+    write(collection, &triggers)?;
+    Ok(trigger)
+}
+
+
+/// Records that the trigger with `id` on `collection` was fired by `filename`, finding the number
+/// of rows in `result`, or failing with its error. Triggers removed while they ran are not recorded.
+fn record(collection: &str, id: &str, filename: &str, result: &Result<usize, ZenithError>) {
+    let _guard = TRIGGERS_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let recorded = read(collection).and_then(|mut triggers| {
+        if let Some(trigger) = triggers.iter_mut().find(|t| t.trigger_id == id) {
+            trigger.last_fired = Some(now());
+            trigger.last_filename = Some(filename.to_string());
+            (trigger.last_rows, trigger.last_error) = match result {
+                Ok(rows) => (Some(*rows), None),
+                Err(err) => (None, Some(err.to_string())),
+            };
+        }
+        write(collection, &triggers)
+    });
+    if let Err(err) = recorded {
+        warn!("Could not record the firing of trigger '{}' on collection '{}': {}", id, collection, err);
+    }
+}
+
+
+/// Fires each trigger on the collection of `change`, if it created a file, in the background,
+/// at batch priority. The change has already been made, so failures are only reported and
+/// recorded with the trigger. Files written by triggers do not fire triggers, so that they cannot loop.
+pub fn fire(change: &Change) {
+    let Some(filename) = &change.filename else {
+        return;
+    };
+    if !INSERT_ACTIONS.contains(&change.action.as_str()) || change.rows.is_none() {
+        return;
+    }
+    let triggers = match read(&change.collection) {
+        Ok(triggers) => triggers,
+        Err(err) => {
+            warn!("Could not read the triggers of collection '{}': {}", change.collection, err);
+            return;
+        }
+    };
+    for trigger in triggers {
+        let (collection, filename) = (change.collection.clone(), filename.clone());
+        shutdown::spawn(request_id::inherit(async move {
+            let result = execute(&collection, &filename, &trigger).await;
+            match &result {
+                Ok(rows) => info!("Fired trigger '{}' on collection '{}' for '{}', finding {} rows", trigger.trigger_id, collection, filename, rows),
+                Err(err) => warn!("Could not fire trigger '{}' on collection '{}' for '{}': {}", trigger.trigger_id, collection, filename, err),
+            }
+            record(&collection, &trigger.trigger_id, &filename, &result);
+        }));
+    }
+}
+
+
+/// Finds the rows of `trigger` for the file `filename` created in `collection`, and writes them to
+/// its target, as a file of the same name, or named after its query, or sends them to its URL, as
+/// JSON with the `trigger_id`, `collection`, `filename`, `query`, `header`, and `rows`. Nothing is
+/// written or sent if no rows are found. The rows are found as the principal that created the
+/// trigger. Returns the number of rows found.
+async fn execute(collection: &str, filename: &str, trigger: &Trigger) -> Result<usize, ZenithError> {
+    let _slot = limit::SlotGuard::wait(Priority::Batch).await;
+    let principal = creator(trigger);
+    let (header, rows) = match &trigger.query {
+        Some(query) => {
+            let (collection, predicates) = saved_queries::bind(query, &trigger.parameters)?;
+            crate::run_query(&collection, predicates, principal, &Cancelled::default(), false).await?.0
+        },
+        None => {
+            let predicates = QueryPredicates { fields: trigger.fields.clone(), predicates: trigger.predicates.clone() };
+            let (collection, filename) = (collection.to_string(), filename.to_string());
+            request_id::spawn_blocking(move || db::select_file(&collection, &filename, predicates, Some(&principal), &Cancelled::default())).await?
+        },
+    };
+    let found = rows.len();
+    if found == 0 {
+        return Ok(0);
+    }
+
+    if let Some(target) = &trigger.target {
+        let payload = CreatePayload {
+            filename: trigger.query.as_ref().map_or_else(|| filename.to_string(), |query| format!("{}.csv", query)),
+            header,
+            rows,
+            on_conflict: Default::default(),
+            delimiter: None,
+            quote: None,
+            partial: false,
+            keyed: false,
+        };
+        let (target, target_filename) = (target.clone(), payload.filename.clone());
+        let previous_rows = db::file_rows(&target, &target_filename).ok().flatten();
+        let contents = changes::watched().then(|| (payload.header.clone(), payload.rows.clone()));
+        request_id::spawn_blocking({
+            let target = target.clone();
+            move || db::insert(&target, payload)
+        }).await?;
+        let entry = AuditEntry::new(&triggerer(), TRIGGER_ACTION, &target)
+            .filename(&target_filename)
+            .rows(previous_rows, Some(found))
+            .detail(format!("trigger '{}' on collection '{}' for '{}'", trigger.trigger_id, collection, filename));
+        changes::publish(&entry, contents);
+        audit::record(entry);
+    }
+    else if let Some(url) = &trigger.url {
+        let body = serde_json::json!({
+            "trigger_id": trigger.trigger_id,
+            "collection": collection,
+            "filename": filename,
+            "query": trigger.query,
+            "header": header,
+            "rows": rows,
+        });
+        remote::post_json(url, &body).await?;
+    }
+    Ok(found)
+}
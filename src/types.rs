@@ -5,18 +5,106 @@ pub mod error {
         response::{Response, IntoResponse},
     };
     use serde::Serialize;
+    use serde_json::{json, Value};
+    use tracing::error;
 
     pub enum ZenithError {
         FileSystemError(std::io::Error),
         RegexError(regex::Error),
         CSVError(csv::Error),
+        JSONError(serde_json::Error),
         PredicateError(String),
         QueryError(String),
+        EncryptionError(String),
+        IntegrityError(String),
+        RemoteError(String),
+        Unauthorized(String),
+        Forbidden(String),
+        TooManyRequests(String),
+        TlsError(String),
+        InsufficientStorage(String),
+        ResultTooLarge(String),
+        ServiceUnavailable(String),
+        Cancelled(String),
+        UnsupportedMediaType(String),
+        /// The header of a file is not the header `expected` by the collection.
+        HeaderMismatch { collection: String, expected: Vec<String>, found: Vec<String> },
+        NotFound(Missing),
+        /// The request cannot be done in the current state of what it names, such as
+        /// rows with keys that are already in the collection, or a job that is not done.
+        Conflict(String),
+        PayloadTooLarge(String),
         // more error types here as needed
     }
 
+    /// What was not found, so that the response can say which collection or file it was.
+    #[derive(Debug)]
+    pub enum Missing {
+        Collection(String),
+        /// A file, by its collection and name.
+        File(String, String),
+        /// Anything else, such as a version, job, or webhook, described in full.
+        Other(String),
+    }
+
+    impl std::fmt::Display for Missing {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            match self {
+                Missing::Collection(collection) => write!(f, "Collection '{}' does not exist", collection),
+                Missing::File(collection, filename) => write!(f, "File '{}' does not exist in collection '{}'", filename, collection),
+                Missing::Other(description) => write!(f, "{}", description),
+            }
+        }
+    }
+
+    impl ZenithError {
+        /// Returns the code of the error, which does not change between versions, so that
+        /// clients can tell errors apart without reading their messages.
+        pub fn code(&self) -> &'static str {
+            match self {
+                ZenithError::NotFound(Missing::Collection(_)) => "COLLECTION_NOT_FOUND",
+                ZenithError::NotFound(Missing::File(..)) => "FILE_NOT_FOUND",
+                ZenithError::NotFound(Missing::Other(_)) => "NOT_FOUND",
+                ZenithError::FileSystemError(error) if error.kind() == std::io::ErrorKind::NotFound => "NOT_FOUND",
+                ZenithError::FileSystemError(error) if error.kind() == std::io::ErrorKind::StorageFull => "INSUFFICIENT_STORAGE",
+                ZenithError::FileSystemError(_) | ZenithError::RegexError(_) | ZenithError::CSVError(_)
+                    | ZenithError::JSONError(_) | ZenithError::EncryptionError(_) | ZenithError::IntegrityError(_)
+                    | ZenithError::TlsError(_) => "INTERNAL_ERROR",
+                ZenithError::PredicateError(_) => "PREDICATE_PARSE",
+                ZenithError::QueryError(_) => "INVALID_REQUEST",
+                ZenithError::HeaderMismatch { .. } => "HEADER_MISMATCH",
+                ZenithError::RemoteError(_) => "REMOTE_ERROR",
+                ZenithError::Unauthorized(_) => "UNAUTHORIZED",
+                ZenithError::Forbidden(_) => "FORBIDDEN",
+                ZenithError::TooManyRequests(_) => "TOO_MANY_REQUESTS",
+                ZenithError::InsufficientStorage(_) => "INSUFFICIENT_STORAGE",
+                ZenithError::ResultTooLarge(_) => "RESULT_TOO_LARGE",
+                ZenithError::ServiceUnavailable(_) => "SERVICE_UNAVAILABLE",
+                ZenithError::Cancelled(_) => "CANCELLED",
+                ZenithError::UnsupportedMediaType(_) => "UNSUPPORTED_MEDIA_TYPE",
+                ZenithError::Conflict(_) => "CONFLICT",
+                ZenithError::PayloadTooLarge(_) => "PAYLOAD_TOO_LARGE",
+            }
+        }
+
+        /// Returns what the error is about, for errors that have more to say than their message,
+        /// such as the `collection` and `filename` that were not found.
+        pub fn details(&self) -> Option<Value> {
+            match self {
+                ZenithError::NotFound(Missing::Collection(collection)) => Some(json!({ "collection": collection })),
+                ZenithError::NotFound(Missing::File(collection, filename)) => {
+                    Some(json!({ "collection": collection, "filename": filename }))
+                },
+                ZenithError::HeaderMismatch { collection, expected, found } => {
+                    Some(json!({ "collection": collection, "expected": expected, "found": found }))
+                },
+                _ => None,
+            }
+        }
+    }
+
     fn server_error(error: ZenithError) -> (StatusCode, String) {
-        eprintln!("Logging: {error}");
+        error!("{error}");
         (
             StatusCode::INTERNAL_SERVER_ERROR,
             "Something went wrong".to_owned()
@@ -29,12 +117,48 @@ pub mod error {
             #[derive(Serialize)]
             struct ErrorResponse {
                 message: String,
+                /// The code of the error. See `ZenithError::code`.
+                code: &'static str,
+                #[serde(skip_serializing_if = "Option::is_none")]
+                details: Option<Value>,
+                /// The id of the request, to find it in the logs.
+                #[serde(skip_serializing_if = "Option::is_none")]
+                request_id: Option<String>,
             }
     
+            let (code, details) = (self.code(), self.details());
             let (status, message) = match self {
+                ZenithError::FileSystemError(error) if error.kind() == std::io::ErrorKind::StorageFull => {
+                    (
+                        StatusCode::INSUFFICIENT_STORAGE,
+                        format!("Insufficient storage: {error}")
+                    )
+                },
+                ZenithError::NotFound(missing @ (Missing::Collection(_) | Missing::File(..))) => {
+                    (
+                        StatusCode::NOT_FOUND,
+                        format!("Collection or file not found: {missing}")
+                    )
+                },
+                ZenithError::NotFound(missing) => {
+                    (
+                        StatusCode::NOT_FOUND,
+                        format!("Not found: {missing}")
+                    )
+                },
+                ZenithError::FileSystemError(error) if error.kind() == std::io::ErrorKind::NotFound => {
+                    (
+                        StatusCode::NOT_FOUND,
+                        format!("Not found: {error}")
+                    )
+                },
                 ZenithError::FileSystemError(error) => server_error(error.into()),
                 ZenithError::RegexError(error) => server_error(error.into()),
                 ZenithError::CSVError(error) => server_error(error.into()),
+                ZenithError::JSONError(error) => server_error(error.into()),
+                ZenithError::EncryptionError(error) => server_error(ZenithError::EncryptionError(error)),
+                ZenithError::IntegrityError(error) => server_error(ZenithError::IntegrityError(error)),
+                ZenithError::TlsError(error) => server_error(ZenithError::TlsError(error)),
                 ZenithError::PredicateError(error) => {
                     (
                         StatusCode::UNPROCESSABLE_ENTITY,
@@ -47,11 +171,85 @@ pub mod error {
                         format!("Incorrect header, rows, or query body: {error}")
                     )
                 },
+                ZenithError::RemoteError(error) => {
+                    (
+                        StatusCode::BAD_GATEWAY,
+                        format!("Remote request failed: {error}")
+                    )
+                },
+                ZenithError::Unauthorized(error) => {
+                    (
+                        StatusCode::UNAUTHORIZED,
+                        format!("Unauthorized: {error}")
+                    )
+                },
+                ZenithError::Forbidden(error) => {
+                    (
+                        StatusCode::FORBIDDEN,
+                        format!("Forbidden: {error}")
+                    )
+                },
+                ZenithError::TooManyRequests(error) => {
+                    (
+                        StatusCode::TOO_MANY_REQUESTS,
+                        format!("Too many requests: {error}")
+                    )
+                },
+                ZenithError::InsufficientStorage(error) => {
+                    (
+                        StatusCode::INSUFFICIENT_STORAGE,
+                        format!("Insufficient storage: {error}")
+                    )
+                },
+                ZenithError::ResultTooLarge(error) => {
+                    (
+                        StatusCode::UNPROCESSABLE_ENTITY,
+                        format!("Result too large: {error}")
+                    )
+                },
+                ZenithError::ServiceUnavailable(error) => {
+                    (
+                        StatusCode::SERVICE_UNAVAILABLE,
+                        format!("Service unavailable: {error}")
+                    )
+                },
+                ZenithError::Cancelled(error) => {
+                    (
+                        // As used by nginx for requests the client closed.
+                        StatusCode::from_u16(499).unwrap_or(StatusCode::BAD_REQUEST),
+                        format!("Cancelled: {error}")
+                    )
+                },
+                ZenithError::UnsupportedMediaType(error) => {
+                    (
+                        StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                        format!("Unsupported media type: {error}")
+                    )
+                },
+                error @ ZenithError::HeaderMismatch { .. } => {
+                    (
+                        StatusCode::UNPROCESSABLE_ENTITY,
+                        format!("Incorrect header, rows, or query body: {error}")
+                    )
+                },
+                ZenithError::Conflict(error) => {
+                    (
+                        StatusCode::CONFLICT,
+                        format!("Conflict: {error}")
+                    )
+                },
+                ZenithError::PayloadTooLarge(error) => {
+                    (
+                        StatusCode::PAYLOAD_TOO_LARGE,
+                        format!("Payload too large: {error}")
+                    )
+                },
                 // Handle more errors here as needed
                 // Client errors return more specific messages
             };
             
-            (status, Json(ErrorResponse { message })).into_response()
+            let request_id = crate::request_id::current();
+            (status, Json(ErrorResponse { message, code, details, request_id })).into_response()
         }
     }
 
@@ -61,8 +259,27 @@ pub mod error {
                 ZenithError::FileSystemError(error) => write!(f, "File system IO error: {}", error),
                 ZenithError::RegexError(error) => write!(f, "Regex error: {}", error),
                 ZenithError::CSVError(error) => write!(f, "CSV read or write error: {}", error),
+                ZenithError::JSONError(error) => write!(f, "JSON read or write error: {}", error),
                 ZenithError::PredicateError(error) => write!(f, "Predicate error: {}", error),
                 ZenithError::QueryError(error) => write!(f, "Query error: {}", error),
+                ZenithError::EncryptionError(error) => write!(f, "Encryption error: {}", error),
+                ZenithError::IntegrityError(error) => write!(f, "Integrity error: {}", error),
+                ZenithError::RemoteError(error) => write!(f, "Remote error: {}", error),
+                ZenithError::Unauthorized(error) => write!(f, "Unauthorized: {}", error),
+                ZenithError::Forbidden(error) => write!(f, "Forbidden: {}", error),
+                ZenithError::TooManyRequests(error) => write!(f, "Too many requests: {}", error),
+                ZenithError::TlsError(error) => write!(f, "TLS error: {}", error),
+                ZenithError::InsufficientStorage(error) => write!(f, "Insufficient storage: {}", error),
+                ZenithError::ResultTooLarge(error) => write!(f, "Result too large: {}", error),
+                ZenithError::ServiceUnavailable(error) => write!(f, "Service unavailable: {}", error),
+                ZenithError::Cancelled(error) => write!(f, "Cancelled: {}", error),
+                ZenithError::UnsupportedMediaType(error) => write!(f, "Unsupported media type: {}", error),
+                ZenithError::HeaderMismatch { collection, found, .. } => {
+                    write!(f, "Header {:?} does not match header in collection '{}'", found, collection)
+                },
+                ZenithError::NotFound(missing) => write!(f, "Not found: {}", missing),
+                ZenithError::Conflict(error) => write!(f, "Conflict: {}", error),
+                ZenithError::PayloadTooLarge(error) => write!(f, "Payload too large: {}", error),
             }
         }
     }
@@ -76,14 +293,18 @@ pub mod error {
     impl From::<csv::Error> for ZenithError {
         fn from(error: csv::Error) -> Self { Self::CSVError(error) }
     }
+    impl From::<serde_json::Error> for ZenithError {
+        fn from(error: serde_json::Error) -> Self { Self::JSONError(error) }
+    }
 }
 
 
 pub mod query {
-    use std::path::PathBuf;
+    use std::{collections::HashMap, path::PathBuf, time::SystemTime};
     use serde::{Deserialize, Serialize};
     use regex::Regex;
     use super::error::ZenithError;
+    use crate::schema::{ColumnType, MaskAction, Schema};
 
     /// Operations on a query predicate.
     #[derive(Deserialize, Debug)]
@@ -108,6 +329,9 @@ pub mod query {
         pub field: String,
         op: PredOp,
         value: String,
+        /// How values are compared, given by the schema of the collection.
+        #[serde(default)]
+        column_type: ColumnType,
         // No logical operators for now. Just assume
         // that multiple predicates are joined with AND.
         // logical_op: Option<LogicalOperator>
@@ -119,6 +343,17 @@ pub mod query {
         pub collection: String,
         pub filepath: PathBuf,
         pub size: u64,
+        pub modified: SystemTime,
+    }
+
+    /// The result of checking a file against its recorded checksum.
+    #[derive(Serialize, Debug, PartialEq)]
+    #[serde(rename_all = "lowercase")]
+    pub enum IntegrityStatus {
+        Ok,
+        Modified,
+        Missing,
+        Untracked,
     }
 
     /// A convenient way to group header and records. Can be removed later.
@@ -126,23 +361,52 @@ pub mod query {
     pub struct CSVData {
         pub header: Vec<String>,
         pub records: Vec<Vec<String>>,
+        /// The rows read after the header, whether or not they were returned in `records`.
+        #[serde(default)]
+        pub rows_read: usize,
+    }
+
+    impl PredOp {
+        /// The operator as it is written in a predicate.
+        pub fn symbol(&self) -> &'static str {
+            match self {
+                PredOp::EQ => "==",
+                PredOp::NE => "!=",
+                PredOp::LT => "<",
+                PredOp::GT => ">",
+                PredOp::LE => "<=",
+                PredOp::GE => ">=",
+                PredOp::CONTAINS => "CONTAINS",
+            }
+        }
     }
 
     impl Predicate {
         pub fn new(field: String, op: PredOp, value: String) -> Predicate {
-            Predicate { field, op, value }
+            Predicate { field, op, value, column_type: ColumnType::String }
         }
 
-        pub fn satisfied_by(&self, value: &String) -> bool {
-            // Do we need to do some parsing to see if we can do int and
-            // float comparisons? Or it is alright to leave them as strings?
+        /// Describes the predicate as it was parsed, and how its values are compared.
+        pub fn explain(&self) -> super::api::ExplainedPredicate {
+            super::api::ExplainedPredicate {
+                field: self.field.clone(),
+                op: self.op.symbol().to_string(),
+                value: self.value.clone(),
+                column_type: self.column_type,
+            }
+        }
+
+        /// Checks if `value` satisfies the predicate. Values are compared as strings,
+        /// unless the predicate has been given a type by `DataQuery::apply_schema`.
+        pub fn satisfied_by(&self, value: &str) -> bool {
+            let ordering = || self.column_type.compare(value, &self.value);
             match self.op {
-                PredOp::EQ => *value == self.value,
-                PredOp::NE => *value != self.value,
-                PredOp::LT => *value < self.value,
-                PredOp::GT => *value > self.value,
-                PredOp::LE => *value <= self.value,
-                PredOp::GE => *value >= self.value,
+                PredOp::EQ => ordering().is_eq(),
+                PredOp::NE => ordering().is_ne(),
+                PredOp::LT => ordering().is_lt(),
+                PredOp::GT => ordering().is_gt(),
+                PredOp::LE => ordering().is_le(),
+                PredOp::GE => ordering().is_ge(),
                 PredOp::CONTAINS => value.contains(&self.value),
             }
         }
@@ -153,6 +417,8 @@ pub mod query {
         pub fields: Vec<String>,
         pub predicates: Vec<Predicate>,
         pub filename_regex_predicates: Vec<Predicate>,
+        /// The masked columns the query cannot see, and how they are masked.
+        pub masks: HashMap<String, MaskAction>,
     }
 
     impl DataQuery {
@@ -206,7 +472,17 @@ pub mod query {
                 }
             }
 
-            Ok(DataQuery { fields, predicates, filename_regex_predicates })
+            Ok(DataQuery { fields, predicates, filename_regex_predicates, masks: HashMap::new() })
+        }
+
+        /// Types the row predicates by the columns they refer to in the `schema`,
+        /// so that values are compared as numbers, booleans, or dates where given.
+        pub fn apply_schema(&mut self, schema: &Schema) {
+            for predicate in self.predicates.iter_mut() {
+                if let Some(column) = schema.columns.iter().find(|c| c.name == predicate.field) {
+                    predicate.column_type = column.column_type;
+                }
+            }
         }
     }
 }
@@ -215,37 +491,1109 @@ pub mod query {
 pub mod api {
     use serde::{Deserialize, Serialize};
 
-    #[derive(Deserialize)]
+    /// What to do with rows whose key is already in another file of the collection.
+    #[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq, Default)]
+    #[serde(rename_all = "lowercase")]
+    pub enum OnConflict {
+        #[default]
+        Reject,
+        Upsert,
+    }
+
+    /// A character that separates or quotes the values of a CSV, given as the character itself.
+    /// The separators `comma`, `tab`, `semicolon`, and `pipe` can also be given by name.
+    #[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq)]
+    #[serde(try_from = "String", into = "String")]
+    pub struct CsvCharacter(pub u8);
+
+    impl TryFrom<String> for CsvCharacter {
+        type Error = String;
+
+        fn try_from(value: String) -> Result<Self, Self::Error> {
+            match value.as_str() {
+                "comma" => Ok(CsvCharacter(b',')),
+                "tab" => Ok(CsvCharacter(b'\t')),
+                "semicolon" => Ok(CsvCharacter(b';')),
+                "pipe" => Ok(CsvCharacter(b'|')),
+                _ => match value.as_bytes() {
+                    [c] if c.is_ascii() && !matches!(c, b'\n' | b'\r') => Ok(CsvCharacter(*c)),
+                    _ => Err(format!("'{}' is not a single character, or one of comma, tab, semicolon, or pipe", value)),
+                },
+            }
+        }
+    }
+
+    impl From<CsvCharacter> for String {
+        fn from(c: CsvCharacter) -> Self {
+            (c.0 as char).to_string()
+        }
+    }
+
+    /// How the values of the CSVs in a collection are separated and quoted.
+    #[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq)]
+    #[serde(default)]
+    pub struct Dialect {
+        pub delimiter: CsvCharacter,
+        pub quote: CsvCharacter,
+    }
+
+    impl Default for Dialect {
+        fn default() -> Self {
+            Dialect { delimiter: CsvCharacter(b','), quote: CsvCharacter(b'"') }
+        }
+    }
+
+    impl Dialect {
+        pub fn is_default(&self) -> bool {
+            *self == Dialect::default()
+        }
+
+        /// Returns the dialect with the `delimiter` and `quote` given in place of its own.
+        pub fn with(self, delimiter: Option<CsvCharacter>, quote: Option<CsvCharacter>) -> Dialect {
+            Dialect { delimiter: delimiter.unwrap_or(self.delimiter), quote: quote.unwrap_or(self.quote) }
+        }
+
+        pub fn reader(&self) -> csv::ReaderBuilder {
+            let mut builder = csv::ReaderBuilder::new();
+            builder.delimiter(self.delimiter.0).quote(self.quote.0);
+            builder
+        }
+
+        pub fn writer(&self) -> csv::WriterBuilder {
+            let mut builder = csv::WriterBuilder::new();
+            builder.delimiter(self.delimiter.0).quote(self.quote.0);
+            builder
+        }
+    }
+
+    #[derive(Deserialize, Serialize, Clone)]
+    #[serde(try_from = "CreateBody")]
     pub struct CreatePayload {
         pub filename: String,
         pub header: Vec<String>,
         pub rows: Vec<Vec<String>>,
+        #[serde(default)]
+        pub on_conflict: OnConflict,
+        /// The delimiter and quote of the collection, if it has no files yet. Files are
+        /// otherwise written with those the collection already has. See `Dialect`.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub delimiter: Option<CsvCharacter>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub quote: Option<CsvCharacter>,
+        /// Whether rows with problems are left out and reported, instead of failing the whole payload.
+        #[serde(skip_serializing_if = "std::ops::Not::not")]
+        pub partial: bool,
+        /// Whether the rows were given as objects keyed by column name, so that
+        /// their columns can be put in the order of the collection.
+        #[serde(skip)]
+        pub keyed: bool,
     }
 
+    /// A create request as it is sent, with rows given either as arrays of values in
+    /// the order of the header, or as objects keyed by column name.
     #[derive(Deserialize)]
+    struct CreateBody {
+        filename: String,
+        #[serde(default)]
+        header: Vec<String>,
+        rows: Vec<serde_json::Value>,
+        #[serde(default)]
+        on_conflict: OnConflict,
+        delimiter: Option<CsvCharacter>,
+        quote: Option<CsvCharacter>,
+        #[serde(default)]
+        partial: bool,
+    }
+
+    impl TryFrom<CreateBody> for CreatePayload {
+        type Error = String;
+
+        /// Turns rows given as objects into arrays of values in the order of the header. If no
+        /// header is given, it is made of the keys of the objects in the order they first appear.
+        /// Keys missing from an object are given empty values, and `null` is the empty value.
+        fn try_from(body: CreateBody) -> Result<Self, Self::Error> {
+            let CreateBody { filename, mut header, rows, on_conflict, delimiter, quote, partial } = body;
+            let keyed = rows.first().is_some_and(|row| row.is_object());
+            if !keyed {
+                let rows = serde_json::from_value(serde_json::Value::Array(rows)).map_err(|e| e.to_string())?;
+                return Ok(CreatePayload { filename, header, rows, on_conflict, delimiter, quote, partial, keyed });
+            }
+
+            let mut objects = Vec::new();
+            for (i, row) in rows.into_iter().enumerate() {
+                match row {
+                    serde_json::Value::Object(object) => objects.push(object),
+                    _ => return Err(format!("Row {} is not an object like the first row", i)),
+                }
+            }
+            let derived = header.is_empty();
+            if derived {
+                for key in objects.iter().flat_map(|object| object.keys()) {
+                    if !header.contains(key) {
+                        header.push(key.clone());
+                    }
+                }
+            }
+            let mut values = Vec::new();
+            for (i, object) in objects.into_iter().enumerate() {
+                if let Some(key) = object.keys().find(|key| !header.contains(key)) {
+                    return Err(format!("Row {} has field '{}', which is not in the header", i, key));
+                }
+                let mut row = vec![String::new(); header.len()];
+                for (key, value) in object {
+                    let position = header.iter().position(|h| *h == key).unwrap_or_default();
+                    row[position] = match value {
+                        serde_json::Value::String(s) => s,
+                        serde_json::Value::Null => String::new(),
+                        serde_json::Value::Bool(_) | serde_json::Value::Number(_) => value.to_string(),
+                        _ => return Err(format!("Row {} has a value for '{}' that is not a string, number, boolean, or null", i, key)),
+                    };
+                }
+                values.push(row);
+            }
+            Ok(CreatePayload { filename, header, rows: values, on_conflict, delimiter, quote, partial, keyed })
+        }
+    }
+
+    #[derive(Deserialize)]
+    pub struct ImportPayload {
+        pub url: String,
+        pub filename: Option<String>,
+        #[serde(default)]
+        pub on_conflict: OnConflict,
+        /// The sheet to import, if the file is a workbook. The first sheet is imported if none is given.
+        pub sheet: Option<String>,
+        /// The delimiter and quote of the file, if they are not those of the collection.
+        pub delimiter: Option<CsvCharacter>,
+        pub quote: Option<CsvCharacter>,
+    }
+
+    #[derive(Deserialize)]
+    pub struct UploadParameters {
+        #[serde(default)]
+        pub on_conflict: OnConflict,
+        pub sheet: Option<String>,
+        pub delimiter: Option<CsvCharacter>,
+        pub quote: Option<CsvCharacter>,
+    }
+
+    #[derive(Deserialize)]
+    pub struct RenderParameters {
+        pub delimiter: Option<CsvCharacter>,
+        pub quote: Option<CsvCharacter>,
+        #[serde(default)]
+        pub skip_rows: usize,
+        pub header_row: Option<usize>,
+        pub max_rows: Option<usize>,
+    }
+
+    /// Where the header and rows of a CSV are found when it is rendered. See `db::render_with`.
+    #[derive(Clone, Copy, Debug, Default)]
+    pub struct RenderOptions {
+        /// The number of lines skipped before the first record, such as a preamble.
+        pub skip_rows: usize,
+        /// The record taken as the header, counting from 0 after the skipped lines.
+        pub header_row: Option<usize>,
+        pub max_rows: Option<usize>,
+    }
+
+    #[derive(Serialize)]
+    pub struct ImportResponse {
+        pub filename: String,
+        pub rows: usize,
+        pub removed: usize,
+    }
+
+    /// How a file is exported.
+    #[derive(Deserialize, Clone, Copy, Debug, PartialEq, Default)]
+    #[serde(rename_all = "lowercase")]
+    pub enum ExportFormat {
+        #[default]
+        Csv,
+        /// An Avro object container file, with a schema from the schema of the collection.
+        Avro,
+    }
+
+    #[derive(Deserialize)]
+    pub struct ExportParameters {
+        pub escape_formulas: Option<bool>,
+        pub format: Option<ExportFormat>,
+    }
+
+    #[derive(Deserialize)]
+    pub struct InferParameters {
+        pub save: Option<bool>,
+    }
+
+    #[derive(Deserialize)]
+    pub struct ChangesParameters {
+        /// Whether to send the header and rows of files that are created.
+        pub rows: Option<bool>,
+    }
+
+    #[derive(Deserialize)]
+    pub struct AuditParameters {
+        pub collection: Option<String>,
+        pub filename: Option<String>,
+        pub principal: Option<String>,
+        pub action: Option<String>,
+        /// Only entries at or after this time, in milliseconds since the Unix epoch.
+        pub since: Option<u64>,
+        pub limit: Option<usize>,
+    }
+
+    #[derive(Deserialize)]
+    pub struct ChangeLogParameters {
+        /// Only changes after this offset.
+        pub since: Option<u64>,
+        pub collection: Option<String>,
+        pub limit: Option<usize>,
+    }
+
+    /// Which queries run first when the data service is busy.
+    #[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq, Default)]
+    #[serde(rename_all = "lowercase")]
+    pub enum Priority {
+        /// Queries that someone is waiting on, such as those from dashboards.
+        #[default]
+        Interactive,
+        /// Long queries, such as exports, that can wait for interactive ones.
+        Batch,
+    }
+
+    /// How far a query job has got.
+    #[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq)]
+    #[serde(rename_all = "lowercase")]
+    pub enum JobState {
+        Queued,
+        Running,
+        Done,
+        Failed,
+        Cancelled,
+    }
+
+    /// A webhook registered on a collection, which is sent a signed `POST` for each change to its files.
+    #[derive(Deserialize, Serialize, Clone, Debug)]
+    pub struct Webhook {
+        pub webhook_id: String,
+        pub url: String,
+        /// The actions it is sent, such as `create` and `delete`, or every action if it is empty.
+        #[serde(default)]
+        pub actions: Vec<String>,
+        /// The key its requests are signed with, which is only returned when it is registered.
+        #[serde(default, skip_serializing_if = "String::is_empty")]
+        pub secret: String,
+        /// When it was registered, in milliseconds since the Unix epoch.
+        pub created: u64,
+    }
+
+    #[derive(Deserialize)]
+    pub struct WebhookPayload {
+        pub url: String,
+        #[serde(default)]
+        pub actions: Vec<String>,
+        /// The key to sign requests with. A new one is made if it is not given.
+        pub secret: Option<String>,
+    }
+
+    #[derive(Serialize)]
+    pub struct WebhooksResponse {
+        pub webhooks: Vec<Webhook>,
+    }
+
+    /// How far the delivery of a change to a webhook has got.
+    #[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq)]
+    #[serde(rename_all = "lowercase")]
+    pub enum DeliveryState {
+        /// Not yet delivered, but will be tried again.
+        Pending,
+        Delivered,
+        /// Not delivered after every retry.
+        Failed,
+    }
+
+    /// The delivery of a change to a webhook. Times are in milliseconds since the Unix epoch.
+    #[derive(Deserialize, Serialize, Clone, Debug)]
+    pub struct Delivery {
+        pub delivery_id: String,
+        pub action: String,
+        pub filename: Option<String>,
+        pub state: DeliveryState,
+        pub attempts: usize,
+        /// The HTTP status of the last response, if there was one.
+        pub status: Option<u16>,
+        /// Why the last attempt failed, if it did.
+        pub error: Option<String>,
+        pub created: u64,
+        pub updated: u64,
+    }
+
+    #[derive(Serialize)]
+    pub struct DeliveriesResponse {
+        pub deliveries: Vec<Delivery>,
+    }
+
+    /// A query job, as returned when it is submitted and when its status is asked for.
+    /// Times are in milliseconds since the Unix epoch.
+    #[derive(Deserialize, Serialize, Clone, Debug)]
+    pub struct JobStatus {
+        pub job_id: String,
+        pub collection: String,
+        pub state: JobState,
+        pub submitted: u64,
+        pub started: Option<u64>,
+        pub finished: Option<u64>,
+        /// The number of rows found, once the job is done.
+        pub rows: Option<usize>,
+        pub error: Option<String>,
+    }
+
+    /// How the rows of a query are returned.
+    #[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq, Default)]
+    #[serde(rename_all = "lowercase")]
+    pub enum ResponseFormat {
+        /// A JSON object with the `header` and `rows`.
+        #[default]
+        Json,
+        /// A JSON object keyed by the header on each line, for each row.
+        Ndjson,
+        /// The header and rows as CSV.
+        Csv,
+        /// Server-sent events with the header and rows, sent as the files are read.
+        Sse,
+    }
+
+    impl ResponseFormat {
+        const ALL: [ResponseFormat; 4] = [ResponseFormat::Json, ResponseFormat::Ndjson, ResponseFormat::Csv, ResponseFormat::Sse];
+
+        /// The media types of the format, the first of which is the one it is sent as.
+        pub fn media_types(&self) -> &'static [&'static str] {
+            match self {
+                ResponseFormat::Json => &["application/json"],
+                ResponseFormat::Ndjson => &["application/x-ndjson", "application/ndjson"],
+                ResponseFormat::Csv => &["text/csv"],
+                ResponseFormat::Sse => &["text/event-stream"],
+            }
+        }
+
+        /// Chooses the format an `Accept` header prefers, by the quality (`q`) of the most
+        /// specific media range matching each format. Ties go to the format whose media range
+        /// comes first in the header, and then to JSON. If no format is acceptable, JSON is chosen.
+        pub fn negotiate(accept: &str) -> ResponseFormat {
+            let ranges: Vec<(String, f32)> = accept.split(',')
+                .map(|range| {
+                    let mut parts = range.split(';');
+                    let media_type = parts.next().unwrap_or("").trim().to_lowercase();
+                    let quality = parts
+                        .filter_map(|p| p.trim().strip_prefix("q=").and_then(|q| q.trim().parse::<f32>().ok()))
+                        .next()
+                        .unwrap_or(1.0);
+                    (media_type, quality)
+                })
+                .collect();
+
+            // The quality, specificity, and position of the range matching each format.
+            let mut best: Option<(ResponseFormat, f32, u8, usize)> = None;
+            for format in ResponseFormat::ALL {
+                let matched = ranges.iter().enumerate()
+                    .filter_map(|(position, (range, quality))| {
+                        let specificity = match range.as_str() {
+                            "*/*" => 0,
+                            r if format.media_types().contains(&r) => 2,
+                            r => match r.strip_suffix("/*") {
+                                Some(t) if format.media_types().iter().any(|m| m.split('/').next() == Some(t)) => 1,
+                                _ => return None,
+                            },
+                        };
+                        Some((*quality, specificity, position))
+                    })
+                    .max_by_key(|(_, specificity, _)| *specificity);
+                let Some((quality, specificity, position)) = matched else {
+                    continue;
+                };
+                if quality <= 0.0 {
+                    continue;
+                }
+                let better = match best {
+                    None => true,
+                    Some((_, q, s, p)) => quality > q || (quality == q && (specificity > s || (specificity == s && position < p))),
+                };
+                if better {
+                    best = Some((format, quality, specificity, position));
+                }
+            }
+            best.map(|(format, ..)| format).unwrap_or_default()
+        }
+    }
+
+    #[derive(Deserialize, Default)]
     pub struct QueryParameters {
         pub page: Option<usize>,
         pub per_page: Option<usize>,
+        pub typed: Option<bool>,
+        pub debug: Option<bool>,
+        pub priority: Option<Priority>,
+        pub store: Option<bool>,
+        pub format: Option<ResponseFormat>,
+        pub escape_formulas: Option<bool>,
+        /// A snapshot id, or a time to query the latest snapshot taken by, instead of the current files.
+        pub as_of: Option<String>,
     }
 
-    #[derive(Deserialize)]
+    #[derive(Deserialize, Serialize, Clone)]
     pub struct QueryPredicates {
         pub fields: Vec<String>,
         pub predicates: Vec<String>, // given as strings in api
     }
 
-    #[derive(Serialize)]
-    pub struct QueryResponse {
+    /// The body of a query: the `fields` and `predicates` of the rows to find, as with
+    /// `QueryPredicates`, and the window functions computed over the rows found.
+    #[derive(Deserialize)]
+    pub struct QueryPayload {
+        pub fields: Vec<String>,
+        pub predicates: Vec<String>,
+        #[serde(default)]
+        pub windows: Vec<Window>,
+    }
+
+    /// A function computed for each row from the rows of its partition, in order.
+    #[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq)]
+    #[serde(rename_all = "snake_case")]
+    pub enum WindowFunction {
+        RowNumber,
+        Rank,
+        Lag,
+        Lead,
+        RunningSum,
+    }
+
+    /// A window function, computed over the rows with the same value of `partition_by`, or every
+    /// row if it is not given, ordered by `order_by`. Its values are added to each row as the column `as`.
+    #[derive(Deserialize, Clone, Debug)]
+    pub struct Window {
+        pub function: WindowFunction,
+        /// The column whose values are given by `lag` and `lead`, and summed by `running_sum`.
+        pub column: Option<String>,
+        pub partition_by: Option<String>,
+        pub order_by: String,
+        #[serde(default)]
+        pub descending: bool,
+        /// How many rows before or after to take the value of, for `lag` and `lead`. 1 by default.
+        pub offset: Option<usize>,
+        /// The name of the column added. The name of the function by default.
+        #[serde(rename = "as")]
+        pub name: Option<String>,
+    }
+
+    /// The ticket of an Arrow Flight `DoGet`, given as JSON: the query to run on `collection`,
+    /// and the page of rows to return. Every row is returned if no page is asked for.
+    #[derive(Deserialize, Serialize, Default)]
+    pub struct FlightTicket {
+        pub collection: String,
+        #[serde(default)]
+        pub fields: Vec<String>,
+        #[serde(default)]
+        pub predicates: Vec<String>,
+        pub page: Option<usize>,
+        pub per_page: Option<usize>,
+        pub priority: Option<Priority>,
+    }
+
+    /// The query options of an OData request for the entities of an entity set.
+    #[derive(Deserialize, Default)]
+    pub struct ODataParameters {
+        #[serde(rename = "$select")]
+        pub select: Option<String>,
+        #[serde(rename = "$filter")]
+        pub filter: Option<String>,
+        #[serde(rename = "$orderby")]
+        pub orderby: Option<String>,
+        #[serde(rename = "$top")]
+        pub top: Option<usize>,
+        #[serde(rename = "$skip")]
+        pub skip: Option<usize>,
+        #[serde(rename = "$count")]
+        pub count: Option<bool>,
+    }
+
+    #[derive(Deserialize, Serialize, Default)]
+    pub struct QueryResponse<T = String> {
         pub header: Vec<String>,
-        pub rows: Vec<Vec<String>>,
+        pub rows: Vec<Vec<T>>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub profile: Option<QueryProfile>,
+        /// The id of the stored result, to page through it without running the query again.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub result_set: Option<String>,
+        /// The number of rows in every page, when the result is stored.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub total_rows: Option<usize>,
     }
 
-    #[derive(Serialize)]
+    /// How a query was run, returned with its rows when it is made with `debug=true`.
+    #[derive(Deserialize, Serialize, Default, Debug)]
+    pub struct QueryProfile {
+        pub files_scanned: usize,
+        /// Files in the collection that were not scanned, because of filename predicates.
+        pub files_pruned: usize,
+        pub files: Vec<FileProfile>,
+        /// How long each phase of the query took, in the order they were run.
+        pub phases: Vec<PhaseTiming>,
+    }
+
+    #[derive(Deserialize, Serialize, Debug)]
+    pub struct FileProfile {
+        pub filename: String,
+        /// Rows that followed the header, including those that did not match the predicates.
+        pub rows_read: usize,
+        pub rows_matched: usize,
+        pub micros: u64,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub error: Option<String>,
+    }
+
+    #[derive(Deserialize, Serialize, Debug)]
+    pub struct PhaseTiming {
+        pub phase: String,
+        pub micros: u64,
+    }
+
+    #[derive(Deserialize, Serialize)]
     pub struct RenderResponse {
         pub header: Vec<String>,
         pub rows: Vec<Vec<String>>,
         pub removed: Vec<Vec<String>>,
     }
 
+    #[derive(Serialize)]
+    pub struct SnapshotResponse {
+        pub snapshot_id: String,
+        pub files: usize,
+    }
+
+    #[derive(Serialize)]
+    pub struct FileVersion {
+        pub version_id: String,
+        pub size: u64,
+    }
+
+    #[derive(Serialize)]
+    pub struct VersionsResponse {
+        pub filename: String,
+        pub versions: Vec<FileVersion>,
+    }
+
+    #[derive(Serialize)]
+    pub struct FileIntegrity {
+        pub filename: String,
+        pub status: super::query::IntegrityStatus,
+    }
+
+    #[derive(Serialize)]
+    pub struct VerifyResponse {
+        pub verified: usize,
+        pub problems: Vec<FileIntegrity>,
+    }
+
+    /// The kind of problem that would stop rows from being inserted in a collection.
+    #[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq)]
+    #[serde(rename_all = "snake_case")]
+    pub enum ProblemKind {
+        NoHeader,
+        HeaderMismatch,
+        RowLength,
+        InvalidValue,
+        RepeatedKey,
+        ExistingKey,
+    }
+
+    impl ProblemKind {
+        /// The name of the kind, as it is serialized.
+        pub fn name(&self) -> &'static str {
+            match self {
+                ProblemKind::NoHeader => "no_header",
+                ProblemKind::HeaderMismatch => "header_mismatch",
+                ProblemKind::RowLength => "row_length",
+                ProblemKind::InvalidValue => "invalid_value",
+                ProblemKind::RepeatedKey => "repeated_key",
+                ProblemKind::ExistingKey => "existing_key",
+            }
+        }
+    }
+
+    /// A problem found by `validate`, with the row, counting from 0 in `rows`, and the column
+    /// and value it is in, where it is in one.
+    #[derive(Deserialize, Serialize, Clone, Debug)]
+    pub struct ValidationProblem {
+        pub kind: ProblemKind,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub row: Option<usize>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub column: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub value: Option<String>,
+        pub message: String,
+    }
+
+    /// The rows created, and the problems with the rows left out, when `partial` is set.
+    #[derive(Deserialize, Serialize)]
+    pub struct CreateResponse {
+        pub rows: usize,
+        pub rejected: Vec<ValidationProblem>,
+    }
+
+    #[derive(Serialize)]
+    pub struct ValidateResponse {
+        pub valid: bool,
+        pub rows: usize,
+        /// The first 1000 problems found.
+        pub problems: Vec<ValidationProblem>,
+        pub total_problems: usize,
+    }
+
+    #[derive(Deserialize, Serialize)]
+    pub struct FileSummary {
+        pub filename: String,
+        pub size: u64,
+    }
+
+    #[derive(Deserialize, Serialize)]
+    pub struct FilesResponse {
+        pub files: Vec<FileSummary>,
+    }
+
+    #[derive(Serialize)]
+    pub struct PeerReplication {
+        pub peer: String,
+        pub created: usize,
+        pub deleted: usize,
+        pub error: Option<String>,
+    }
+
+    #[derive(Serialize)]
+    pub struct ReplicateResponse {
+        pub peers: Vec<PeerReplication>,
+    }
+
+    #[derive(Serialize)]
+    pub struct RebuildCatalogResponse {
+        pub catalog: crate::catalog::Catalog,
+        pub added: Vec<String>,
+        pub removed: Vec<String>,
+        pub changed: Vec<String>,
+        pub mismatched: Vec<String>,
+    }
+
+    #[derive(Deserialize)]
+    pub struct RenameColumnPayload {
+        pub from: String,
+        pub to: String,
+    }
+
+    #[derive(Deserialize)]
+    pub struct DropColumnPayload {
+        pub column: String,
+    }
+
+    /// Which of a set of duplicate rows is kept.
+    #[derive(Deserialize, Clone, Copy, Debug, PartialEq, Default)]
+    #[serde(rename_all = "lowercase")]
+    pub enum Keep {
+        #[default]
+        First,
+        Last,
+    }
+
+    #[derive(Deserialize)]
+    pub struct DedupePayload {
+        /// The columns compared to find duplicates, or every column if none are given.
+        #[serde(default)]
+        pub columns: Vec<String>,
+        #[serde(default)]
+        pub keep: Keep,
+    }
+
+    #[derive(Deserialize, Default)]
+    pub struct ProfilePayload {
+        #[serde(default)]
+        pub fields: Vec<String>,
+        #[serde(default)]
+        pub predicates: Vec<String>,
+        /// The percentiles, from 0 to 100, to estimate for each numeric column.
+        #[serde(default)]
+        pub percentiles: Vec<f64>,
+    }
+
+    /// A summary of the values of a column. The `min` and `max` are compared as the type of the
+    /// column, and the `mean`, `variance`, `stddev`, `median`, and `percentiles` are only found for numbers.
+    #[derive(Serialize)]
+    pub struct ColumnProfile {
+        pub name: String,
+        #[serde(rename = "type")]
+        pub column_type: crate::schema::ColumnType,
+        pub nulls: usize,
+        pub null_rate: f64,
+        pub distinct: usize,
+        pub min: Option<String>,
+        pub max: Option<String>,
+        pub mean: Option<f64>,
+        /// The sample variance, and its square root, found once there are at least two numbers.
+        pub variance: Option<f64>,
+        pub stddev: Option<f64>,
+        pub median: Option<f64>,
+        #[serde(skip_serializing_if = "Vec::is_empty")]
+        pub percentiles: Vec<PercentileValue>,
+        pub samples: Vec<String>,
+    }
+
+    #[derive(Serialize)]
+    pub struct PercentileValue {
+        pub percentile: f64,
+        pub value: f64,
+    }
+
+    #[derive(Serialize)]
+    pub struct ProfileResponse {
+        pub rows: usize,
+        pub columns: Vec<ColumnProfile>,
+    }
+
+    #[derive(Deserialize)]
+    pub struct TopValuesPayload {
+        pub column: String,
+        #[serde(default)]
+        pub predicates: Vec<String>,
+        /// How many values are returned, or `stats::TOP_VALUES` if it is not given.
+        pub limit: Option<usize>,
+    }
+
+    #[derive(Serialize)]
+    pub struct ValueCount {
+        pub value: String,
+        pub count: usize,
+    }
+
+    #[derive(Serialize)]
+    pub struct TopValuesResponse {
+        pub column: String,
+        pub rows: usize,
+        pub distinct: usize,
+        pub values: Vec<ValueCount>,
+    }
+
+    #[derive(Deserialize)]
+    pub struct HistogramPayload {
+        pub column: String,
+        #[serde(default)]
+        pub predicates: Vec<String>,
+        /// The number of buckets, or `stats::HISTOGRAM_BUCKETS` if neither it nor a `width` is given.
+        pub buckets: Option<usize>,
+        pub width: Option<f64>,
+    }
+
+    /// The number of values from `start`, up to but not including `end`, except in the last bucket.
+    #[derive(Serialize)]
+    pub struct Bucket {
+        pub start: f64,
+        pub end: f64,
+        pub count: usize,
+    }
+
+    #[derive(Serialize)]
+    pub struct HistogramResponse {
+        pub column: String,
+        pub rows: usize,
+        pub skipped: usize,
+        pub min: Option<f64>,
+        pub max: Option<f64>,
+        pub buckets: Vec<Bucket>,
+    }
+
+    #[derive(Serialize)]
+    pub struct DedupeResponse {
+        pub removed: usize,
+        pub rewritten: Vec<String>,
+    }
+
+    /// When the rows of a materialized view are refreshed: only when asked to,
+    /// or also whenever the files of its collection change.
+    #[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq, Default)]
+    #[serde(rename_all = "snake_case")]
+    pub enum ViewRefresh {
+        #[default]
+        Manual,
+        OnChange,
+    }
+
+    #[derive(Deserialize)]
+    pub struct SavedQueryPayload {
+        pub collection: String,
+        #[serde(default)]
+        pub fields: Vec<String>,
+        /// Predicates as with `query`, which can have `{{placeholders}}` given values when the query is run.
+        #[serde(default)]
+        pub predicates: Vec<String>,
+        #[serde(default)]
+        pub description: String,
+    }
+
+    /// A query saved under a name, so that it can be run by its name with values for its parameters.
+    #[derive(Deserialize, Serialize, Clone, Debug)]
+    pub struct SavedQuery {
+        pub name: String,
+        pub collection: String,
+        pub fields: Vec<String>,
+        pub predicates: Vec<String>,
+        #[serde(default, skip_serializing_if = "String::is_empty")]
+        pub description: String,
+        /// The names of the placeholders in its predicates, in the order they are first found.
+        pub parameters: Vec<String>,
+        /// When it was first saved, and last saved, in milliseconds since the Unix epoch.
+        pub created: u64,
+        pub updated: u64,
+    }
+
+    #[derive(Serialize)]
+    pub struct SavedQueriesResponse {
+        pub queries: Vec<SavedQuery>,
+    }
+
+    #[derive(Deserialize, Default)]
+    pub struct RunQueryPayload {
+        /// The values of the parameters of the query, as strings, numbers, or booleans.
+        #[serde(default)]
+        pub parameters: std::collections::HashMap<String, serde_json::Value>,
+    }
+
+    #[derive(Deserialize)]
+    pub struct SchedulePayload {
+        /// The name of the saved query to run, and the values of its parameters.
+        pub query: String,
+        #[serde(default)]
+        pub parameters: std::collections::HashMap<String, serde_json::Value>,
+        /// When to run it, as a cron expression in UTC, such as `0 2 * * *`.
+        pub cron: String,
+        /// The collection to create a file of the rows found in each time, or the URL to send them to.
+        pub collection: Option<String>,
+        pub url: Option<String>,
+    }
+
+    /// A saved query that is run on a schedule, with its rows written to a collection or sent to a URL.
+    #[derive(Deserialize, Serialize, Clone, Debug)]
+    pub struct Schedule {
+        pub schedule_id: String,
+        pub query: String,
+        pub parameters: std::collections::HashMap<String, serde_json::Value>,
+        pub cron: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub collection: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub url: Option<String>,
+        /// When it was created, and last run, in milliseconds since the Unix epoch.
+        pub created: u64,
+        pub last_run: Option<u64>,
+        /// The number of rows found the last time it ran, or why it failed.
+        pub last_rows: Option<usize>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub last_error: Option<String>,
+    }
+
+    #[derive(Serialize)]
+    pub struct SchedulesResponse {
+        pub schedules: Vec<Schedule>,
+    }
+
+    #[derive(Deserialize)]
+    pub struct TriggerPayload {
+        /// The fields and predicates that pick the rows of each file created to write or send.
+        #[serde(default)]
+        pub fields: Vec<String>,
+        #[serde(default)]
+        pub predicates: Vec<String>,
+        /// The name of a saved query to run instead, and the values of its parameters.
+        pub query: Option<String>,
+        #[serde(default)]
+        pub parameters: std::collections::HashMap<String, serde_json::Value>,
+        /// The collection to write the rows found to, or the URL to send them to.
+        pub target: Option<String>,
+        pub url: Option<String>,
+    }
+
+    /// A trigger on a collection, fired after each file created in it, which writes rows to
+    /// another collection or sends them to a URL. The rows are those of the file that match its
+    /// predicates, or those found by its saved query.
+    #[derive(Deserialize, Serialize, Clone, Debug)]
+    pub struct Trigger {
+        pub trigger_id: String,
+        #[serde(default)]
+        pub fields: Vec<String>,
+        #[serde(default)]
+        pub predicates: Vec<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub query: Option<String>,
+        #[serde(default, skip_serializing_if = "std::collections::HashMap::is_empty")]
+        pub parameters: std::collections::HashMap<String, serde_json::Value>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub target: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub url: Option<String>,
+        /// The principal that created it, and its roles, which decide the columns of the rows it finds.
+        #[serde(default)]
+        pub principal: String,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        pub roles: Vec<String>,
+        /// When it was created, and last fired, in milliseconds since the Unix epoch.
+        pub created: u64,
+        pub last_fired: Option<u64>,
+        /// The file it was last fired by, and the number of rows found, or why it failed.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub last_filename: Option<String>,
+        pub last_rows: Option<usize>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub last_error: Option<String>,
+    }
+
+    #[derive(Serialize)]
+    pub struct TriggersResponse {
+        pub triggers: Vec<Trigger>,
+    }
+
+    #[derive(Serialize)]
+    pub struct ViewsResponse {
+        pub views: Vec<View>,
+    }
+
+    #[derive(Deserialize)]
+    pub struct ViewPayload {
+        pub name: String,
+        pub collection: String,
+        #[serde(default)]
+        pub fields: Vec<String>,
+        #[serde(default)]
+        pub predicates: Vec<String>,
+        #[serde(default)]
+        pub refresh: ViewRefresh,
+    }
+
+    /// A materialized view: a query on a collection whose rows are kept, so that they can be
+    /// queried without scanning the collection again.
+    #[derive(Deserialize, Serialize, Clone, Debug)]
+    pub struct View {
+        pub name: String,
+        pub collection: String,
+        pub fields: Vec<String>,
+        pub predicates: Vec<String>,
+        pub refresh: ViewRefresh,
+        /// When it was defined, in milliseconds since the Unix epoch.
+        pub created: u64,
+        /// When its rows were last refreshed, in milliseconds since the Unix epoch, and how many there are.
+        pub refreshed: u64,
+        pub rows: usize,
+        /// Why the last refresh failed, if it did, in which case the rows are those of the refresh before it.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub error: Option<String>,
+    }
+
+    #[derive(Serialize)]
+    pub struct SchemaChangeResponse {
+        pub schema: Option<crate::schema::Schema>,
+        pub rewritten: Vec<String>,
+    }
+
+    #[derive(Serialize)]
+    pub struct ExplainedPredicate {
+        pub field: String,
+        pub op: String,
+        pub value: String,
+        pub column_type: crate::schema::ColumnType,
+    }
+
+    #[derive(Serialize)]
+    pub struct ExplainedFile {
+        pub filename: String,
+        pub size: u64,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub rows: Option<usize>,
+    }
+
+    #[derive(Serialize)]
+    pub struct ExplainResponse {
+        pub fields: Vec<String>,
+        pub predicates: Vec<ExplainedPredicate>,
+        pub filename_predicates: Vec<ExplainedPredicate>,
+        pub files: Vec<ExplainedFile>,
+        pub pruned: Vec<String>,
+        pub groups: Vec<Vec<String>>,
+        pub bytes: u64,
+        pub rows: Option<usize>,
+    }
+
+    #[derive(Serialize)]
+    pub struct ConfigSetting {
+        pub name: String,
+        pub value: String,
+        /// Where the value is from: `command_line`, `environment`, `file`, or `default`.
+        pub source: String,
+        pub restart_required: bool,
+    }
+
+    #[derive(Serialize)]
+    pub struct ConfigResponse {
+        pub file: Option<String>,
+        pub settings: Vec<ConfigSetting>,
+    }
+
+    #[derive(Serialize)]
+    pub struct ReloadResponse {
+        pub changed: Vec<String>,
+        pub restart_required: Vec<String>,
+        pub errors: Vec<String>,
+    }
+
+    /// Whether a maintenance task is running.
+    #[derive(Serialize, Clone, Copy, Debug, PartialEq)]
+    #[serde(rename_all = "lowercase")]
+    pub enum MaintenanceState {
+        Idle,
+        Running,
+    }
+
+    /// A task the data service runs in the background to maintain its collections, with how it has run.
+    #[derive(Serialize, Clone, Debug)]
+    pub struct MaintenanceTask {
+        pub name: String,
+        /// The seconds between its runs.
+        pub period: u64,
+        /// Whether it runs at the start of each period since the Unix epoch, rather than a period after its last run.
+        pub aligned: bool,
+        pub state: MaintenanceState,
+        pub runs: u64,
+        pub failures: u64,
+        /// When its last run started and finished, and when it next runs, in milliseconds since the Unix epoch.
+        pub last_started: Option<u64>,
+        pub last_finished: Option<u64>,
+        /// The error its last run failed with, if it did.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub last_error: Option<String>,
+        pub next_run: Option<u64>,
+    }
+
+    #[derive(Serialize)]
+    pub struct MaintenanceResponse {
+        pub tasks: Vec<MaintenanceTask>,
+    }
+
+    #[derive(Serialize)]
+    pub struct AuditResponse {
+        pub entries: Vec<crate::audit::AuditEntry>,
+    }
+
+    #[derive(Serialize)]
+    pub struct ChangeLogResponse {
+        pub changes: Vec<crate::changelog::LoggedChange>,
+        /// The offset to give as `since` for the changes after these.
+        pub offset: u64,
+    }
+
     // api functions
 }
@@ -0,0 +1,217 @@
+use std::{
+    path::PathBuf,
+    sync::Mutex,
+    time::SystemTime,
+};
+use tracing::{info, warn};
+
+use crate::auth::Principal;
+use crate::changes::Change;
+use crate::queries::Cancelled;
+use crate::storage::storage;
+use crate::types::{
+    api::{Priority, QueryPredicates, QueryProfile, View, ViewPayload, ViewRefresh},
+    error::{Missing, ZenithError},
+};
+use crate::{config, db, limit, request_id, shutdown};
+
+/// The file in the directory of a view holding its definition.
+const VIEW_FILENAME: &str = ".view.json";
+
+/// The file in the directory of a view holding its rows.
+const ROWS_FILENAME: &str = "rows.csv";
+
+
+/// Held while views are defined, refreshed, or removed, so that their rows and definitions agree.
+static VIEWS_LOCK: Mutex<()> = Mutex::new(());
+
+
+/// Returns the time now, in milliseconds since the Unix epoch.
+fn now() -> u64 {
+    SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).map(|d| d.as_millis() as u64).unwrap_or(0)
+}
+
+
+fn view_path(name: &str) -> PathBuf {
+    config::views_path().join(name)
+}
+
+
+fn not_found(name: &str) -> ZenithError {
+    ZenithError::NotFound(Missing::Other(format!("View '{}' does not exist", name)))
+}
+
+
+/// Reads the definition of the view `name`, raising a `NotFound` error if there is none.
+fn read(name: &str) -> Result<View, ZenithError> {
+    db::validate_name("view", name)?;
+    let path = view_path(name).join(VIEW_FILENAME);
+    if !storage().is_file(&path) {
+        return Err(not_found(name));
+    }
+    Ok(serde_json::from_slice(&storage().read(&path)?)?)
+}
+
+
+/// Writes the definition of `view`, replacing the one it had.
+///
+/// The views lock must be held while calling this.
+fn write(view: &View) -> Result<(), ZenithError> {
+    let path = view_path(&view.name).join(VIEW_FILENAME);
+    let temp_path = path.with_extension("json.tmp");
+    storage().write(&temp_path, &serde_json::to_vec_pretty(view)?)?;
+    storage().rename(&temp_path, &path)?;
+    Ok(())
+}
+
+
+/// Lists the views, in order of their names.
+pub fn list() -> Result<Vec<View>, ZenithError> {
+    let path = config::views_path();
+    if !storage().is_dir(&path) {
+        return Ok(Vec::new());
+    }
+    let mut views = Vec::new();
+    for entry in storage().list(&path)? {
+        if !entry.is_file && storage().is_file(&entry.path.join(VIEW_FILENAME)) {
+            views.push(read(&entry.name)?);
+        }
+    }
+    views.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(views)
+}
+
+
+/// Returns the view `name`, raising a `NotFound` error if there is none.
+pub fn get(name: &str) -> Result<View, ZenithError> {
+    read(name)
+}
+
+
+/// Defines the view in `payload`, as a query on its collection, and materializes its rows,
+/// returning it. Raises a `Conflict` error if a view of the same name exists, and a `NotFound`
+/// error if the collection does not exist. The view is not kept if its rows cannot be found.
+pub fn create(payload: ViewPayload) -> Result<View, ZenithError> {
+    db::validate_name("view", &payload.name)?;
+    db::validate_name("collection", &payload.collection)?;
+    if !storage().is_dir(&config::collection_path(&payload.collection)) {
+        return Err(ZenithError::NotFound(Missing::Collection(payload.collection)));
+    }
+
+    let _guard = VIEWS_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let path = view_path(&payload.name);
+    if storage().is_file(&path.join(VIEW_FILENAME)) {
+        return Err(ZenithError::Conflict(format!("View '{}' already exists", payload.name)));
+    }
+    let mut view = View {
+        name: payload.name,
+        collection: payload.collection,
+        fields: payload.fields,
+        predicates: payload.predicates,
+        refresh: payload.refresh,
+        created: now(),
+        refreshed: 0,
+        rows: 0,
+        error: None,
+    };
+    storage().create_dir_all(&path)?;
+    if let Err(err) = materialize(&mut view).and_then(|_| write(&view)) {
+        let _ = remove_files(&view.name);
+        return Err(err);
+    }
+    Ok(view)
+}
+
+
+/// Runs the query of `view` on its collection, and replaces its rows with the rows found.
+///
+/// The views lock must be held while calling this.
+fn materialize(view: &mut View) -> Result<(), ZenithError> {
+    let predicates = QueryPredicates { fields: view.fields.clone(), predicates: view.predicates.clone() };
+    // Every column is kept, and masked as each principal that queries the view sees it.
+    let selection = db::select(&view.collection, predicates, None, &Cancelled::default())?;
+    let rows = selection.1.len();
+    db::write_selection(&view.collection, &view_path(&view.name), ROWS_FILENAME, selection)?;
+    (view.refreshed, view.rows, view.error) = (now(), rows, None);
+    Ok(())
+}
+
+
+/// Refreshes the rows of the view `name` from its collection, returning it. If they cannot be
+/// found, the view keeps the rows it had, and records the error until a refresh succeeds.
+pub fn refresh(name: &str) -> Result<View, ZenithError> {
+    let _guard = VIEWS_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let mut view = read(name)?;
+    match materialize(&mut view) {
+        Ok(()) => {
+            write(&view)?;
+            Ok(view)
+        },
+        Err(err) => {
+            view.error = Some(err.to_string());
+            write(&view)?;
+            Err(err)
+        },
+    }
+}
+
+
+/// Removes the view `name` and its rows, returning it. Raises a `NotFound` error if it does not exist.
+pub fn remove(name: &str) -> Result<View, ZenithError> {
+    let _guard = VIEWS_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let view = read(name)?;
+    remove_files(name)?;
+    Ok(view)
+}
+
+
+/// Removes the files in the directory of the view `name`, its definition last, so that a view
+/// is not left without its definition if they cannot all be removed. The directory is left.
+fn remove_files(name: &str) -> Result<(), ZenithError> {
+    let mut entries: Vec<_> = storage().list(&view_path(name))?.into_iter().filter(|e| e.is_file).collect();
+    entries.sort_by_key(|e| e.name == VIEW_FILENAME);
+    for entry in entries {
+        storage().remove(&entry.path)?;
+    }
+    Ok(())
+}
+
+
+/// Runs a query with `predicates` on the rows of the view `name`, as `principal`, returning
+/// the collection it is a view of, the rows matched, and, if it is `profiled`, a profile of
+/// how it was run. The rows are read as files of the collection, with its schema and masks.
+pub fn query(
+    name: &str,
+    predicates: QueryPredicates,
+    principal: &Principal,
+    cancelled: &Cancelled,
+    profiled: bool,
+) -> Result<(String, db::Selection, Option<QueryProfile>), ZenithError> {
+
+    let view = read(name)?;
+    let (selection, profile) = db::select_files_in(&view.collection, &view_path(name), predicates, Some(principal), cancelled, profiled)?;
+    Ok((view.collection, selection, profile))
+}
+
+
+/// Refreshes each view of the collection of `change` that is refreshed when it changes, in the
+/// background, at batch priority. The change has already been made, so failures are only reported.
+pub fn trigger(change: &Change) {
+    let views = match list() {
+        Ok(views) => views,
+        Err(err) => {
+            warn!("Could not read the views of collection '{}': {}", change.collection, err);
+            return;
+        }
+    };
+    for view in views.into_iter().filter(|v| v.collection == change.collection && v.refresh == ViewRefresh::OnChange) {
+        shutdown::spawn(request_id::inherit(async move {
+            let _slot = limit::SlotGuard::wait(Priority::Batch).await;
+            let name = view.name.clone();
+            match request_id::spawn_blocking(move || refresh(&view.name)).await {
+                Ok(view) => info!("Refreshed view '{}' with {} rows", name, view.rows),
+                Err(err) => warn!("Could not refresh view '{}': {}", name, err),
+            }
+        }));
+    }
+}
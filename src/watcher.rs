@@ -0,0 +1,188 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+use notify::{event::EventKind, Event, RecursiveMode, Watcher};
+use tracing::{error, info, warn};
+
+use crate::audit::{self, AuditEntry};
+use crate::auth::Principal;
+use crate::db::{self, Registration};
+use crate::storage::list_collections;
+use crate::{changes, config, shutdown};
+
+/// How long a file must go without changing before it is checked, so that
+/// a file still being written by another process is not checked part of the way through.
+const SETTLE_TIME: Duration = Duration::from_secs(2);
+
+/// How often files that have changed are looked at to see if they have settled.
+const CHECK_INTERVAL: Duration = Duration::from_millis(500);
+
+
+/// Returns `path` in full, so that it can be compared with the paths of events.
+fn canonical(path: PathBuf) -> PathBuf {
+    path.canonicalize().unwrap_or(path)
+}
+
+
+/// Returns the collection and filename of the file at `path`, if it is a data file directly in
+/// the directory of a collection: a directory in `data_path`, or one of `collection_paths`.
+fn locate(path: &Path, data_path: &Path, collection_paths: &[(String, PathBuf)]) -> Option<(String, String)> {
+    let filename = path.file_name()?.to_str()?;
+    if filename.starts_with('.') {
+        return None;
+    }
+    let directory = path.parent()?;
+    if let Some((collection, _)) = collection_paths.iter().find(|(_, p)| p == directory) {
+        return Some((collection.clone(), filename.to_string()));
+    }
+    let collection = directory.file_name()?.to_str()?;
+    let in_data_path = directory.parent()? == data_path
+        && !collection.starts_with('.')
+        && !collection_paths.iter().any(|(c, _)| c == collection);
+    in_data_path.then(|| (collection.to_string(), filename.to_string()))
+}
+
+
+/// Watches the data path, and the directories in `ZENITHDS_COLLECTION_PATHS`, for files added,
+/// changed, or removed by other processes, if `ZENITHDS_WATCH` is set, until the data service is
+/// asked to stop. Each file is checked once it has settled. See `check`.
+pub async fn watch() {
+    if config::envar_str("ZENITHDS_WATCH").is_empty() {
+        return;
+    }
+    if config::envar_str("ZENITHDS_STORAGE") == "memory" {
+        warn!("Files cannot be watched with in-memory storage");
+        return;
+    }
+
+    let (sender, mut receiver) = tokio::sync::mpsc::unbounded_channel();
+    let mut watcher = match notify::recommended_watcher(move |event: notify::Result<Event>| {
+        let _ = sender.send(event);
+    }) {
+        Ok(watcher) => watcher,
+        Err(err) => {
+            error!("Could not watch for files: {}", err);
+            return;
+        },
+    };
+    let data_path = canonical(config::data_path());
+    let collection_paths: Vec<(String, PathBuf)> = config::collection_paths().into_iter()
+        .map(|(collection, path)| (collection, canonical(path)))
+        .collect();
+    if let Err(err) = watcher.watch(&data_path, RecursiveMode::Recursive) {
+        error!("Could not watch '{}' for files: {}", data_path.display(), err);
+        return;
+    }
+    for (collection, path) in &collection_paths {
+        if let Err(err) = watcher.watch(path, RecursiveMode::NonRecursive) {
+            warn!("Could not watch '{}' for files in collection '{}': {}", path.display(), collection, err);
+        }
+    }
+    info!("Watching '{}' for files added by other processes", data_path.display());
+
+    // The files already in each collection are as they were before the data service watched them.
+    let catalogued = tokio::task::spawn_blocking(|| {
+        for collection in list_collections().unwrap_or_default() {
+            if let Err(err) = db::ensure_catalog(&collection) {
+                warn!("Could not record the files in collection '{}': {}", collection, err);
+            }
+        }
+    }).await;
+    if let Err(err) = catalogued {
+        error!("Failed to join catalog: {:?}", err);
+    }
+
+    // When each file that changed last changed.
+    let mut changed: HashMap<(String, String), Instant> = HashMap::new();
+    let mut interval = tokio::time::interval(CHECK_INTERVAL);
+    loop {
+        tokio::select! {
+            event = receiver.recv() => match event {
+                // Files are only read when they are checked.
+                Some(Ok(event)) if matches!(event.kind, EventKind::Access(_)) => (),
+                Some(Ok(event)) => {
+                    for path in &event.paths {
+                        if let Some(file) = locate(path, &data_path, &collection_paths) {
+                            changed.insert(file, Instant::now());
+                        }
+                    }
+                },
+                Some(Err(err)) => warn!("Could not watch for files: {}", err),
+                None => return,
+            },
+            _ = interval.tick() => {
+                let settled: Vec<(String, String)> = changed.iter()
+                    .filter(|(_, last)| last.elapsed() >= SETTLE_TIME)
+                    .map(|(file, _)| file.clone())
+                    .collect();
+                if settled.is_empty() {
+                    continue;
+                }
+                for file in &settled {
+                    changed.remove(file);
+                }
+                let checked = tokio::task::spawn_blocking(move || {
+                    for (collection, filename) in settled {
+                        check(&collection, &filename);
+                    }
+                }).await;
+                if let Err(err) = checked {
+                    error!("Failed to join file check: {:?}", err);
+                }
+            },
+            _ = shutdown::requested() => return,
+        }
+    }
+}
+
+
+/// Records `filename` in `collection` after it changed, if it was changed by another process,
+/// rejecting it if it is not valid in the collection and `ZENITHDS_WATCH_REJECT` is set.
+/// What was done is recorded by `watcher`, as `register`, `unregister`, or `reject`.
+fn check(collection: &str, filename: &str) {
+    let reject = !config::envar_str("ZENITHDS_WATCH_REJECT").is_empty();
+    let entry = match db::register(collection, filename, reject) {
+        Ok(Registration::Unchanged) => return,
+        Ok(Registration::Recorded { previous_rows, rows, problem }) => {
+            let entry = AuditEntry::new(&watcher(), "register", collection)
+                .filename(filename)
+                .rows(previous_rows, Some(rows));
+            match problem {
+                Some(problem) => {
+                    warn!("Recorded '{}' in collection '{}', which is not valid in it: {}", filename, collection, problem);
+                    entry.detail(problem)
+                },
+                None => {
+                    info!("Recorded '{}' in collection '{}', with {} rows", filename, collection, rows);
+                    entry
+                },
+            }
+        },
+        Ok(Registration::Forgotten { previous_rows }) => {
+            info!("Forgot '{}' in collection '{}', which was removed", filename, collection);
+            AuditEntry::new(&watcher(), "unregister", collection)
+                .filename(filename)
+                .rows(Some(previous_rows), None)
+        },
+        Ok(Registration::Rejected { previous_rows, problem }) => {
+            warn!("Rejected '{}' in collection '{}': {}", filename, collection, problem);
+            AuditEntry::new(&watcher(), "reject", collection)
+                .filename(filename)
+                .rows(previous_rows, None)
+                .detail(problem)
+        },
+        Err(err) => {
+            warn!("Could not check '{}' in collection '{}': {}", filename, collection, err);
+            return;
+        },
+    };
+    changes::publish(&entry, None);
+    audit::record(entry);
+}
+
+
+fn watcher() -> Principal {
+    Principal { name: "watcher".to_string(), ..Principal::default() }
+}
@@ -0,0 +1,255 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    path::PathBuf,
+    sync::{LazyLock, Mutex},
+    time::{Duration, SystemTime},
+};
+use hmac::{Hmac, KeyInit, Mac};
+use reqwest::header::{HeaderMap, HeaderValue, CONTENT_TYPE};
+use sha2::Sha256;
+use tracing::{info, warn};
+
+use crate::changes::Change;
+use crate::storage::storage;
+use crate::types::{api::{Delivery, DeliveryState, Webhook, WebhookPayload}, error::{Missing, ZenithError}};
+use crate::{config, db, remote, request_id, shutdown};
+
+/// The file in a collection directory holding the webhooks registered on it.
+pub const WEBHOOKS_FILENAME: &str = ".webhooks.json";
+
+/// The header giving the HMAC-SHA256 of the body of a delivery, keyed by the secret of the webhook.
+pub const SIGNATURE_HEADER: &str = "x-zenithds-signature";
+
+/// The header giving the action of the change delivered, such as `create`.
+pub const EVENT_HEADER: &str = "x-zenithds-event";
+
+/// The header giving the id of a delivery, which is the same for each attempt.
+pub const DELIVERY_HEADER: &str = "x-zenithds-delivery";
+
+/// The most deliveries remembered for each webhook. The oldest are forgotten first.
+const MAX_DELIVERIES: usize = 100;
+
+/// The longest wait between attempts to deliver a change.
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(60);
+
+
+/// Held while the webhooks of a collection are changed, so that changes are not lost.
+static WEBHOOKS_LOCK: Mutex<()> = Mutex::new(());
+
+/// The recent deliveries to each webhook, by its id, oldest first.
+static DELIVERIES: LazyLock<Mutex<HashMap<String, VecDeque<Delivery>>>> = LazyLock::new(|| Mutex::new(HashMap::new()));
+
+
+/// Returns the time now, in milliseconds since the Unix epoch.
+fn now() -> u64 {
+    SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).map(|d| d.as_millis() as u64).unwrap_or(0)
+}
+
+
+fn webhooks_path(collection: &str) -> PathBuf {
+    config::collection_path(collection).join(WEBHOOKS_FILENAME)
+}
+
+
+/// Reads the webhooks registered on `collection`, with their secrets.
+fn read(collection: &str) -> Result<Vec<Webhook>, ZenithError> {
+    let path = webhooks_path(collection);
+    if !storage().is_file(&path) {
+        return Ok(Vec::new());
+    }
+    Ok(serde_json::from_slice(&storage().read(&path)?)?)
+}
+
+
+/// Writes the `webhooks` registered on `collection`, replacing those it had,
+/// or removes the file if there are none.
+///
+/// The webhooks lock must be held while calling this.
+fn write(collection: &str, webhooks: &[Webhook]) -> Result<(), ZenithError> {
+    let path = webhooks_path(collection);
+    if webhooks.is_empty() {
+        return Ok(storage().remove(&path)?);
+    }
+    let temp_path = path.with_extension("json.tmp");
+    storage().write(&temp_path, &serde_json::to_vec_pretty(webhooks)?)?;
+    storage().rename(&temp_path, &path)?;
+    Ok(())
+}
+
+
+/// Lists the webhooks registered on `collection`, without their secrets.
+pub fn list(collection: &str) -> Result<Vec<Webhook>, ZenithError> {
+    db::validate_name("collection", collection)?;
+    Ok(read(collection)?.into_iter().map(|webhook| Webhook { secret: String::new(), ..webhook }).collect())
+}
+
+
+/// Registers a webhook on `collection` with the `url` and `actions` in `payload`, returning it
+/// with its secret. Only `http` and `https` URLs that can reach the addresses `remote::download`
+/// can are allowed. Raises a `NotFound` error if the collection does not exist.
+pub fn register(collection: &str, payload: WebhookPayload) -> Result<Webhook, ZenithError> {
+    db::validate_name("collection", collection)?;
+    if !storage().is_dir(&config::collection_path(collection)) {
+        return Err(ZenithError::NotFound(Missing::Collection(collection.to_string())));
+    }
+    remote::parse_url(&payload.url)?;
+
+    let webhook = Webhook {
+        webhook_id: request_id::generate(),
+        url: payload.url,
+        actions: payload.actions,
+        secret: payload.secret.filter(|s| !s.is_empty()).unwrap_or_else(request_id::generate),
+        created: now(),
+    };
+    let _guard = WEBHOOKS_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let mut webhooks = read(collection)?;
+    webhooks.push(webhook.clone());
+    write(collection, &webhooks)?;
+    Ok(webhook)
+}
+
+
+/// Removes the webhook with `id` from `collection`, with its deliveries, returning it without
+/// its secret. Raises a `NotFound` error if it does not exist.
+pub fn remove(collection: &str, id: &str) -> Result<Webhook, ZenithError> {
+    db::validate_name("collection", collection)?;
+    let _guard = WEBHOOKS_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let mut webhooks = read(collection)?;
+    let position = webhooks.iter().position(|w| w.webhook_id == id)
+        .ok_or_else(|| ZenithError::NotFound(Missing::Other(format!("Webhook '{}' does not exist in collection '{}'", id, collection))))?;
+    let webhook = webhooks.remove(position);
+    write(collection, &webhooks)?;
+    DELIVERIES.lock().unwrap_or_else(|e| e.into_inner()).remove(id);
+    Ok(Webhook { secret: String::new(), ..webhook })
+}
+
+
+/// Returns the recent deliveries to the webhook with `id` on `collection`, oldest first.
+/// Raises a `NotFound` error if it does not exist.
+pub fn deliveries(collection: &str, id: &str) -> Result<Vec<Delivery>, ZenithError> {
+    if !list(collection)?.iter().any(|w| w.webhook_id == id) {
+        return Err(ZenithError::NotFound(Missing::Other(format!("Webhook '{}' does not exist in collection '{}'", id, collection))));
+    }
+    let deliveries = DELIVERIES.lock().unwrap_or_else(|e| e.into_inner());
+    Ok(deliveries.get(id).map(|d| d.iter().cloned().collect()).unwrap_or_default())
+}
+
+
+/// Returns the signature of `body` with `secret`, as `sha256=` and the hexadecimal HMAC-SHA256.
+fn signature(secret: &str, body: &[u8]) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC takes a key of any length");
+    mac.update(body);
+    let hmac: String = mac.finalize().into_bytes().iter().map(|b| format!("{:02x}", b)).collect();
+    format!("sha256={}", hmac)
+}
+
+
+/// Records the latest state of `delivery` to the webhook with `id`.
+fn record(id: &str, delivery: &Delivery) {
+    let mut deliveries = DELIVERIES.lock().unwrap_or_else(|e| e.into_inner());
+    let recent = deliveries.entry(id.to_string()).or_default();
+    match recent.iter_mut().find(|d| d.delivery_id == delivery.delivery_id) {
+        Some(recorded) => *recorded = delivery.clone(),
+        None => {
+            recent.push_back(delivery.clone());
+            if recent.len() > MAX_DELIVERIES {
+                recent.pop_front();
+            }
+        },
+    }
+}
+
+
+/// Delivers `change` to each webhook registered on its collection for its action,
+/// in the background. The change has already been made, so failures are only reported.
+pub fn trigger(change: &Change) {
+    let webhooks = match read(&change.collection) {
+        Ok(webhooks) => webhooks,
+        Err(err) => {
+            warn!("Could not read the webhooks of collection '{}': {}", change.collection, err);
+            return;
+        }
+    };
+    let webhooks: Vec<Webhook> = webhooks.into_iter()
+        .filter(|w| w.actions.is_empty() || w.actions.contains(&change.action))
+        .collect();
+    if webhooks.is_empty() {
+        return;
+    }
+    let body = match serde_json::to_vec(change) {
+        Ok(body) => body,
+        Err(err) => {
+            warn!("Could not deliver {} in collection '{}' to webhooks: {}", change.action, change.collection, err);
+            return;
+        }
+    };
+    for webhook in webhooks {
+        let delivery = Delivery {
+            delivery_id: request_id::generate(),
+            action: change.action.clone(),
+            filename: change.filename.clone(),
+            state: DeliveryState::Pending,
+            attempts: 0,
+            status: None,
+            error: None,
+            created: now(),
+            updated: now(),
+        };
+        record(&webhook.webhook_id, &delivery);
+        shutdown::spawn(request_id::inherit(deliver(webhook, delivery, body.clone())));
+    }
+}
+
+
+/// Sends `body` to `webhook` as `delivery`, signed with its secret. A delivery that fails,
+/// by an error or a response that is not `2xx`, is retried up to `ZENITHDS_WEBHOOK_RETRIES`
+/// times, waiting twice as long before each retry, from a second up to a minute.
+async fn deliver(webhook: Webhook, mut delivery: Delivery, body: Vec<u8>) {
+    let retries = config::envar_usize("ZENITHDS_WEBHOOK_RETRIES");
+    let timeout = config::envar_usize("ZENITHDS_WEBHOOK_TIMEOUT");
+    let mut headers = HeaderMap::new();
+    headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+    for (name, value) in [(EVENT_HEADER, &delivery.action), (DELIVERY_HEADER, &delivery.delivery_id), (SIGNATURE_HEADER, &signature(&webhook.secret, &body))] {
+        if let Ok(value) = HeaderValue::from_str(value) {
+            headers.insert(name, value);
+        }
+    }
+    loop {
+        delivery.attempts += 1;
+        (delivery.status, delivery.error) = match remote::post(&webhook.url, headers.clone(), body.clone(), timeout).await {
+            Ok(status) if status.is_success() => (Some(status.as_u16()), None),
+            Ok(status) => (Some(status.as_u16()), Some(format!("The webhook responded with {}", status))),
+            Err(err) => (None, Some(err.to_string())),
+        };
+        delivery.updated = now();
+        delivery.state = match &delivery.error {
+            None => DeliveryState::Delivered,
+            Some(_) if delivery.attempts > retries => DeliveryState::Failed,
+            Some(_) => DeliveryState::Pending,
+        };
+        record(&webhook.webhook_id, &delivery);
+
+        match delivery.state {
+            DeliveryState::Delivered => {
+                info!("Delivered {} to webhook '{}'", delivery.action, webhook.webhook_id);
+                return;
+            },
+            DeliveryState::Failed => {
+                warn!("Could not deliver {} to webhook '{}' after {} attempts: {}",
+                    delivery.action, webhook.webhook_id, delivery.attempts, delivery.error.as_deref().unwrap_or_default());
+                return;
+            },
+            DeliveryState::Pending => (),
+        }
+        let delay = Duration::from_secs(1 << (delivery.attempts - 1).min(6)).min(MAX_RETRY_DELAY);
+        tokio::select! {
+            _ = tokio::time::sleep(delay) => (),
+            _ = shutdown::requested() => {
+                delivery.state = DeliveryState::Failed;
+                delivery.error = Some("The data service stopped before the delivery was retried".to_string());
+                record(&webhook.webhook_id, &delivery);
+                return;
+            },
+        }
+    }
+}
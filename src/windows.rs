@@ -0,0 +1,126 @@
+use std::cmp::Ordering;
+
+use crate::db::Selection;
+use crate::types::{
+    api::{Window, WindowFunction},
+    error::ZenithError,
+};
+
+
+/// Returns the name of the column a window `function` adds, if it is not named.
+fn function_name(function: WindowFunction) -> &'static str {
+    match function {
+        WindowFunction::RowNumber => "row_number",
+        WindowFunction::Rank => "rank",
+        WindowFunction::Lag => "lag",
+        WindowFunction::Lead => "lead",
+        WindowFunction::RunningSum => "running_sum",
+    }
+}
+
+
+/// Returns the index of `column` in `header`, raising a `QueryError` if it is not there.
+fn index(header: &[String], column: &str) -> Result<usize, ZenithError> {
+    header.iter().position(|h| h == column)
+        .ok_or_else(|| ZenithError::QueryError(format!("The column '{}' of a window is not in the rows found", column)))
+}
+
+
+/// Returns the value at `i` in `row`, or an empty value if it is short.
+fn value(row: &[String], i: usize) -> &str {
+    row.get(i).map_or("", String::as_str)
+}
+
+
+/// Compares `a` and `b` as numbers if both are, and as text if neither is. Numbers come before text.
+fn compare(a: &str, b: &str) -> Ordering {
+    match (a.parse::<f64>(), b.parse::<f64>()) {
+        (Ok(x), Ok(y)) => x.total_cmp(&y),
+        (Ok(_), Err(_)) => Ordering::Less,
+        (Err(_), Ok(_)) => Ordering::Greater,
+        (Err(_), Err(_)) => a.cmp(b),
+    }
+}
+
+
+/// Computes each of the `windows` over the rows of `selection`, in turn, adding a column of its
+/// values to the header and each row. The rows are sorted by the partition and order of each
+/// window as it is computed, so they are returned in the order of the last. Raises a `QueryError`
+/// if a window names a column that is not in the rows, or a column that is already there.
+pub fn apply(selection: Selection, windows: &[Window]) -> Result<Selection, ZenithError> {
+    let (mut header, mut rows) = selection;
+    for window in windows {
+        let name = window.name.clone().unwrap_or_else(|| function_name(window.function).to_string());
+        if header.contains(&name) {
+            return Err(ZenithError::QueryError(format!("The window '{}' has the name of a column in the rows found", name)));
+        }
+        let order = index(&header, &window.order_by)?;
+        let partition = window.partition_by.as_deref().map(|column| index(&header, column)).transpose()?;
+        let column = match window.function {
+            WindowFunction::RowNumber | WindowFunction::Rank => None,
+            WindowFunction::Lag | WindowFunction::Lead | WindowFunction::RunningSum => {
+                let column = window.column.as_deref().ok_or_else(|| ZenithError::QueryError(format!(
+                    "The window '{}' needs a column for {}", name, function_name(window.function)
+                )))?;
+                Some(index(&header, column)?)
+            },
+        };
+
+        rows.sort_by(|a, b| {
+            let partitioned = partition.map_or(Ordering::Equal, |p| value(a, p).cmp(value(b, p)));
+            let ordered = compare(value(a, order), value(b, order));
+            partitioned.then(if window.descending { ordered.reverse() } else { ordered })
+        });
+        let mut values = Vec::with_capacity(rows.len());
+        let mut start = 0;
+        while start < rows.len() {
+            let end = start + rows[start..].iter()
+                .take_while(|row| partition.is_none_or(|p| value(row, p) == value(&rows[start], p)))
+                .count();
+            compute(&rows[start..end], window, order, column, &mut values);
+            start = end;
+        }
+        header.push(name);
+        for (row, value) in rows.iter_mut().zip(values) {
+            row.push(value);
+        }
+    }
+    Ok((header, rows))
+}
+
+
+/// Computes `window` for each of the `rows` of a partition, in order, adding its values to `values`.
+/// The rows are ordered by the column at `order`, and `column` is the column it takes values from.
+fn compute(
+    rows: &[Vec<String>],
+    window: &Window,
+    order: usize,
+    column: Option<usize>,
+    values: &mut Vec<String>,
+) {
+
+    let offset = window.offset.unwrap_or(1);
+    let column = column.unwrap_or_default();
+    let mut rank = 0;
+    let mut sum = 0.0;
+    for (i, row) in rows.iter().enumerate() {
+        let computed = match window.function {
+            WindowFunction::RowNumber => (i + 1).to_string(),
+            WindowFunction::Rank => {
+                // Rows that tie share the rank of the first, and the ranks after them are skipped.
+                if i == 0 || compare(value(&rows[i - 1], order), value(row, order)) != Ordering::Equal {
+                    rank = i + 1;
+                }
+                rank.to_string()
+            },
+            WindowFunction::Lag => i.checked_sub(offset).map_or("", |j| value(&rows[j], column)).to_string(),
+            WindowFunction::Lead => rows.get(i + offset).map_or("", |row| value(row, column)).to_string(),
+            WindowFunction::RunningSum => {
+                // Values that are not numbers are left out of the sum.
+                sum += value(row, column).parse::<f64>().unwrap_or(0.0);
+                sum.to_string()
+            },
+        };
+        values.push(computed);
+    }
+}
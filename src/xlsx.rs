@@ -0,0 +1,68 @@
+use std::io::Cursor;
+
+use calamine::{open_workbook_auto_from_rs, Data, Reader};
+
+use crate::types::error::ZenithError;
+
+
+/// Whether `bytes` look like a workbook rather than CSV data, which is the case for
+/// .xlsx and .ods files (zip archives) and .xls files (compound documents).
+pub fn is_workbook(bytes: &[u8]) -> bool {
+    bytes.starts_with(b"PK\x03\x04") || bytes.starts_with(&[0xD0, 0xCF, 0x11, 0xE0, 0xA1, 0xB1, 0x1A, 0xE1])
+}
+
+
+/// Whether `filename` is that of a workbook, whose extension is replaced when it is stored as CSV.
+pub fn is_workbook_name(filename: &str) -> bool {
+    let lower = filename.to_lowercase();
+    [".xlsx", ".xlsm", ".xls", ".ods"].iter().any(|ext| lower.ends_with(ext))
+}
+
+
+/// Returns `filename` with the extension of a workbook replaced by `.csv`.
+pub fn csv_name(filename: &str) -> String {
+    match filename.rsplit_once('.') {
+        Some((stem, _)) if is_workbook_name(filename) => format!("{}.csv", stem),
+        _ => filename.to_string(),
+    }
+}
+
+
+/// Returns a cell as it is written in CSV. Dates are written as `YYYY-MM-DD`, or
+/// `YYYY-MM-DD HH:MM:SS` if they have a time, and errors such as `#DIV/0!` are left empty.
+fn cell(data: &Data) -> String {
+    match data {
+        Data::DateTime(datetime) if datetime.is_datetime() => match datetime.as_datetime() {
+            Some(datetime) if datetime.time() == chrono::NaiveTime::MIN => datetime.format("%Y-%m-%d").to_string(),
+            Some(datetime) => datetime.format("%Y-%m-%d %H:%M:%S").to_string(),
+            None => data.to_string(),
+        },
+        Data::Error(_) => String::new(),
+        _ => data.to_string(),
+    }
+}
+
+
+/// Converts the sheet named `sheet` of the workbook in `bytes`, or its first sheet if
+/// none is named, to CSV data. Raises a `QueryError` if the workbook cannot be read or
+/// does not have the sheet.
+pub fn to_csv(bytes: &[u8], sheet: Option<&str>) -> Result<Vec<u8>, ZenithError> {
+    let mut workbook = open_workbook_auto_from_rs(Cursor::new(bytes))
+        .map_err(|err| ZenithError::QueryError(format!("Workbook cannot be read: {}", err)))?;
+    let range = match sheet {
+        Some(sheet) => {
+            if !workbook.sheet_names().iter().any(|name| name == sheet) {
+                return Err(ZenithError::QueryError(format!("Sheet '{}' cannot be found in the workbook", sheet)));
+            }
+            workbook.worksheet_range(sheet)
+        },
+        None => workbook.worksheet_range_at(0)
+            .ok_or_else(|| ZenithError::QueryError("Workbook has no sheets".to_string()))?,
+    }.map_err(|err| ZenithError::QueryError(format!("Sheet cannot be read: {}", err)))?;
+
+    let mut writer = csv::WriterBuilder::new().flexible(true).from_writer(Vec::new());
+    for row in range.rows() {
+        writer.write_record(row.iter().map(cell))?;
+    }
+    writer.into_inner().map_err(|err| ZenithError::FileSystemError(err.into_error()))
+}